@@ -0,0 +1,460 @@
+//! A uniform grid acceleration structure
+//!
+//! The grid splits the bounding box of the scene into equally sized cells (voxels) and keeps a
+//! list of the objects that overlap each cell. A ray walks through the cells it passes through in
+//! order, using the 3D-DDA from Amanatides and Woo's "A Fast Voxel Traversal Algorithm for Ray
+//! Tracing", and only tests the objects in those cells. The cells are visited front to back, so
+//! the walk can stop at the first cell that contains a hit.
+//!
+//! A grid is much simpler to build than a hierarchy, and it works well when the objects are spread
+//! evenly through the scene. It struggles with scenes that are mostly empty space, or that have a
+//! few very large objects next to a lot of small ones.
+
+use crate::{
+    accel::{is_closer, is_within, Accel, AccelError, AccelRecord, AccelResult, TraversalStats},
+    hittable::Aabb,
+    ray::Ray,
+    renderer::Arena,
+    types::{Dimension, Float},
+};
+use cgmath::{EuclideanSpace, Vector3};
+use serde::{Deserialize, Serialize};
+
+/// The most cells a grid can have
+///
+/// Every cell has a list of objects, even if it's empty, so a grid with a resolution in the
+/// thousands would run out of memory before a single ray was traced.
+pub const MAX_GRID_CELLS: usize = 1 << 24;
+
+/// The parameters for a uniform grid
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct GridParams {
+    /// The number of cells along the longest side of the scene's bounding box
+    ///
+    /// The other sides are split into as many cells as it takes to keep the cells close to cubes.
+    /// More cells means fewer objects to test in each cell, but more cells to walk through and
+    /// more memory. A grid can have at most `MAX_GRID_CELLS` cells.
+    pub resolution: u32,
+}
+
+/// A uniform grid of cells over the objects in the scene
+///
+/// An object that overlaps several cells is listed in each of them, so a ray may test it more
+/// than once.
+#[derive(Debug, Clone)]
+pub struct UniformGrid {
+    /// A list of every object in the scene
+    objects: Arena,
+
+    /// The box covered by the cells
+    bounds: Aabb,
+
+    /// The number of cells along each axis
+    dims: [usize; 3],
+
+    /// The size of a cell along each axis
+    cell_size: Vector3<Float>,
+
+    /// The indices of the objects that overlap each cell
+    ///
+    /// The cells are laid out with x changing the fastest, then y, then z. This is empty if there
+    /// aren't any objects with a finite bounding box.
+    cells: Vec<Vec<usize>>,
+
+    /// The indices of the objects that don't have a finite bounding box
+    ///
+    /// These can't be placed in any cell, so every ray tests them.
+    unbounded: Vec<usize>,
+}
+
+impl UniformGrid {
+    pub fn new(objects: Arena, params: GridParams) -> AccelResult<Self> {
+        if params.resolution == 0 {
+            return Err(AccelError::InvalidGridResolution);
+        }
+        let boxes: Vec<Aabb> = objects.iter().map(|x| x.geometry.bounding_box()).collect();
+        let mut bounds: Option<Aabb> = None;
+        let mut unbounded = Vec::new();
+
+        for (index, bounding_box) in boxes.iter().enumerate() {
+            if bounding_box.is_finite() {
+                bounds = Some(bounds.map_or(*bounding_box, |x| x.union(bounding_box)));
+            } else {
+                unbounded.push(index);
+            }
+        }
+        let mut grid = UniformGrid {
+            objects,
+            bounds: Aabb {
+                min: Vector3::new(0.0, 0.0, 0.0),
+                max: Vector3::new(0.0, 0.0, 0.0),
+            },
+            dims: [0; 3],
+            cell_size: Vector3::new(0.0, 0.0, 0.0),
+            cells: Vec::new(),
+            unbounded,
+        };
+        let bounds = match bounds {
+            Some(x) => x,
+            None => return Ok(grid),
+        };
+
+        // The bounds are padded so that a flat scene still has cells with a volume, and so that
+        // objects on the edge of the scene aren't lost to rounding errors
+        let size = bounds.max - bounds.min;
+        let padding = (size.x.max(size.y).max(size.z) * 1e-4).max(1e-4);
+        let padding = Vector3::new(padding, padding, padding);
+        grid.bounds = Aabb {
+            min: bounds.min - padding,
+            max: bounds.max + padding,
+        };
+        let size = grid.bounds.max - grid.bounds.min;
+        let cells_per_unit = params.resolution as Float / size.x.max(size.y).max(size.z);
+
+        for axis in Dimension::all().iter().map(|d| d.index()) {
+            let cells = (size[axis] * cells_per_unit).round() as usize;
+            grid.dims[axis] = cells.clamp(1, params.resolution as usize);
+            grid.cell_size[axis] = size[axis] / grid.dims[axis] as Float;
+        }
+        let n_cells = grid
+            .dims
+            .iter()
+            .try_fold(1_usize, |n, &cells| n.checked_mul(cells))
+            .filter(|&n| n <= MAX_GRID_CELLS)
+            .ok_or(AccelError::GridTooLarge(params.resolution))?;
+        grid.cells = vec![Vec::new(); n_cells];
+
+        for (index, bounding_box) in boxes.iter().enumerate() {
+            if !bounding_box.is_finite() {
+                continue;
+            }
+            let lo = grid.cell_of(bounding_box.min);
+            let hi = grid.cell_of(bounding_box.max);
+
+            for z in lo[2]..=hi[2] {
+                for y in lo[1]..=hi[1] {
+                    for x in lo[0]..=hi[0] {
+                        let cell = grid.cell_index([x, y, z]);
+                        grid.cells[cell].push(index);
+                    }
+                }
+            }
+        }
+        Ok(grid)
+    }
+
+    /// The coordinates of the cell that contains a point
+    ///
+    /// Points outside of the grid are clamped to the closest cell.
+    fn cell_of(&self, point: Vector3<Float>) -> [usize; 3] {
+        let mut cell = [0; 3];
+
+        for (axis, coordinate) in cell.iter_mut().enumerate() {
+            let offset = (point[axis] - self.bounds.min[axis]) / self.cell_size[axis];
+            *coordinate = offset.floor().clamp(0.0, (self.dims[axis] - 1) as Float) as usize;
+        }
+        cell
+    }
+
+    /// The index of a cell in `cells` from its coordinates
+    fn cell_index(&self, cell: [usize; 3]) -> usize {
+        cell[0] + self.dims[0] * (cell[1] + self.dims[1] * cell[2])
+    }
+
+    /// Test the ray against an object, and keep the collision if it's the closest one so far
+    fn test_object<'a>(
+        &'a self,
+        index: usize,
+        ray: &Ray,
+        closest: &mut Option<AccelRecord<'a>>,
+        stats: &mut TraversalStats,
+    ) {
+        let object = &self.objects[index];
+        stats.intersection_tests += 1;
+
+        if let Some(hit_record) = object.geometry.hit(ray) {
+            if is_closer(hit_record.distance, closest) {
+                *closest = Some(AccelRecord { object, hit_record });
+            }
+        }
+    }
+}
+
+impl UniformGrid {
+    /// Walk through the cells that a ray passes through, in order
+    ///
+    /// `visit` is called with the objects in each cell and the distance along the ray at which
+    /// the ray leaves the cell, and the walk stops as soon as it returns true.
+    fn walk(
+        &self,
+        ray: &Ray,
+        stats: &mut TraversalStats,
+        mut visit: impl FnMut(&[usize], Float, &mut TraversalStats) -> bool,
+    ) {
+        if self.cells.is_empty() {
+            return;
+        }
+        let t_enter = match self.bounds.intersection_range(ray) {
+            Some((t_enter, _)) => t_enter.max(0.0),
+            None => return,
+        };
+        let mut cell = self.cell_of(ray.at(t_enter).to_vec());
+
+        // For each axis: the direction the ray steps through the cells in, the distance along the
+        // ray to the next cell boundary, and the distance between boundaries. A ray that's
+        // parallel to an axis never crosses a boundary on it.
+        let mut step = [0; 3];
+        let mut t_next = [Float::INFINITY; 3];
+        let mut t_delta = [Float::INFINITY; 3];
+
+        for axis in Dimension::all().iter().map(|d| d.index()) {
+            let direction = ray.direction[axis];
+            let boundary = if direction > 0.0 {
+                step[axis] = 1;
+                cell[axis] + 1
+            } else if direction < 0.0 {
+                step[axis] = -1;
+                cell[axis]
+            } else {
+                continue;
+            };
+            let boundary = self.bounds.min[axis] + boundary as Float * self.cell_size[axis];
+            t_next[axis] = (boundary - ray.origin[axis]) / direction;
+            t_delta[axis] = self.cell_size[axis] / direction.abs();
+        }
+
+        loop {
+            stats.nodes_visited += 1;
+            // The ray leaves the cell through the closest boundary
+            let axis = Dimension::all()
+                .iter()
+                .map(|d| d.index())
+                .min_by(|&a, &b| t_next[a].total_cmp(&t_next[b]))
+                .unwrap_or(0);
+
+            if visit(&self.cells[self.cell_index(cell)], t_next[axis], stats) || step[axis] == 0 {
+                break;
+            }
+            let next = cell[axis] as isize + step[axis];
+
+            if next < 0 || next >= self.dims[axis] as isize {
+                break;
+            }
+            cell[axis] = next as usize;
+            t_next[axis] += t_delta[axis];
+        }
+    }
+}
+
+impl Accel for UniformGrid {
+    fn collision_with_stats<'a>(
+        &'a self,
+        ray: &Ray,
+        stats: &mut TraversalStats,
+    ) -> Option<AccelRecord<'a>> {
+        let mut closest: Option<AccelRecord> = None;
+
+        for &index in &self.unbounded {
+            self.test_object(index, ray, &mut closest, stats);
+        }
+        self.walk(ray, stats, |objects, t_exit, stats| {
+            for &index in objects {
+                self.test_object(index, ray, &mut closest, stats);
+            }
+            // Everything in the cells further along the ray is further away than a collision
+            // inside of this cell. An object can also overlap later cells, so a collision with it
+            // outside of this cell doesn't end the walk.
+            closest.is_some_and(|c| c.hit_record.distance <= t_exit)
+        });
+        closest
+    }
+
+    /// Walk the cells like `collision`, but stop at the first collision in range, or once the
+    /// cells are further away than `max_distance`
+    fn collision_any(&self, ray: &Ray, max_distance: Float) -> bool {
+        let blocks = |index: &usize| {
+            self.objects[*index]
+                .geometry
+                .hit(ray)
+                .is_some_and(|hit_record| is_within(hit_record.distance, max_distance))
+        };
+
+        if self.unbounded.iter().any(blocks) {
+            return true;
+        }
+        let mut found = false;
+        self.walk(ray, &mut TraversalStats::default(), |objects, t_exit, _| {
+            found = objects.iter().any(blocks);
+            found || t_exit >= max_distance
+        });
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        accel::ObjectList,
+        hittable::{Sphere, Textured, TriangleParameters},
+        material::Mirror,
+    };
+    use cgmath::{InnerSpace, Point3};
+    use std::sync::Arc;
+
+    /// A scene with a mix of small spheres, a big sphere, and a flat triangle
+    fn scene() -> Arena {
+        let mut objects = Vec::new();
+
+        for i in 0..27 {
+            let (x, y, z) = ((i % 3) as Float, ((i / 3) % 3) as Float, (i / 9) as Float);
+            objects.push(Textured::new(
+                Sphere {
+                    center: Point3::new(x * 3.0, y * 3.0, z * -3.0),
+                    radius: 0.5 + 0.1 * (i % 4) as Float,
+                },
+                Mirror::default(),
+            ));
+        }
+        objects.push(Textured::new(
+            Sphere {
+                center: Point3::new(3.0, 3.0, -3.0),
+                radius: 2.5,
+            },
+            Mirror::default(),
+        ));
+        let triangle = TriangleParameters {
+            vertices: [
+                Vector3::new(-1.0, -1.0, -8.0),
+                Vector3::new(8.0, -1.0, -8.0),
+                Vector3::new(-1.0, 8.0, -8.0),
+            ],
+            double_sided: true,
+            ..Default::default()
+        };
+        objects.push(Textured::new(triangle.init(), Mirror::default()));
+        Arc::new(objects)
+    }
+
+    // The grid finds the same closest collision as testing every object
+    #[test]
+    fn matches_object_list() {
+        let arena = scene();
+        let list = ObjectList::new(arena.clone()).unwrap();
+        let origins = [
+            Point3::new(3.0, 3.0, 10.0),
+            Point3::new(-5.0, 1.0, -2.0),
+            // Inside of the grid
+            Point3::new(1.5, 1.5, -1.5),
+        ];
+        let mut hits = 0;
+
+        for resolution in &[1, 3, 8, 20] {
+            let grid = UniformGrid::new(
+                arena.clone(),
+                GridParams {
+                    resolution: *resolution,
+                },
+            )
+            .unwrap();
+
+            for origin in &origins {
+                for i in 0..200 {
+                    // Directions spread over the sphere, plus the axes themselves, which are
+                    // parallel to every cell boundary but one
+                    let direction = if i < 6 {
+                        let mut d = Vector3::new(0.0, 0.0, 0.0);
+                        d[i / 2] = if i % 2 == 0 { 1.0 } else { -1.0 };
+                        d
+                    } else {
+                        let t = i as Float;
+                        Vector3::new((t * 0.37).sin(), (t * 0.91).cos(), (t * 0.53).sin() - 0.3)
+                            .normalize()
+                    };
+                    let ray = Ray::new(*origin, direction);
+                    let expected = list.collision(&ray);
+                    let actual = grid.collision(&ray);
+                    assert_eq!(
+                        expected.map(|x| x.hit_record.distance),
+                        actual.map(|x| x.hit_record.distance),
+                        "resolution {}, {:?}",
+                        resolution,
+                        ray
+                    );
+
+                    if let (Some(expected), Some(actual)) = (expected, actual) {
+                        assert!(std::ptr::eq(expected.object, actual.object));
+                        hits += 1;
+                    }
+
+                    // Occlusion queries agree with the closest collision
+                    for max_distance in [1.0, 4.0, 10.0, Float::INFINITY] {
+                        let occluded =
+                            expected.is_some_and(|x| x.hit_record.distance < max_distance);
+                        assert_eq!(list.collision_any(&ray, max_distance), occluded);
+                        assert_eq!(grid.collision_any(&ray, max_distance), occluded);
+                    }
+                }
+            }
+        }
+        // Make sure that the rays actually hit things
+        assert!(hits > 500);
+    }
+
+    // A ray along the row of cells at the bottom of the grid visits each of them once
+    #[test]
+    fn stats() {
+        let grid = UniformGrid::new(scene(), GridParams { resolution: 3 }).unwrap();
+        let ray = Ray::new(Point3::new(-10.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        let mut stats = TraversalStats::default();
+        let hit = grid.collision_with_stats(&ray, &mut stats).unwrap();
+        assert!((hit.hit_record.distance - 9.5).abs() < 1e-5);
+        // The first cell has a hit, so the walk stops there
+        assert_eq!(stats.nodes_visited, 1);
+        assert!(stats.intersection_tests > 0);
+
+        let miss = Ray::new(Point3::new(-10.0, -20.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        let mut stats = TraversalStats::default();
+        assert!(grid.collision_with_stats(&miss, &mut stats).is_none());
+        assert_eq!(stats, TraversalStats::default());
+    }
+
+    #[test]
+    fn empty_grid() {
+        let grid = UniformGrid::new(Arc::new(vec![]), GridParams { resolution: 8 }).unwrap();
+        let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -1.0));
+        assert!(grid.collision(&ray).is_none());
+    }
+
+    #[test]
+    fn zero_resolution() {
+        assert!(matches!(
+            UniformGrid::new(scene(), GridParams { resolution: 0 }),
+            Err(AccelError::InvalidGridResolution)
+        ));
+    }
+
+    #[test]
+    fn too_many_cells() {
+        for resolution in [5000, u32::MAX] {
+            assert!(matches!(
+                UniformGrid::new(scene(), GridParams { resolution }),
+                Err(AccelError::GridTooLarge(x)) if x == resolution
+            ));
+        }
+        // A flat scene only has one cell along its thin side, so it can use a finer grid
+        let flat: Arena = Arc::new(vec![Textured::new(
+            TriangleParameters {
+                vertices: [
+                    Vector3::new(0.0, 0.0, 0.0),
+                    Vector3::new(1.0, 0.0, 0.0),
+                    Vector3::new(0.0, 1.0, 0.0),
+                ],
+                ..Default::default()
+            }
+            .init(),
+            Mirror::default(),
+        )]);
+        assert!(UniformGrid::new(flat, GridParams { resolution: 2048 }).is_ok());
+    }
+}
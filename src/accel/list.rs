@@ -1,13 +1,12 @@
 //! The "list" acceleration structure for computing intersections.
 
 use crate::{
-    accel::{Accel, AccelRecord, AccelResult},
+    accel::{is_closer, is_within, Accel, AccelRecord, AccelResult, TraversalStats},
     ray::Ray,
     renderer::Arena,
-    types::eta,
+    types::Float,
 };
 use serde::{Deserialize, Serialize};
-use std::cmp::Ordering::Equal;
 
 /// The parameters for a basic object list
 ///
@@ -35,37 +34,58 @@ impl ObjectList {
 }
 
 impl Accel for ObjectList {
-    fn collision(&self, ray: &Ray) -> Option<AccelRecord> {
-        // Collect every object that was hit so we can sort them out and find the closest
-        // intersection to the origin point of the ray after every object has been traversed. We
-        // also filter out any collisions that are less than the margin of error.
-        let mut intersections: Vec<AccelRecord> = self
-            .objects
-            .iter()
-            .filter_map(|obj| {
-                if let Some(hit_record) = obj.geometry.hit(ray) {
-                    Some(AccelRecord {
+    fn collision_with_stats<'a>(
+        &'a self,
+        ray: &Ray,
+        stats: &mut TraversalStats,
+    ) -> Option<AccelRecord<'a>> {
+        // We only need the closest intersection, so we keep a running minimum rather than
+        // collecting and sorting every hit, which would allocate and take O(n log n) time.
+        // Collisions within the margin of error and collisions with a NaN or infinite distance are
+        // skipped, see `is_closer`.
+        let mut closest: Option<AccelRecord> = None;
+        // The list has no nodes to speak of, every object is tested
+        stats.intersection_tests += self.objects.len();
+
+        for obj in self.objects.iter() {
+            if let Some(hit_record) = obj.geometry.hit(ray) {
+                if is_closer(hit_record.distance, &closest) {
+                    closest = Some(AccelRecord {
                         object: obj,
                         hit_record,
-                    })
-                } else {
-                    None
+                    });
                 }
-            })
-            .filter(|x| x.hit_record.distance >= eta())
-            .collect();
+            }
+        }
+        closest
+    }
+
+    /// Test every object against the whole packet before moving on to the next object, so each
+    /// object is only loaded once per packet
+    fn collision_packet<'a>(&'a self, rays: &[Ray]) -> Vec<Option<AccelRecord<'a>>> {
+        let mut closest: Vec<Option<AccelRecord>> = vec![None; rays.len()];
 
-        // If the list is empty, then the sort method will be a no-op. We don't need to preserve
-        // the order of elements, so we can use the fast unstable sort.
-        intersections.sort_unstable_by(|a, b| {
-            let a_dist = a.hit_record.distance;
-            let b_dist = b.hit_record.distance;
-            // We treat NaN values as equal. If we hit NaNs by this point the entire list is likely
-            // useless anyway and there are other issues that have propagated to this point.
-            a_dist.partial_cmp(&b_dist).unwrap_or(Equal)
-        });
-        // Convert `Option<&AccelRecord>` to `Option<AccelRecord>`
-        intersections.first().map(|&x| x)
+        for obj in self.objects.iter() {
+            for (ray, closest) in rays.iter().zip(closest.iter_mut()) {
+                if let Some(hit_record) = obj.geometry.hit(ray) {
+                    if is_closer(hit_record.distance, closest) {
+                        *closest = Some(AccelRecord {
+                            object: obj,
+                            hit_record,
+                        });
+                    }
+                }
+            }
+        }
+        closest
+    }
+
+    fn collision_any(&self, ray: &Ray, max_distance: Float) -> bool {
+        self.objects.iter().any(|obj| {
+            obj.geometry
+                .hit(ray)
+                .is_some_and(|hit_record| is_within(hit_record.distance, max_distance))
+        })
     }
 }
 
@@ -73,20 +93,17 @@ impl Accel for ObjectList {
 mod tests {
     use super::*;
     use crate::{
-        hittable::{HitRecord, Sphere, Textured},
-        material::Mirror,
+        hittable::{Aabb, HitRecord, Hittable, Sphere, Textured},
+        material::{Mirror, BSDF},
     };
-    use cgmath::Vector3;
+    use cgmath::{Point3, Vector2, Vector3};
     use std::sync::Arc;
 
     // A convenience method to help create an ObjectList of references
     fn create_list(objects: Vec<Sphere>) -> ObjectList {
         let box_objects = objects
             .into_iter()
-            .map(|geom| Textured {
-                geometry: Box::new(geom),
-                mat: Box::new(Mirror::default()),
-            })
+            .map(|geom| Textured::new(geom, Mirror::default()))
             .collect();
         ObjectList::new(Arc::new(box_objects)).unwrap()
     }
@@ -96,7 +113,7 @@ mod tests {
     fn no_objects() {
         let list = create_list(vec![]);
         let ray = Ray {
-            origin: Vector3::new(0.0, 0.0, 0.0),
+            origin: Point3::new(0.0, 0.0, 0.0),
             direction: Vector3::new(0.0, 0.0, 0.0),
         };
         assert!(list.collision(&ray).is_none());
@@ -107,20 +124,20 @@ mod tests {
     fn no_collisions() {
         let list = create_list(vec![
             Sphere {
-                center: Vector3::new(0.0, 0.0, 0.0),
+                center: Point3::new(0.0, 0.0, 0.0),
                 radius: 1.0,
             },
             Sphere {
-                center: Vector3::new(0.0, 5.0, 0.0),
+                center: Point3::new(0.0, 5.0, 0.0),
                 radius: 1.0,
             },
             Sphere {
-                center: Vector3::new(0.0, -5.0, 0.0),
+                center: Point3::new(0.0, -5.0, 0.0),
                 radius: 1.0,
             },
         ]);
         let ray = Ray {
-            origin: Vector3::new(0.0, 0.0, -1.0),
+            origin: Point3::new(0.0, 0.0, -1.0),
             direction: Vector3::new(0.0, 0.0, -1.0),
         };
         assert!(list.collision(&ray).is_none());
@@ -131,11 +148,11 @@ mod tests {
     #[test]
     fn one_possible_collision() {
         let list = create_list(vec![Sphere {
-            center: Vector3::new(0.0, 0.0, 0.0),
+            center: Point3::new(0.0, 0.0, 0.0),
             radius: 1.0,
         }]);
         let ray = Ray {
-            origin: Vector3::new(0.0, -2.0, 0.0),
+            origin: Point3::new(0.0, -2.0, 0.0),
             direction: Vector3::new(0.0, 1.0, 0.0),
         };
         assert!(list.collision(&ray).is_some());
@@ -146,31 +163,224 @@ mod tests {
     fn multiple_collisions() {
         let list = create_list(vec![
             Sphere {
-                center: Vector3::new(0.0, 0.0, 0.0),
+                center: Point3::new(0.0, 0.0, 0.0),
                 radius: 1.0,
             },
             Sphere {
-                center: Vector3::new(0.0, 2.0, 0.0),
+                center: Point3::new(0.0, 2.0, 0.0),
                 radius: 1.0,
             },
             Sphere {
-                center: Vector3::new(-5.0, -5.0, -5.0),
+                center: Point3::new(-5.0, -5.0, -5.0),
                 radius: 2.0,
             },
             Sphere {
-                center: Vector3::new(5.0, 5.0, 5.0),
+                center: Point3::new(5.0, 5.0, 5.0),
                 radius: 2.0,
             },
         ]);
         let ray = Ray {
-            origin: Vector3::new(0.0, -2.0, 0.0),
+            origin: Point3::new(0.0, -2.0, 0.0),
             direction: Vector3::new(0.0, 1.0, 0.0),
         };
         let expected = HitRecord {
-            p: Vector3::new(0.0, -1.0, 0.0),
+            p: Point3::new(0.0, -1.0, 0.0),
             distance: 1.0,
-            normal: Vector3::new(0.0, -1.0, 0.0),
+            front_face: true,
+            geometric_normal: Vector3::new(0.0, -1.0, 0.0),
+            shading_normal: Vector3::new(0.0, -1.0, 0.0),
+            uv: Vector2::new(0.5, 0.0),
         };
         assert_eq!(list.collision(&ray).unwrap().hit_record, expected);
     }
+
+    // The closest hit is found no matter where the object is in the list
+    #[test]
+    fn closest_hit_is_order_independent() {
+        let spheres = vec![
+            Sphere {
+                center: Point3::new(0.0, 6.0, 0.0),
+                radius: 1.0,
+            },
+            Sphere {
+                center: Point3::new(0.0, 3.0, 0.0),
+                radius: 1.0,
+            },
+            Sphere {
+                center: Point3::new(0.0, 0.0, 0.0),
+                radius: 1.0,
+            },
+        ];
+        let ray = Ray {
+            origin: Point3::new(0.0, -2.0, 0.0),
+            direction: Vector3::new(0.0, 1.0, 0.0),
+        };
+
+        for rotation in 0..spheres.len() {
+            let mut rotated = spheres.clone();
+            rotated.rotate_left(rotation);
+            let hit = create_list(rotated).collision(&ray).unwrap().hit_record;
+            assert!((hit.distance - 1.0).abs() < 1e-6);
+        }
+    }
+
+    // The records returned by the list borrow from the shared arena, and stay valid even if the
+    // original handle to the arena is dropped, since the list holds its own reference.
+    #[test]
+    fn records_borrow_from_shared_arena() {
+        let arena: Arena = Arc::new(vec![Textured::new(
+            Sphere {
+                center: Point3::new(0.0, 0.0, 0.0),
+                radius: 1.0,
+            },
+            Mirror::default(),
+        )]);
+        let accel: Box<dyn Accel> = Box::new(ObjectList::new(arena.clone()).unwrap());
+        let ray = Ray {
+            origin: Point3::new(0.0, -2.0, 0.0),
+            direction: Vector3::new(0.0, 1.0, 0.0),
+        };
+
+        let record = accel.collision(&ray).unwrap();
+        assert!(std::ptr::eq(record.object, &arena[0]));
+
+        let object: *const Textured = &arena[0];
+        drop(arena);
+        let record = accel.collision(&ray).unwrap();
+        assert!(std::ptr::eq(record.object, object));
+        assert_eq!(record.hit_record.distance, 1.0);
+    }
+
+    // A packet finds the same hits as tracing each of its rays on its own
+    #[test]
+    fn packet_matches_single_rays() {
+        let list = create_list(vec![
+            Sphere {
+                center: Point3::new(0.0, 0.0, -3.0),
+                radius: 1.0,
+            },
+            Sphere {
+                center: Point3::new(0.0, 0.0, -6.0),
+                radius: 2.0,
+            },
+            Sphere {
+                center: Point3::new(3.0, 0.0, -3.0),
+                radius: 1.0,
+            },
+        ]);
+        let rays: Vec<Ray> = (-4..=4)
+            .map(|x| Ray {
+                origin: Point3::new(0.0, 0.0, 0.0),
+                direction: Vector3::new(x as Float * 0.25, 0.0, -1.0),
+            })
+            .collect();
+        let packet = list.collision_packet(&rays);
+        assert_eq!(packet.len(), rays.len());
+
+        for (ray, record) in rays.iter().zip(&packet) {
+            let single = list.collision(ray);
+            assert_eq!(record.map(|x| x.hit_record), single.map(|x| x.hit_record));
+            assert_eq!(
+                record.map(|x| x.object as *const Textured),
+                single.map(|x| x.object as *const Textured)
+            );
+        }
+        assert!(packet.iter().any(|x| x.is_some()));
+        assert!(packet.iter().any(|x| x.is_none()));
+        assert!(list.collision_packet(&[]).is_empty());
+    }
+
+    /// A broken primitive that "hits" every ray at the same distance
+    #[derive(Debug)]
+    struct Degenerate(Float);
+
+    impl Hittable for Degenerate {
+        fn hit(&self, ray: &Ray) -> Option<HitRecord> {
+            Some(HitRecord {
+                p: ray.origin,
+                geometric_normal: Vector3::new(0.0, 0.0, 1.0),
+                shading_normal: Vector3::new(0.0, 0.0, 1.0),
+                distance: self.0,
+                front_face: true,
+                uv: Vector2::new(0.0, 0.0),
+            })
+        }
+
+        fn area(&self) -> Float {
+            0.0
+        }
+
+        fn bounding_box(&self) -> Aabb {
+            Aabb {
+                min: Vector3::new(0.0, 0.0, 0.0),
+                max: Vector3::new(0.0, 0.0, 0.0),
+            }
+        }
+    }
+
+    // Collisions with a NaN or infinite distance can't win, no matter where they are in the list
+    #[test]
+    fn non_finite_distances_are_ignored() {
+        let sphere = || {
+            Textured::new(
+                Sphere {
+                    center: Point3::new(0.0, 0.0, -5.0),
+                    radius: 1.0,
+                },
+                Mirror::default(),
+            )
+        };
+        let degenerate = |distance| Textured::new(Degenerate(distance), Mirror::default());
+        let ray = Ray {
+            origin: Point3::new(0.0, 0.0, 0.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+
+        for distance in [Float::NAN, Float::INFINITY, Float::NEG_INFINITY] {
+            let list = ObjectList::new(Arc::new(vec![degenerate(distance), sphere()])).unwrap();
+            let hit = list.collision(&ray).unwrap();
+            assert_eq!(hit.hit_record.distance, 4.0);
+            assert_eq!(
+                list.collision_packet(std::slice::from_ref(&ray))[0]
+                    .unwrap()
+                    .hit_record
+                    .distance,
+                4.0
+            );
+
+            let list = ObjectList::new(Arc::new(vec![sphere(), degenerate(distance)])).unwrap();
+            assert_eq!(list.collision(&ray).unwrap().hit_record.distance, 4.0);
+
+            let list = ObjectList::new(Arc::new(vec![degenerate(distance)])).unwrap();
+            assert!(list.collision(&ray).is_none());
+        }
+    }
+
+    // Objects can share a single material instance
+    #[test]
+    fn shared_material() {
+        let mat: Arc<dyn BSDF> = Arc::new(Mirror::default());
+        let objects = (0..3)
+            .map(|i| Textured {
+                geometry: Box::new(Sphere {
+                    center: Point3::new(0.0, 0.0, -5.0 * i as Float),
+                    radius: 1.0,
+                }),
+                mat: mat.clone(),
+            })
+            .collect();
+        let list = ObjectList::new(Arc::new(objects)).unwrap();
+        assert_eq!(Arc::strong_count(&mat), 4);
+
+        let ray = Ray {
+            origin: Point3::new(0.0, 0.0, 2.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        let record = list.collision(&ray).unwrap();
+        assert!(Arc::ptr_eq(&record.object.mat, &mat));
+
+        let mut stats = TraversalStats::default();
+        list.collision_with_stats(&ray, &mut stats);
+        assert_eq!(stats.intersection_tests, 3);
+    }
 }
@@ -3,18 +3,20 @@
 //! This module provides the generic interface for acceleration structures as well as
 //! implementations of various acceleration structures.
 
+mod grid;
 mod list;
 
+pub use grid::{GridParams, UniformGrid, MAX_GRID_CELLS};
 pub use list::{ObjectList, ObjectListParams};
 
 use crate::{
-    hittable::{HitRecord, Hittable, Textured},
-    material::BSDF,
+    hittable::{HitRecord, Textured},
     ray::Ray,
     renderer::Arena,
+    types::{eta, Float},
 };
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
+use std::{fmt::Debug, sync::Arc};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -22,6 +24,15 @@ use thiserror::Error;
 pub enum AccelError {
     #[error("There must be at least one object passed to the constructor")]
     NoObjects,
+
+    #[error("The grid resolution must be at least 1")]
+    InvalidGridResolution,
+
+    #[error(
+        "A grid resolution of {0} would split the scene into more than {max} cells",
+        max = MAX_GRID_CELLS
+    )]
+    GridTooLarge(u32),
 }
 
 /// The different types of acceleration structures that can be used in the scene description
@@ -32,14 +43,29 @@ pub enum AccelError {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum SerializedAccelerationStruct {
     ObjectList(ObjectListParams),
+    Grid(GridParams),
 }
 
 impl SerializedAccelerationStruct {
+    /// The name of the acceleration structure, as it's written in scene files
+    pub fn name(&self) -> &'static str {
+        match self {
+            SerializedAccelerationStruct::ObjectList(_) => "ObjectList",
+            SerializedAccelerationStruct::Grid(_) => "Grid",
+        }
+    }
+
     /// Construct an acceleration structure from a list of parameters and a reference to the object
     /// arena
-    pub fn to_accel(self, arena: Arena) -> AccelResult<Box<dyn Accel>> {
-        let accel = match self {
-            SerializedAccelerationStruct::ObjectList(_params) => Box::new(ObjectList::new(arena)?),
+    ///
+    /// The structure is reference counted so it can be shared by several renders of the same
+    /// scene without being rebuilt.
+    pub fn to_accel(&self, arena: Arena) -> AccelResult<Arc<dyn Accel>> {
+        let accel: Arc<dyn Accel> = match self {
+            SerializedAccelerationStruct::ObjectList(_params) => Arc::new(ObjectList::new(arena)?),
+            SerializedAccelerationStruct::Grid(params) => {
+                Arc::new(UniformGrid::new(arena, *params)?)
+            }
         };
         Ok(accel)
     }
@@ -54,6 +80,12 @@ pub type AccelResult<T> = Result<T, AccelError>;
 /// object. This requires both the information about the geometric primitive and the physical
 /// collision, as well as a reference to the actual object and the associated BSDF/material
 /// function.
+///
+/// The record borrows the object from the acceleration structure that produced it. Every
+/// acceleration structure holds its own clone of the shared `Arena`, so the objects it hands out
+/// live at least as long as the structure itself, and `'a` is simply the lifetime of the borrow of
+/// the acceleration structure. This means an acceleration structure never needs unsafe code or
+/// `'static` objects to return a reference into the arena.
 #[derive(Debug, Clone, Copy)]
 pub struct AccelRecord<'a> {
     /// The details of the collision
@@ -63,14 +95,37 @@ pub struct AccelRecord<'a> {
     pub object: &'a Textured,
 }
 
-/// A reference to an object that consists of a geometric form and a texture
-#[derive(Debug, Clone, Copy)]
-pub struct TexturedRef<'a> {
-    /// The geometric primitive that might be hit by the light ray or path
-    pub geometry: &'a dyn Hittable,
+/// Whether a collision at `distance` should replace the closest collision found so far
+///
+/// Collisions within the margin of error of the ray's origin are ignored, so a ray doesn't hit the
+/// surface it's leaving. So are collisions whose distance isn't finite, which come from degenerate
+/// primitives: NaN fails every comparison, so it could otherwise be kept and never replaced if it
+/// came first, and an infinite distance isn't a point on any surface.
+fn is_closer(distance: Float, closest: &Option<AccelRecord>) -> bool {
+    distance.is_finite()
+        && distance >= eta()
+        && closest.is_none_or(|c| distance < c.hit_record.distance)
+}
 
-    /// A reference to the BSDF method for
-    pub mat: &'a dyn BSDF,
+/// Whether a collision at `distance` is in front of the ray and closer than `max_distance`
+///
+/// Like `is_closer`, collisions within the margin of error of the ray's origin and collisions
+/// whose distance isn't finite don't count.
+fn is_within(distance: Float, max_distance: Float) -> bool {
+    distance.is_finite() && distance >= eta() && distance < max_distance
+}
+
+/// Counters for the work that an acceleration structure did to answer a query
+///
+/// These are used to profile acceleration structures, such as with a heatmap of the work done for
+/// each pixel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TraversalStats {
+    /// The number of nodes (or cells) of the structure that were visited
+    pub nodes_visited: usize,
+
+    /// The number of objects that the ray was tested against
+    pub intersection_tests: usize,
 }
 
 /// The `Accel` trait is a generic trait for acceleration structures in the renderer.
@@ -79,5 +134,41 @@ pub struct TexturedRef<'a> {
 /// with some object in the scene.
 pub trait Accel: Debug + Send + Sync {
     /// Return whether the incoming ray collided with any of the objects in the scene
-    fn collision(&self, ray: &Ray) -> Option<AccelRecord>;
+    ///
+    /// The returned record borrows the object that was hit from the acceleration structure's
+    /// `Arena`, see `AccelRecord` for details.
+    fn collision<'a>(&'a self, ray: &Ray) -> Option<AccelRecord<'a>> {
+        self.collision_with_stats(ray, &mut TraversalStats::default())
+    }
+
+    /// Find the closest collision like `collision`, and add the work it took to `stats`
+    ///
+    /// Incrementing the counters is cheap enough that `collision` uses this method as well, so
+    /// the profile matches what the renderer actually does.
+    fn collision_with_stats<'a>(
+        &'a self,
+        ray: &Ray,
+        stats: &mut TraversalStats,
+    ) -> Option<AccelRecord<'a>>;
+
+    /// Find the closest collision for each ray in a packet
+    ///
+    /// The records are in the same order as the rays. Traversing a packet of coherent rays (such
+    /// as camera rays through neighboring pixels) together lets a structure visit each of its
+    /// nodes once per packet rather than once per ray. The default implementation calls
+    /// `collision` for each ray, so structures only need to override this if they can do better.
+    fn collision_packet<'a>(&'a self, rays: &[Ray]) -> Vec<Option<AccelRecord<'a>>> {
+        rays.iter().map(|ray| self.collision(ray)).collect()
+    }
+
+    /// Return whether the ray collides with any object closer than `max_distance`
+    ///
+    /// This is meant for shadow rays, which only need to know whether something is in the way,
+    /// not what the closest object is. The default implementation finds the closest collision
+    /// with `collision`, so structures should override this to stop at the first collision in
+    /// range instead.
+    fn collision_any(&self, ray: &Ray, max_distance: Float) -> bool {
+        self.collision(ray)
+            .is_some_and(|c| c.hit_record.distance < max_distance)
+    }
 }
@@ -0,0 +1,325 @@
+//! What a ray sees when it doesn't hit anything
+//!
+//! A scene's background is either a constant color, a vertical gradient, or a procedural sky. In
+//! a scene file it can be written as a bare color, like `[0.0, 0.0, 0.0]` or `"#87ceeb"`, which is
+//! short for a constant color, or as an object with a `type` field like every other part of the
+//! scene, like `{ "type": "Sky", "sun_direction": [0.0, 1.0, -1.0] }`.
+
+use crate::{
+    math::{safe_normalize, UpAxis},
+    ray::Ray,
+    types::{Float, PixelValue},
+};
+use cgmath::{ElementWise, InnerSpace, Vector3};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::f32::consts::PI;
+
+/// The light arriving from directions where a ray doesn't hit anything
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type")]
+pub enum Background {
+    /// The same color in every direction
+    Color {
+        #[serde(deserialize_with = "crate::color::deserialize")]
+        color: PixelValue<Float>,
+    },
+
+    /// A blend from one color straight down to another color straight up
+    Gradient {
+        #[serde(deserialize_with = "crate::color::deserialize")]
+        bottom: PixelValue<Float>,
+
+        #[serde(deserialize_with = "crate::color::deserialize")]
+        top: PixelValue<Float>,
+    },
+
+    /// A clear daytime sky
+    Sky(Sky),
+}
+
+impl Background {
+    /// Convert the background from a scene with some up axis to the internal convention
+    ///
+    /// Gradients always run from the bottom to the top of the scene, so only the direction of
+    /// the sun changes.
+    pub fn convert_up_axis(&mut self, up_axis: UpAxis) {
+        if let Background::Sky(sky) = self {
+            sky.sun_direction = up_axis.convert_vector(sky.sun_direction);
+        }
+    }
+
+    /// The light arriving along the reverse of the ray's direction
+    pub fn sample(&self, ray: &Ray) -> PixelValue<Float> {
+        match self {
+            Background::Color { color } => *color,
+            Background::Gradient { bottom, top } => {
+                let t = 0.5 * (ray.direction.normalize().y + 1.0);
+                (bottom * (1.0 - t)) + (top * t)
+            }
+            Background::Sky(sky) => sky.radiance(ray.direction),
+        }
+    }
+}
+
+/// The forms a background can take in a scene file
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BackgroundRepr {
+    Color(#[serde(deserialize_with = "crate::color::deserialize")] PixelValue<Float>),
+    Background(Background),
+}
+
+/// Deserialize a background that is either a bare color or a `Background`
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Background, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(match BackgroundRepr::deserialize(deserializer)? {
+        BackgroundRepr::Color(color) => Background::Color { color },
+        BackgroundRepr::Background(x) => x,
+    })
+}
+
+/// The Preetham analytic model of a clear sky
+///
+/// This is the model from "A Practical Analytic Model for Daylight" by Preetham, Shirley, and
+/// Smits. It fits the brightness and color of the sky to the angle from the zenith and the angle
+/// from the sun, so it captures the glow around the sun and the brighter horizon. The sun itself
+/// isn't drawn, and it isn't sampled as a light, so the sky only lights the scene through the
+/// rays that happen to escape the scene.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct Sky {
+    /// The direction towards the sun, in the scene's coordinate system
+    ///
+    /// The sun is above the horizon when this points along the scene's `up_axis`, which is +y
+    /// unless the scene says otherwise. This doesn't have to be normalized. A sun below the
+    /// horizon is treated as if it's on the horizon, since the model doesn't cover twilight.
+    pub sun_direction: Vector3<Float>,
+
+    /// How hazy the air is, from about 2 for a very clear sky to 10 for a hazy one
+    #[serde(default = "default_turbidity")]
+    pub turbidity: Float,
+
+    /// The color of the ground, which is lit by the sky and fills every direction below the
+    /// horizon
+    #[serde(
+        default = "default_ground_albedo",
+        deserialize_with = "crate::color::deserialize"
+    )]
+    pub ground_albedo: PixelValue<Float>,
+
+    /// A scale for the brightness of the sky
+    ///
+    /// The model gives luminance in kcd/m^2, which is around 10 for a clear sky, so this
+    /// defaults to 0.1 to bring it in line with the other colors in a scene.
+    #[serde(default = "default_intensity")]
+    pub intensity: Float,
+}
+
+fn default_turbidity() -> Float {
+    3.0
+}
+
+fn default_ground_albedo() -> PixelValue<Float> {
+    PixelValue::new(0.3, 0.3, 0.3)
+}
+
+fn default_intensity() -> Float {
+    0.1
+}
+
+/// The coefficients of the Perez sky distribution function for one of the sky's xyY components
+struct Perez([Float; 5]);
+
+impl Perez {
+    /// The coefficients for a turbidity, given as `(slope, intercept)` pairs for each coefficient
+    fn new(turbidity: Float, coefficients: [(Float, Float); 5]) -> Self {
+        let mut perez = [0.0; 5];
+
+        for (x, (slope, intercept)) in perez.iter_mut().zip(&coefficients) {
+            *x = slope * turbidity + intercept;
+        }
+        Perez(perez)
+    }
+
+    /// The relative value of the component for a direction `theta` from the zenith and `gamma`
+    /// from the sun
+    fn eval(&self, cos_theta: Float, gamma: Float) -> Float {
+        let [a, b, c, d, e] = self.0;
+        let cos_gamma = gamma.cos();
+        (1.0 + a * (b / cos_theta).exp())
+            * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+}
+
+impl Sky {
+    /// The light arriving from a direction
+    pub fn radiance(&self, direction: Vector3<Float>) -> PixelValue<Float> {
+        let up = Vector3::new(0.0, 1.0, 0.0);
+        let direction = safe_normalize(&direction).unwrap_or(up);
+
+        // The ground is diffuse, so it's lit by the whole sky. The sky straight up is a cheap
+        // stand-in for the average.
+        if direction.y < 0.0 {
+            return self.sky_radiance(up).mul_element_wise(self.ground_albedo);
+        }
+        self.sky_radiance(direction)
+    }
+
+    /// The light arriving from a direction at or above the horizon
+    fn sky_radiance(&self, direction: Vector3<Float>) -> PixelValue<Float> {
+        let t = self.turbidity;
+        let sun = safe_normalize(&self.sun_direction).unwrap_or(Vector3::new(0.0, 1.0, 0.0));
+        let theta_s = sun.y.clamp(0.0, 1.0).acos();
+        let (theta_s2, theta_s3) = (theta_s * theta_s, theta_s * theta_s * theta_s);
+
+        // The luminance and chromaticity of the zenith
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let zenith_x = t * t * (0.00166 * theta_s3 - 0.00375 * theta_s2 + 0.00209 * theta_s)
+            + t * (-0.02903 * theta_s3 + 0.06377 * theta_s2 - 0.03202 * theta_s + 0.00394)
+            + (0.11693 * theta_s3 - 0.21196 * theta_s2 + 0.06052 * theta_s + 0.25886);
+        let zenith_y_chroma = t * t * (0.00275 * theta_s3 - 0.00610 * theta_s2 + 0.00317 * theta_s)
+            + t * (-0.04214 * theta_s3 + 0.08970 * theta_s2 - 0.04153 * theta_s + 0.00516)
+            + (0.15346 * theta_s3 - 0.26756 * theta_s2 + 0.06670 * theta_s + 0.26688);
+
+        let perez_luminance = Perez::new(
+            t,
+            [
+                (0.1787, -1.4630),
+                (-0.3554, 0.4275),
+                (-0.0227, 5.3251),
+                (0.1206, -2.5771),
+                (-0.0670, 0.3703),
+            ],
+        );
+        let perez_x = Perez::new(
+            t,
+            [
+                (-0.0193, -0.2592),
+                (-0.0665, 0.0008),
+                (-0.0004, 0.2125),
+                (-0.0641, -0.8989),
+                (-0.0033, 0.0452),
+            ],
+        );
+        let perez_y = Perez::new(
+            t,
+            [
+                (-0.0167, -0.2608),
+                (-0.0950, 0.0092),
+                (-0.0079, 0.2102),
+                (-0.0441, -1.6537),
+                (-0.0109, 0.0529),
+            ],
+        );
+
+        // Each component is its value at the zenith, scaled by how the distribution at the
+        // direction compares to the distribution at the zenith. The cosine is kept away from zero
+        // so the horizon doesn't divide by zero.
+        let cos_theta = direction.y.max(1e-3);
+        let gamma = direction.dot(sun).clamp(-1.0, 1.0).acos();
+        let relative = |perez: &Perez| perez.eval(cos_theta, gamma) / perez.eval(1.0, theta_s);
+        let luminance = zenith_y * relative(&perez_luminance) * self.intensity;
+        let x = zenith_x * relative(&perez_x);
+        let y = zenith_y_chroma * relative(&perez_y);
+        xyy_to_rgb(x, y, luminance)
+    }
+}
+
+/// Convert a color from CIE xyY to linear sRGB
+///
+/// Colors outside of the sRGB gamut have their negative channels clamped to zero.
+fn xyy_to_rgb(x: Float, y: Float, luminance: Float) -> PixelValue<Float> {
+    if y <= 0.0 {
+        return PixelValue::new(0.0, 0.0, 0.0);
+    }
+    let big_x = x * luminance / y;
+    let big_z = (1.0 - x - y) * luminance / y;
+    PixelValue::new(
+        (3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z).max(0.0),
+        (-0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z).max(0.0),
+        (0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z).max(0.0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::luminance;
+    use cgmath::Point3;
+
+    fn test_sky() -> Sky {
+        Sky {
+            sun_direction: Vector3::new(0.0, 1.0, -1.0),
+            turbidity: 3.0,
+            ground_albedo: PixelValue::new(0.5, 0.25, 0.0),
+            intensity: 1.0,
+        }
+    }
+
+    #[test]
+    fn sky() {
+        let sky = test_sky();
+        let up = Vector3::new(0.0, 1.0, 0.0);
+
+        // The zenith has the luminance that the model predicts for a sun 45 degrees up, which is
+        // about 7.32 kcd/m^2
+        let zenith = sky.radiance(up);
+        assert!((luminance(&zenith) - 7.32).abs() < 0.05, "{:?}", zenith);
+
+        // The sky is brighter near the sun than opposite of it, and a clear sky is blue
+        let near_sun = sky.radiance(Vector3::new(0.0, 1.0, -1.2));
+        let away_from_sun = sky.radiance(Vector3::new(0.0, 1.0, 1.2));
+        assert!(luminance(&near_sun) > luminance(&away_from_sun));
+        assert!(away_from_sun.z > away_from_sun.x);
+
+        // The ground is lit by the sky
+        let ground = sky.radiance(Vector3::new(1.0, -1.0, 0.0));
+        assert_eq!(
+            ground,
+            PixelValue::new(zenith.x * 0.5, zenith.y * 0.25, 0.0)
+        );
+    }
+
+    #[test]
+    fn gradient() {
+        let gradient = Background::Gradient {
+            bottom: PixelValue::new(1.0, 1.0, 1.0),
+            top: PixelValue::new(0.0, 0.0, 1.0),
+        };
+        let ray = |y| Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::new(0.0, y, 0.0));
+        assert_eq!(gradient.sample(&ray(1.0)), PixelValue::new(0.0, 0.0, 1.0));
+        assert_eq!(gradient.sample(&ray(-2.0)), PixelValue::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn deserialize_formats() {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            #[serde(deserialize_with = "super::deserialize")]
+            background: Background,
+        }
+        let parse = |s: &str| json5::from_str::<Wrapper>(s).unwrap().background;
+        let blue = Background::Color {
+            color: PixelValue::new(0.0, 0.0, 1.0),
+        };
+        assert_eq!(parse(r#"{ "background": [0.0, 0.0, 1.0] }"#), blue);
+        assert_eq!(parse(r##"{ "background": "#00f" }"##), blue);
+        assert_eq!(
+            parse(r#"{ "background": { "type": "Color", "color": [0.0, 0.0, 1.0] } }"#),
+            blue
+        );
+
+        let sky =
+            parse(r#"{ "background": { "type": "Sky", "sun_direction": [0.0, 1.0, -1.0] } }"#);
+        assert_eq!(
+            sky,
+            Background::Sky(Sky {
+                ground_albedo: default_ground_albedo(),
+                intensity: default_intensity(),
+                ..test_sky()
+            })
+        );
+    }
+}
@@ -1,7 +1,8 @@
 //! A module defining the generic interface for cameras and providing interfaces for various camera
 //! types
 
-use crate::{ray::Ray, types::Float};
+use crate::{math::UpAxis, ray::Ray, types::Float};
+use cgmath::Vector3;
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 
@@ -14,7 +15,9 @@ pub use thin_lens::ThinLens;
 
 /// The generic interface for a camera type
 ///
-/// A camera simply needs to convert u, v coordinates to a 3D ray.
+/// A camera simply needs to convert u, v coordinates to a 3D ray. Cameras that model a lens or a
+/// shutter can override `generate_ray`, which also receives a sample on the lens and a point in
+/// time.
 pub trait Camera: Debug + Send + Sync {
     /// Convert (u, v) pixel coordinates to a ray in 3D space
     ///
@@ -22,12 +25,67 @@ pub trait Camera: Debug + Send + Sync {
     /// an aspect ratio, which is just nx / ny, where nx and ny are the horizontal and vertical
     /// pixels, respectively.
     fn to_ray(&self, u: Float, v: Float) -> Ray;
+
+    /// Generate a ray for (u, v) pixel coordinates with a lens sample and a time
+    ///
+    /// `lens_sample` is a point in the unit square [0, 1]^2 that cameras with an aperture can use
+    /// to pick a point on the lens, and `time` is a point in [0, 1] within the shutter interval
+    /// for cameras that support motion blur. The renderer always calls this method rather than
+    /// `to_ray`.
+    ///
+    /// The default implementation ignores the lens sample and the time and defers to `to_ray`,
+    /// which is the correct behavior for pinhole cameras.
+    fn generate_ray(&self, u: Float, v: Float, _lens_sample: (Float, Float), _time: Float) -> Ray {
+        self.to_ray(u, v)
+    }
 }
 
 /// The different types of cameras that can be used in the scene description
+///
+/// The camera is named by a `type` field next to its parameters.
 #[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+#[serde(tag = "type")]
 pub enum SerializedCamera {
     BasicPinhole(BasicPinhole),
     Pinhole(Pinhole),
     ThinLens(ThinLens),
 }
+
+impl SerializedCamera {
+    /// The name of the camera, as it's written in scene files
+    pub fn name(&self) -> &'static str {
+        match self {
+            SerializedCamera::BasicPinhole(_) => "BasicPinhole",
+            SerializedCamera::Pinhole(_) => "Pinhole",
+            SerializedCamera::ThinLens(_) => "ThinLens",
+        }
+    }
+
+    /// Convert the camera from a scene with some up axis to the internal convention
+    pub fn convert_up_axis(&mut self, up_axis: UpAxis) {
+        let convert = |v: &mut Vector3<Float>| *v = up_axis.convert_vector(*v);
+
+        match self {
+            SerializedCamera::BasicPinhole(x) => {
+                for v in [
+                    &mut x.origin,
+                    &mut x.horizontal,
+                    &mut x.vertical,
+                    &mut x.lower_left,
+                ] {
+                    convert(v);
+                }
+            }
+            SerializedCamera::Pinhole(x) => {
+                for v in [&mut x.origin, &mut x.target, &mut x.up] {
+                    convert(v);
+                }
+            }
+            SerializedCamera::ThinLens(x) => {
+                for v in [&mut x.origin, &mut x.target, &mut x.up] {
+                    convert(v);
+                }
+            }
+        }
+    }
+}
@@ -1,7 +1,7 @@
 //! Implementations of pinhole cameras
 
-use crate::{camera::Camera, ray::Ray, types::Float};
-use cgmath::{InnerSpace, Vector3};
+use crate::{camera::Camera, math::safe_normalize, ray::Ray, types::Float};
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3};
 use serde::{Deserialize, Serialize};
 
 /// The classic pinhole camera
@@ -21,11 +21,16 @@ pub struct BasicPinhole {
 
 impl Camera for BasicPinhole {
     fn to_ray(&self, u: Float, v: Float) -> Ray {
+        let direction = self.lower_left + (self.horizontal * u) + (self.vertical * v) - self.origin;
+        // A degenerate camera (e.g. one where the image plane passes through the origin) can
+        // produce a zero-length direction, so we fall back to looking straight out of the image
+        // plane
+        let direction = safe_normalize(&direction)
+            .or_else(|| safe_normalize(&self.vertical.cross(self.horizontal)))
+            .unwrap_or(Vector3::new(0.0, 0.0, -1.0));
         Ray {
-            origin: self.origin,
-            direction: (self.lower_left + (self.horizontal * u) + (self.vertical * v)
-                - self.origin)
-                .normalize(),
+            origin: Point3::from_vec(self.origin),
+            direction,
         }
     }
 }
@@ -71,9 +76,13 @@ impl Pinhole {
     }
 }
 
-/// A pinhole camera, much like `BasicPinhole`, that allows you to specify the aspect ratio and the
-/// field of view.
+/// A pinhole camera, much like `BasicPinhole`, that allows you to specify the field of view and
+/// where the camera is looking.
+///
+/// The aspect ratio isn't part of the camera. It comes from the scene, which derives it from the
+/// output resolution unless the scene's `aspect_ratio` overrides it, and it's passed to `init`.
 #[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Pinhole {
     /// The target that the camera is pointing towards from the origin
     pub target: Vector3<Float>,
@@ -83,8 +92,6 @@ pub struct Pinhole {
     pub vfov: Float,
     /// Which direction you consider up for the camera
     pub up: Vector3<Float>,
-    /// The aspect ratio of
-    pub aspect_ratio: Float,
 }
 
 #[cfg(test)]
@@ -97,35 +104,35 @@ mod test {
 
         // this is equivalent to the lower left corner of the frame
         let ray = Ray {
-            origin: Vector3::new(0.0, 0.0, 0.0),
+            origin: Point3::new(0.0, 0.0, 0.0),
             direction: Vector3::new(-2.0, -1.0, -1.0).normalize(),
         };
         assert_eq!(camera.to_ray(0.0, 0.0), ray);
 
         // middle
         let ray = Ray {
-            origin: Vector3::new(0.0, 0.0, 0.0),
+            origin: Point3::new(0.0, 0.0, 0.0),
             direction: Vector3::new(0.0, 0.0, -1.0).normalize(),
         };
         assert_eq!(camera.to_ray(0.5, 0.5), ray);
 
         // upper left corner
         let ray = Ray {
-            origin: Vector3::new(0.0, 0.0, 0.0),
+            origin: Point3::new(0.0, 0.0, 0.0),
             direction: Vector3::new(-2.0, 1.0, -1.0).normalize(),
         };
         assert_eq!(camera.to_ray(0.0, 1.0), ray);
 
         // upper right corner
         let ray = Ray {
-            origin: Vector3::new(0.0, 0.0, 0.0),
+            origin: Point3::new(0.0, 0.0, 0.0),
             direction: Vector3::new(2.0, 1.0, -1.0).normalize(),
         };
         assert_eq!(camera.to_ray(1.0, 1.0), ray);
 
         // lower right corner
         let ray = Ray {
-            origin: Vector3::new(0.0, 0.0, 0.0),
+            origin: Point3::new(0.0, 0.0, 0.0),
             direction: Vector3::new(2.0, -1.0, -1.0).normalize(),
         };
         assert_eq!(camera.to_ray(1.0, 0.0), ray);
@@ -133,42 +140,53 @@ mod test {
 
     #[test]
     fn test_get_ray_f64() {
-        type FType = f64;
         let camera: BasicPinhole = Default::default();
 
         // this is equivalent to the lower left corner of the frame
         let ray = Ray {
-            origin: Vector3::new(0.0, 0.0, 0.0),
+            origin: Point3::new(0.0, 0.0, 0.0),
             direction: Vector3::new(-2.0, -1.0, -1.0).normalize(),
         };
         assert_eq!(camera.to_ray(0.0, 0.0), ray);
 
         // middle
         let ray = Ray {
-            origin: Vector3::new(0.0, 0.0, 0.0),
+            origin: Point3::new(0.0, 0.0, 0.0),
             direction: Vector3::new(0.0, 0.0, -1.0).normalize(),
         };
         assert_eq!(camera.to_ray(0.5, 0.5), ray);
 
         // upper left corner
         let ray = Ray {
-            origin: Vector3::new(0.0, 0.0, 0.0),
+            origin: Point3::new(0.0, 0.0, 0.0),
             direction: Vector3::new(-2.0, 1.0, -1.0).normalize(),
         };
         assert_eq!(camera.to_ray(0.0, 1.0), ray);
 
         // upper right corner
         let ray = Ray {
-            origin: Vector3::new(0.0, 0.0, 0.0),
+            origin: Point3::new(0.0, 0.0, 0.0),
             direction: Vector3::new(2.0, 1.0, -1.0).normalize(),
         };
         assert_eq!(camera.to_ray(1.0, 1.0), ray);
 
         // lower right corner
         let ray = Ray {
-            origin: Vector3::new(0.0, 0.0, 0.0),
+            origin: Point3::new(0.0, 0.0, 0.0),
             direction: Vector3::new(2.0, -1.0, -1.0).normalize(),
         };
         assert_eq!(camera.to_ray(1.0, 0.0), ray);
     }
+
+    #[test]
+    fn degenerate_direction() {
+        // The lower left corner of the image plane is the origin, so the ray through (0, 0) has no
+        // direction
+        let camera = BasicPinhole {
+            lower_left: Vector3::new(0.0, 0.0, 0.0),
+            ..Default::default()
+        };
+        let ray = camera.to_ray(0.0, 0.0);
+        assert_eq!(ray.direction, Vector3::new(0.0, 0.0, -1.0));
+    }
 }
@@ -1,11 +1,13 @@
 //! A model of a thin-lens camera
 
 use crate::{
-    camera::{BasicPinhole, Camera},
+    camera::{BasicPinhole, Camera, Pinhole},
+    math::safe_normalize,
     ray::Ray,
+    sampler::primitives::map_unit_disk,
     types::Float,
 };
-use cgmath::Vector3;
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3};
 use serde::{Deserialize, Serialize};
 
 /// A thin lens camera model
@@ -13,9 +15,19 @@ use serde::{Deserialize, Serialize};
 /// This camera model uses an approximation of a lens, rather than simulating a full lens. This
 /// allows for effects like depth of field and adjustable apertures, without the cost of simulating
 /// light refracting through len(ses).
+///
+/// Every ray starts at a point on the lens and passes through the point on the focal plane that
+/// the pinhole ray through the center of the lens would hit. Objects on the focal plane are in
+/// focus, and objects in front of it or behind it are blurred more the larger the aperture is.
+///
+/// Like `Pinhole`, the aspect ratio comes from the scene rather than the camera.
 #[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ThinLens {
     /// The internal basic pinhole camera that we keep around for precomputed values
+    ///
+    /// Its image plane is the focal plane. This is computed by `init`.
+    #[serde(skip)]
     basic_pinhole: Option<BasicPinhole>,
 
     /// The target that the camera is pointing towards from the origin
@@ -26,12 +38,156 @@ pub struct ThinLens {
     pub vfov: Float,
     /// Which direction you consider up for the camera
     pub up: Vector3<Float>,
-    /// The aspect ratio of the camera
-    pub aspect: Float,
+
+    /// The radius of the lens
+    ///
+    /// Larger apertures blur objects that are out of focus more. An aperture of 0 keeps
+    /// everything in focus, like a pinhole camera.
+    #[serde(default)]
+    pub aperture: Float,
+
+    /// The distance from the origin to the plane that's in focus, along the view direction
+    ///
+    /// This defaults to the distance to the target, so the target is in focus.
+    #[serde(default)]
+    pub focus_distance: Option<Float>,
+}
+
+impl ThinLens {
+    /// Initialize the camera for an aspect ratio, precomputing the focal plane
+    ///
+    /// This is the same computation as `Pinhole::init`, with the image plane pushed out to the
+    /// focus distance.
+    pub fn init(mut self, aspect_ratio: Float) -> Self {
+        self.basic_pinhole = Some(self.focal_plane(aspect_ratio));
+        self
+    }
+
+    /// The pinhole camera whose image plane is the focal plane
+    fn focal_plane(&self, aspect_ratio: Float) -> BasicPinhole {
+        let pinhole = Pinhole {
+            target: self.target,
+            origin: self.origin,
+            vfov: self.vfov,
+            up: self.up,
+        }
+        .init(aspect_ratio);
+        let focus_distance = self
+            .focus_distance
+            .unwrap_or_else(|| (self.target - self.origin).magnitude());
+        // The pinhole camera's image plane is one unit away, so scaling it about the origin moves
+        // it to the focus distance without changing the field of view
+        BasicPinhole {
+            origin: pinhole.origin,
+            horizontal: pinhole.horizontal * focus_distance,
+            vertical: pinhole.vertical * focus_distance,
+            lower_left: pinhole.origin + (pinhole.lower_left - pinhole.origin) * focus_distance,
+        }
+    }
 }
 
 impl Camera for ThinLens {
-    fn to_ray(&self, _u: Float, _v: Float) -> Ray {
-        unimplemented!()
+    /// The ray through the center of the lens, which is the same as a pinhole camera's ray
+    fn to_ray(&self, u: Float, v: Float) -> Ray {
+        self.generate_ray(u, v, (0.5, 0.5), 0.0)
+    }
+
+    /// The ray from a point on the lens through the focal plane
+    ///
+    /// The lens sample is mapped to a point on the lens. If the camera wasn't initialized with
+    /// `init`, the focal plane is computed for a square image.
+    fn generate_ray(&self, u: Float, v: Float, lens_sample: (Float, Float), _time: Float) -> Ray {
+        let plane = self.basic_pinhole.unwrap_or_else(|| self.focal_plane(1.0));
+        let focus = plane.lower_left + (plane.horizontal * u) + (plane.vertical * v);
+        let lens = map_unit_disk(lens_sample.0, lens_sample.1) * self.aperture;
+        let right = safe_normalize(&plane.horizontal).unwrap_or(Vector3::new(1.0, 0.0, 0.0));
+        let up = safe_normalize(&plane.vertical).unwrap_or(Vector3::new(0.0, 1.0, 0.0));
+        let origin = plane.origin + right * lens.x + up * lens.y;
+        // The focal point can coincide with the origin in a degenerate camera, in which case we
+        // fall back to the pinhole ray
+        let direction =
+            safe_normalize(&(focus - origin)).unwrap_or_else(|| plane.to_ray(u, v).direction);
+        Ray {
+            origin: Point3::from_vec(origin),
+            direction,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A camera at the origin looking down -z with the target two units away
+    fn camera(aperture: Float, focus_distance: Option<Float>) -> ThinLens {
+        ThinLens {
+            basic_pinhole: None,
+            target: Vector3::new(0.0, 0.0, -2.0),
+            origin: Vector3::new(0.0, 0.0, 0.0),
+            vfov: 90.0,
+            up: Vector3::new(0.0, 1.0, 0.0),
+            aperture,
+            focus_distance,
+        }
+        .init(2.0)
+    }
+
+    /// Where a ray crosses the plane at some depth in front of the camera
+    fn at_depth(ray: &Ray, depth: Float) -> Point3<Float> {
+        ray.at(depth / -ray.direction.z)
+    }
+
+    #[test]
+    fn focus() {
+        let lens_samples = [(0.5, 0.5), (0.0, 0.0), (1.0, 0.3), (0.2, 0.9)];
+
+        for (focus_distance, depth) in [(None, 2.0), (Some(5.0), 5.0)] {
+            let camera = camera(0.25, focus_distance);
+
+            for (u, v) in [(0.5, 0.5), (0.1, 0.8), (1.0, 0.0)] {
+                let rays: Vec<Ray> = lens_samples
+                    .iter()
+                    .map(|&sample| camera.generate_ray(u, v, sample, 0.0))
+                    .collect();
+                // The rays start at different points on the lens, but meet on the focal plane
+                let focus = at_depth(&rays[0], depth);
+
+                for ray in &rays[1..] {
+                    assert!((ray.origin - rays[0].origin).magnitude() > 0.05);
+                    assert!((at_depth(ray, depth) - focus).magnitude() < 1e-4);
+                    // In front of and behind the focal plane they spread apart
+                    assert!(
+                        (at_depth(ray, depth * 0.5) - at_depth(&rays[0], depth * 0.5)).magnitude()
+                            > 0.02
+                    );
+                    assert!(
+                        (at_depth(ray, depth * 3.0) - at_depth(&rays[0], depth * 3.0)).magnitude()
+                            > 0.02
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pinhole_equivalence() {
+        // Without an aperture, and through the center of the lens, the camera is a pinhole camera
+        let pinhole = Pinhole {
+            target: Vector3::new(0.0, 0.0, -2.0),
+            origin: Vector3::new(0.0, 0.0, 0.0),
+            vfov: 90.0,
+            up: Vector3::new(0.0, 1.0, 0.0),
+        }
+        .init(2.0);
+        let thin_lens = camera(0.5, Some(3.0));
+        let pinhole_lens = camera(0.0, None);
+
+        for (u, v) in [(0.5, 0.5), (0.0, 0.0), (0.3, 0.9)] {
+            let expected = pinhole.to_ray(u, v);
+            assert!((thin_lens.to_ray(u, v).direction - expected.direction).magnitude() < 1e-5);
+            let ray = pinhole_lens.generate_ray(u, v, (0.9, 0.1), 0.0);
+            assert_eq!(ray.origin, expected.origin);
+            assert!((ray.direction - expected.direction).magnitude() < 1e-5);
+        }
     }
 }
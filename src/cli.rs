@@ -1,12 +1,22 @@
 //! This module handles everything related to the CLI interface, such as arguments, "UI", and
 //! control flow.
 
-use crate::scene::*;
 use anyhow::{self, format_err};
-use json5;
-use ron;
-use serde_yaml;
-use std::{fs::File, io::Read, path::PathBuf};
+use cgmath::Vector3;
+use nib::{
+    types::{Float, PixelValue},
+    BitDepth, ChannelOrder, DownscaleKernel, HeatmapMetric, NoiseMode, PixelSampling, PpmEncoding,
+    Renderer, Scene, SceneFragment, SerializedHittable, SerializedTextured, MESH_FORMATS,
+    OUTPUT_FORMATS,
+};
+use serde::de::DeserializeOwned;
+use std::{
+    convert::TryFrom,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 use structopt::StructOpt;
 
 /// An oxidized renderer
@@ -14,7 +24,8 @@ use structopt::StructOpt;
 #[structopt(author = "Afnan Enayet")]
 pub struct Args {
     /// The path to the file describing the scene
-    pub scene: PathBuf,
+    #[structopt(required_unless = "list-formats")]
+    pub scene: Option<PathBuf>,
 
     /// The file type of the scene description file. If this is not supplied, the application will
     /// attempt to guess the file type from the file extension. Valid values are: "ron", "yaml",
@@ -22,26 +33,245 @@ pub struct Args {
     #[structopt(short, long)]
     pub filetype: Option<String>,
 
+    /// Print the supported scene file formats, mesh formats, and output image formats, then exit.
+    #[structopt(long)]
+    pub list_formats: bool,
+
     /// Only parse the scene file and do nothing else. This can be helpful when trying to construct
     /// scene files to ensure that they conform to the spec and will be deserialized properly.
     #[structopt(short = "r", long)]
     pub only_parse: bool,
 
+    /// Prepare the scene and print a summary of it instead of rendering it: the number of objects,
+    /// primitives, materials, and lights, the camera, the resolution, the total number of samples,
+    /// and an estimate of the memory the render needs. Unlike "--only-parse", this loads meshes
+    /// and builds the acceleration structure, and it takes the other options into account, like
+    /// "--supersample".
+    #[structopt(long)]
+    pub info: bool,
+
+    /// Write the scene to this file instead of rendering it, in the format given by its
+    /// extension. The exported scene has the objects of every included file, the overrides from
+    /// the other options like "--seed" and "--scale", and absolute paths to its meshes, so it can
+    /// be saved anywhere. Valid extensions are: "ron", "json", "yaml", "yml".
+    #[structopt(long)]
+    pub export_scene: Option<PathBuf>,
+
     /// If enabled, this flag will hide the progress bar. The progress bar is ordinarily displayed
-    /// to STDERR.
+    /// to STDERR, as long as STDERR is a terminal.
     #[structopt(short = "p", long = "hide-progress")]
     pub hide_progress: bool,
 
+    /// Draw the progress bar even if STDERR isn't a terminal, such as when it's redirected to a
+    /// file.
+    #[structopt(long, conflicts_with = "hide-progress")]
+    pub show_progress: bool,
+
+    /// Log how much the image changes with each pass, as the root mean square difference from the
+    /// image after the previous pass. This shows how quickly the render is converging, which helps
+    /// to pick the number of samples per pixel. The values are logged at the info level, so they
+    /// need "RUST_LOG=info".
+    #[structopt(long)]
+    pub log_convergence: bool,
+
     /// The number of threads to use in the renderer. If this isn't set, the renderer will default
     /// to the number of CPUs detected. This can also be set with the environment variable
     /// "RAYON_NUM_THREADS". If this is set to 0, then the default number of threads will be used.
+    /// With 1, the render runs on the main thread without a thread pool, which is easier to debug
+    /// and gives the same image.
     #[structopt(short, long)]
     pub threads: Option<usize>,
 
+    /// The minimum number of pixels that each thread renders at a time. Larger values reduce the
+    /// scheduling overhead for cheap integrators, smaller values balance the load between threads
+    /// better for expensive ones. If this isn't set, it's derived from the size of the image.
+    #[structopt(long)]
+    pub chunk_size: Option<usize>,
+
     /// The filename of the output file. If this is not provided it will default to "out.png". The
-    /// output file type is inferred from the filename.
+    /// output file type is inferred from the filename. Valid extensions are: "png", "ppm".
+    ///
+    /// The filename can contain placeholders that are filled in for each render: "{scene}" (the
+    /// name of the scene file without its extension), "{frame}", "{width}", "{height}", and
+    /// "{integrator}". Placeholders can be padded to a minimum width, with zeros if the width
+    /// starts with 0, like "{frame:04}". Any directories in the filename are created if they
+    /// don't exist.
     #[structopt(short, long)]
     pub output: Option<String>,
+
+    /// The directory to write the output file to. The output filename is relative to this
+    /// directory, which is created if it doesn't exist.
+    #[structopt(long)]
+    pub output_dir: Option<PathBuf>,
+
+    /// Multiply the width and height of the scene by this factor before rendering. For example,
+    /// 0.5 renders a draft at half of the resolution specified in the scene file.
+    #[structopt(long)]
+    pub scale: Option<f32>,
+
+    /// Render at this many times the width and height of the output image, then downscale the
+    /// render to the output size. The downscaling averages the linear values of the render,
+    /// before they're encoded for the output image, so edges keep their brightness.
+    #[structopt(long)]
+    pub supersample: Option<u32>,
+
+    /// The kernel used to downscale a "--supersample" render. "box" averages the pixels that each
+    /// output pixel covers, "lanczos" is sharper but can ring around edges. This is ignored without
+    /// "--supersample". Valid values are: "box", "lanczos".
+    #[structopt(long, default_value = "box")]
+    pub downscale_kernel: DownscaleKernel,
+
+    /// The number of bits per color channel in the output image. Valid values are: 8, 16.
+    #[structopt(long, default_value = "8")]
+    pub bit_depth: BitDepth,
+
+    /// The order in which the color channels of each pixel are written to the output image. Valid
+    /// values are: "rgb", "bgr".
+    #[structopt(long, default_value = "rgb")]
+    pub channel_order: ChannelOrder,
+
+    /// How the pixels of a PPM image are stored. ASCII images can be read as text, binary images
+    /// are much smaller. This is ignored for other output formats. Valid values are: "ascii",
+    /// "binary".
+    #[structopt(long, default_value = "ascii")]
+    pub ppm_encoding: PpmEncoding,
+
+    /// Where the samples of each pixel are placed within it. "tent" jitters every sample around
+    /// the center of the pixel, favoring positions near the center, "random" jitters every sample
+    /// to a uniformly random position in the pixel, "grid" spreads the samples evenly over the
+    /// pixel, and "center" puts every sample at the center of the pixel, which turns anti-aliasing
+    /// off. This overrides the `pixel_sampling` setting in the scene file. Valid values are:
+    /// "center", "grid", "random", "tent".
+    #[structopt(long)]
+    pub pixel_sampling: Option<PixelSampling>,
+
+    /// The distribution that samples are jittered with around the center of each pixel. "box"
+    /// jitters them uniformly within the pixel, and "tent" favors positions near the center, which
+    /// approximates a triangle filter. This is a shorthand for `--pixel-sampling random` and
+    /// `--pixel-sampling tent`. Valid values are: "box", "tent".
+    #[structopt(long, conflicts_with = "pixel-sampling")]
+    pub aa_filter: Option<AaFilter>,
+
+    /// The exposure of the image, in stops. Each stop doubles the brightness of the image, and
+    /// negative values darken it. This overrides the `exposure` setting in the scene file.
+    #[structopt(long, allow_hyphen_values = true)]
+    pub exposure: Option<f32>,
+
+    /// The aspect ratio (width / height) of the camera's image plane. By default this is derived
+    /// from the resolution of the scene so the image isn't stretched. This overrides the
+    /// `aspect_ratio` setting in the scene file.
+    #[structopt(long)]
+    pub aspect_ratio: Option<f32>,
+
+    /// Move the camera to this position, written as "x,y,z". This overrides the origin of
+    /// `Pinhole` and `ThinLens` cameras in the scene file, and is ignored for `BasicPinhole`
+    /// cameras.
+    #[structopt(long, parse(try_from_str = parse_vector), allow_hyphen_values = true)]
+    pub cam_origin: Option<Vector3<f32>>,
+
+    /// Point the camera at this position, written as "x,y,z". This overrides the target of
+    /// `Pinhole` and `ThinLens` cameras in the scene file, and is ignored for `BasicPinhole`
+    /// cameras.
+    #[structopt(long, parse(try_from_str = parse_vector), allow_hyphen_values = true)]
+    pub cam_target: Option<Vector3<f32>>,
+
+    /// The maximum amount of time the render may take, in seconds. Once this budget is exceeded,
+    /// the renderer stops sampling and writes out the image it has accumulated so far. This
+    /// overrides the `max_time` setting in the scene file.
+    #[structopt(long)]
+    pub max_time: Option<f32>,
+
+    /// The seed for the random numbers used while rendering. Renders with the same seed produce
+    /// the same image. This overrides the `seed` setting in the scene file. If neither is set, a
+    /// random seed is used, which is logged so the render can be reproduced.
+    #[structopt(long)]
+    pub seed: Option<u64>,
+
+    /// The maximum number of primitives the scene may contain once its meshes are loaded, such as
+    /// the triangles of a mesh. Loading a scene with more primitives fails with an error instead
+    /// of running out of memory. This overrides the `max_primitives` setting in the scene file.
+    /// There's no limit by default.
+    #[structopt(long)]
+    pub max_primitives: Option<usize>,
+
+    /// The index of the frame being rendered, for animations that are rendered one frame at a
+    /// time. This fills in the "{frame}" placeholder in the output filename, and changes the seed
+    /// depending on "--animate-noise". The seed that's logged is the animation's, so passing it
+    /// to "--seed" along with the same frame renders the same image.
+    #[structopt(long, default_value = "0")]
+    pub frame: u32,
+
+    /// How the noise changes between the frames of an animation. With "boil", every frame gets
+    /// its own reproducible seed derived from the frame index. With "static", every frame uses the
+    /// same seed, so the noise stays in place, which is useful for comparing frames. Valid values
+    /// are: "boil", "static".
+    #[structopt(long, default_value = "boil")]
+    pub animate_noise: NoiseMode,
+
+    /// A reference image to compare the render to, such as a known good render of the same
+    /// scene. The maximum absolute error, mean squared error, and PSNR of the render are printed
+    /// after it's written. The reference must have the same resolution as the render.
+    #[structopt(long)]
+    pub compare: Option<PathBuf>,
+
+    /// Where to write a false color image of the difference between the render and the
+    /// "--compare" reference. Pixels are red where the error is largest and black where they
+    /// match.
+    #[structopt(long, requires = "compare")]
+    pub compare_output: Option<PathBuf>,
+
+    /// Instead of rendering the scene, write a heatmap of how much work the acceleration
+    /// structure does for the camera ray through each pixel, from blue (the least) to red (the
+    /// most). With "traversal", this counts the nodes that were visited and the objects that were
+    /// tested. With "intersections", this only counts the objects that were tested. Valid values
+    /// are: "traversal", "intersections".
+    #[structopt(long)]
+    pub heatmap: Option<HeatmapMetric>,
+}
+
+/// The distribution of the sub-pixel jitter, for `--aa-filter`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AaFilter {
+    /// Samples are spread uniformly over the pixel
+    Box,
+
+    /// Samples are drawn from a tent distribution centered on the pixel
+    Tent,
+}
+
+impl AaFilter {
+    /// The pixel sampling pattern that jitters samples with this distribution
+    pub fn pixel_sampling(self) -> PixelSampling {
+        match self {
+            AaFilter::Box => PixelSampling::Random,
+            AaFilter::Tent => PixelSampling::Tent,
+        }
+    }
+}
+
+impl FromStr for AaFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "box" => Ok(AaFilter::Box),
+            "tent" => Ok(AaFilter::Tent),
+            _ => Err(format_err!(
+                "\"{}\" is not a supported anti-aliasing filter. Valid values are: \"box\", \"tent\".",
+                s
+            )),
+        }
+    }
+}
+
+/// The file extensions of the scene description formats that can be parsed
+pub const SCENE_FORMATS: &[&str] = &["ron", "json", "yaml", "yml"];
+
+/// Print the supported scene file formats, mesh formats, and output image formats
+pub fn list_formats() {
+    println!("Scene formats: {}", SCENE_FORMATS.join(", "));
+    println!("Mesh formats: {}", MESH_FORMATS.join(", "));
+    println!("Output formats: {}", OUTPUT_FORMATS.join(", "));
 }
 
 /// Parse the input scene file based on the file extension
@@ -59,7 +289,69 @@ pub struct Args {
 /// I would recommend using RON since it's the most expressive give that we are using Rust data
 /// structures, and it has full support for all of serde's data types, which is what we're using to
 /// serialize.
-pub fn dispatch_scene_parse(path: &PathBuf, ext: Option<&str>) -> anyhow::Result<Scene> {
+///
+/// The objects of any files that the scene includes are added to the scene. The filetype of an
+/// included file is always determined from its extension.
+pub fn dispatch_scene_parse(path: &Path, ext: Option<&str>) -> anyhow::Result<Scene> {
+    let mut scene: Scene = parse_file(path, ext)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    scene.resolve_paths(dir);
+
+    let mut objects = Vec::new();
+    let mut includers = vec![path.canonicalize()?];
+
+    for include in std::mem::take(&mut scene.include) {
+        include_fragment(&dir.join(include), &mut includers, &mut objects)?;
+    }
+    objects.append(&mut scene.objects);
+    scene.objects = objects;
+    Ok(scene)
+}
+
+/// Parse a scene fragment and the fragments it includes, adding their objects to `objects`
+///
+/// `includers` holds the canonical paths of the chain of files that included this one, which is
+/// used to catch files that include themselves.
+fn include_fragment(
+    path: &Path,
+    includers: &mut Vec<PathBuf>,
+    objects: &mut Vec<SerializedTextured>,
+) -> anyhow::Result<()> {
+    // A missing file is reported when it's parsed
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+
+    if includers.contains(&canonical) {
+        let chain: Vec<_> = includers
+            .iter()
+            .chain(std::iter::once(&canonical))
+            .map(|x| format!("\"{}\"", x.to_string_lossy()))
+            .collect();
+        return Err(format_err!(
+            "Scene files can't include themselves: {}",
+            chain.join(" includes ")
+        ));
+    }
+    let mut fragment: SceneFragment = parse_file(path, None)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    fragment.resolve_paths(dir);
+    includers.push(canonical);
+
+    for include in std::mem::take(&mut fragment.include) {
+        include_fragment(&dir.join(include), includers, objects)?;
+    }
+    includers.pop();
+    objects.append(&mut fragment.objects);
+    Ok(())
+}
+
+/// Parse a scene file or fragment, using `ext` as the filetype if it's supplied
+fn parse_file<T: DeserializeOwned>(path: &Path, ext: Option<&str>) -> anyhow::Result<T> {
+    if let Some(ext) = ext {
+        if !SCENE_FORMATS.contains(&ext) {
+            return Err(unsupported_filetype(ext));
+        }
+    }
+
     if !path.exists() {
         return Err(format_err!(
             "Path to scene file \"{}\" does not exist",
@@ -78,10 +370,400 @@ pub fn dispatch_scene_parse(path: &PathBuf, ext: Option<&str>) -> anyhow::Result
             "Could not determine the filetype of the scene file"
         )),
         Some(ext) => match ext {
-            "ron" => ron::de::from_str(&file_str).map_err(|x| x.into()),
+            // Optional fields like an object's material can be written without wrapping them in
+            // `Some`
+            "ron" => ron::Options::default()
+                .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+                .from_str(&file_str)
+                .map_err(|x| x.into()),
             "json" => json5::from_str(&file_str).map_err(|x| x.into()),
             "yaml" | "yml" => serde_yaml::from_str(&file_str).map_err(|x| x.into()),
-            _ => Err(format_err!("Filetype \"{}\" is not supported", ext)),
+            _ => Err(unsupported_filetype(ext)),
         },
     }
 }
+
+/// Write a scene to a file, in the format given by the file's extension
+///
+/// Relative mesh paths are resolved against the working directory, since the scene file they
+/// were relative to was already resolved when it was parsed, and the new file can be anywhere.
+pub fn export_scene(scene: &Scene, path: &Path) -> anyhow::Result<()> {
+    let ext = path
+        .extension()
+        .map(|x| x.to_string_lossy().to_lowercase())
+        .ok_or_else(|| format_err!("Could not determine the filetype of the exported scene"))?;
+    let mut scene = scene.clone();
+
+    for object in &mut scene.objects {
+        if let SerializedHittable::Mesh(mesh) = &mut object.geometry {
+            mesh.path = std::path::absolute(&mesh.path)?;
+        }
+    }
+    let contents = match ext.as_str() {
+        "ron" => ron::ser::to_string_pretty(&scene, ron::ser::PrettyConfig::default())?,
+        "json" => serde_json::to_string_pretty(&scene)?,
+        "yaml" | "yml" => serde_yaml::to_string(&scene)?,
+        _ => return Err(unsupported_filetype(&ext)),
+    };
+    std::fs::write(path, contents)?;
+    Ok(())
+}
+
+/// Prepare a scene for rendering and print a summary of it
+///
+/// The memory estimate covers the primitives and the buffers that samples are accumulated in,
+/// which are the bulk of the memory for most scenes, but not the acceleration structure or any
+/// memory that the primitives allocate themselves.
+pub fn print_info(name: &str, scene: Scene) -> anyhow::Result<()> {
+    let (camera, integrator, accel) = (
+        scene.camera.name(),
+        scene.integrator.name(),
+        scene.acceleration_structure.name(),
+    );
+    let renderer = Renderer::try_from(scene)?;
+    let stats = renderer.scene.stats;
+    let n_pixels = renderer.width as u64 * renderer.height as u64;
+    let geometry_bytes: usize = renderer
+        .scene
+        .arena
+        .iter()
+        .map(|object| std::mem::size_of_val(object) + std::mem::size_of_val(&*object.geometry))
+        .sum();
+    // The color and weight sums that samples are accumulated in, and the final image
+    let framebuffer_bytes = n_pixels as usize
+        * (2 * std::mem::size_of::<PixelValue<Float>>() + std::mem::size_of::<Float>());
+    let rows = [
+        ("Scene", name.to_string()),
+        (
+            "Resolution",
+            format!("{}x{}", renderer.width, renderer.height),
+        ),
+        ("Samples per pixel", renderer.samples_per_pixel.to_string()),
+        (
+            "Total samples",
+            (n_pixels * u64::from(renderer.samples_per_pixel)).to_string(),
+        ),
+        ("Objects", stats.objects.to_string()),
+        ("Primitives", stats.primitives.to_string()),
+        ("Materials (one per object)", stats.materials.to_string()),
+        ("Lights", stats.lights.to_string()),
+        ("Camera", camera.to_string()),
+        ("Integrator", integrator.to_string()),
+        ("Acceleration structure", accel.to_string()),
+        ("Prepared in", format!("{:.2?}", stats.elapsed)),
+        (
+            "Estimated memory",
+            format_bytes(geometry_bytes + framebuffer_bytes),
+        ),
+    ];
+    let width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+
+    for (label, value) in &rows {
+        println!("{:<width$}  {}", label, value, width = width);
+    }
+    Ok(())
+}
+
+/// Format a number of bytes with a binary unit, like "1.50 MiB"
+fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", value, UNITS[unit])
+}
+
+/// The values that can be substituted into the placeholders of an output filename
+#[derive(Debug, Clone, Copy)]
+pub struct OutputNameValues<'a> {
+    /// The name of the scene file, without its extension
+    pub scene: &'a str,
+
+    /// The index of the frame being rendered
+    ///
+    /// This is the value of `--frame`, which defaults to 0.
+    pub frame: u32,
+
+    /// The width of the output image, in pixels
+    pub width: u32,
+
+    /// The height of the output image, in pixels
+    pub height: u32,
+
+    /// The name of the integrator used to render the image
+    pub integrator: &'a str,
+}
+
+/// Fill in the placeholders of an output filename template
+///
+/// A placeholder is the name of a value in braces, like "{scene}", optionally followed by a
+/// minimum width, like "{frame:4}". The value is padded with zeros instead of spaces if the width
+/// starts with a 0, like "{frame:04}". It's an error to use an unknown placeholder or a stray
+/// brace.
+pub fn expand_output_template(template: &str, values: &OutputNameValues) -> anyhow::Result<String> {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find(['{', '}']) {
+        expanded.push_str(&rest[..start]);
+        let end = match (rest[start..].starts_with('{'), rest[start..].find('}')) {
+            (true, Some(end)) => start + end,
+            _ => {
+                return Err(format_err!(
+                    "Unmatched brace in output filename \"{}\"",
+                    template
+                ))
+            }
+        };
+        let placeholder = &rest[start + 1..end];
+        let (name, spec) = match placeholder.split_once(':') {
+            Some((name, spec)) => (name, Some(spec)),
+            None => (placeholder, None),
+        };
+        let value = match name {
+            "scene" => values.scene.to_string(),
+            "frame" => values.frame.to_string(),
+            "width" => values.width.to_string(),
+            "height" => values.height.to_string(),
+            "integrator" => values.integrator.to_string(),
+            _ => {
+                return Err(format_err!(
+                    "Unknown placeholder \"{{{}}}\" in output filename. Valid placeholders are: \
+                     {{scene}}, {{frame}}, {{width}}, {{height}}, {{integrator}}",
+                    placeholder
+                ))
+            }
+        };
+
+        match spec {
+            None => expanded.push_str(&value),
+            Some(spec) => {
+                let width: usize = spec.parse().map_err(|_| {
+                    format_err!(
+                        "\"{}\" in the placeholder \"{{{}}}\" is not a width",
+                        spec,
+                        placeholder
+                    )
+                })?;
+                let pad = if spec.starts_with('0') { '0' } else { ' ' };
+                let padding = width.saturating_sub(value.chars().count());
+                expanded.extend(std::iter::repeat_n(pad, padding));
+                expanded.push_str(&value);
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Parse a vector written as three comma-separated numbers, like "1,-2.5,3"
+fn parse_vector(s: &str) -> Result<Vector3<f32>, String> {
+    let components = s
+        .split(',')
+        .map(|x| x.trim().parse::<f32>())
+        .collect::<Result<Vec<f32>, _>>()
+        .map_err(|e| format!("\"{}\" is not a vector: {}", s, e))?;
+
+    match components[..] {
+        [x, y, z] => Ok(Vector3::new(x, y, z)),
+        _ => Err(format!(
+            "\"{}\" is not a vector, it should have three comma-separated components like \"0,1,2\"",
+            s
+        )),
+    }
+}
+
+/// The error for a scene filetype that we don't know how to parse
+fn unsupported_filetype(ext: &str) -> anyhow::Error {
+    format_err!(
+        "Filetype \"{}\" is not supported. Valid values are: {}",
+        ext,
+        SCENE_FORMATS.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_filetype_lists_formats() {
+        let path = PathBuf::from("scene.toml");
+        let err = dispatch_scene_parse(&path, Some("toml")).unwrap_err();
+        let message = err.to_string();
+
+        for format in SCENE_FORMATS {
+            assert!(message.contains(format), "{}", message);
+        }
+    }
+
+    #[test]
+    fn bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.50 KiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024), "5.00 MiB");
+        assert_eq!(format_bytes(3 << 40), "3.00 TiB");
+    }
+
+    #[test]
+    fn vectors() {
+        assert_eq!(parse_vector("1,2,3"), Ok(Vector3::new(1.0, 2.0, 3.0)));
+        assert_eq!(
+            parse_vector(" -1.5, 0 ,2e1"),
+            Ok(Vector3::new(-1.5, 0.0, 20.0))
+        );
+
+        for invalid in ["", "1,2", "1,2,3,4", "1,a,3", "1;2;3"] {
+            assert!(parse_vector(invalid).is_err(), "{}", invalid);
+        }
+
+        // Negative components don't get mistaken for flags
+        let args = Args::from_iter_safe(["nib", "scene.json", "--cam-origin", "-1,0,2"]).unwrap();
+        assert_eq!(args.cam_origin, Some(Vector3::new(-1.0, 0.0, 2.0)));
+    }
+
+    #[test]
+    fn output_templates() {
+        let values = OutputNameValues {
+            scene: "spheres",
+            frame: 7,
+            width: 640,
+            height: 480,
+            integrator: "Whitted",
+        };
+        let expand = |template| expand_output_template(template, &values);
+
+        assert_eq!(expand("out.png").unwrap(), "out.png");
+        assert_eq!(
+            expand("{scene}_{width}x{height}.png").unwrap(),
+            "spheres_640x480.png"
+        );
+        assert_eq!(
+            expand("{integrator}/{frame:04}.ppm").unwrap(),
+            "Whitted/0007.ppm"
+        );
+        assert_eq!(expand("[{frame:3}]").unwrap(), "[  7]");
+        // A width shorter than the value doesn't truncate it
+        assert_eq!(expand("{width:02}").unwrap(), "640");
+
+        for invalid in ["{name}.png", "{scene.png", "scene}.png", "{frame:x}"] {
+            assert!(expand(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn includes() {
+        let dir = std::env::temp_dir().join(format!("nib-includes-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("parts")).unwrap();
+        let sphere = |x| {
+            format!(
+                r#"{{ "geometry": {{ "type": "Sphere", "center": [{}, 0, 0], "radius": 1 }},
+                     "mat": {{ "type": "Diffuse", "albedo": [1, 1, 1] }} }}"#,
+                x
+            )
+        };
+        // The library's test scenes aren't built for the binary's tests, so this starts from one
+        // of the example scenes instead
+        let example = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/spheres.json");
+        let mut base = dispatch_scene_parse(&example, None).unwrap();
+        base.objects = vec![json5::from_str(&sphere(0)).unwrap()];
+        let scene = |include: &str| {
+            let mut scene = base.clone();
+            scene.include = vec![PathBuf::from(include)];
+            serde_json::to_string(&scene).unwrap()
+        };
+        let write = |name: &str, contents: &str| std::fs::write(dir.join(name), contents).unwrap();
+
+        // Includes are relative to the file that includes them, and can be in other formats
+        write("scene.json", &scene("parts/a.json"));
+        write(
+            "parts/a.json",
+            &format!(r#"{{ "include": ["b.yaml"], "objects": [{}] }}"#, sphere(1)),
+        );
+        write(
+            "parts/b.yaml",
+            "objects:\n  - geometry: { type: Mesh, path: cube.ply }\n    mat: { type: Diffuse, albedo: [1, 1, 1] }\n",
+        );
+        let parsed = dispatch_scene_parse(&dir.join("scene.json"), None).unwrap();
+        assert!(parsed.include.is_empty());
+        assert_eq!(parsed.objects.len(), 3);
+
+        match &parsed.objects[0].geometry {
+            nib::SerializedHittable::Mesh(mesh) => {
+                assert_eq!(mesh.path, dir.join("parts/cube.ply"))
+            }
+            x => panic!("expected the mesh from the nested include, got {:?}", x),
+        }
+
+        // Files that include each other are an error rather than an infinite loop
+        write("parts/b.yaml", "include: [../scene.json]\n");
+        write("loop.json", &scene("loop.json"));
+        let err = dispatch_scene_parse(&dir.join("loop.json"), None).unwrap_err();
+        assert!(
+            err.to_string().contains("can't include themselves"),
+            "{}",
+            err
+        );
+        write("parts/b.yaml", "include: [a.json]\n");
+        let err = dispatch_scene_parse(&dir.join("scene.json"), None).unwrap_err();
+        assert!(
+            err.to_string().contains("can't include themselves"),
+            "{}",
+            err
+        );
+
+        // Fragments can only add objects
+        write("parts/a.json", r#"{ "samples_per_pixel": 4 }"#);
+        assert!(dispatch_scene_parse(&dir.join("scene.json"), None).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn export() {
+        let dir = std::env::temp_dir().join(format!("nib-export-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let scenes = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes");
+
+        // Every scene survives a round trip through every format, and mesh paths still point at
+        // the meshes from a file in a different directory
+        for entry in std::fs::read_dir(&scenes).unwrap() {
+            let original = entry.unwrap().path();
+
+            if original.is_dir() {
+                continue;
+            }
+            let mut scene = dispatch_scene_parse(&original, None).unwrap();
+            scene.seed = Some(7);
+            let expected = format!("{:?}", scene);
+
+            for format in SCENE_FORMATS {
+                let path = dir.join(format!("scene.{}", format));
+                export_scene(&scene, &path).unwrap();
+                let exported = dispatch_scene_parse(&path, None).unwrap();
+                assert_eq!(
+                    format!("{:?}", exported),
+                    expected,
+                    "{:?} as {}",
+                    original,
+                    format
+                );
+            }
+        }
+        let scene = dispatch_scene_parse(&scenes.join("mesh.ron"), None).unwrap();
+        let err = export_scene(&scene, &dir.join("scene.toml")).unwrap_err();
+        assert!(err.to_string().contains("not supported"), "{}", err);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,158 @@
+//! Parsing colors from scene files
+//!
+//! Colors in scene files are linear RGB triples, which can be written like any other vector. Since
+//! that's tedious, color fields also accept sRGB hex strings like `"#b3b3b3"` or `"#fff"`, which
+//! are converted to linear RGB when the scene is loaded. Fields opt into this with
+//! `#[serde(deserialize_with = "crate::color::deserialize")]`.
+
+use crate::types::{Float, PixelValue};
+use serde::{de::Error, Deserialize, Deserializer};
+use thiserror::Error;
+
+/// The possible errors that can arise when parsing a hex color
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ColorError {
+    #[error("The hex color \"{0}\" must start with '#'")]
+    MissingHash(String),
+
+    #[error("The hex color \"{0}\" must have 3 or 6 digits")]
+    InvalidLength(String),
+
+    #[error("The hex color \"{0}\" contains a character that isn't a hexadecimal digit")]
+    InvalidDigit(String),
+}
+
+/// The forms a color can take in a scene file
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorRepr {
+    Hex(String),
+    Linear(PixelValue<Float>),
+}
+
+/// Deserialize a color that is either a linear RGB vector or an sRGB hex string
+pub fn deserialize<'de, D>(deserializer: D) -> Result<PixelValue<Float>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match ColorRepr::deserialize(deserializer)? {
+        ColorRepr::Hex(s) => parse_hex(&s).map_err(D::Error::custom),
+        ColorRepr::Linear(v) => Ok(v),
+    }
+}
+
+/// Parse an sRGB hex string (`#rrggbb` or the shorthand `#rgb`) into a linear RGB color
+pub fn parse_hex(s: &str) -> Result<PixelValue<Float>, ColorError> {
+    let digits = s
+        .strip_prefix('#')
+        .ok_or_else(|| ColorError::MissingHash(s.to_string()))?;
+
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ColorError::InvalidDigit(s.to_string()));
+    }
+    // Every character is an ASCII hex digit at this point, so slicing by bytes is safe
+    let channel = |i: usize, width: usize| {
+        let value = u8::from_str_radix(&digits[i * width..(i + 1) * width], 16).unwrap();
+        // A shorthand digit is repeated, so "f" is the same as "ff"
+        let value = if width == 1 { value * 17 } else { value };
+        srgb_to_linear(Float::from(value) / 255.0)
+    };
+    let width = match digits.len() {
+        3 => 1,
+        6 => 2,
+        _ => return Err(ColorError::InvalidLength(s.to_string())),
+    };
+    Ok(PixelValue::new(
+        channel(0, width),
+        channel(1, width),
+        channel(2, width),
+    ))
+}
+
+/// Convert an sRGB encoded channel value in [0, 1] to linear RGB
+pub fn srgb_to_linear(c: Float) -> Float {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: PixelValue<Float>, b: PixelValue<Float>) {
+        assert!(
+            (a.x - b.x).abs() < 1e-4 && (a.y - b.y).abs() < 1e-4 && (a.z - b.z).abs() < 1e-4,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn hex_colors() {
+        assert_close(
+            parse_hex("#000000").unwrap(),
+            PixelValue::new(0.0, 0.0, 0.0),
+        );
+        assert_close(parse_hex("#fff").unwrap(), PixelValue::new(1.0, 1.0, 1.0));
+        assert_close(
+            parse_hex("#FF0000").unwrap(),
+            PixelValue::new(1.0, 0.0, 0.0),
+        );
+        // sRGB 0xb3 (70%) is about 45% in linear RGB
+        assert_close(
+            parse_hex("#b3b3b3").unwrap(),
+            PixelValue::new(0.4508, 0.4508, 0.4508),
+        );
+        assert_eq!(parse_hex("#abc").unwrap(), parse_hex("#aabbcc").unwrap());
+    }
+
+    #[test]
+    fn invalid_hex_colors() {
+        assert!(matches!(parse_hex("fff"), Err(ColorError::MissingHash(_))));
+        assert!(matches!(
+            parse_hex("#ffff"),
+            Err(ColorError::InvalidLength(_))
+        ));
+        assert!(matches!(
+            parse_hex("#ggg"),
+            Err(ColorError::InvalidDigit(_))
+        ));
+        assert!(matches!(
+            parse_hex("#ééé"),
+            Err(ColorError::InvalidDigit(_))
+        ));
+    }
+
+    /// Both forms of a color can be used in every scene format
+    #[test]
+    fn deserialize_formats() {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            #[serde(deserialize_with = "deserialize")]
+            color: PixelValue<Float>,
+        }
+        let white = PixelValue::new(1.0, 1.0, 1.0);
+        let half = PixelValue::new(0.5, 0.5, 0.5);
+
+        let json: Wrapper = json5::from_str(r##"{ "color": "#fff" }"##).unwrap();
+        assert_close(json.color, white);
+        let json: Wrapper = json5::from_str(r#"{ "color": [0.5, 0.5, 0.5] }"#).unwrap();
+        assert_close(json.color, half);
+
+        let yaml: Wrapper = serde_yaml::from_str("color: \"#fff\"").unwrap();
+        assert_close(yaml.color, white);
+        let yaml: Wrapper = serde_yaml::from_str("color: { x: 0.5, y: 0.5, z: 0.5 }").unwrap();
+        assert_close(yaml.color, half);
+
+        let ron: Wrapper = ron::de::from_str(r##"(color: "#fff")"##).unwrap();
+        assert_close(ron.color, white);
+        let ron: Wrapper = ron::de::from_str("(color: (x: 0.5, y: 0.5, z: 0.5))").unwrap();
+        assert_close(ron.color, half);
+
+        assert!(json5::from_str::<Wrapper>(r##"{ "color": "#ffff" }"##).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! Comparing a render against a reference image
+//!
+//! This is used to check a change to the renderer for regressions: render a scene, compare it to
+//! a known good render of the same scene, and look at how far apart they are. Both images are
+//! compared as linear RGB values in [0, 1], the same values that the exporters write out.
+
+use crate::types::{false_color, Float, PixelValue};
+use thiserror::Error;
+
+/// The possible errors that can arise when comparing images
+#[derive(Error, Debug)]
+pub enum CompareError {
+    #[error("The rendered image has {rendered} pixels, but the reference image has {reference}")]
+    DifferentSizes { rendered: usize, reference: usize },
+
+    #[cfg(feature = "cli")]
+    #[error("The reference image couldn't be loaded")]
+    Image {
+        #[from]
+        source: image::ImageError,
+    },
+}
+
+/// A result that can return a `CompareError`
+pub type CompareResult<T> = Result<T, CompareError>;
+
+/// How far a render is from a reference image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageDiff {
+    /// The largest absolute difference of any color channel of any pixel
+    pub max_abs_error: Float,
+
+    /// The mean of the squared differences over every color channel of every pixel
+    pub mse: Float,
+
+    /// The peak signal to noise ratio in decibels, which is infinite for identical images
+    ///
+    /// Higher is better. Since the peak value of a channel is 1, this is `-10 * log10(mse)`.
+    pub psnr: Float,
+}
+
+/// Compare a render to a reference image with the same dimensions
+///
+/// The rendered values are clamped to [0, 1] first, since that's all that an exported image can
+/// store.
+pub fn compare(
+    rendered: &[PixelValue<Float>],
+    reference: &[PixelValue<Float>],
+) -> CompareResult<ImageDiff> {
+    if rendered.len() != reference.len() {
+        return Err(CompareError::DifferentSizes {
+            rendered: rendered.len(),
+            reference: reference.len(),
+        });
+    }
+    let mut max_abs_error: Float = 0.0;
+    let mut squared_error_sum = 0.0;
+
+    for (a, b) in rendered.iter().zip(reference) {
+        for channel in 0..3 {
+            let error = (a[channel].clamp(0.0, 1.0) - b[channel]).abs();
+            max_abs_error = max_abs_error.max(error);
+            squared_error_sum += f64::from(error * error);
+        }
+    }
+    let n_channels = (rendered.len() * 3).max(1) as f64;
+    let mse = (squared_error_sum / n_channels) as Float;
+    Ok(ImageDiff {
+        max_abs_error,
+        mse,
+        psnr: -10.0 * mse.log10(),
+    })
+}
+
+/// A false color image of the difference between a render and a reference image
+///
+/// Each pixel is colored by the largest difference of its channels, scaled so the largest
+/// difference in the image is red. Smaller differences fade through yellow and green to blue, and
+/// identical pixels are black. The images must have the same dimensions.
+pub fn difference_image(
+    rendered: &[PixelValue<Float>],
+    reference: &[PixelValue<Float>],
+) -> CompareResult<Vec<PixelValue<Float>>> {
+    let max_abs_error = compare(rendered, reference)?.max_abs_error;
+    Ok(rendered
+        .iter()
+        .zip(reference)
+        .map(|(a, b)| {
+            let error = (0..3)
+                .map(|channel| (a[channel].clamp(0.0, 1.0) - b[channel]).abs())
+                .fold(0.0, Float::max);
+
+            if error <= 0.0 {
+                return PixelValue::new(0.0, 0.0, 0.0);
+            }
+            false_color(error / max_abs_error)
+        })
+        .collect())
+}
+
+/// Load an image as linear RGB values in [0, 1], along with its width and height
+///
+/// This needs the `image` crate, so it's only available with the `cli` feature. The alpha channel
+/// is ignored.
+#[cfg(feature = "cli")]
+pub fn load_image(path: &std::path::Path) -> CompareResult<(Vec<PixelValue<Float>>, u32, u32)> {
+    let image = image::open(path)?.to_rgb32f();
+    let (width, height) = image.dimensions();
+    let pixels = image
+        .pixels()
+        .map(|p| PixelValue::new(p[0], p[1], p[2]))
+        .collect();
+    Ok((pixels, width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics() {
+        let reference = vec![PixelValue::new(0.5, 0.5, 0.5); 4];
+        let same = compare(&reference, &reference).unwrap();
+        assert_eq!(same.max_abs_error, 0.0);
+        assert_eq!(same.mse, 0.0);
+        assert!(same.psnr.is_infinite());
+
+        // One channel of one pixel is off by 0.3
+        let mut rendered = reference.clone();
+        rendered[1].y = 0.8;
+        let diff = compare(&rendered, &reference).unwrap();
+        assert!((diff.max_abs_error - 0.3).abs() < 1e-6);
+        assert!((diff.mse - 0.09 / 12.0).abs() < 1e-6);
+        assert!((diff.psnr - 21.249).abs() < 1e-2);
+
+        // Values above 1 are clamped before they're compared
+        let bright = vec![PixelValue::new(5.0, 1.0, 1.0)];
+        let white = vec![PixelValue::new(1.0, 1.0, 1.0)];
+        assert_eq!(compare(&bright, &white).unwrap().mse, 0.0);
+
+        assert!(matches!(
+            compare(&rendered[..2], &reference),
+            Err(CompareError::DifferentSizes {
+                rendered: 2,
+                reference: 4
+            })
+        ));
+    }
+
+    #[test]
+    fn difference_image() {
+        let reference = vec![PixelValue::new(0.0, 0.0, 0.0); 3];
+        let rendered = vec![
+            PixelValue::new(0.0, 0.0, 0.0),
+            PixelValue::new(0.1, 0.0, 0.0),
+            PixelValue::new(0.0, 0.0, 0.4),
+        ];
+        let image = super::difference_image(&rendered, &reference).unwrap();
+        assert_eq!(image[0], PixelValue::new(0.0, 0.0, 0.0));
+        // The largest difference is red, and smaller ones are bluer
+        assert_eq!(image[2], PixelValue::new(1.0, 0.0, 0.0));
+        assert!(image[1].z > image[1].x);
+    }
+}
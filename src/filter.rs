@@ -0,0 +1,102 @@
+//! Pixel reconstruction filters
+//!
+//! A reconstruction filter determines how much each sample contributes to the pixels around it.
+//! The simplest filter is the box filter, where every sample only contributes to the pixel it was
+//! taken in, with equal weight. Wider filters let a sample contribute to neighboring pixels,
+//! weighted by the filter kernel evaluated at the offset between the sample and the pixel center,
+//! which yields smoother edges for the same number of samples.
+
+use crate::types::Float;
+use serde::{Deserialize, Serialize};
+
+/// The falloff rate for the Gaussian filter
+const GAUSSIAN_ALPHA: Float = 2.0;
+
+/// The different reconstruction filters that can be used to combine samples into pixels
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FilterType {
+    /// Every sample contributes only to its own pixel, with equal weight
+    #[default]
+    Box,
+
+    /// The weight falls off linearly with the distance from the pixel center, reaching zero one
+    /// pixel away
+    Tent,
+
+    /// The weight falls off with a (truncated) Gaussian curve, reaching zero 1.5 pixels away
+    Gaussian,
+}
+
+impl FilterType {
+    /// The radius of the filter's support, in pixels
+    ///
+    /// A sample contributes to every pixel whose center lies within this distance along both the
+    /// horizontal and vertical axis.
+    pub fn radius(&self) -> Float {
+        match self {
+            FilterType::Box => 0.5,
+            FilterType::Tent => 1.0,
+            FilterType::Gaussian => 1.5,
+        }
+    }
+
+    /// Evaluate the filter kernel for a given offset from the center of a pixel
+    ///
+    /// The offsets are in pixels. Every filter is separable, so the weight is the product of the
+    /// one-dimensional kernel evaluated for each axis.
+    pub fn weight(&self, dx: Float, dy: Float) -> Float {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    /// Evaluate the one dimensional kernel for this filter
+    fn weight_1d(&self, d: Float) -> Float {
+        let radius = self.radius();
+        let d = d.abs();
+
+        if d > radius {
+            return 0.0;
+        }
+
+        match self {
+            FilterType::Box => 1.0,
+            FilterType::Tent => 1.0 - (d / radius),
+            FilterType::Gaussian => {
+                // We subtract the value at the edge of the support so the kernel goes to zero
+                // smoothly instead of having a discontinuity at the cutoff
+                (-GAUSSIAN_ALPHA * d * d).exp() - (-GAUSSIAN_ALPHA * radius * radius).exp()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weights_peak_at_center() {
+        for filter in [FilterType::Box, FilterType::Tent, FilterType::Gaussian] {
+            let center = filter.weight(0.0, 0.0);
+            assert!(center > 0.0);
+            assert!(filter.weight(0.3, 0.2) <= center);
+            assert!(filter.weight(-0.3, 0.2) <= center);
+        }
+    }
+
+    #[test]
+    fn weights_vanish_outside_radius() {
+        for filter in [FilterType::Box, FilterType::Tent, FilterType::Gaussian] {
+            let outside = filter.radius() + 0.01;
+            assert_eq!(filter.weight(outside, 0.0), 0.0);
+            assert_eq!(filter.weight(0.0, -outside), 0.0);
+        }
+    }
+
+    #[test]
+    fn filters_are_symmetric() {
+        for filter in [FilterType::Box, FilterType::Tent, FilterType::Gaussian] {
+            assert_eq!(filter.weight(0.4, 0.7), filter.weight(-0.4, -0.7));
+            assert_eq!(filter.weight(0.4, 0.7), filter.weight(0.7, 0.4));
+        }
+    }
+}
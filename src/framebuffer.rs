@@ -0,0 +1,212 @@
+//! A buffer of pixels with the dimensions of the image it holds
+//!
+//! Renders are stored as a flat list of pixels in row-major order, starting from the top left
+//! corner of the image. Anything that works with neighboring pixels needs to know the width of
+//! the image to find them, so the `Framebuffer` keeps the pixels and the dimensions together and
+//! does the indexing in one place. It dereferences to the slice of pixels, which is what the
+//! exporters take.
+
+use crate::{
+    post::{PostError, PostResult},
+    types::{Float, PixelValue},
+};
+use std::ops::{Deref, DerefMut};
+
+/// The pixels of an image, in row-major order starting from the top left corner
+#[derive(Debug, Clone, PartialEq)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<PixelValue<Float>>,
+}
+
+impl Framebuffer {
+    /// Create a black image
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![PixelValue::new(0.0, 0.0, 0.0); width as usize * height as usize],
+        }
+    }
+
+    /// Wrap a list of pixels in row-major order
+    ///
+    /// This fails if the number of pixels doesn't match the dimensions.
+    pub fn from_vec(width: u32, height: u32, pixels: Vec<PixelValue<Float>>) -> PostResult<Self> {
+        let expected = width as usize * height as usize;
+
+        if pixels.len() != expected {
+            return Err(PostError::WrongBufferSize {
+                width,
+                height,
+                expected,
+                actual: pixels.len(),
+            });
+        }
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// The width of the image, in pixels
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// The height of the image, in pixels
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The index of a pixel in the buffer, if it's in the image
+    fn index(&self, x: u32, y: u32) -> Option<usize> {
+        if x < self.width && y < self.height {
+            Some(y as usize * self.width as usize + x as usize)
+        } else {
+            None
+        }
+    }
+
+    /// The pixel at a column and row, where (0, 0) is the top left corner of the image
+    ///
+    /// Returns `None` if the pixel is outside of the image.
+    pub fn get(&self, x: u32, y: u32) -> Option<PixelValue<Float>> {
+        self.index(x, y).map(|i| self.pixels[i])
+    }
+
+    /// Set the pixel at a column and row, where (0, 0) is the top left corner of the image
+    ///
+    /// # Panics
+    ///
+    /// This panics if the pixel is outside of the image, like indexing a slice out of bounds.
+    pub fn set(&mut self, x: u32, y: u32, value: PixelValue<Float>) {
+        let i = self.index(x, y).unwrap_or_else(|| {
+            panic!(
+                "({}, {}) is outside of a {}x{} image",
+                x, y, self.width, self.height
+            )
+        });
+        self.pixels[i] = value;
+    }
+
+    /// The rows of the image, from top to bottom
+    pub fn rows(&self) -> impl Iterator<Item = &[PixelValue<Float>]> + '_ {
+        // An image without any columns has no pixels, so the chunk size doesn't matter
+        self.pixels.chunks_exact(self.width.max(1) as usize)
+    }
+
+    /// The pixels within `radius` columns and rows of a pixel, including the pixel itself
+    ///
+    /// The neighborhood is a square that's clipped to the image, so pixels near the edges have
+    /// fewer neighbors. Each neighbor comes with its column and row, and they're in row-major
+    /// order.
+    pub fn neighbors(
+        &self,
+        x: u32,
+        y: u32,
+        radius: u32,
+    ) -> impl Iterator<Item = (u32, u32, PixelValue<Float>)> + '_ {
+        let columns =
+            x.saturating_sub(radius)..x.saturating_add(radius).saturating_add(1).min(self.width);
+        let rows =
+            y.saturating_sub(radius)..y.saturating_add(radius).saturating_add(1).min(self.height);
+
+        rows.flat_map(move |ny| {
+            columns
+                .clone()
+                .map(move |nx| (nx, ny, self.pixels[self.index(nx, ny).unwrap()]))
+        })
+    }
+
+    /// The pixels of the image, as a flat list in row-major order
+    pub fn into_vec(self) -> Vec<PixelValue<Float>> {
+        self.pixels
+    }
+}
+
+impl Deref for Framebuffer {
+    type Target = [PixelValue<Float>];
+
+    fn deref(&self) -> &Self::Target {
+        &self.pixels
+    }
+}
+
+impl DerefMut for Framebuffer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.pixels
+    }
+}
+
+impl<'a> IntoIterator for &'a Framebuffer {
+    type Item = &'a PixelValue<Float>;
+    type IntoIter = std::slice::Iter<'a, PixelValue<Float>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.pixels.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 3x2 image where the red channel of each pixel is its index
+    fn numbered() -> Framebuffer {
+        let pixels = (0..6)
+            .map(|i| PixelValue::new(i as Float, 0.0, 0.0))
+            .collect();
+        Framebuffer::from_vec(3, 2, pixels).unwrap()
+    }
+
+    #[test]
+    fn indexing() {
+        let mut image = numbered();
+        assert_eq!(image.get(2, 0).unwrap().x, 2.0);
+        assert_eq!(image.get(0, 1).unwrap().x, 3.0);
+        assert_eq!(image.get(3, 0), None);
+        assert_eq!(image.get(0, 2), None);
+
+        image.set(1, 1, PixelValue::new(9.0, 9.0, 9.0));
+        assert_eq!(image[4], PixelValue::new(9.0, 9.0, 9.0));
+
+        let rows: Vec<Vec<Float>> = image
+            .rows()
+            .map(|row| row.iter().map(|p| p.x).collect())
+            .collect();
+        assert_eq!(rows, [vec![0.0, 1.0, 2.0], vec![3.0, 9.0, 5.0]]);
+        assert_eq!(Framebuffer::new(0, 4).rows().count(), 0);
+
+        assert!(matches!(
+            Framebuffer::from_vec(2, 3, vec![PixelValue::new(0.0, 0.0, 0.0); 4]),
+            Err(PostError::WrongBufferSize { expected: 6, .. })
+        ));
+    }
+
+    #[test]
+    #[should_panic]
+    fn set_out_of_bounds() {
+        numbered().set(3, 0, PixelValue::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn neighbors() {
+        let image = numbered();
+        let indices = |x, y, radius| -> Vec<Float> {
+            image.neighbors(x, y, radius).map(|(_, _, p)| p.x).collect()
+        };
+
+        // The neighborhood is clipped at the edges of the image
+        assert_eq!(indices(0, 0, 1), [0.0, 1.0, 3.0, 4.0]);
+        assert_eq!(indices(1, 1, 1), [0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(indices(2, 1, 0), [5.0]);
+        assert_eq!(image.neighbors(1, 0, u32::MAX).count(), 6);
+        assert_eq!(
+            image.neighbors(2, 1, 1).next().map(|(x, y, _)| (x, y)),
+            Some((1, 0))
+        );
+    }
+}
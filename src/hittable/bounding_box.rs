@@ -0,0 +1,259 @@
+//! Bounding boxes
+//!
+//! Bounding boxes are cheap to intersect, so they're used to skip the expensive intersection
+//! tests for whatever they enclose. An axis-aligned bounding box (AABB) is the cheapest to test,
+//! since the ray only has to be compared against three pairs of planes. An oriented bounding box
+//! (OBB) can be rotated to follow what it encloses, which fits elongated, diagonal objects much
+//! more tightly, at the cost of transforming the ray into the box's frame first.
+
+use crate::{
+    ray::Ray,
+    types::{Dimension, Float, ETA},
+};
+use cgmath::{InnerSpace, Point3, Vector3};
+
+/// An axis-aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    /// The corner of the box with the smallest coordinates
+    pub min: Vector3<Float>,
+
+    /// The corner of the box with the largest coordinates
+    pub max: Vector3<Float>,
+}
+
+/// A bounding box that's rotated to fit what it encloses
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrientedBoundingBox {
+    /// The center of the box
+    pub center: Point3<Float>,
+
+    /// The directions of the box's edges
+    ///
+    /// These must be orthonormal.
+    pub axes: [Vector3<Float>; 3],
+
+    /// Half of the size of the box along each of its axes
+    pub half_extents: Vector3<Float>,
+}
+
+/// Where a ray crosses the slabs of a box
+struct SlabHit {
+    /// The distance at which the ray enters the box
+    t_near: Float,
+
+    /// The distance at which the ray leaves the box
+    t_far: Float,
+}
+
+impl Aabb {
+    /// Whether the ray hits the box in front of its origin
+    ///
+    /// Acceleration structures only need to know whether to look inside the box, so this skips
+    /// computing a hit record.
+    pub fn intersects(&self, ray: &Ray) -> bool {
+        self.slabs(ray).is_some()
+    }
+
+    /// The distances along the ray at which it enters and leaves the box
+    ///
+    /// The entry distance is negative if the ray starts inside the box. Returns `None` if the ray
+    /// misses the box, or if the box is behind the ray.
+    pub fn intersection_range(&self, ray: &Ray) -> Option<(Float, Float)> {
+        self.slabs(ray).map(|hit| (hit.t_near, hit.t_far))
+    }
+
+    /// The smallest box that encloses both boxes
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: Vector3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: Vector3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    /// Whether every coordinate of the box is finite
+    ///
+    /// Objects that extend infinitely far can't be placed in a spatial structure.
+    pub fn is_finite(&self) -> bool {
+        Dimension::all()
+            .iter()
+            .map(|d| d.index())
+            .all(|axis| self.min[axis].is_finite() && self.max[axis].is_finite())
+    }
+
+    /// Intersect the ray with the three pairs of planes (slabs) that bound the box
+    ///
+    /// The ray is inside the box where it's between every pair of planes at once, so it enters the
+    /// box at the furthest of the near planes and leaves at the closest of the far planes.
+    fn slabs(&self, ray: &Ray) -> Option<SlabHit> {
+        let mut hit = SlabHit {
+            t_near: Float::NEG_INFINITY,
+            t_far: Float::INFINITY,
+        };
+
+        // A ray that's parallel to a slab divides by zero, which yields infinite distances that
+        // still compare correctly, unless the origin lies exactly on a plane
+        let inverse_dir = ray.inverse_dir();
+
+        for axis in Dimension::all().iter().map(|d| d.index()) {
+            let inverse = inverse_dir[axis];
+            let mut t0 = (self.min[axis] - ray.origin[axis]) * inverse;
+            let mut t1 = (self.max[axis] - ray.origin[axis]) * inverse;
+
+            if t0 > t1 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            hit.t_near = hit.t_near.max(t0);
+            hit.t_far = hit.t_far.min(t1);
+        }
+
+        if hit.t_near > hit.t_far || hit.t_far < ETA {
+            return None;
+        }
+        Some(hit)
+    }
+}
+
+impl OrientedBoundingBox {
+    /// Whether the ray hits the box in front of its origin
+    pub fn intersects(&self, ray: &Ray) -> bool {
+        self.intersection_range(ray).is_some()
+    }
+
+    /// The distances along the ray at which it enters and leaves the box
+    ///
+    /// The entry distance is negative if the ray starts inside the box. Returns `None` if the ray
+    /// misses the box, or if the box is behind the ray.
+    pub fn intersection_range(&self, ray: &Ray) -> Option<(Float, Float)> {
+        let local_box = Aabb {
+            min: -self.half_extents,
+            max: self.half_extents,
+        };
+        local_box.intersection_range(&self.local_ray(ray))
+    }
+
+    /// Transform a ray into the box's frame
+    ///
+    /// In the box's frame, the box is centered on the origin and its edges are parallel to the
+    /// coordinate axes, so it's an axis-aligned box. The axes are orthonormal, so distances along
+    /// the ray are the same in both frames.
+    fn local_ray(&self, ray: &Ray) -> Ray {
+        let offset = ray.origin - self.center;
+        let [u, v, w] = self.axes;
+        Ray::new(
+            Point3::new(offset.dot(u), offset.dot(v), offset.dot(w)),
+            Vector3::new(
+                ray.direction.dot(u),
+                ray.direction.dot(v),
+                ray.direction.dot(w),
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use float_cmp::approx_eq;
+
+    fn unit_aabb() -> Aabb {
+        Aabb {
+            min: Vector3::new(-1.0, -1.0, -1.0),
+            max: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    /// A long, thin box along the diagonal between the +x and +y axes
+    fn diagonal_obb() -> OrientedBoundingBox {
+        let sqrt_half = (0.5 as Float).sqrt();
+        OrientedBoundingBox {
+            center: Point3::new(0.0, 0.0, 0.0),
+            axes: [
+                Vector3::new(sqrt_half, sqrt_half, 0.0),
+                Vector3::new(-sqrt_half, sqrt_half, 0.0),
+                Vector3::new(0.0, 0.0, 1.0),
+            ],
+            half_extents: Vector3::new(2.0, 0.25, 0.25),
+        }
+    }
+
+    #[test]
+    fn aabb_intersections() {
+        let aabb = unit_aabb();
+        let hits = [
+            Ray::new(Point3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0)),
+            Ray::new(Point3::new(5.0, 5.0, 5.0), Vector3::new(-1.0, -1.0, -1.0)),
+            // Starting inside the box
+            Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0)),
+        ];
+        let misses = [
+            Ray::new(Point3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, 1.0)),
+            Ray::new(Point3::new(0.0, 2.0, 5.0), Vector3::new(0.0, 0.0, -1.0)),
+            Ray::new(Point3::new(5.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0)),
+        ];
+
+        for ray in &hits {
+            assert!(aabb.intersects(ray), "{:?}", ray);
+        }
+        for ray in &misses {
+            assert!(!aabb.intersects(ray), "{:?}", ray);
+        }
+    }
+
+    #[test]
+    fn obb_intersections() {
+        let obb = diagonal_obb();
+        let hits = [
+            Ray::new(Point3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0)),
+            // Near the far end of the box
+            Ray::new(Point3::new(1.2, 1.2, 5.0), Vector3::new(0.0, 0.0, -1.0)),
+            // Along the length of the box
+            Ray::new(Point3::new(5.0, 5.0, 0.0), Vector3::new(-1.0, -1.0, 0.0)),
+            // Starting inside the box
+            Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0)),
+        ];
+        let misses = [
+            Ray::new(Point3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, 1.0)),
+            Ray::new(Point3::new(5.0, -5.0, 0.0), Vector3::new(-1.0, -1.0, 0.0)),
+            // These pass through the axis-aligned box around the OBB, but miss the OBB itself
+            Ray::new(Point3::new(1.2, -1.2, 5.0), Vector3::new(0.0, 0.0, -1.0)),
+            Ray::new(Point3::new(-1.2, 1.2, 5.0), Vector3::new(0.0, 0.0, -1.0)),
+        ];
+
+        for ray in &hits {
+            assert!(obb.intersects(ray), "{:?}", ray);
+        }
+        for ray in &misses {
+            assert!(!obb.intersects(ray), "{:?}", ray);
+        }
+    }
+
+    #[test]
+    fn obb_intersection_range() {
+        let obb = diagonal_obb();
+        let along = obb.axes[0];
+        let across = obb.axes[1];
+        let cases = [
+            // Enters the end of the box 3 units away, and leaves through the other end
+            (Ray::new(obb.center - (along * 5.0), along), (3.0, 7.0)),
+            // Crosses the thin side of the box
+            (Ray::new(obb.center - (across * 5.0), across), (4.75, 5.25)),
+            // Starts inside the box, so the entry is behind the origin
+            (Ray::new(obb.center, -along), (-2.0, 2.0)),
+        ];
+
+        for (ray, (t_near, t_far)) in &cases {
+            let (near, far) = obb.intersection_range(ray).unwrap();
+            assert!(approx_eq!(Float, near, *t_near, epsilon = 1e-5), "{}", near);
+            assert!(approx_eq!(Float, far, *t_far, epsilon = 1e-5), "{}", far);
+        }
+    }
+}
@@ -0,0 +1,815 @@
+//! Triangle meshes that are loaded from model files
+//!
+//! A mesh is a collection of triangles that share vertices. Meshes are loaded from a file when the
+//! scene is converted into a renderer, and each face of the mesh is expanded into its own
+//! `Triangle` so it can be placed in the acceleration structure like any other object. Polygonal
+//! faces (quads and n-gons) are split into triangles with a fan triangulation, so they should be
+//! convex.
+//!
+//! The only supported format right now is [PLY](http://paulbourke.net/dataformats/ply/), in both
+//! its ASCII and binary little-endian encodings.
+
+use crate::{
+    hittable::triangle::{Triangle, TriangleHandedness, TriangleParameters},
+    math::UpAxis,
+    types::{string_enum, Float},
+};
+use cgmath::{InnerSpace, Vector3};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::{
+    convert::TryInto,
+    fs,
+    path::{Path, PathBuf},
+    str::{FromStr, SplitAsciiWhitespace},
+};
+use thiserror::Error;
+
+/// The file extensions of the mesh formats that can be loaded
+pub const MESH_FORMATS: &[&str] = &["ply"];
+
+/// The possible errors that can arise when loading a mesh
+#[derive(Error, Debug)]
+pub enum MeshError {
+    #[error("Failed to read the mesh file \"{path}\"")]
+    IO {
+        path: String,
+        source: std::io::Error,
+    },
+
+    #[error(
+        "Mesh file \"{0}\" doesn't have a supported extension. Valid values are: {formats}",
+        formats = MESH_FORMATS.join(", ")
+    )]
+    UnsupportedFormat(String),
+
+    #[error("The PLY file is malformed: {0}")]
+    MalformedPly(String),
+
+    #[error("A face refers to vertex {index}, but the mesh only has {count} vertices")]
+    InvalidVertexIndex { index: f64, count: usize },
+}
+
+/// A result that can return a `MeshError`
+pub type MeshResult<T> = Result<T, MeshError>;
+
+string_enum! {
+    /// How the winding order of a mesh's faces is treated when it's loaded
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum Winding {
+        /// Use the faces as they are in the file
+        #[default]
+        Unchanged,
+
+        /// Reverse the winding of every face, which turns the mesh inside out
+        Flip,
+
+        /// Reverse the winding of every face if most of the mesh faces inwards
+        ///
+        /// See `Mesh::faces_inward`. This is meant for closed meshes, open surfaces like a single
+        /// plane don't have an inside and are left alone.
+        Auto,
+    }
+}
+
+/// The parameters for a mesh that may be input by a user
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MeshParameters {
+    /// The path to the model file
+    ///
+    /// The format of the file is inferred from its extension. Relative paths are resolved relative
+    /// to the directory of the scene file.
+    pub path: PathBuf,
+
+    /// Whether the triangles of the mesh can be hit from behind
+    ///
+    /// See `TriangleParameters::double_sided`. Closed meshes don't need this, but it's useful for
+    /// open surfaces or meshes with inconsistent winding.
+    #[serde(default)]
+    pub double_sided: bool,
+
+    /// How to treat the winding order of the mesh's faces
+    ///
+    /// By default the faces are used as they're stored in the file, with counterclockwise
+    /// vertices on the front. A mesh that was exported with the opposite convention renders
+    /// inside out, since the faces that should be visible are culled, which `Flip` or `Auto` can
+    /// fix.
+    #[serde(default)]
+    pub winding: Winding,
+
+    /// Whether to move and scale the mesh to fit in a unit cube at the origin
+    ///
+    /// Models from other tools come in all sorts of units and are often far from the origin,
+    /// which puts them out of view and costs precision, since floating point numbers get coarser
+    /// the further they are from zero. With this set, the mesh's bounding box is centered on the
+    /// origin and its longest side is scaled to 1. See `Mesh::normalize`.
+    #[serde(default)]
+    pub normalize: bool,
+
+    /// The up axis of the scene the mesh is in
+    ///
+    /// The mesh's vertices and normals are converted from this convention when the mesh is
+    /// loaded. This isn't part of the scene file, it's set from the scene's `up_axis` when the
+    /// scene is prepared.
+    #[serde(skip)]
+    pub up_axis: UpAxis,
+}
+
+impl MeshParameters {
+    /// Load the mesh file, dispatching on its extension
+    pub fn load(&self) -> MeshResult<Mesh> {
+        let ext = self
+            .path
+            .extension()
+            .map(|x| x.to_string_lossy().to_lowercase());
+
+        match ext.as_deref() {
+            Some("ply") => {
+                let bytes = fs::read(&self.path).map_err(|source| MeshError::IO {
+                    path: self.path.to_string_lossy().into_owned(),
+                    source,
+                })?;
+                parse_ply(&bytes)
+            }
+            _ => Err(MeshError::UnsupportedFormat(
+                self.path.to_string_lossy().into_owned(),
+            )),
+        }
+    }
+
+    /// Load the mesh file and expand it into triangles
+    pub fn init(&self) -> MeshResult<Vec<Triangle>> {
+        let mut mesh = self.load()?;
+
+        if self.up_axis != UpAxis::Y {
+            let convert = |v: &mut Vector3<Float>| *v = self.up_axis.convert_vector(*v);
+            mesh.vertices.iter_mut().for_each(convert);
+            mesh.normals.iter_mut().flatten().for_each(convert);
+        }
+
+        if self.normalize {
+            mesh.normalize();
+        }
+
+        match self.winding {
+            Winding::Unchanged => (),
+            Winding::Flip => mesh.flip_winding(),
+            Winding::Auto => {
+                if mesh.faces_inward() {
+                    info!(
+                        "Flipping the winding of \"{}\", since its faces point inwards",
+                        self.path.display()
+                    );
+                    mesh.flip_winding();
+                }
+            }
+        }
+        Ok(mesh.triangles(self.double_sided))
+    }
+
+    /// Resolve a relative mesh path against some base directory
+    pub fn resolve_path(&mut self, base: &Path) {
+        if self.path.is_relative() {
+            self.path = base.join(&self.path);
+        }
+    }
+}
+
+/// The geometry of a mesh as it was loaded from a file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Mesh {
+    /// The positions of the vertices
+    pub vertices: Vec<Vector3<Float>>,
+
+    /// The normal at each vertex, if the file provides them
+    pub normals: Option<Vec<Vector3<Float>>>,
+
+    /// The indices of the vertices of each triangle, after triangulating polygonal faces
+    ///
+    /// The vertices of each face are in counterclockwise order when they're viewed from the front
+    /// of the face, which is the convention used by most modeling software.
+    pub faces: Vec<[usize; 3]>,
+}
+
+impl Mesh {
+    /// Reverse the winding order of every face, so the front of each face becomes its back
+    pub fn flip_winding(&mut self) {
+        for face in &mut self.faces {
+            face.swap(1, 2);
+        }
+    }
+
+    /// Move and scale the mesh so that it fits in a unit cube at the origin
+    ///
+    /// The center of the mesh's bounding box is moved to the origin, and the mesh is scaled
+    /// uniformly so that the longest side of the box is 1, which keeps its proportions and its
+    /// normals. A mesh whose vertices are all in the same place is only moved.
+    pub fn normalize(&mut self) {
+        let mut vertices = self.vertices.iter();
+        let first = match vertices.next() {
+            Some(x) => *x,
+            None => return,
+        };
+        let (min, max) = vertices.fold((first, first), |(min, max), v| {
+            (
+                Vector3::new(min.x.min(v.x), min.y.min(v.y), min.z.min(v.z)),
+                Vector3::new(max.x.max(v.x), max.y.max(v.y), max.z.max(v.z)),
+            )
+        });
+        let center = (min + max) / 2.0;
+        let extent = max - min;
+        let longest = extent.x.max(extent.y).max(extent.z);
+        let scale = if longest > 0.0 { 1.0 / longest } else { 1.0 };
+
+        for vertex in &mut self.vertices {
+            *vertex = (*vertex - center) * scale;
+        }
+    }
+
+    /// Whether most of the mesh faces towards its center rather than away from it
+    ///
+    /// Every face votes with its area on whether its front faces away from the centroid of the
+    /// vertices, which for a closed mesh adds up to six times its signed volume. The mesh faces
+    /// inwards if the total is negative. A flat mesh has no volume, so it never faces inwards.
+    pub fn faces_inward(&self) -> bool {
+        if self.vertices.is_empty() {
+            return false;
+        }
+        let centroid = self.vertices.iter().sum::<Vector3<Float>>() / self.vertices.len() as Float;
+        let volume: Float = self
+            .faces
+            .iter()
+            .map(|&[a, b, c]| {
+                let [a, b, c] = [self.vertices[a], self.vertices[b], self.vertices[c]];
+                // The cross product is the normal scaled by twice the face's area
+                (b - a).cross(c - a).dot(a - centroid)
+            })
+            .sum();
+        volume < 0.0
+    }
+
+    /// Expand the mesh into individual triangles
+    ///
+    /// Degenerate faces, which have no area and therefore no normal, are skipped.
+    pub fn triangles(&self, double_sided: bool) -> Vec<Triangle> {
+        self.faces
+            .iter()
+            .filter_map(|&[a, b, c]| {
+                let vertices = [self.vertices[a], self.vertices[b], self.vertices[c]];
+
+                if (vertices[1] - vertices[0])
+                    .cross(vertices[2] - vertices[0])
+                    .magnitude2()
+                    == 0.0
+                {
+                    return None;
+                }
+                // Model files list the vertices counterclockwise when viewed from the front,
+                // which is what the triangle calls clockwise handedness
+                let mut triangle = TriangleParameters {
+                    vertices,
+                    handedness: TriangleHandedness::Clockwise,
+                    double_sided,
+                }
+                .init();
+                triangle.vertex_normals = self
+                    .normals
+                    .as_ref()
+                    .map(|normals| [normals[a], normals[b], normals[c]]);
+                Some(triangle)
+            })
+            .collect()
+    }
+}
+
+/// The error for a malformed PLY file
+fn malformed(message: impl Into<String>) -> MeshError {
+    MeshError::MalformedPly(message.into())
+}
+
+/// The encodings of the body of a PLY file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+}
+
+/// The numeric types that a PLY property can have
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScalarType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl FromStr for ScalarType {
+    type Err = MeshError;
+
+    /// Parse a type name, which can be either the original name or the sized alias
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "char" | "int8" => Ok(ScalarType::Int8),
+            "uchar" | "uint8" => Ok(ScalarType::UInt8),
+            "short" | "int16" => Ok(ScalarType::Int16),
+            "ushort" | "uint16" => Ok(ScalarType::UInt16),
+            "int" | "int32" => Ok(ScalarType::Int32),
+            "uint" | "uint32" => Ok(ScalarType::UInt32),
+            "float" | "float32" => Ok(ScalarType::Float32),
+            "double" | "float64" => Ok(ScalarType::Float64),
+            _ => Err(malformed(format!("unknown property type \"{}\"", s))),
+        }
+    }
+}
+
+/// The type of a property of a PLY element
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PropertyKind {
+    /// A single value
+    Scalar(ScalarType),
+
+    /// A variable length list of values, prefixed with the length of the list
+    List { count: ScalarType, item: ScalarType },
+}
+
+/// A named property of a PLY element
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Property {
+    name: String,
+    kind: PropertyKind,
+}
+
+/// A type of element in a PLY file, such as vertices or faces
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    /// The index of the scalar property with the given name
+    fn scalar(&self, name: &str) -> Option<usize> {
+        self.properties
+            .iter()
+            .position(|p| p.name == name && matches!(p.kind, PropertyKind::Scalar(_)))
+    }
+}
+
+/// Reads values out of the body of a PLY file
+enum PlyReader<'a> {
+    Ascii(SplitAsciiWhitespace<'a>),
+    BinaryLittleEndian(&'a [u8]),
+}
+
+impl<'a> PlyReader<'a> {
+    /// Read the next value from the body of the file
+    ///
+    /// Every type fits in an `f64` without losing precision, so we don't need to keep track of
+    /// the original type of each value.
+    fn read(&mut self, ty: ScalarType) -> MeshResult<f64> {
+        match self {
+            PlyReader::Ascii(tokens) => {
+                let token = tokens
+                    .next()
+                    .ok_or_else(|| malformed("unexpected end of file"))?;
+                token
+                    .parse()
+                    .map_err(|_| malformed(format!("\"{}\" is not a number", token)))
+            }
+            PlyReader::BinaryLittleEndian(bytes) => {
+                let size = match ty {
+                    ScalarType::Int8 | ScalarType::UInt8 => 1,
+                    ScalarType::Int16 | ScalarType::UInt16 => 2,
+                    ScalarType::Int32 | ScalarType::UInt32 | ScalarType::Float32 => 4,
+                    ScalarType::Float64 => 8,
+                };
+
+                if bytes.len() < size {
+                    return Err(malformed("unexpected end of file"));
+                }
+                let (value, rest) = bytes.split_at(size);
+                *bytes = rest;
+                Ok(match ty {
+                    ScalarType::Int8 => f64::from(value[0] as i8),
+                    ScalarType::UInt8 => f64::from(value[0]),
+                    ScalarType::Int16 => f64::from(i16::from_le_bytes([value[0], value[1]])),
+                    ScalarType::UInt16 => f64::from(u16::from_le_bytes([value[0], value[1]])),
+                    ScalarType::Int32 => f64::from(i32::from_le_bytes(value.try_into().unwrap())),
+                    ScalarType::UInt32 => f64::from(u32::from_le_bytes(value.try_into().unwrap())),
+                    ScalarType::Float32 => f64::from(f32::from_le_bytes(value.try_into().unwrap())),
+                    ScalarType::Float64 => f64::from_le_bytes(value.try_into().unwrap()),
+                })
+            }
+        }
+    }
+
+    /// Read one instance of an element, storing the values of each property in `row`
+    ///
+    /// `row` must have one entry for each property of the element. Scalar properties are stored as
+    /// a list with a single value.
+    fn read_row(&mut self, element: &Element, row: &mut [Vec<f64>]) -> MeshResult<()> {
+        for (property, values) in element.properties.iter().zip(row.iter_mut()) {
+            values.clear();
+
+            match property.kind {
+                PropertyKind::Scalar(ty) => values.push(self.read(ty)?),
+                PropertyKind::List { count, item } => {
+                    let n = self.read(count)? as usize;
+
+                    for _ in 0..n {
+                        values.push(self.read(item)?);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Split a PLY file into its header lines and its body
+fn split_header(bytes: &[u8]) -> MeshResult<(Vec<&str>, &[u8])> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+
+    loop {
+        let rest = &bytes[offset..];
+        let len = rest
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(|| malformed("the header isn't terminated by \"end_header\""))?;
+        let line = std::str::from_utf8(&rest[..len])
+            .map_err(|_| malformed("the header isn't valid text"))?
+            .trim();
+        offset += len + 1;
+
+        if line == "end_header" {
+            return Ok((lines, &bytes[offset..]));
+        }
+        lines.push(line);
+    }
+}
+
+/// Parse the header of a PLY file into its format and the elements it declares
+fn parse_header(lines: &[&str]) -> MeshResult<(PlyFormat, Vec<Element>)> {
+    if lines.first() != Some(&"ply") {
+        return Err(malformed("the file doesn't start with \"ply\""));
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+
+    for line in &lines[1..] {
+        let words: Vec<&str> = line.split_ascii_whitespace().collect();
+
+        match words.as_slice() {
+            ["format", "ascii", _] => format = Some(PlyFormat::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(PlyFormat::BinaryLittleEndian),
+            ["format", other, ..] => {
+                return Err(malformed(format!(
+                    "the \"{}\" format isn't supported, only \"ascii\" and \
+                     \"binary_little_endian\" are",
+                    other
+                )))
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => (),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| malformed(format!("invalid element count \"{}\"", count)))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let kind = PropertyKind::List {
+                    count: count.parse()?,
+                    item: item.parse()?,
+                };
+                push_property(&mut elements, name, kind)?;
+            }
+            ["property", ty, name] => {
+                push_property(&mut elements, name, PropertyKind::Scalar(ty.parse()?))?
+            }
+            _ => return Err(malformed(format!("invalid header line \"{}\"", line))),
+        }
+    }
+    let format = format.ok_or_else(|| malformed("the header doesn't declare a format"))?;
+    Ok((format, elements))
+}
+
+/// Add a property to the element that was most recently declared
+fn push_property(elements: &mut [Element], name: &str, kind: PropertyKind) -> MeshResult<()> {
+    let element = elements
+        .last_mut()
+        .ok_or_else(|| malformed("a property was declared before any element"))?;
+    element.properties.push(Property {
+        name: name.to_string(),
+        kind,
+    });
+    Ok(())
+}
+
+/// Parse the contents of a PLY file
+///
+/// This reads the positions of the vertices, the vertex normals if every vertex has one, and the
+/// faces. Any other elements and properties, such as vertex colors, are skipped.
+fn parse_ply(bytes: &[u8]) -> MeshResult<Mesh> {
+    let (lines, body) = split_header(bytes)?;
+    let (format, elements) = parse_header(&lines)?;
+    let mut reader = match format {
+        PlyFormat::Ascii => PlyReader::Ascii(
+            std::str::from_utf8(body)
+                .map_err(|_| malformed("the body of an ASCII file isn't valid text"))?
+                .split_ascii_whitespace(),
+        ),
+        PlyFormat::BinaryLittleEndian => PlyReader::BinaryLittleEndian(body),
+    };
+    let mut mesh = Mesh::default();
+    // The faces are validated after every element has been read, since the faces could be listed
+    // before the vertices
+    let mut polygons: Vec<Vec<f64>> = Vec::new();
+
+    for element in &elements {
+        let mut row = vec![Vec::new(); element.properties.len()];
+
+        match element.name.as_str() {
+            "vertex" => {
+                let position = ["x", "y", "z"]
+                    .iter()
+                    .map(|&name| element.scalar(name))
+                    .collect::<Option<Vec<usize>>>()
+                    .ok_or_else(|| malformed("the vertices don't have x, y, and z properties"))?;
+                let normal = ["nx", "ny", "nz"]
+                    .iter()
+                    .map(|&name| element.scalar(name))
+                    .collect::<Option<Vec<usize>>>();
+                let mut normals = Vec::new();
+
+                for _ in 0..element.count {
+                    reader.read_row(element, &mut row)?;
+                    let vector = |indices: &[usize]| {
+                        Vector3::new(
+                            row[indices[0]][0] as Float,
+                            row[indices[1]][0] as Float,
+                            row[indices[2]][0] as Float,
+                        )
+                    };
+                    mesh.vertices.push(vector(&position));
+
+                    if let Some(normal) = &normal {
+                        normals.push(vector(normal));
+                    }
+                }
+
+                if normal.is_some() {
+                    mesh.normals = Some(normals);
+                }
+            }
+            "face" => {
+                let indices = element
+                    .properties
+                    .iter()
+                    .position(|p| {
+                        (p.name == "vertex_indices" || p.name == "vertex_index")
+                            && matches!(p.kind, PropertyKind::List { .. })
+                    })
+                    .ok_or_else(|| malformed("the faces don't have a vertex_indices list"))?;
+
+                for _ in 0..element.count {
+                    reader.read_row(element, &mut row)?;
+                    polygons.push(row[indices].clone());
+                }
+            }
+            _ => {
+                for _ in 0..element.count {
+                    reader.read_row(element, &mut row)?;
+                }
+            }
+        }
+    }
+    let count = mesh.vertices.len();
+
+    for polygon in polygons {
+        if polygon.len() < 3 {
+            return Err(malformed(format!(
+                "a face has {} vertices, but faces need at least 3",
+                polygon.len()
+            )));
+        }
+        let indices = polygon
+            .iter()
+            .map(|&index| {
+                if index >= 0.0 && index < count as f64 && index.fract() == 0.0 {
+                    Ok(index as usize)
+                } else {
+                    Err(MeshError::InvalidVertexIndex { index, count })
+                }
+            })
+            .collect::<MeshResult<Vec<usize>>>()?;
+
+        // Fan triangulation, every triangle shares the first vertex of the polygon
+        for i in 1..(indices.len() - 1) {
+            mesh.faces.push([indices[0], indices[i], indices[i + 1]]);
+        }
+    }
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hittable::Hittable, ray::Ray};
+    use cgmath::Point3;
+
+    /// A unit square in the XY plane, made of a single quad, with normals facing +z
+    const ASCII_QUAD: &str = "ply
+format ascii 1.0
+comment a unit square
+element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 0 0 1
+1 0 0 0 0 1
+1 1 0 0 0 1
+0 1 0 0 0 1
+4 0 1 2 3
+";
+
+    #[test]
+    fn ascii_quad() {
+        let mesh = parse_ply(ASCII_QUAD.as_bytes()).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.vertices[2], Vector3::new(1.0, 1.0, 0.0));
+        assert_eq!(
+            mesh.normals.as_ref().unwrap()[3],
+            Vector3::new(0.0, 0.0, 1.0)
+        );
+        assert_eq!(mesh.faces, vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn binary_pentagon_with_extra_properties() {
+        let header = "ply
+format binary_little_endian 1.0
+element vertex 5
+property double x
+property double y
+property double z
+property uchar red
+element face 1
+property list uchar uint vertex_indices
+property uchar flags
+end_header
+";
+        let mut bytes = header.as_bytes().to_vec();
+
+        for i in 0..5 {
+            let angle = (i as f64) * std::f64::consts::TAU / 5.0;
+            for x in [angle.cos(), angle.sin(), -1.0] {
+                bytes.extend_from_slice(&x.to_le_bytes());
+            }
+            bytes.push(255);
+        }
+        bytes.push(5);
+        for i in 0u32..5 {
+            bytes.extend_from_slice(&i.to_le_bytes());
+        }
+        bytes.push(0);
+
+        let mesh = parse_ply(&bytes).unwrap();
+        assert_eq!(mesh.vertices.len(), 5);
+        assert!((mesh.vertices[0] - Vector3::new(1.0, 0.0, -1.0)).magnitude() < 1e-6);
+        assert_eq!(mesh.normals, None);
+        assert_eq!(mesh.faces, vec![[0, 1, 2], [0, 2, 3], [0, 3, 4]]);
+    }
+
+    #[test]
+    fn malformed_files() {
+        let cases = [
+            // Truncated body
+            ASCII_QUAD.replace("4 0 1 2 3\n", ""),
+            // Out of bounds vertex index
+            ASCII_QUAD.replace("4 0 1 2 3", "4 0 1 2 4"),
+            // Degenerate face
+            ASCII_QUAD.replace("4 0 1 2 3", "2 0 1"),
+            // Unsupported format
+            ASCII_QUAD.replace("ascii", "binary_big_endian"),
+            // Missing header terminator
+            ASCII_QUAD.replace("end_header", "end"),
+        ];
+
+        for case in cases {
+            assert!(parse_ply(case.as_bytes()).is_err(), "{}", case);
+        }
+    }
+
+    #[test]
+    fn unsupported_extension() {
+        let params = MeshParameters {
+            path: PathBuf::from("model.stl"),
+            double_sided: false,
+            winding: Winding::Unchanged,
+            normalize: false,
+            up_axis: UpAxis::Y,
+        };
+        assert!(matches!(
+            params.load(),
+            Err(MeshError::UnsupportedFormat(_))
+        ));
+    }
+
+    /// The triangles of a mesh face the viewer when their vertices are counterclockwise, and
+    /// interpolate the vertex normals
+    #[test]
+    fn triangles_face_the_front() {
+        let mut mesh = parse_ply(ASCII_QUAD.as_bytes()).unwrap();
+        mesh.normals.as_mut().unwrap()[2] = Vector3::new(1.0, 0.0, 1.0).normalize();
+        let triangles = mesh.triangles(false);
+        assert_eq!(triangles.len(), 2);
+
+        let front = Ray {
+            origin: Point3::new(0.75, 0.25, 1.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        let hit = triangles[0].hit(&front).unwrap();
+        assert!(hit.front_face);
+        // The normal is tilted towards the vertex with the tilted normal
+        assert!(hit.shading_normal.x > 0.0 && hit.shading_normal.z > 0.0);
+        assert!((hit.shading_normal.magnitude() - 1.0).abs() < 1e-5);
+
+        let back = Ray {
+            origin: Point3::new(0.75, 0.25, -1.0),
+            direction: Vector3::new(0.0, 0.0, 1.0),
+        };
+        assert_eq!(triangles[0].hit(&back), None);
+    }
+
+    /// A cube whose faces are wound so that they face outwards
+    const CUBE: &str = include_str!("../../scenes/meshes/cube.ply");
+
+    #[test]
+    fn winding() {
+        let mut mesh = parse_ply(CUBE.as_bytes()).unwrap();
+        let ray = Ray {
+            origin: Point3::new(0.1, 0.2, 5.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        let hits = |mesh: &Mesh| -> Vec<Float> {
+            mesh.triangles(false)
+                .iter()
+                .filter_map(|t| t.hit(&ray))
+                .map(|hit| hit.distance)
+                .collect()
+        };
+        assert!(!mesh.faces_inward());
+        let outward = hits(&mesh);
+
+        // Flipping the cube culls the side that faces the ray, so the ray goes through the cube
+        // and hits the inside of the far side instead
+        mesh.flip_winding();
+        assert!(mesh.faces_inward());
+        let inward = hits(&mesh);
+        assert_eq!((outward.len(), inward.len()), (1, 1));
+        assert!(inward[0] > outward[0]);
+
+        // A flat mesh has no inside
+        let quad = parse_ply(ASCII_QUAD.as_bytes()).unwrap();
+        assert!(!quad.faces_inward());
+        assert!(!Mesh::default().faces_inward());
+    }
+
+    #[test]
+    fn normalize() {
+        // A 2000 x 1000 x 0 rectangle, a long way from the origin
+        let mut mesh = parse_ply(ASCII_QUAD.as_bytes()).unwrap();
+        for vertex in &mut mesh.vertices {
+            *vertex = Vector3::new(vertex.x * 2000.0 + 1e6, vertex.y * 1000.0 - 5e5, 30.0);
+        }
+        mesh.normalize();
+        assert_eq!(mesh.vertices[0], Vector3::new(-0.5, -0.25, 0.0));
+        assert_eq!(mesh.vertices[2], Vector3::new(0.5, 0.25, 0.0));
+        assert_eq!(
+            mesh.normals.as_ref().unwrap()[0],
+            Vector3::new(0.0, 0.0, 1.0)
+        );
+
+        // A single point can only be moved
+        let mut point = Mesh {
+            vertices: vec![Vector3::new(3.0, 4.0, 5.0); 2],
+            ..Default::default()
+        };
+        point.normalize();
+        assert_eq!(point.vertices, vec![Vector3::new(0.0, 0.0, 0.0); 2]);
+        Mesh::default().normalize();
+    }
+}
@@ -7,20 +7,28 @@
 //! yield which object was hit.
 
 use crate::{
-    material::{SerializedMaterial, BSDF},
+    material::{AlbedoCorrection, Emissive, SerializedMaterial, BSDF},
+    math::UpAxis,
     ray::Ray,
-    types::{approx_eq_vec, Float},
+    types::{approx_eq_point, approx_eq_vec, Float},
 };
-use cgmath::Vector3;
+use cgmath::{Point3, Vector2, Vector3};
 use float_cmp::approx_eq;
+use log::warn;
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
+use std::{fmt::Debug, sync::Arc};
 
+mod bounding_box;
+mod mesh;
+mod sdf;
 mod sphere;
 mod triangle;
 
+pub use bounding_box::{Aabb, OrientedBoundingBox};
+pub use mesh::{MeshError, MeshParameters, MESH_FORMATS};
+pub use sdf::SdfParameters;
 pub use sphere::Sphere;
-pub use triangle::Triangle;
+pub use triangle::TriangleParameters;
 
 /// An interface for any object that can intersect with a ray coming from the camera
 ///
@@ -30,39 +38,86 @@ pub use triangle::Triangle;
 pub trait Hittable: Debug + Send + Sync {
     /// A method that returns a hit record if the object was hit
     fn hit(&self, ray: &Ray) -> Option<HitRecord>;
+
+    /// The surface area of the object
+    ///
+    /// This is used to convert a light's power into the radiance emitted from each point on its
+    /// surface.
+    fn area(&self) -> Float;
+
+    /// The smallest axis-aligned box that encloses the object
+    ///
+    /// Acceleration structures use this to decide which parts of space an object can be hit in.
+    fn bounding_box(&self) -> Aabb;
 }
 
 /// The different types of `Hittable` types that can be used as input objects
 ///
 /// This is an enum type that exists for convenient use with serde, so we can create a serializable
-/// struct to expose as a scene description to the user.
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+/// struct to expose as a scene description to the user. The variant is named by a `type` field
+/// next to the geometry's parameters, like `{ "type": "Sphere", "center": ..., "radius": 0.5 }`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
 pub enum SerializedHittable {
     Sphere(Sphere),
-    Triangle(triangle::TriangleParameters),
+    Triangle(TriangleParameters),
+    Mesh(MeshParameters),
+    Sdf(SdfParameters),
 }
 
 /// Information pertaining to a ray intersection
 ///
-/// The hit record has information on where the object was hit and the normal for that hit. This is
-/// the record struct specifically for geometric collisions.
+/// The hit record has information on where the object was hit and the normals for that hit. This
+/// is the record struct specifically for geometric collisions.
+///
+/// There are two normals. The geometric normal is the true normal of the surface, and the shading
+/// normal is the one that the surface should look like it has, such as a normal that's
+/// interpolated over a mesh to make it look smooth. They're the same for flat surfaces and
+/// spheres.
 #[derive(Clone, Debug, Copy)]
 pub struct HitRecord {
     /// The point in space where the object was hit
-    pub p: Vector3<Float>,
+    pub p: Point3<Float>,
 
-    /// The normal vector for the intersection
-    pub normal: Vector3<Float>,
+    /// The true normal of the surface at the intersection
+    ///
+    /// This is what decides which side of the surface a point is on, so it's used to offset rays
+    /// leaving the surface so they don't hit it again.
+    pub geometric_normal: Vector3<Float>,
+
+    /// The normal that the surface is shaded with
+    ///
+    /// This is the normal that materials use for lighting. It faces the same side of the surface
+    /// as the geometric normal.
+    pub shading_normal: Vector3<Float>,
 
     /// The distance from the origin ray to the point of collision
     pub distance: Float,
+
+    /// Whether the ray hit the front (outward facing) side of the surface
+    ///
+    /// The normals always point away from the front side of the surface, so this will be false
+    /// when the ray hits a surface from behind, such as when it hits a sphere from the inside.
+    /// Double-sided triangles are the exception, their normals are flipped to face the ray when they
+    /// are hit from behind.
+    pub front_face: bool,
+
+    /// The surface (texture) coordinates of the point that was hit
+    ///
+    /// Both coordinates lie in [0, 1]. How they're laid out over the surface depends on the
+    /// geometry, see the documentation for each primitive.
+    pub uv: Vector2<Float>,
 }
 
 impl PartialEq for HitRecord {
     fn eq(&self, other: &Self) -> bool {
-        approx_eq_vec(&self.p, &other.p)
-            && approx_eq_vec(&self.normal, &other.normal)
+        approx_eq_point(&self.p, &other.p)
+            && approx_eq_vec(&self.geometric_normal, &other.geometric_normal)
+            && approx_eq_vec(&self.shading_normal, &other.shading_normal)
             && approx_eq!(Float, self.distance, other.distance)
+            && self.front_face == other.front_face
+            && approx_eq!(Float, self.uv.x, other.uv.x)
+            && approx_eq!(Float, self.uv.y, other.uv.y)
     }
 }
 
@@ -80,33 +135,264 @@ pub struct Textured {
     pub geometry: Box<dyn Hittable>,
 
     /// A reference to the BSDF function that corresponds to the geometry
-    pub mat: Box<dyn BSDF>,
+    ///
+    /// Materials are reference counted so that many objects, such as the triangles of a mesh, can
+    /// share a single material instance.
+    pub mat: Arc<dyn BSDF>,
+}
+
+impl Textured {
+    /// Pair a geometric primitive with a material that isn't shared with any other object
+    ///
+    /// This boxes the geometry and the material, so they don't have to be boxed by hand:
+    ///
+    /// ```ignore
+    /// let object = Textured::new(
+    ///     Sphere {
+    ///         center: Point3::new(0.0, 0.0, -1.0),
+    ///         radius: 0.5,
+    ///     },
+    ///     Mirror::default(),
+    /// );
+    /// ```
+    ///
+    /// Objects that share a material, like the triangles of a mesh, should be constructed
+    /// directly with a clone of the same `Arc` instead.
+    pub fn new(geometry: impl Hittable + 'static, mat: impl BSDF + 'static) -> Self {
+        Self {
+            geometry: Box::new(geometry),
+            mat: Arc::new(mat),
+        }
+    }
 }
 
 /// A serializable wrapper for the
-#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SerializedTextured {
     /// The geometric primitive that might be hit by the light ray or path
     pub geometry: SerializedHittable,
 
     /// A reference to the BSDF method for
-    pub mat: SerializedMaterial,
+    ///
+    /// Objects without a material use the scene's `default_material`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mat: Option<SerializedMaterial>,
 }
 
-impl From<SerializedTextured> for Textured {
-    fn from(serialized: SerializedTextured) -> Self {
-        let geometry: Box<dyn Hittable> = match serialized.geometry {
-            SerializedHittable::Sphere(x) => Box::new(x.clone()),
-            SerializedHittable::Triangle(x) => Box::new(x.init()),
+impl SerializedTextured {
+    /// Check whether the material makes sense for the geometry it's attached to
+    ///
+    /// Some pairings are valid, but probably don't do what the user expects. These aren't hard
+    /// errors, so this returns a human readable description of each problem that was found, which
+    /// can be surfaced as a warning.
+    pub fn compatibility_warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let geometry = match self.geometry {
+            SerializedHittable::Sphere(_) => "sphere",
+            SerializedHittable::Triangle(_) => "triangle",
+            SerializedHittable::Mesh(_) => "mesh",
+            SerializedHittable::Sdf(_) => "signed distance function",
+        };
+        // A triangle is infinitely thin, so there is no "inside" for a ray to refract into. A
+        // closed mesh has one, but rays can only leave it if its faces can be hit from behind.
+        let is_thin = match &self.geometry {
+            SerializedHittable::Triangle(_) => true,
+            SerializedHittable::Mesh(mesh) => !mesh.double_sided,
+            SerializedHittable::Sphere(_) | SerializedHittable::Sdf(_) => false,
+        };
+        // Lights can only be sampled directly if we can pick points on their surface
+        let is_area_sampleable = matches!(self.geometry, SerializedHittable::Sphere(_));
+
+        match self.mat {
+            Some(SerializedMaterial::Dielectric(_)) if is_thin => warnings.push(format!(
+                "A dielectric material is attached to a {}, which has no interior to refract \
+                 into, so it won't render like glass",
+                geometry
+            )),
+            Some(SerializedMaterial::Emissive(_)) if !is_area_sampleable => warnings.push(format!(
+                "An emissive material is attached to a {}, which can't be sampled as a light, so \
+                 it will only contribute light when it's hit by chance",
+                geometry
+            )),
+            _ => (),
+        }
+        warnings
+    }
+}
+
+impl SerializedTextured {
+    /// Convert the object from a scene with some up axis to the internal convention
+    ///
+    /// This converts the geometry, and the axis of a spotlight. Meshes are converted when they're
+    /// loaded.
+    pub fn convert_up_axis(&mut self, up_axis: UpAxis) {
+        match &mut self.geometry {
+            SerializedHittable::Sphere(x) => x.center = up_axis.convert_point(x.center),
+            SerializedHittable::Triangle(x) => {
+                for vertex in &mut x.vertices {
+                    *vertex = up_axis.convert_vector(*vertex);
+                }
+            }
+            SerializedHittable::Mesh(x) => x.up_axis = up_axis,
+            SerializedHittable::Sdf(x) => x.shape.convert_up_axis(up_axis),
+        }
+
+        if let Some(SerializedMaterial::Emissive(Emissive {
+            spot: Some(spot), ..
+        })) = &mut self.mat
+        {
+            spot.axis = up_axis.convert_vector(spot.axis);
+        }
+    }
+
+    /// Convert the serialized object into the objects that the renderer uses
+    ///
+    /// Most geometry maps to a single object, but a mesh is expanded into one object for each of
+    /// its triangles. Every object shares the same material, which is a neutral gray diffuse
+    /// material if the object doesn't have one. This fails if a mesh can't be loaded.
+    pub fn expand(self) -> Result<Vec<Textured>, MeshError> {
+        for warning in self.compatibility_warnings() {
+            warn!("{}", warning);
+        }
+        let geometries: Vec<Box<dyn Hittable>> = match self.geometry {
+            SerializedHittable::Sphere(x) => vec![Box::new(x)],
+            SerializedHittable::Triangle(x) => vec![Box::new(x.init())],
+            SerializedHittable::Mesh(x) => x
+                .init()?
+                .into_iter()
+                .map(|triangle| Box::new(triangle) as Box<dyn Hittable>)
+                .collect(),
+            SerializedHittable::Sdf(x) => vec![Box::new(x.init())],
         };
-        let bsdf: Box<dyn BSDF> = match serialized.mat {
-            SerializedMaterial::Mirror(x) => Box::new(x.clone()),
-            SerializedMaterial::Diffuse(x) => Box::new(x.clone()),
-            SerializedMaterial::Dielectric(x) => Box::new(x.clone()),
+        let mut mat = self.mat.unwrap_or_default();
+
+        // A light's power is spread over every object it's expanded into, like all of the
+        // triangles of a mesh
+        if let SerializedMaterial::Emissive(emissive) = &mut mat {
+            emissive.resolve_power(geometries.iter().map(|geometry| geometry.area()).sum());
+        }
+        match mat.clamp_albedo() {
+            Some(AlbedoCorrection::Clamped(albedo)) => warn!(
+                "The albedo {:?} has channels outside of [0, 1], which is not energy conserving, so \
+                 it was clamped",
+                Into::<[Float; 3]>::into(albedo)
+            ),
+            Some(AlbedoCorrection::Scaled { diffuse, specular }) => warn!(
+                "The diffuse albedo {:?} and specular albedo {:?} add up to more than 1, which is \
+                 not energy conserving, so both were scaled down",
+                Into::<[Float; 3]>::into(diffuse),
+                Into::<[Float; 3]>::into(specular)
+            ),
+            None => (),
+        }
+        let bsdf: Arc<dyn BSDF> = match mat {
+            SerializedMaterial::Mirror(x) => Arc::new(x),
+            SerializedMaterial::Diffuse(x) => Arc::new(x),
+            SerializedMaterial::Dielectric(x) => Arc::new(x),
+            SerializedMaterial::Emissive(x) => Arc::new(x),
+            SerializedMaterial::Isotropic(x) => Arc::new(x),
+            SerializedMaterial::HenyeyGreenstein(x) => Arc::new(x),
+            SerializedMaterial::BlinnPhong(x) => Arc::new(x),
         };
-        Textured {
-            geometry,
-            mat: bsdf,
+        Ok(geometries
+            .into_iter()
+            .map(|geometry| Textured {
+                geometry,
+                mat: bsdf.clone(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Dielectric, Diffuse, Emissive};
+    use std::f32::consts::PI;
+
+    fn sphere() -> SerializedHittable {
+        SerializedHittable::Sphere(Sphere {
+            center: Point3::new(0.0, 0.0, 0.0),
+            radius: 1.0,
+        })
+    }
+
+    fn triangle() -> SerializedHittable {
+        SerializedHittable::Triangle(TriangleParameters {
+            vertices: [
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(1.0, 0.0, 0.0),
+                Vector3::new(0.0, 1.0, 0.0),
+            ],
+            ..Default::default()
+        })
+    }
+
+    fn mesh(double_sided: bool) -> SerializedHittable {
+        json5::from_str(&format!(
+            r#"{{ "type": "Mesh", "path": "cube.ply", "double_sided": {} }}"#,
+            double_sided
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn compatibility_warnings() {
+        let emissive = SerializedMaterial::Emissive(
+            json5::from_str::<Emissive>(r#"{ "radiance": [1.0, 1.0, 1.0] }"#).unwrap(),
+        );
+        let dielectric = SerializedMaterial::Dielectric(Dielectric::default());
+        let diffuse = SerializedMaterial::Diffuse(Diffuse {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        });
+        let cases = [
+            (sphere(), dielectric, 0),
+            (sphere(), emissive, 0),
+            (sphere(), diffuse, 0),
+            (triangle(), dielectric, 1),
+            (triangle(), emissive, 1),
+            (triangle(), diffuse, 0),
+            (mesh(false), dielectric, 1),
+            (mesh(true), dielectric, 0),
+        ];
+
+        for (geometry, mat, expected) in cases {
+            let textured = SerializedTextured {
+                geometry,
+                mat: Some(mat),
+            };
+            assert_eq!(
+                textured.compatibility_warnings().len(),
+                expected,
+                "{:?}",
+                textured
+            );
         }
     }
+
+    /// Doubling the size of a light with a fixed power leaves the total power unchanged, so the
+    /// radiance drops with the area
+    #[test]
+    fn power_is_divided_by_area() {
+        let radiance = |radius| {
+            let textured = SerializedTextured {
+                geometry: SerializedHittable::Sphere(Sphere {
+                    center: Point3::new(0.0, 0.0, 0.0),
+                    radius,
+                }),
+                mat: Some(SerializedMaterial::Emissive(
+                    json5::from_str(r#"{ "emission": [100.0, 100.0, 100.0], "unit": "Watts" }"#)
+                        .unwrap(),
+                )),
+            };
+            let object = textured.expand().unwrap().pop().unwrap();
+            let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+            let hit = object.geometry.hit(&ray).unwrap();
+            object.mat.emitted(&ray, &hit).x
+        };
+        let small = radiance(1.0);
+        let large = radiance(2.0);
+        assert!((small * 4.0 * PI * PI - 100.0).abs() < 1e-3);
+        assert!((small / large - 4.0).abs() < 1e-4);
+    }
 }
@@ -0,0 +1,466 @@
+//! Shapes that are defined by a signed distance function
+//!
+//! A signed distance function (SDF) gives the distance from a point to the closest point on a
+//! surface, which is negative inside of the surface. There's usually no closed form for where a
+//! ray hits the surface, so it's found with sphere tracing: since nothing is closer to a point than
+//! its distance to the surface, the ray can always advance by that distance without passing
+//! through the surface. This makes it easy to express shapes that are awkward to build out of
+//! triangles, like smooth blends between shapes or fractals.
+
+use crate::{
+    hittable::{Aabb, HitRecord, Hittable},
+    math::UpAxis,
+    ray::Ray,
+    types::Float,
+};
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector2, Vector3};
+use serde::{Deserialize, Serialize};
+use std::{
+    f32::consts::PI,
+    fmt::{self, Debug},
+};
+
+/// A signed distance function, which maps a point to its signed distance from the surface
+pub type DistanceFunction = Box<dyn Fn(Point3<Float>) -> Float + Send + Sync>;
+
+/// A helper method for serde that bounds the cost of sphere tracing by default
+fn default_max_steps() -> u32 {
+    256
+}
+
+/// A helper method for serde with a tolerance suitable for objects about as big as a unit sphere
+fn default_epsilon() -> Float {
+    1e-4
+}
+
+/// A surface defined by a signed distance function
+///
+/// The distance function must not overestimate the distance to the surface, otherwise the ray
+/// can step through the surface. Distance functions that underestimate it are fine, they just take
+/// more steps.
+pub struct Sdf {
+    /// The signed distance function of the surface
+    distance: DistanceFunction,
+
+    /// A box that encloses the surface, which bounds where the ray is traced
+    bounds: Aabb,
+
+    /// The surface area of the surface
+    area: Float,
+
+    /// The most steps the ray can take before it's considered a miss
+    ///
+    /// Rays that graze the surface take the most steps, since the distance to the surface stays
+    /// small for a long time, so this bounds the cost of the worst rays.
+    pub max_steps: u32,
+
+    /// How close to the surface the ray has to get to count as a hit
+    ///
+    /// This is in world units, and it's also the step used to estimate the normal.
+    pub epsilon: Float,
+}
+
+impl Debug for Sdf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sdf")
+            .field("bounds", &self.bounds)
+            .field("area", &self.area)
+            .field("max_steps", &self.max_steps)
+            .field("epsilon", &self.epsilon)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Sdf {
+    /// A surface from a signed distance function
+    ///
+    /// The surface must lie within `bounds`. SDFs rarely have a closed form for their surface
+    /// area, so it has to be supplied as well, although an estimate is only a problem if the
+    /// object is a light whose emission is given in watts.
+    pub fn new(distance: DistanceFunction, bounds: Aabb, area: Float) -> Self {
+        Self {
+            distance,
+            bounds,
+            area,
+            max_steps: default_max_steps(),
+            epsilon: default_epsilon(),
+        }
+    }
+
+    /// The normal of the surface at a point, estimated by central differences of the distance
+    /// function
+    fn normal(&self, p: Point3<Float>) -> Vector3<Float> {
+        let h = self.epsilon;
+        let gradient = |axis: Vector3<Float>| {
+            (self.distance)(p + (axis * h)) - (self.distance)(p - (axis * h))
+        };
+        let normal = Vector3::new(
+            gradient(Vector3::unit_x()),
+            gradient(Vector3::unit_y()),
+            gradient(Vector3::unit_z()),
+        );
+
+        // The gradient vanishes at creases, like the middle of a box
+        if normal.magnitude2() > 0.0 {
+            normal.normalize()
+        } else {
+            Vector3::unit_y()
+        }
+    }
+}
+
+impl Hittable for Sdf {
+    fn hit(&self, ray: &Ray) -> Option<HitRecord> {
+        let (t_near, t_far) = self.bounds.intersection_range(ray)?;
+        let speed = ray.direction.magnitude();
+        let mut t = t_near.max(0.0);
+
+        // A ray that leaves the surface, like a reflection, starts within epsilon of the surface.
+        // It has to get away from the surface before it can hit it, otherwise it would hit the
+        // surface it came from.
+        let mut left_surface = (self.distance)(ray.origin).abs() >= self.epsilon;
+
+        for _ in 0..self.max_steps {
+            if t > t_far {
+                return None;
+            }
+            let distance = (self.distance)(ray.at(t)).abs();
+
+            if distance >= self.epsilon {
+                left_surface = true;
+                t += distance / speed;
+            } else if left_surface {
+                let p = ray.at(t);
+                let normal = self.normal(p);
+                return Some(HitRecord {
+                    p,
+                    geometric_normal: normal,
+                    shading_normal: normal,
+                    distance: t,
+                    front_face: ray.direction.dot(normal) <= 0.0,
+                    uv: direction_uv(&normal),
+                });
+            } else {
+                t += self.epsilon / speed;
+            }
+        }
+        None
+    }
+
+    fn area(&self) -> Float {
+        self.area
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounds
+    }
+}
+
+/// Surface coordinates for an SDF from the direction of its normal
+///
+/// SDFs don't have a parameterization, so this is the latitude and longitude of the normal, like a
+/// sphere's coordinates.
+fn direction_uv(normal: &Vector3<Float>) -> Vector2<Float> {
+    let theta = (-normal.y).clamp(-1.0, 1.0).acos();
+    let phi = (-normal.z).atan2(normal.x) + PI;
+    Vector2::new(phi / (2.0 * PI), theta / PI)
+}
+
+/// The built-in signed distance functions that can be used in a scene description
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum SdfShape {
+    /// A sphere
+    Sphere {
+        center: Point3<Float>,
+        radius: Float,
+    },
+
+    /// An axis-aligned box
+    Box {
+        center: Point3<Float>,
+
+        /// Half of the size of the box along each axis
+        half_extents: Vector3<Float>,
+    },
+
+    /// Two shapes blended into each other
+    ///
+    /// The shapes are joined with a fillet where they meet, whose size is set by `k`, in world
+    /// units. With a `k` of 0 this is a regular union.
+    SmoothUnion {
+        a: Box<SdfShape>,
+        b: Box<SdfShape>,
+        k: Float,
+    },
+}
+
+impl SdfShape {
+    /// Convert the shape from a scene with some up axis to the internal convention
+    pub fn convert_up_axis(&mut self, up_axis: UpAxis) {
+        match self {
+            SdfShape::Sphere { center, .. } => *center = up_axis.convert_point(*center),
+            SdfShape::Box {
+                center,
+                half_extents,
+            } => {
+                *center = up_axis.convert_point(*center);
+                *half_extents = up_axis.convert_extents(*half_extents);
+            }
+            SdfShape::SmoothUnion { a, b, .. } => {
+                a.convert_up_axis(up_axis);
+                b.convert_up_axis(up_axis);
+            }
+        }
+    }
+
+    /// The signed distance from a point to the shape
+    pub fn distance(&self, p: Point3<Float>) -> Float {
+        match self {
+            SdfShape::Sphere { center, radius } => (p - center).magnitude() - radius,
+            SdfShape::Box {
+                center,
+                half_extents,
+            } => {
+                let d = (p - center).map(Float::abs) - half_extents;
+                let outside = d.map(|x| x.max(0.0)).magnitude();
+                let inside = d.x.max(d.y).max(d.z).min(0.0);
+                outside + inside
+            }
+            SdfShape::SmoothUnion { a, b, k } => {
+                let (a, b) = (a.distance(p), b.distance(p));
+
+                if *k <= 0.0 {
+                    return a.min(b);
+                }
+                // The polynomial smooth minimum, which is never more than k / 4 below the minimum
+                let h = (k - (a - b).abs()).max(0.0) / k;
+                a.min(b) - (h * h * k / 4.0)
+            }
+        }
+    }
+
+    /// A box that encloses the shape
+    pub fn bounds(&self) -> Aabb {
+        match self {
+            SdfShape::Sphere { center, radius } => {
+                let radius = Vector3::new(*radius, *radius, *radius);
+                Aabb {
+                    min: center.to_vec() - radius,
+                    max: center.to_vec() + radius,
+                }
+            }
+            SdfShape::Box {
+                center,
+                half_extents,
+            } => Aabb {
+                min: center.to_vec() - half_extents,
+                max: center.to_vec() + half_extents,
+            },
+            SdfShape::SmoothUnion { a, b, k } => {
+                // The blend can push the surface out by at most k / 4
+                let bounds = a.bounds().union(&b.bounds());
+                let padding = Vector3::new(1.0, 1.0, 1.0) * (k.max(0.0) / 4.0);
+                Aabb {
+                    min: bounds.min - padding,
+                    max: bounds.max + padding,
+                }
+            }
+        }
+    }
+
+    /// The surface area of the shape
+    ///
+    /// This is exact for spheres and boxes. A smooth union uses the total area of its shapes,
+    /// which overestimates the area of shapes that overlap.
+    pub fn area(&self) -> Float {
+        match self {
+            SdfShape::Sphere { radius, .. } => 4.0 * PI * radius * radius,
+            SdfShape::Box { half_extents, .. } => {
+                let size = half_extents * 2.0;
+                2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+            }
+            SdfShape::SmoothUnion { a, b, .. } => a.area() + b.area(),
+        }
+    }
+}
+
+/// The parameters for an SDF that may be input by a user
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SdfParameters {
+    /// The signed distance function of the surface
+    pub shape: SdfShape,
+
+    /// The most steps a ray can take before it's considered a miss
+    #[serde(default = "default_max_steps")]
+    pub max_steps: u32,
+
+    /// How close to the surface a ray has to get to count as a hit, in world units
+    #[serde(default = "default_epsilon")]
+    pub epsilon: Float,
+}
+
+impl SdfParameters {
+    /// Create the SDF that the renderer uses
+    pub fn init(self) -> Sdf {
+        let bounds = self.shape.bounds();
+        let area = self.shape.area();
+        let shape = self.shape;
+        Sdf {
+            max_steps: self.max_steps,
+            epsilon: self.epsilon,
+            ..Sdf::new(Box::new(move |p| shape.distance(p)), bounds, area)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Sphere;
+
+    fn sphere_sdf() -> Sdf {
+        SdfParameters {
+            shape: SdfShape::Sphere {
+                center: Point3::new(0.0, 0.0, -2.0),
+                radius: 0.5,
+            },
+            max_steps: default_max_steps(),
+            epsilon: default_epsilon(),
+        }
+        .init()
+    }
+
+    #[test]
+    fn matches_sphere() {
+        let sdf = sphere_sdf();
+        let sphere = Sphere {
+            center: Point3::new(0.0, 0.0, -2.0),
+            radius: 0.5,
+        };
+        let origin = Point3::new(0.0, 0.0, 0.0);
+
+        for direction in [
+            Vector3::new(0.0, 0.0, -1.0),
+            Vector3::new(0.1, 0.2, -1.0),
+            Vector3::new(-0.2, 0.0, -2.0),
+        ] {
+            let ray = Ray::new(origin, direction);
+            let expected = sphere.hit(&ray).unwrap();
+            let hit = sdf.hit(&ray).unwrap();
+            assert!((hit.distance - expected.distance).abs() < 1e-3);
+            assert!((hit.geometric_normal - expected.geometric_normal).magnitude() < 1e-2);
+            assert!(hit.front_face);
+        }
+        assert!(sdf
+            .hit(&Ray::new(origin, Vector3::new(0.0, 1.0, -1.0)))
+            .is_none());
+        assert!((sdf.area() - sphere.area()).abs() < 1e-4);
+        assert_eq!(sdf.bounding_box(), sphere.bounding_box());
+    }
+
+    #[test]
+    fn rays_leaving_the_surface() {
+        let sdf = sphere_sdf();
+
+        // A reflection off of the front of the sphere doesn't hit the sphere again
+        let hit = sdf
+            .hit(&Ray::new(
+                Point3::new(0.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, -1.0),
+            ))
+            .unwrap();
+        assert!(sdf
+            .hit(&Ray::new(hit.p, Vector3::new(0.0, 0.0, 1.0)))
+            .is_none());
+
+        // A refraction into the sphere hits the back of it from the inside
+        let exit = sdf
+            .hit(&Ray::new(hit.p, Vector3::new(0.0, 0.0, -1.0)))
+            .unwrap();
+        assert!((exit.p.z + 2.5).abs() < 1e-3);
+        assert!(!exit.front_face);
+    }
+
+    #[test]
+    fn max_steps() {
+        // The ray needs a few steps to get from the edge of the bounding box to the sphere
+        let mut sdf = sphere_sdf();
+        let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::new(0.1, 0.2, -1.0));
+        sdf.max_steps = 1;
+        assert!(sdf.hit(&ray).is_none());
+        sdf.max_steps = 16;
+        assert!(sdf.hit(&ray).is_some());
+    }
+
+    #[test]
+    fn shapes() {
+        let cube = SdfShape::Box {
+            center: Point3::new(0.0, 0.0, 0.0),
+            half_extents: Vector3::new(1.0, 1.0, 1.0),
+        };
+        assert_eq!(cube.distance(Point3::new(3.0, 0.0, 0.0)), 2.0);
+        assert_eq!(cube.distance(Point3::new(0.0, 0.5, 0.0)), -0.5);
+        assert!((cube.distance(Point3::new(2.0, 2.0, 0.0)) - Float::sqrt(2.0)).abs() < 1e-6);
+        assert_eq!(cube.area(), 24.0);
+
+        // A box is hit on its face, with the face's normal
+        let hit = SdfParameters {
+            shape: cube.clone(),
+            max_steps: default_max_steps(),
+            epsilon: default_epsilon(),
+        }
+        .init()
+        .hit(&Ray::new(
+            Point3::new(0.2, 0.3, 5.0),
+            Vector3::new(0.0, 0.0, -1.0),
+        ))
+        .unwrap();
+        assert!((hit.distance - 4.0).abs() < 1e-3);
+        assert!((hit.geometric_normal - Vector3::unit_z()).magnitude() < 1e-3);
+
+        // The blend fills in the gap between two spheres, and is never further than either one
+        let sphere = |x| {
+            Box::new(SdfShape::Sphere {
+                center: Point3::new(x, 0.0, 0.0),
+                radius: 1.0,
+            })
+        };
+        let blend = SdfShape::SmoothUnion {
+            a: sphere(-1.2),
+            b: sphere(1.2),
+            k: 1.0,
+        };
+        let between = Point3::new(0.0, 0.3, 0.0);
+        assert!(sphere(-1.2).distance(between) > 0.0);
+        assert!(blend.distance(between) < 0.0);
+        assert_eq!(
+            blend.distance(Point3::new(5.0, 0.0, 0.0)),
+            sphere(1.2).distance(Point3::new(5.0, 0.0, 0.0))
+        );
+        assert!(blend.bounds().max.x >= 2.2 + 0.25);
+    }
+
+    #[test]
+    fn deserialize() {
+        let parameters: SdfParameters = json5::from_str(
+            r#"{
+                "shape": {
+                    "type": "SmoothUnion",
+                    "a": { "type": "Sphere", "center": { "x": 0.0, "y": 0.0, "z": 0.0 }, "radius": 1.0 },
+                    "b": {
+                        "type": "Box",
+                        "center": { "x": 1.0, "y": 0.0, "z": 0.0 },
+                        "half_extents": { "x": 0.5, "y": 0.5, "z": 0.5 }
+                    },
+                    "k": 0.25
+                },
+                "max_steps": 64
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(parameters.max_steps, 64);
+        assert_eq!(parameters.epsilon, default_epsilon());
+        assert!(matches!(parameters.shape, SdfShape::SmoothUnion { k, .. } if k == 0.25));
+    }
+}
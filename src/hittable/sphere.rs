@@ -1,18 +1,21 @@
 //! An implementation of the sphere primitive
 
 use crate::{
-    hittable::{HitRecord, Hittable},
+    hittable::{Aabb, HitRecord, Hittable},
+    math::from_local,
     ray::Ray,
-    types::Float,
+    sampler::Sampler,
+    types::{Float, ETA},
 };
-use cgmath::{prelude::*, Vector3};
+use cgmath::{prelude::*, Point3, Vector2, Vector3};
 use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
 
 /// A sphere primitive
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 pub struct Sphere {
     /// The center of the sphere in spatial coordinates
-    pub center: Vector3<Float>,
+    pub center: Point3<Float>,
 
     /// The radius of the sphere
     pub radius: Float,
@@ -22,33 +25,152 @@ impl Hittable for Sphere {
     fn hit(&self, ray: &Ray) -> Option<HitRecord> {
         let oc = ray.origin - self.center;
         let a = ray.direction.magnitude2();
-        let b = 2.0 * oc.dot(ray.direction);
-        let c = oc.magnitude2() - (self.radius * self.radius);
-        let discriminant = (b * b) - (4.0 * a * c);
+        let half_b = oc.dot(ray.direction);
 
-        // Otherwise we'll get a NaN
+        // The discriminant is usually written as `b^2 - 4ac`, but for a sphere that's small
+        // compared to its distance from the ray's origin, both terms are about the squared
+        // distance, and the radius is lost to rounding. The distance from the center to the line
+        // of the ray gives the same value without the cancellation.
+        let closest = oc - (ray.direction * (half_b / a));
+        let discriminant = a * ((self.radius * self.radius) - closest.magnitude2());
+
+        // Otherwise we'll get a NaN. A discriminant of exactly zero is a ray that's tangent to the
+        // sphere, which grazes it at a single point and is treated as a hit on the front face.
         if discriminant < 0.0 {
             return None;
         }
-        let t = ((-1.0 * b) - discriminant.sqrt()) / (2.0 * a);
+        let sqrt_discriminant = discriminant.sqrt();
 
-        // A collision can't have a negative distance
-        if t < 0.0 {
+        // We want the nearest intersection in front of the ray. If the near intersection is
+        // behind the ray's origin (or is the point the ray is leaving from), then the ray started
+        // inside the sphere and the far intersection is the one it hits.
+        //
+        // The ray enters the sphere at the near intersection and leaves at the far one, which
+        // decides which face was hit. The sign of the dot product between the ray and the normal
+        // would say the same, but for rays that graze the silhouette the two are almost
+        // perpendicular, and rounding can flip the sign, which shows up as speckles along the
+        // edges of the sphere.
+        let t_near = (-half_b - sqrt_discriminant) / a;
+        let t_far = (-half_b + sqrt_discriminant) / a;
+        let (t, front_face) = if t_near >= ETA {
+            (t_near, true)
+        } else if t_far >= ETA {
+            (t_far, false)
+        } else {
+            // A collision can't have a negative distance
             return None;
-        }
-        let p = ray.origin + (ray.direction * t);
+        };
+        let p = ray.at(t);
         let normal = (p - self.center).normalize();
         Some(HitRecord {
             distance: t,
             p,
-            normal,
+            geometric_normal: normal,
+            shading_normal: normal,
+            front_face,
+            uv: sphere_uv(&normal),
         })
     }
+
+    fn area(&self) -> Float {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let radius = Vector3::new(self.radius, self.radius, self.radius);
+        Aabb {
+            min: self.center.to_vec() - radius,
+            max: self.center.to_vec() + radius,
+        }
+    }
+}
+
+/// Compute the surface coordinates of a point on a sphere from its outward normal
+///
+/// `u` is the azimuth around the y axis, starting from -x and going towards +z, and `v` is the
+/// polar angle going from the bottom (-y) to the top (+y) of the sphere, both scaled to [0, 1].
+fn sphere_uv(normal: &Vector3<Float>) -> Vector2<Float> {
+    let theta = (-normal.y).clamp(-1.0, 1.0).acos();
+    let phi = (-normal.z).atan2(normal.x) + PI;
+    Vector2::new(phi / (2.0 * PI), theta / PI)
+}
+
+impl Sphere {
+    /// Sample a point uniformly distributed over the surface of the sphere
+    ///
+    /// Returns the point and the outward facing normal at that point. Since the points are
+    /// uniformly distributed, the pdf with respect to surface area is just `1 / self.area()`.
+    pub fn sample_surface(
+        &self,
+        sampler: &mut dyn Sampler<Float>,
+    ) -> (Point3<Float>, Vector3<Float>) {
+        let rs = sampler.next(2).unwrap();
+
+        // Picking z uniformly in [-1, 1] and the azimuth uniformly in [0, 2pi) yields a uniform
+        // distribution over the sphere, since every band of equal height has equal area
+        let z = 1.0 - (2.0 * rs[0]);
+        let r = (1.0 - (z * z)).max(0.0).sqrt();
+        let phi = 2.0 * PI * rs[1];
+        let normal = Vector3::new(r * phi.cos(), r * phi.sin(), z);
+        (self.center + (normal * self.radius), normal)
+    }
+
+    /// Sample a direction from `from` towards the sphere
+    ///
+    /// This only samples the cone of directions subtended by the sphere as seen from `from`, so
+    /// every sampled direction will hit the sphere. This is the standard importance sampling
+    /// strategy for spherical lights, since sampling a point on the entire surface of the sphere
+    /// wastes samples on the half that faces away from the shading point.
+    ///
+    /// Returns a normalized direction and the pdf of that direction with respect to solid angle.
+    /// If `from` lies inside the sphere, every direction hits the sphere, so this falls back to
+    /// sampling the entire unit sphere of directions.
+    pub fn sample_toward(
+        &self,
+        from: Point3<Float>,
+        sampler: &mut dyn Sampler<Float>,
+    ) -> (Vector3<Float>, Float) {
+        let rs = sampler.next(2).unwrap();
+        let phi = 2.0 * PI * rs[1];
+        let to_center = self.center - from;
+        let distance2 = to_center.magnitude2();
+        let radius2 = self.radius * self.radius;
+
+        if distance2 <= radius2 {
+            let z = 1.0 - (2.0 * rs[0]);
+            let r = (1.0 - (z * z)).max(0.0).sqrt();
+            let direction = Vector3::new(r * phi.cos(), r * phi.sin(), z);
+            return (direction, 1.0 / (4.0 * PI));
+        }
+
+        // The cone is centered around the direction to the center of the sphere, and the maximum
+        // angle is where a ray from `from` is tangent to the sphere
+        let sin2_theta_max = radius2 / distance2;
+        let cos_theta_max = (1.0 - sin2_theta_max).max(0.0).sqrt();
+
+        // For a sphere that's small compared to its distance, `cos_theta_max` rounds to 1, and
+        // `1 - cos_theta_max` would be 0. Since `(1 - cos)(1 + cos) = sin^2`, this gets the same
+        // value without subtracting two numbers that are almost equal.
+        let one_minus_cos_theta_max = sin2_theta_max / (1.0 + cos_theta_max);
+        let one_minus_cos_theta = rs[0] * one_minus_cos_theta_max;
+        let cos_theta = 1.0 - one_minus_cos_theta;
+        let sin_theta = (one_minus_cos_theta * (2.0 - one_minus_cos_theta))
+            .max(0.0)
+            .sqrt();
+
+        // The cone is sampled around the +z axis and then rotated into place around the direction
+        // to the center of the sphere
+        let local = Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+        let direction = from_local(&local, &to_center.normalize());
+        let pdf = 1.0 / (2.0 * PI * one_minus_cos_theta_max);
+        (direction, pdf)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sampler::Random;
 
     /// Represents a test case for the sphere collision, with the input value (the ray), and the
     /// expected result, an optional `HitRecord`.
@@ -69,33 +191,33 @@ mod tests {
         let test_cases: Vec<TestCase> = vec![
             TestCase {
                 ray: Ray {
-                    origin: Vector3::new(2.0, 0.0, 0.0),
+                    origin: Point3::new(2.0, 0.0, 0.0),
                     direction: Vector3::new(1.0, 0.0, 0.0),
                 },
                 sphere: Sphere {
-                    center: Vector3::new(0.0, 0.0, 0.0),
+                    center: Point3::new(0.0, 0.0, 0.0),
                     radius: 1.0,
                 },
                 expected: None,
             },
             TestCase {
                 ray: Ray {
-                    origin: Vector3::new(0.0, 2.0, 0.0),
+                    origin: Point3::new(0.0, 2.0, 0.0),
                     direction: Vector3::new(0.0, 1.0, 0.0),
                 },
                 sphere: Sphere {
-                    center: Vector3::new(0.0, 0.0, 0.0),
+                    center: Point3::new(0.0, 0.0, 0.0),
                     radius: 0.2,
                 },
                 expected: None,
             },
             TestCase {
                 ray: Ray {
-                    origin: Vector3::new(0.0, 0.0, 2.0),
+                    origin: Point3::new(0.0, 0.0, 2.0),
                     direction: Vector3::new(0.0, 0.0, 1.0),
                 },
                 sphere: Sphere {
-                    center: Vector3::new(0.0, 0.0, 0.0),
+                    center: Point3::new(0.0, 0.0, 0.0),
                     radius: 0.7,
                 },
                 expected: None,
@@ -115,47 +237,56 @@ mod tests {
         let test_cases: Vec<TestCase> = vec![
             TestCase {
                 ray: Ray {
-                    origin: Vector3::new(-1.0, -1.0, 0.0),
+                    origin: Point3::new(-1.0, -1.0, 0.0),
                     direction: Vector3::new(0.0, 1.0, 0.0),
                 },
                 sphere: Sphere {
-                    center: Vector3::new(0.0, 0.0, 0.0),
+                    center: Point3::new(0.0, 0.0, 0.0),
                     radius: 1.0,
                 },
                 expected: Some(HitRecord {
-                    p: Vector3::new(-1.0, 0.0, 0.0),
-                    normal: Vector3::new(-1.0, 0.0, 0.0),
+                    p: Point3::new(-1.0, 0.0, 0.0),
+                    geometric_normal: Vector3::new(-1.0, 0.0, 0.0),
+                    shading_normal: Vector3::new(-1.0, 0.0, 0.0),
                     distance: 1.0,
+                    front_face: true,
+                    uv: sphere_uv(&Vector3::new(-1.0, 0.0, 0.0)),
                 }),
             },
             TestCase {
                 ray: Ray {
-                    origin: Vector3::new(-1.0, -1.0, 0.0),
+                    origin: Point3::new(-1.0, -1.0, 0.0),
                     direction: Vector3::new(1.0, 0.0, 0.0),
                 },
                 sphere: Sphere {
-                    center: Vector3::new(0.0, 0.0, 0.0),
+                    center: Point3::new(0.0, 0.0, 0.0),
                     radius: 1.0,
                 },
                 expected: Some(HitRecord {
-                    p: Vector3::new(0.0, -1.0, 0.0),
-                    normal: Vector3::new(0.0, -1.0, 0.0),
+                    p: Point3::new(0.0, -1.0, 0.0),
+                    geometric_normal: Vector3::new(0.0, -1.0, 0.0),
+                    shading_normal: Vector3::new(0.0, -1.0, 0.0),
                     distance: 1.0,
+                    front_face: true,
+                    uv: sphere_uv(&Vector3::new(0.0, -1.0, 0.0)),
                 }),
             },
             TestCase {
                 ray: Ray {
-                    origin: Vector3::new(0.0, 1.0, -1.0),
+                    origin: Point3::new(0.0, 1.0, -1.0),
                     direction: Vector3::new(0.0, 0.0, 1.0),
                 },
                 sphere: Sphere {
-                    center: Vector3::new(0.0, 0.0, 0.0),
+                    center: Point3::new(0.0, 0.0, 0.0),
                     radius: 1.0,
                 },
                 expected: Some(HitRecord {
-                    p: Vector3::new(0.0, 1.0, 0.0),
-                    normal: Vector3::new(0.0, 1.0, 0.0),
+                    p: Point3::new(0.0, 1.0, 0.0),
+                    geometric_normal: Vector3::new(0.0, 1.0, 0.0),
+                    shading_normal: Vector3::new(0.0, 1.0, 0.0),
                     distance: 1.0,
+                    front_face: true,
+                    uv: sphere_uv(&Vector3::new(0.0, 1.0, 0.0)),
                 }),
             },
         ];
@@ -167,6 +298,46 @@ mod tests {
         }
     }
 
+    /// Rays that graze the silhouette of a sphere hit its front face, with a finite normal, even
+    /// when rounding makes the normal point slightly along the ray
+    #[test]
+    fn tangent_intersections() {
+        let sphere = Sphere {
+            center: Point3::new(0.0, 0.0, 0.0),
+            radius: 1.0,
+        };
+        // This ray is exactly tangent, so the discriminant is exactly zero
+        let ray = Ray {
+            origin: Point3::new(-2.0, 1.0, 0.0),
+            direction: Vector3::new(1.0, 0.0, 0.0),
+        };
+        let hit = sphere.hit(&ray).unwrap();
+        assert_eq!(hit.p, Point3::new(0.0, 1.0, 0.0));
+        assert_eq!(hit.geometric_normal, Vector3::new(0.0, 1.0, 0.0));
+        assert!(hit.front_face);
+
+        // Rays from all around the sphere that just barely graze it
+        let mut hits = 0;
+
+        for i in 0..10_000 {
+            let angle = i as Float * 0.001;
+            let outward = Vector3::new(angle.cos(), angle.sin(), 0.3).normalize();
+            let along = Vector3::new(-angle.sin(), angle.cos(), 0.0);
+            let ray = Ray {
+                origin: Point3::from_vec(outward * (1.0 - 1e-7) - along * 3.0),
+                direction: along * 0.7,
+            };
+
+            if let Some(hit) = sphere.hit(&ray) {
+                hits += 1;
+                assert!(hit.distance.is_finite() && hit.p.to_vec().magnitude().is_finite());
+                assert!((hit.geometric_normal.magnitude() - 1.0).abs() < 1e-5);
+                assert!(hit.front_face, "{}: {:?}", i, hit);
+            }
+        }
+        assert!(hits > 0);
+    }
+
     // Testing cases where the ray intersects the sphere at two points, which should yield the
     // point of intersection that's closest to the ray's origin
     #[test]
@@ -174,62 +345,74 @@ mod tests {
         let test_cases: Vec<TestCase> = vec![
             TestCase {
                 ray: Ray {
-                    origin: Vector3::new(0.0, -2.0, 0.0),
+                    origin: Point3::new(0.0, -2.0, 0.0),
                     direction: Vector3::new(0.0, 1.0, 0.0),
                 },
                 sphere: Sphere {
-                    center: Vector3::new(0.0, 0.0, 0.0),
+                    center: Point3::new(0.0, 0.0, 0.0),
                     radius: 1.0,
                 },
                 expected: Some(HitRecord {
-                    p: Vector3::new(0.0, -1.0, 0.0),
-                    normal: Vector3::new(0.0, -1.0, 0.0),
+                    p: Point3::new(0.0, -1.0, 0.0),
+                    geometric_normal: Vector3::new(0.0, -1.0, 0.0),
+                    shading_normal: Vector3::new(0.0, -1.0, 0.0),
                     distance: 1.0,
+                    front_face: true,
+                    uv: sphere_uv(&Vector3::new(0.0, -1.0, 0.0)),
                 }),
             },
             TestCase {
                 ray: Ray {
-                    origin: Vector3::new(0.0, 2.0, 0.0),
+                    origin: Point3::new(0.0, 2.0, 0.0),
                     direction: Vector3::new(0.0, -1.0, 0.0),
                 },
                 sphere: Sphere {
-                    center: Vector3::new(0.0, 0.0, 0.0),
+                    center: Point3::new(0.0, 0.0, 0.0),
                     radius: 1.0,
                 },
                 expected: Some(HitRecord {
-                    p: Vector3::new(0.0, 1.0, 0.0),
-                    normal: Vector3::new(0.0, 1.0, 0.0),
+                    p: Point3::new(0.0, 1.0, 0.0),
+                    geometric_normal: Vector3::new(0.0, 1.0, 0.0),
+                    shading_normal: Vector3::new(0.0, 1.0, 0.0),
                     distance: 1.0,
+                    front_face: true,
+                    uv: sphere_uv(&Vector3::new(0.0, 1.0, 0.0)),
                 }),
             },
             TestCase {
                 ray: Ray {
-                    origin: Vector3::new(0.0, 0.0, 2.0),
+                    origin: Point3::new(0.0, 0.0, 2.0),
                     direction: Vector3::new(0.0, 0.0, -1.0),
                 },
                 sphere: Sphere {
-                    center: Vector3::new(0.0, 0.0, 0.0),
+                    center: Point3::new(0.0, 0.0, 0.0),
                     radius: 1.0,
                 },
                 expected: Some(HitRecord {
-                    p: Vector3::new(0.0, 0.0, 1.0),
-                    normal: Vector3::new(0.0, 0.0, 1.0),
+                    p: Point3::new(0.0, 0.0, 1.0),
+                    geometric_normal: Vector3::new(0.0, 0.0, 1.0),
+                    shading_normal: Vector3::new(0.0, 0.0, 1.0),
                     distance: 1.0,
+                    front_face: true,
+                    uv: sphere_uv(&Vector3::new(0.0, 0.0, 1.0)),
                 }),
             },
             TestCase {
                 ray: Ray {
-                    origin: Vector3::new(-2.0, 0.0, 0.0),
+                    origin: Point3::new(-2.0, 0.0, 0.0),
                     direction: Vector3::new(1.0, 0.0, 0.0),
                 },
                 sphere: Sphere {
-                    center: Vector3::new(0.0, 0.0, 0.0),
+                    center: Point3::new(0.0, 0.0, 0.0),
                     radius: 1.0,
                 },
                 expected: Some(HitRecord {
-                    p: Vector3::new(-1.0, 0.0, 0.0),
-                    normal: Vector3::new(-1.0, 0.0, 0.0),
+                    p: Point3::new(-1.0, 0.0, 0.0),
+                    geometric_normal: Vector3::new(-1.0, 0.0, 0.0),
+                    shading_normal: Vector3::new(-1.0, 0.0, 0.0),
                     distance: 1.0,
+                    front_face: true,
+                    uv: sphere_uv(&Vector3::new(-1.0, 0.0, 0.0)),
                 }),
             },
         ];
@@ -240,4 +423,207 @@ mod tests {
             assert_eq!(test_case.expected, result);
         }
     }
+
+    // A ray that starts inside the sphere should hit the far side of the sphere from behind
+    #[test]
+    fn inside_intersection() {
+        let sphere = Sphere {
+            center: Point3::new(0.0, 0.0, 0.0),
+            radius: 1.0,
+        };
+        let ray = Ray {
+            origin: Point3::new(0.0, 0.0, 0.0),
+            direction: Vector3::new(0.0, 1.0, 0.0),
+        };
+        let expected = Some(HitRecord {
+            p: Point3::new(0.0, 1.0, 0.0),
+            geometric_normal: Vector3::new(0.0, 1.0, 0.0),
+            shading_normal: Vector3::new(0.0, 1.0, 0.0),
+            distance: 1.0,
+            front_face: false,
+            uv: sphere_uv(&Vector3::new(0.0, 1.0, 0.0)),
+        });
+        assert_eq!(expected, sphere.hit(&ray));
+    }
+
+    /// Every direction sampled towards the sphere should actually hit the sphere
+    #[test]
+    fn sample_toward_hits_sphere() {
+        let mut sampler = Random::default();
+        let sphere = Sphere {
+            center: Point3::new(0.0, 3.0, -2.0),
+            radius: 0.5,
+        };
+        let from = Point3::new(0.0, 0.0, 0.0);
+        // Samples at the edge of the cone graze the sphere, so we test against a slightly larger
+        // sphere to keep round-off error from turning them into misses
+        let padded = Sphere {
+            radius: sphere.radius * 1.001,
+            ..sphere
+        };
+
+        for _ in 0..1000 {
+            let (direction, pdf) = sphere.sample_toward(from, &mut sampler);
+            assert!((direction.magnitude() - 1.0).abs() < 1e-4);
+            assert!(pdf > 0.0);
+            let ray = Ray {
+                origin: from,
+                direction,
+            };
+            assert!(padded.hit(&ray).is_some());
+        }
+    }
+
+    /// The pdf of `sample_toward` should integrate to 1 over the cone subtended by the sphere
+    ///
+    /// We integrate numerically over a uniform, deterministic set of directions on the unit sphere
+    /// (a Fibonacci lattice), counting the pdf for every direction that falls inside the cone.
+    #[test]
+    fn sample_toward_pdf_integrates_to_one() {
+        let mut sampler = Random::default();
+        let from = Point3::new(0.0, 0.0, 0.0);
+        let spheres = vec![
+            Sphere {
+                center: Point3::new(0.0, 0.0, -2.0),
+                radius: 1.0,
+            },
+            Sphere {
+                center: Point3::new(3.0, 1.0, 1.0),
+                radius: 0.5,
+            },
+        ];
+        let n = 200_000;
+        let golden_angle = PI * (3.0 - (5.0 as Float).sqrt());
+
+        for sphere in spheres {
+            let (_, pdf) = sphere.sample_toward(from, &mut sampler);
+            let to_center = (sphere.center - from).normalize();
+            let distance2 = (sphere.center - from).magnitude2();
+            let cos_theta_max = (1.0 - (sphere.radius * sphere.radius / distance2)).sqrt();
+
+            let mut integral = 0.0;
+            for i in 0..n {
+                let z = 1.0 - (2.0 * (i as Float + 0.5) / n as Float);
+                let r = (1.0 - (z * z)).sqrt();
+                let phi = golden_angle * i as Float;
+                let direction = Vector3::new(r * phi.cos(), r * phi.sin(), z);
+
+                if direction.dot(to_center) >= cos_theta_max {
+                    integral += pdf * 4.0 * PI / n as Float;
+                }
+            }
+            assert!((integral - 1.0).abs() < 0.01, "integral was {}", integral);
+        }
+    }
+
+    /// A sphere that's tiny compared to its distance should still be sampled with a finite pdf
+    ///
+    /// In single precision, `1 - cos_theta_max` rounds to 0 for these spheres, which used to make
+    /// the pdf infinite, so the light contributed nothing.
+    #[test]
+    fn sample_toward_tiny_distant_sphere() {
+        let mut sampler = Random::default();
+        let from = Point3::new(0.0, 0.0, 0.0);
+
+        for &(radius, distance) in &[(0.001, 10.0), (0.01, 100.0), (0.01, 1000.0)] {
+            let sphere = Sphere {
+                center: Point3::new(0.0, distance, 0.0),
+                radius,
+            };
+            // Round-off error in the direction is large compared to such a narrow cone, so grazing
+            // samples are checked against a slightly larger sphere
+            let padded = Sphere {
+                radius: sphere.radius * 1.01,
+                ..sphere
+            };
+            // The solid angle of the cone, computed in double precision
+            let sin2_theta_max = (f64::from(radius) / f64::from(distance)).powi(2);
+            let solid_angle = 2.0 * std::f64::consts::PI * (1.0 - (1.0 - sin2_theta_max).sqrt());
+
+            for _ in 0..100 {
+                let (direction, pdf) = sphere.sample_toward(from, &mut sampler);
+                assert!(pdf.is_finite());
+                assert!(
+                    ((f64::from(pdf) * solid_angle) - 1.0).abs() < 1e-3,
+                    "pdf was {} for a solid angle of {}",
+                    pdf,
+                    solid_angle
+                );
+                let ray = Ray {
+                    origin: from,
+                    direction,
+                };
+                assert!(padded.hit(&ray).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn area() {
+        let sphere = Sphere {
+            center: Point3::new(1.0, 2.0, 3.0),
+            radius: 2.0,
+        };
+        assert!((sphere.area() - (16.0 * PI)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn sample_surface_is_uniform() {
+        let mut sampler = Random::default();
+        let sphere = Sphere {
+            center: Point3::new(1.0, -2.0, 0.5),
+            radius: 3.0,
+        };
+
+        // Split the sphere into equal area buckets: bands of equal height along the z axis, each
+        // split into sectors of equal angle
+        let bands = 4;
+        let sectors = 4;
+        let buckets = bands * sectors;
+        let n = 16_000;
+        let mut counts = vec![0; buckets];
+
+        for _ in 0..n {
+            let (point, normal) = sphere.sample_surface(&mut sampler);
+            let offset = point - sphere.center;
+            assert!((offset.magnitude() - sphere.radius).abs() < 1e-4);
+            assert!((normal - (offset / sphere.radius)).magnitude() < 1e-4);
+
+            let band = (((normal.z + 1.0) / 2.0) * bands as Float) as usize;
+            let phi = normal.y.atan2(normal.x) + PI;
+            let sector = ((phi / (2.0 * PI)) * sectors as Float) as usize;
+            counts[band.min(bands - 1) * sectors + sector.min(sectors - 1)] += 1;
+        }
+
+        let expected = n as Float / buckets as Float;
+        let chi_squared: Float = counts
+            .iter()
+            .map(|&count| {
+                let diff = count as Float - expected;
+                diff * diff / expected
+            })
+            .sum();
+
+        // The critical value for 15 degrees of freedom at p = 0.0001 is about 42.6
+        assert!(chi_squared < 42.6, "chi squared was {}", chi_squared);
+    }
+
+    #[test]
+    fn uv_coordinates() {
+        let cases = [
+            (Vector3::new(0.0, -1.0, 0.0), 0.0),
+            (Vector3::new(0.0, 1.0, 0.0), 1.0),
+            (Vector3::new(1.0, 0.0, 0.0), 0.5),
+        ];
+
+        for (normal, v) in cases {
+            assert!((sphere_uv(&normal).y - v).abs() < 1e-6);
+        }
+        // The azimuth increases from -x towards +z
+        let u = |normal| sphere_uv(&normal).x;
+        assert!((u(Vector3::new(-1.0, 0.0, 0.0)) - 0.0).abs() < 1e-6);
+        assert!((u(Vector3::new(0.0, 0.0, 1.0)) - 0.25).abs() < 1e-6);
+        assert!((u(Vector3::new(1.0, 0.0, 0.0)) - 0.5).abs() < 1e-6);
+        assert!((u(Vector3::new(0.0, 0.0, -1.0)) - 0.75).abs() < 1e-6);
+    }
 }
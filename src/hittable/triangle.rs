@@ -4,21 +4,24 @@
 //! rendering and modeling, as most OBJ files are defined in terms of triangles.
 
 use crate::{
-    hittable::{HitRecord, Hittable},
+    hittable::{Aabb, HitRecord, Hittable},
+    math::safe_normalize,
     ray::Ray,
-    types::{Float, ETA},
+    types::{string_enum, Float, ETA},
 };
-use cgmath::{InnerSpace, Vector3};
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector2, Vector3};
 use serde::{Deserialize, Serialize};
 
-/// The "handedness" of the coordinate system used to define the triangle
-///
-/// The way we compute the normal of a triangle depends on the direction of the points used to
-/// define the triangle.
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-pub enum TriangleHandedness {
-    Clockwise,
-    CounterClockwise,
+string_enum! {
+    /// The "handedness" of the coordinate system used to define the triangle
+    ///
+    /// The way we compute the normal of a triangle depends on the direction of the points used to
+    /// define the triangle.
+    #[derive(Debug, Clone, Copy)]
+    pub enum TriangleHandedness {
+        Clockwise,
+        CounterClockwise,
+    }
 }
 
 /// A geometric triangle
@@ -39,6 +42,14 @@ pub struct TriangleParameters {
     /// The convention we use is to use the counterclockwise points, so serde will deserialize this
     #[serde(default = "default_handedness")]
     pub handedness: TriangleHandedness,
+
+    /// Whether the triangle can be hit from behind
+    ///
+    /// Triangles are single-sided by default, so rays that hit the back of the triangle pass
+    /// straight through it, which is cheaper to compute. Double-sided triangles are useful for
+    /// walls that should be visible from both sides, like the walls of an enclosed room.
+    #[serde(default)]
+    pub double_sided: bool,
 }
 
 /// A helper method for serde to infer the default handedness of a triangle
@@ -56,15 +67,14 @@ impl Default for TriangleParameters {
     ///
     /// Proper usage for this method:
     ///
-    /// ```
-    /// # use super::*;
+    /// ```ignore
     /// let triangle = TriangleParameters {
     ///     vertices: [
-    ///         Vector3::new(1, 2, 1),
-    ///         Vector3::new(2, 1, 1),
-    ///         Vector3::new(4, 5, 2),
+    ///         Vector3::new(1.0, 2.0, 1.0),
+    ///         Vector3::new(2.0, 1.0, 1.0),
+    ///         Vector3::new(4.0, 5.0, 2.0),
     ///     ],
-    ///     ..default(),
+    ///     ..Default::default()
     /// };
     /// ```
     fn default() -> Self {
@@ -72,6 +82,7 @@ impl Default for TriangleParameters {
         TriangleParameters {
             vertices: [zeroes, zeroes, zeroes],
             handedness: TriangleHandedness::CounterClockwise,
+            double_sided: false,
         }
     }
 }
@@ -80,18 +91,22 @@ impl TriangleParameters {
     /// Initialize a `Triangle` from its parameters
     ///
     /// This will compute the normal vector by getting two sides of the triangle and computing the
-    /// cross product of the two vectors.
+    /// cross product of the two vectors. The handedness only determines the direction of the
+    /// normal, the edges are always computed the same way so that the barycentric coordinates of a
+    /// hit map to the same vertices regardless of the handedness.
     pub fn init(self) -> Triangle {
-        let a = self.vertices[2] - self.vertices[0];
-        let b = self.vertices[1] - self.vertices[0];
-        let (normal, edges) = match self.handedness {
-            TriangleHandedness::Clockwise => (b.cross(a).normalize(), [b, a]),
-            TriangleHandedness::CounterClockwise => (a.cross(b).normalize(), [a, b]),
+        let e1 = self.vertices[1] - self.vertices[0];
+        let e2 = self.vertices[2] - self.vertices[0];
+        let normal = match self.handedness {
+            TriangleHandedness::Clockwise => e1.cross(e2).normalize(),
+            TriangleHandedness::CounterClockwise => e2.cross(e1).normalize(),
         };
         Triangle {
             vertices: self.vertices,
-            edges,
+            edges: [e1, e2],
             normal,
+            double_sided: self.double_sided,
+            vertex_normals: None,
         }
     }
 }
@@ -109,7 +124,7 @@ pub struct Triangle {
     /// These are the "real-world" coordinates of the vertices
     pub vertices: [Vector3<Float>; 3],
 
-    /// The edges of the triangle
+    /// The edges of the triangle from vertex 0 to vertices 1 and 2, in that order
     ///
     /// This is precomputed for the intersection calculation, so we don't have to repeat it for
     /// every collision.
@@ -120,6 +135,17 @@ pub struct Triangle {
     /// Any collision of the triangle will yield the same normal, since the triangle lies on a
     /// normal plane. We can precompute this and avoid wasting CPU cycles on every collision.
     pub normal: Vector3<Float>,
+
+    /// Whether rays that hit the back of the triangle count as intersections
+    pub double_sided: bool,
+
+    /// The normals at each vertex, in the same order as `vertices`
+    ///
+    /// If these are set, the normal of a collision is interpolated between the vertex normals,
+    /// which makes a mesh look smooth rather than faceted. Otherwise every collision uses the
+    /// normal of the supporting plane.
+    #[serde(default)]
+    pub vertex_normals: Option<[Vector3<Float>; 3]>,
 }
 
 impl Hittable for Triangle {
@@ -127,61 +153,109 @@ impl Hittable for Triangle {
     ///
     /// This is an implementation of the [Moller-Trumbore algorithm]
     /// (http://webserver2.tecgraf.puc-rio.br/~mgattass/cg/trbRR/Fast%20MinimumStorage%20RayTriangle%20Intersection.pdf).
+    ///
+    /// The barycentric coordinates of the hit are `(u, v, w)`, which are the weights of vertices 0,
+    /// 1, and 2 respectively, so the point that was hit is `u * v0 + v * v1 + w * v2`.
     fn hit(&self, ray: &Ray) -> Option<HitRecord> {
+        // The ray hits the front of the triangle if it's travelling against the normal. Rays that
+        // hit the back of the triangle are culled unless the triangle is double-sided.
+        let front_face = ray.direction.dot(self.normal) < 0.0;
+
+        if !front_face && !self.double_sided {
+            return None;
+        }
+
         // begin calculating the determinant
-        // TODO remove the `dbg` macro calls
-        let p = dbg!(ray.direction.cross(self.edges[1]));
-        let determinant = dbg!(self.edges[0].dot(p));
-
-        // This means that the ray and the plane that the triangle lies on are parallel. We exit
-        // early because we know that there's no possible intersection, and also to avoid a
-        // division by zero error.
-        if determinant < ETA {
-            eprintln!("determinant is less than `eta`, bailing");
+        let p = ray.direction.cross(self.edges[1]);
+        let determinant = self.edges[0].dot(p);
+
+        // A determinant near zero means that the ray and the plane that the triangle lies on are
+        // parallel. We exit early because we know that there's no possible intersection, and also
+        // to avoid a division by zero error.
+        if determinant.abs() < ETA {
             return None;
         }
+        let inverse_determinant = 1.0 / determinant;
 
         // Distance from vertex[0] to the ray's origin
-        let t = ray.origin - self.vertices[0];
+        let t = ray.origin - Point3::from_vec(self.vertices[0]);
 
-        // Get u, the first barycentric coordinate
-        let u = t.dot(p);
+        // The weight of the vertex at the end of the first edge (vertex 1)
+        let v = t.dot(p) * inverse_determinant;
 
-        // Short circuit if u isn't within the bounds of the triangle
-        if u < 0.0 || u > determinant {
+        // Short circuit if v isn't within the bounds of the triangle
+        if !(0.0..=1.0).contains(&v) {
             return None;
         }
         let q = t.cross(self.edges[0]);
-        let v = ray.direction.dot(q);
+        // The weight of the vertex at the end of the second edge (vertex 2)
+        let w = ray.direction.dot(q) * inverse_determinant;
 
         // Check it the barycentric coordinates are outside of the bounds of the triangle
-        if v < 0.0 || u + v > determinant {
+        if w < 0.0 || v + w > 1.0 {
             return None;
         }
 
         // Now we know the ray intersects the triangle, and we can calculate `t`,
-        let inverse_determinant = 1.0 / determinant;
         let distance = self.edges[1].dot(q) * inverse_determinant;
-
-        // Scale the barycentric coordinates
-        let u = u * inverse_determinant;
-        let v = v * inverse_determinant;
-        let w = 1.0 - u - v;
-
-        // Convert the barycentric coordinates to a real world coordinate
-        let intersection_point =
-            (self.vertices[0] * u) + (self.vertices[1] * v) + (self.vertices[2] * w);
+        let u = 1.0 - v - w;
+        let intersection_point = ray.at(distance);
+        // The interpolated normal is only used for shading. It's kept on the same side of the
+        // triangle as the true normal, in case the vertex normals disagree with the winding. On a
+        // broken mesh, opposing vertex normals can cancel out, and then we use the true normal.
+        let shading_normal = match self.vertex_normals {
+            Some([n0, n1, n2]) => {
+                let normal = safe_normalize(&(n0 * u + n1 * v + n2 * w)).unwrap_or(self.normal);
+
+                if normal.dot(self.normal) < 0.0 {
+                    -normal
+                } else {
+                    normal
+                }
+            }
+            None => self.normal,
+        };
+        // The normals of a double-sided triangle are flipped to face the ray when it's hit from
+        // behind, so materials shade both sides the same way
+        let flip = if front_face { 1.0 } else { -1.0 };
         Some(HitRecord {
             p: intersection_point,
-            normal: self.normal,
+            geometric_normal: self.normal * flip,
+            shading_normal: shading_normal * flip,
             distance,
+            front_face,
+            // Triangles don't have texture coordinates of their own, so we use the barycentric
+            // weights of vertices 0 and 1
+            uv: Vector2::new(u, v),
         })
     }
+
+    fn area(&self) -> Float {
+        // The cross product of two edges spans a parallelogram, which is twice the triangle
+        self.edges[0].cross(self.edges[1]).magnitude() / 2.0
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let [v0, v1, v2] = self.vertices;
+        Aabb {
+            min: Vector3::new(
+                v0.x.min(v1.x).min(v2.x),
+                v0.y.min(v1.y).min(v2.y),
+                v0.z.min(v1.z).min(v2.z),
+            ),
+            max: Vector3::new(
+                v0.x.max(v1.x).max(v2.x),
+                v0.y.max(v1.y).max(v2.y),
+                v0.z.max(v1.z).max(v2.z),
+            ),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::approx_eq_vec;
 
     struct TestCase {
         pub triangle: Triangle,
@@ -210,11 +284,14 @@ mod tests {
         assert!((p_a.y - p_b.y).abs() < ETA);
         assert!((p_a.z - p_b.z).abs() < ETA);
 
-        let n_a = a.normal;
-        let n_b = b.normal;
-        assert!((n_a.x - n_b.x).abs() < ETA);
-        assert!((n_a.y - n_b.y).abs() < ETA);
-        assert!((n_a.z - n_b.z).abs() < ETA);
+        for (n_a, n_b) in &[
+            (a.geometric_normal, b.geometric_normal),
+            (a.shading_normal, b.shading_normal),
+        ] {
+            assert!((n_a.x - n_b.x).abs() < ETA);
+            assert!((n_a.y - n_b.y).abs() < ETA);
+            assert!((n_a.z - n_b.z).abs() < ETA);
+        }
 
         assert!((a.distance - b.distance).abs() < ETA);
     }
@@ -232,10 +309,11 @@ mod tests {
                         Vector3::new(0.0, 1.0, 0.0),
                     ],
                     handedness: TriangleHandedness::CounterClockwise,
+                    double_sided: false,
                 }
                 .init(),
                 ray: Ray {
-                    origin: Vector3::new(-1.0, 0.5, 0.0),
+                    origin: Point3::new(-1.0, 0.5, 0.0),
                     direction: Vector3::new(1.0, 0.0, 0.0),
                 },
                 expected: None,
@@ -248,10 +326,11 @@ mod tests {
                         Vector3::new(0.0, 1.0, 0.0),
                     ],
                     handedness: TriangleHandedness::CounterClockwise,
+                    double_sided: false,
                 }
                 .init(),
                 ray: Ray {
-                    origin: Vector3::new(2.0, 0.5, 0.0),
+                    origin: Point3::new(2.0, 0.5, 0.0),
                     direction: Vector3::new(-1.0, 0.0, 0.0),
                 },
                 expected: None,
@@ -264,10 +343,11 @@ mod tests {
                         Vector3::new(0.0, 1.0, 0.0),
                     ],
                     handedness: TriangleHandedness::CounterClockwise,
+                    double_sided: false,
                 }
                 .init(),
                 ray: Ray {
-                    origin: Vector3::new(0.5, 5.0, 0.0),
+                    origin: Point3::new(0.5, 5.0, 0.0),
                     direction: Vector3::new(0.0, -1.0, 0.0),
                 },
                 expected: None,
@@ -292,10 +372,11 @@ mod tests {
                         Vector3::new(0.0, 1.0, 0.0),
                     ],
                     handedness: TriangleHandedness::CounterClockwise,
+                    double_sided: false,
                 }
                 .init(),
                 ray: Ray {
-                    origin: Vector3::new(2.0, 2.5, 0.0),
+                    origin: Point3::new(2.0, 2.5, 0.0),
                     direction: Vector3::new(1.0, 0.0, 0.0),
                 },
                 expected: None,
@@ -308,10 +389,11 @@ mod tests {
                         Vector3::new(0.0, 1.0, 0.0),
                     ],
                     handedness: TriangleHandedness::CounterClockwise,
+                    double_sided: false,
                 }
                 .init(),
                 ray: Ray {
-                    origin: Vector3::new(2.0, 2.5, 100.0),
+                    origin: Point3::new(2.0, 2.5, 100.0),
                     direction: Vector3::new(1.0, 0.0, 1.0),
                 },
                 expected: None,
@@ -337,10 +419,11 @@ mod tests {
                         Vector3::new(3.0, 0.0, -1.0),
                     ],
                     handedness: TriangleHandedness::CounterClockwise,
+                    double_sided: false,
                 }
                 .init(),
                 ray: Ray {
-                    origin: Vector3::new(1.0, 1.0, -2.0),
+                    origin: Point3::new(1.0, 1.0, -2.0),
                     direction: Vector3::new(0.0, 0.0, 1.0),
                 },
                 expected: None,
@@ -353,10 +436,11 @@ mod tests {
                         Vector3::new(1.0, 0.0, -1.0),
                     ],
                     handedness: TriangleHandedness::CounterClockwise,
+                    double_sided: false,
                 }
                 .init(),
                 ray: Ray {
-                    origin: Vector3::new(0.0, 0.5, -2.0),
+                    origin: Point3::new(0.0, 0.5, -2.0),
                     direction: Vector3::new(0.0, 0.0, 1.0),
                 },
                 expected: None,
@@ -381,16 +465,20 @@ mod tests {
                         Vector3::new(3.0, 0.0, -1.0),
                     ],
                     handedness: TriangleHandedness::CounterClockwise,
+                    double_sided: false,
                 }
                 .init(),
                 ray: Ray {
-                    origin: Vector3::new(1.0, 1.0, 0.0),
+                    origin: Point3::new(1.0, 1.0, 0.0),
                     direction: Vector3::new(0.0, 0.0, -1.0),
                 },
                 expected: Some(HitRecord {
-                    p: Vector3::new(1.0, 1.0, -1.0),
+                    p: Point3::new(1.0, 1.0, -1.0),
                     distance: 1.0,
-                    normal: Vector3::new(0.0, 0.0, 1.0),
+                    front_face: true,
+                    uv: Vector2::new(1.0 / 3.0, 1.0 / 3.0),
+                    geometric_normal: Vector3::new(0.0, 0.0, 1.0),
+                    shading_normal: Vector3::new(0.0, 0.0, 1.0),
                 }),
             },
             TestCase {
@@ -401,16 +489,20 @@ mod tests {
                         Vector3::new(1.0, 0.0, -1.0),
                     ],
                     handedness: TriangleHandedness::CounterClockwise,
+                    double_sided: false,
                 }
                 .init(),
                 ray: Ray {
-                    origin: Vector3::new(0.0, 0.5, 0.0),
+                    origin: Point3::new(0.0, 0.5, 0.0),
                     direction: Vector3::new(0.0, 0.0, -1.0),
                 },
                 expected: Some(HitRecord {
-                    p: Vector3::new(0.0, 0.5, -1.0),
+                    p: Point3::new(0.0, 0.5, -1.0),
                     distance: 1.0,
-                    normal: Vector3::new(0.0, 0.0, 1.0),
+                    front_face: true,
+                    uv: Vector2::new(0.375, 0.25),
+                    geometric_normal: Vector3::new(0.0, 0.0, 1.0),
+                    shading_normal: Vector3::new(0.0, 0.0, 1.0),
                 }),
             },
         ];
@@ -420,4 +512,157 @@ mod tests {
             fuzzy_eq(test_case.expected, result);
         }
     }
+
+    /// Interpolating per-vertex values with the barycentric coordinates of a hit reproduces any
+    /// linear function of the position, for either handedness
+    #[test]
+    fn barycentric_interpolation() {
+        let vertices = [
+            Vector3::new(-1.0, -1.0, -2.0),
+            Vector3::new(2.0, 0.0, -2.5),
+            Vector3::new(0.0, 3.0, -1.5),
+        ];
+        let f = |p: Vector3<Float>| 2.0 * p.x + 3.0 * p.y - p.z + 1.0;
+        let values = [f(vertices[0]), f(vertices[1]), f(vertices[2])];
+        let targets = [
+            vertices[0],
+            vertices[1],
+            vertices[2],
+            (vertices[0] + vertices[1] + vertices[2]) / 3.0,
+            vertices[0] * 0.5 + vertices[1] * 0.3 + vertices[2] * 0.2,
+        ];
+
+        for handedness in [
+            TriangleHandedness::Clockwise,
+            TriangleHandedness::CounterClockwise,
+        ] {
+            let triangle = TriangleParameters {
+                vertices,
+                handedness,
+                double_sided: true,
+            }
+            .init();
+
+            for &target in &targets {
+                // Pull the target slightly towards the centroid so rays through the vertices
+                // don't graze the edges
+                let centroid = (vertices[0] + vertices[1] + vertices[2]) / 3.0;
+                let target = target * 0.999 + centroid * 0.001;
+                let origin = Point3::new(0.5, 0.5, 5.0);
+                let ray = Ray::new(origin, (Point3::from_vec(target) - origin).normalize());
+                let hit = triangle.hit(&ray).unwrap();
+                let (u, v) = (hit.uv.x, hit.uv.y);
+                let w = 1.0 - u - v;
+
+                let position = vertices[0] * u + vertices[1] * v + vertices[2] * w;
+                assert!((position - hit.p.to_vec()).magnitude() < 1e-4);
+                let interpolated = values[0] * u + values[1] * v + values[2] * w;
+                assert!((interpolated - f(target)).abs() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn area() {
+        let triangle = TriangleParameters {
+            vertices: [
+                Vector3::new(0.0, 0.0, -1.0),
+                Vector3::new(0.0, 3.0, -1.0),
+                Vector3::new(4.0, 0.0, -1.0),
+            ],
+            ..Default::default()
+        }
+        .init();
+        assert!((triangle.area() - 6.0).abs() < 1e-6);
+    }
+
+    /// Rays that hit a double-sided triangle from behind are intersections, with the normal
+    /// flipped to face the ray
+    #[test]
+    fn ray_hits_double_sided_triangle_from_behind() {
+        let triangle = TriangleParameters {
+            vertices: [
+                Vector3::new(0.0, 0.0, -1.0),
+                Vector3::new(0.0, 3.0, -1.0),
+                Vector3::new(3.0, 0.0, -1.0),
+            ],
+            handedness: TriangleHandedness::CounterClockwise,
+            double_sided: true,
+        }
+        .init();
+        let back = Ray {
+            origin: Point3::new(1.0, 1.0, -2.0),
+            direction: Vector3::new(0.0, 0.0, 1.0),
+        };
+        let expected = HitRecord {
+            p: Point3::new(1.0, 1.0, -1.0),
+            distance: 1.0,
+            front_face: false,
+            uv: Vector2::new(1.0 / 3.0, 1.0 / 3.0),
+            geometric_normal: Vector3::new(0.0, 0.0, -1.0),
+            shading_normal: Vector3::new(0.0, 0.0, -1.0),
+        };
+        assert_eq!(triangle.hit(&back), Some(expected));
+
+        let front = Ray {
+            origin: Point3::new(1.0, 1.0, 0.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        let expected = HitRecord {
+            p: Point3::new(1.0, 1.0, -1.0),
+            distance: 1.0,
+            front_face: true,
+            uv: Vector2::new(1.0 / 3.0, 1.0 / 3.0),
+            geometric_normal: Vector3::new(0.0, 0.0, 1.0),
+            shading_normal: Vector3::new(0.0, 0.0, 1.0),
+        };
+        assert_eq!(triangle.hit(&front), Some(expected));
+
+        // The ray still misses if it passes beside the triangle
+        let miss = Ray {
+            origin: Point3::new(4.0, 4.0, -2.0),
+            direction: Vector3::new(0.0, 0.0, 1.0),
+        };
+        assert_eq!(triangle.hit(&miss), None);
+    }
+
+    /// Vertex normals only change the shading normal, and it stays on the same side of the
+    /// triangle as the geometric normal
+    #[test]
+    fn smooth_normals() {
+        let mut triangle = TriangleParameters {
+            vertices: [
+                Vector3::new(0.0, 0.0, -1.0),
+                Vector3::new(0.0, 3.0, -1.0),
+                Vector3::new(3.0, 0.0, -1.0),
+            ],
+            ..Default::default()
+        }
+        .init();
+        let tilted = Vector3::new(1.0, 0.0, 1.0).normalize();
+        triangle.vertex_normals = Some([tilted; 3]);
+        let ray = Ray {
+            origin: Point3::new(1.0, 1.0, 0.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        let hit = triangle.hit(&ray).unwrap();
+        assert!(approx_eq_vec(
+            &hit.geometric_normal,
+            &Vector3::new(0.0, 0.0, 1.0)
+        ));
+        assert!(approx_eq_vec(&hit.shading_normal, &tilted));
+
+        triangle.vertex_normals = Some([-tilted; 3]);
+        let hit = triangle.hit(&ray).unwrap();
+        assert!(approx_eq_vec(&hit.shading_normal, &tilted));
+
+        // Halfway along the edge between vertices 0 and 1, their opposing normals cancel out
+        triangle.vertex_normals = Some([tilted, -tilted, tilted]);
+        let edge = Ray {
+            origin: Point3::new(0.0, 1.5, 0.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        let hit = triangle.hit(&edge).unwrap();
+        assert!(approx_eq_vec(&hit.shading_normal, &hit.geometric_normal));
+    }
 }
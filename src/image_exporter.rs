@@ -4,21 +4,134 @@
 //! interfaces to export that framebuffer to a file, such as a PNG or PPM.
 
 use crate::types::{Float, PixelValue};
-use image::{self, save_buffer_with_format};
-use num::traits::*;
-use std::{fs::File, io::prelude::*, path::Path};
+#[cfg(feature = "cli")]
+use image::{self, save_buffer_with_format, ImageBuffer, Rgb};
+use std::{
+    convert::TryFrom,
+    fs::File,
+    io::{prelude::*, BufWriter},
+    path::Path,
+    str::FromStr,
+};
 use thiserror::Error;
 
+/// The file extensions of the image formats that can be exported
+pub const OUTPUT_FORMATS: &[&str] = &["png", "ppm"];
+
 /// An enum type describing the possible output filetypes for the resulting image
-#[derive(Debug, PartialEq, Eq)]
-pub enum OuputType {
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum OutputType {
     PNG,
     PPM,
 }
 
+impl OutputType {
+    /// Infer the output type from the extension of a path
+    pub fn from_path(path: &Path) -> ExporterResult<Self> {
+        let ext = path
+            .extension()
+            .map(|x| x.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        match ext.as_str() {
+            "png" => Ok(OutputType::PNG),
+            "ppm" => Ok(OutputType::PPM),
+            _ => Err(ExporterError::UnsupportedOutputType(ext)),
+        }
+    }
+}
+
+/// The number of bits used to store each color channel in the output image
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum BitDepth {
+    #[default]
+    Eight,
+    Sixteen,
+}
+
+impl BitDepth {
+    /// The maximum integer value a color channel can take at this bit depth
+    pub fn max_color(&self) -> u32 {
+        match self {
+            BitDepth::Eight => u8::MAX as u32,
+            BitDepth::Sixteen => u16::MAX as u32,
+        }
+    }
+}
+
+impl FromStr for BitDepth {
+    type Err = ExporterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(BitDepth::Eight),
+            "16" => Ok(BitDepth::Sixteen),
+            _ => Err(ExporterError::InvalidBitDepth(s.to_string())),
+        }
+    }
+}
+
+/// The order in which the color channels of each pixel are written out
+///
+/// Some downstream tools (such as certain video encoders) expect pixels to be stored as BGR
+/// rather than RGB.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum ChannelOrder {
+    #[default]
+    RGB,
+    BGR,
+}
+
+impl ChannelOrder {
+    /// Arrange the channels of a pixel in this order
+    pub fn arrange<T: Copy>(&self, pixel: &PixelValue<T>) -> [T; 3] {
+        match self {
+            ChannelOrder::RGB => [pixel.x, pixel.y, pixel.z],
+            ChannelOrder::BGR => [pixel.z, pixel.y, pixel.x],
+        }
+    }
+}
+
+impl FromStr for ChannelOrder {
+    type Err = ExporterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "rgb" => Ok(ChannelOrder::RGB),
+            "bgr" => Ok(ChannelOrder::BGR),
+            _ => Err(ExporterError::InvalidChannelOrder(s.to_string())),
+        }
+    }
+}
+
+/// How the pixels of a PPM image are stored
+///
+/// ASCII (`P3`) images can be read and edited as text, while binary (`P6`) images store each
+/// channel as one byte (or two big-endian bytes at 16 bits), which is several times smaller and
+/// faster to write.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum PpmEncoding {
+    #[default]
+    Ascii,
+    Binary,
+}
+
+impl FromStr for PpmEncoding {
+    type Err = ExporterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ascii" => Ok(PpmEncoding::Ascii),
+            "binary" => Ok(PpmEncoding::Binary),
+            _ => Err(ExporterError::InvalidPpmEncoding(s.to_string())),
+        }
+    }
+}
+
 /// The possible errors that can arise when exporting a framebuffer
 #[derive(Error, Debug)]
 pub enum ExporterError {
+    #[cfg(feature = "cli")]
     #[error("There was some error from the image library")]
     Image {
         #[from]
@@ -36,11 +149,69 @@ pub enum ExporterError {
 
     #[error("The supplied width or height were invalid. These values must be greater than 0.")]
     InvalidDimensions,
+
+    #[error("\"{0}\" is not a supported bit depth. Valid values are: 8, 16.")]
+    InvalidBitDepth(String),
+
+    #[error("\"{0}\" is not a supported channel order. Valid values are: \"rgb\", \"bgr\".")]
+    InvalidChannelOrder(String),
+
+    #[error("\"{0}\" is not a supported PPM encoding. Valid values are: \"ascii\", \"binary\".")]
+    InvalidPpmEncoding(String),
+
+    #[error(
+        "Output filetype \"{0}\" is not supported. Valid values are: {formats}",
+        formats = OUTPUT_FORMATS.join(", ")
+    )]
+    UnsupportedOutputType(String),
 }
 
 /// A result that can return an `ExporterError`
 pub type ExporterResult<T> = Result<T, ExporterError>;
 
+/// Convert a color channel value in [0, 1] to an integer color value in [0, `max_color`]
+///
+/// The conversion saturates, so values at or below 0 (as well as NaN) map to 0 and values at or
+/// above 1 map to `max_color`. Values in between are rounded to the nearest integer rather than
+/// truncated, since truncating biases every color slightly darker.
+pub fn quantize(x: Float, max_color: u32) -> u32 {
+    // This comparison is false for NaN, which lets it fall through to 0
+    if x > 0.0 {
+        if x >= 1.0 {
+            max_color
+        } else {
+            (x * max_color as Float).round() as u32
+        }
+    } else {
+        0
+    }
+}
+
+/// Convert a color channel value in [0, 1] to an 8-bit color value
+///
+/// This is the 8-bit case of `quantize`, with the same saturating and rounding behavior.
+pub fn to_color_byte(x: Float) -> u8 {
+    quantize(x, u8::MAX as u32) as u8
+}
+
+/// Convert a framebuffer to 8-bit RGBA bytes
+///
+/// The alpha channel is always opaque. This is the layout that canvases and textures usually
+/// expect, so the result can be uploaded directly without going through an image file.
+pub fn to_rgba8(buffer: &[PixelValue<Float>]) -> Vec<u8> {
+    buffer
+        .iter()
+        .flat_map(|pixel| {
+            [
+                to_color_byte(pixel.x),
+                to_color_byte(pixel.y),
+                to_color_byte(pixel.z),
+                u8::MAX,
+            ]
+        })
+        .collect()
+}
+
 /// The "base" trait for a `FrameBufferExporter`
 ///
 /// Implementing this trait automatically implements the `FrameBufferExporter` trait, which
@@ -51,11 +222,11 @@ pub trait FramebufferExporterBase {
     ///
     /// The `buffer` is a vector of RGB pixel values, and the `path` is the desired path to write
     /// the file. You can assume that the buffer will consist of integers between 0 and
-    /// `MAX_COLOR`.
+    /// `max_color()`.
     fn export(&self, buffer: &[PixelValue<u32>], path: &Path) -> ExporterResult<()>;
 
-    /// The maximum color value that a framebuffer
-    const MAX_COLOR: u32;
+    /// The maximum color value that a framebuffer can store for a single channel
+    fn max_color(&self) -> u32;
 }
 
 /// Something that can export a framebuffer of `PixelValue`s to some other format
@@ -74,13 +245,11 @@ pub trait FramebufferExporter {
 impl<T: FramebufferExporterBase> FramebufferExporter for T {
     fn export(&self, buffer: &[PixelValue<Float>], path: &Path) -> ExporterResult<()> {
         // Convert the floating point color values to proper N-bit integer color values, based on
-        // the `MAX_COLOR` value
+        // the `max_color` value
+        let max_color = self.max_color();
         let int_buffer: Vec<PixelValue<u32>> = buffer
             .iter()
-            .map(|pixel| {
-                let max_value = num::NumCast::from(T::MAX_COLOR).unwrap();
-                (pixel * max_value).map(|x| x.to_u32().unwrap())
-            })
+            .map(|pixel| pixel.map(|x| quantize(x, max_color)))
             .collect();
         self.export(&int_buffer[..], path)
     }
@@ -89,7 +258,8 @@ impl<T: FramebufferExporterBase> FramebufferExporter for T {
 /// Export a framebuffer to the PPM image format
 ///
 /// The PPM format is extremely simple and does not offer any compression. This is a poor choice
-/// for large images, as file sizes scale linearly with pixel counts.
+/// for large images, as file sizes scale linearly with pixel counts, though the binary encoding
+/// helps.
 #[derive(Debug)]
 pub struct PPMExporter {
     /// The width of the output image
@@ -97,6 +267,15 @@ pub struct PPMExporter {
 
     /// The height of the output image
     pub height: u32,
+
+    /// The number of bits per color channel
+    pub bit_depth: BitDepth,
+
+    /// The order in which the channels of each pixel are written
+    pub channel_order: ChannelOrder,
+
+    /// Whether the pixels are written as ASCII text or as binary
+    pub encoding: PpmEncoding,
 }
 
 impl PPMExporter {
@@ -105,33 +284,73 @@ impl PPMExporter {
         if self.width == 0 || self.height == 0 {
             return Err(ExporterError::InvalidDimensions);
         }
-        Ok("P3\n".to_owned() + &format!("{} {}\n", self.width, self.height) + "255\n")
+        let magic_number = match self.encoding {
+            PpmEncoding::Ascii => "P3",
+            PpmEncoding::Binary => "P6",
+        };
+        Ok(format!(
+            "{}\n{} {}\n{}\n",
+            magic_number,
+            self.width,
+            self.height,
+            self.max_color()
+        ))
     }
-}
-
-impl FramebufferExporterBase for PPMExporter {
-    const MAX_COLOR: u32 = 255;
 
-    fn export(&self, buffer: &[PixelValue<u32>], path: &Path) -> ExporterResult<()> {
-        let header = self.header()?;
+    /// Write the image to a stream
+    ///
+    /// The stream should be buffered, since the pixels are written a few bytes at a time.
+    fn write_to(&self, buffer: &[PixelValue<u32>], writer: &mut impl Write) -> ExporterResult<()> {
+        writer.write_all(self.header()?.as_bytes())?;
+        let wide = self.max_color() > u32::from(u8::MAX);
 
-        let io_result = {
-            let mut file = File::create(path)?;
-            file.write(header.as_bytes())?;
-            // write each RGB value to the file
+        for pixel in buffer {
+            let channels = self.channel_order.arrange(pixel);
 
-            for pixel in buffer {
-                let pixel_str = format!("{} {} {}\n", pixel.x, pixel.y, pixel.z);
-                file.write(pixel_str.as_bytes())?;
+            match self.encoding {
+                PpmEncoding::Ascii => {
+                    writeln!(writer, "{} {} {}", channels[0], channels[1], channels[2])?
+                }
+                // Binary PPMs store each channel in one byte, or two big-endian bytes if the
+                // maximum color value doesn't fit in a byte
+                PpmEncoding::Binary if wide => {
+                    for channel in channels {
+                        let channel = u16::try_from(channel).unwrap_or(u16::MAX);
+                        writer.write_all(&channel.to_be_bytes())?;
+                    }
+                }
+                PpmEncoding::Binary => {
+                    for channel in channels {
+                        writer.write_all(&[u8::try_from(channel).unwrap_or(u8::MAX)])?;
+                    }
+                }
             }
-            Ok(())
-        };
+        }
+        Ok(())
+    }
+}
 
-        // convert the IO error to an exporter error
-        io_result.map_err(|e| ExporterError::IO { source: e })
+impl FramebufferExporterBase for PPMExporter {
+    fn max_color(&self) -> u32 {
+        self.bit_depth.max_color()
+    }
+
+    fn export(&self, buffer: &[PixelValue<u32>], path: &Path) -> ExporterResult<()> {
+        // Check the dimensions before creating the file so we don't leave an empty file behind
+        self.header()?;
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(buffer, &mut writer)?;
+        writer.flush()?;
+        Ok(())
     }
 }
 
+/// Export a framebuffer to the PNG image format
+///
+/// PNGs can store either 8 or 16 bits per color channel. 16-bit images are larger, but avoid the
+/// banding that 8-bit images show on smooth gradients. This needs the `image` crate, so it's only
+/// available with the `cli` feature.
+#[cfg(feature = "cli")]
 #[derive(Debug)]
 pub struct PNGExporter {
     /// The width of the output image
@@ -139,15 +358,38 @@ pub struct PNGExporter {
 
     /// The height of the output image
     pub height: u32,
+
+    /// The number of bits per color channel
+    pub bit_depth: BitDepth,
+
+    /// The order in which the channels of each pixel are stored
+    pub channel_order: ChannelOrder,
 }
 
+#[cfg(feature = "cli")]
 impl FramebufferExporterBase for PNGExporter {
-    const MAX_COLOR: u32 = 255;
+    fn max_color(&self) -> u32 {
+        self.bit_depth.max_color()
+    }
 
     fn export(&self, buffer: &[PixelValue<u32>], path: &Path) -> ExporterResult<()> {
         if self.width < 1 || self.height < 1 {
             return Err(ExporterError::InvalidDimensions);
         }
+
+        if self.bit_depth == BitDepth::Sixteen {
+            let u16_buffer: Vec<u16> = buffer
+                .iter()
+                .flat_map(|v| self.channel_order.arrange(v))
+                .map(|x| u16::try_from(x).unwrap_or(u16::MAX))
+                .collect();
+            let image: ImageBuffer<Rgb<u16>, Vec<u16>> =
+                ImageBuffer::from_raw(self.width, self.height, u16_buffer)
+                    .ok_or(ExporterError::InvalidDimensions)?;
+            return image
+                .save_with_format(path, image::ImageFormat::Png)
+                .map_err(|e| ExporterError::Image { source: e });
+        }
         // We need to flatten our vector of (mathematical) vectors into a `Vec` of 8 bit color
         // values. We convert the vector values into `Vec` types so we can iterate over the pixels.
         // This lets us leverage Rust's built-in method to flatten iterators of iterators. The
@@ -157,24 +399,20 @@ impl FramebufferExporterBase for PNGExporter {
         // indirection, which incurs some extra allocation.
         let u8_buffer = buffer
             .iter()
-            // We're doing some shenanigans to convert a range [0, 1] to [0, 255], which you can
-            // also interpret as converting a float to an 8-bit integer.
+            // The values have already been quantized to [0, 255], so this conversion saturates
+            // rather than panicking if something upstream produced an out of range value.
             .map(|v| {
-                vec![
-                    v.x.to_u8().unwrap(),
-                    v.y.to_u8().unwrap(),
-                    v.z.to_u8().unwrap(),
-                ]
+                self.channel_order
+                    .arrange(v)
+                    .iter()
+                    .map(|&x| u8::try_from(x).unwrap_or(u8::MAX))
+                    .collect()
             })
             .collect::<Vec<Vec<u8>>>();
 
         // We need to flatten the buffer in another step, because we lose the temporary vector if
         // we try to flatten out the structure in one go
-        let flat_buffer = u8_buffer
-            .iter()
-            .flatten()
-            .map(|x| x.clone())
-            .collect::<Vec<u8>>();
+        let flat_buffer = u8_buffer.iter().flatten().copied().collect::<Vec<u8>>();
 
         save_buffer_with_format(
             path,
@@ -187,3 +425,122 @@ impl FramebufferExporterBase for PNGExporter {
         .map_err(|e| ExporterError::Image { source: e })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_byte_boundaries() {
+        assert_eq!(to_color_byte(-0.1), 0);
+        assert_eq!(to_color_byte(0.0), 0);
+        assert_eq!(to_color_byte(0.5), 128);
+        assert_eq!(to_color_byte(1.0), 255);
+        assert_eq!(to_color_byte(1.5), 255);
+        assert_eq!(to_color_byte(Float::NAN), 0);
+    }
+
+    #[test]
+    fn quantize_rounds() {
+        // 0.499 * 255 = 127.2, and 0.501 * 255 = 127.8
+        assert_eq!(quantize(0.499, 255), 127);
+        assert_eq!(quantize(0.501, 255), 128);
+        assert_eq!(quantize(1.0, 65535), 65535);
+        assert_eq!(quantize(Float::INFINITY, 255), 255);
+        assert_eq!(quantize(Float::NEG_INFINITY, 255), 0);
+    }
+
+    #[test]
+    fn output_type_from_path() {
+        assert_eq!(
+            OutputType::from_path(Path::new("out.png")).unwrap(),
+            OutputType::PNG
+        );
+        assert_eq!(
+            OutputType::from_path(Path::new("dir/out.PPM")).unwrap(),
+            OutputType::PPM
+        );
+        assert!(OutputType::from_path(Path::new("out.jpg")).is_err());
+        assert!(OutputType::from_path(Path::new("out")).is_err());
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn sixteen_bit_png() {
+        let path = std::env::temp_dir().join(format!("nib-16-bit-{}.png", std::process::id()));
+        let exporter = PNGExporter {
+            width: 2,
+            height: 1,
+            bit_depth: "16".parse().unwrap(),
+            channel_order: ChannelOrder::default(),
+        };
+        let buffer = vec![
+            PixelValue::new(0.0, 0.5, 1.0),
+            PixelValue::new(0.25, 2.0, -1.0),
+        ];
+        FramebufferExporter::export(&exporter, &buffer, &path).unwrap();
+
+        let image = image::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let image = image.as_rgb16().unwrap();
+        assert_eq!(image.get_pixel(0, 0).0, [0, 32768, 65535]);
+        assert_eq!(image.get_pixel(1, 0).0, [16384, 65535, 0]);
+    }
+
+    #[test]
+    fn bgr_ppm() {
+        let path = std::env::temp_dir().join(format!("nib-bgr-{}.ppm", std::process::id()));
+        let exporter = PPMExporter {
+            width: 1,
+            height: 1,
+            bit_depth: BitDepth::Eight,
+            channel_order: "BGR".parse().unwrap(),
+            encoding: PpmEncoding::Ascii,
+        };
+        FramebufferExporter::export(&exporter, &[PixelValue::new(1.0, 0.0, 0.2)], &path).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "P3\n1 1\n255\n51 0 255\n");
+    }
+
+    fn ppm(encoding: PpmEncoding, bit_depth: BitDepth) -> Vec<u8> {
+        let exporter = PPMExporter {
+            width: 2,
+            height: 1,
+            bit_depth,
+            channel_order: ChannelOrder::RGB,
+            encoding,
+        };
+        let max_color = exporter.max_color();
+        let buffer: Vec<PixelValue<u32>> = [
+            PixelValue::new(0.0, 0.5, 1.0),
+            PixelValue::new(0.2, 2.0, -1.0),
+        ]
+        .iter()
+        .map(|pixel| pixel.map(|x| quantize(x, max_color)))
+        .collect();
+        let mut bytes = Vec::new();
+        exporter.write_to(&buffer, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn ascii_ppm() {
+        assert_eq!(
+            ppm(PpmEncoding::Ascii, BitDepth::Eight),
+            b"P3\n2 1\n255\n0 128 255\n51 255 0\n"
+        );
+    }
+
+    #[test]
+    fn binary_ppm() {
+        let mut expected = b"P6\n2 1\n255\n".to_vec();
+        expected.extend_from_slice(&[0, 128, 255, 51, 255, 0]);
+        assert_eq!(ppm(PpmEncoding::Binary, BitDepth::Eight), expected);
+
+        // 16 bit channels are stored big-endian
+        let mut expected = b"P6\n2 1\n65535\n".to_vec();
+        expected.extend_from_slice(&[0, 0, 128, 0, 255, 255, 51, 51, 255, 255, 0, 0]);
+        assert_eq!(ppm(PpmEncoding::Binary, BitDepth::Sixteen), expected);
+    }
+}
@@ -0,0 +1,32 @@
+//! The coverage integrator renders a mask of the pixels that are covered by the scene's geometry.
+//!
+//! Each sample is white if its primary ray hits any object and black if it misses, regardless of
+//! the background color. Since the samples in a pixel are averaged, pixels on the edge of an object
+//! end up with fractional coverage, which makes the output usable as an antialiased alpha matte
+//! when compositing the render over a different background.
+
+use crate::{
+    integrator::{Integrator, RenderParams},
+    types::{Float, PixelValue},
+};
+use serde::{Deserialize, Serialize};
+
+/// The parameters for the `Coverage` integrator
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct Coverage {}
+
+impl Integrator for Coverage {
+    fn render(&self, params: RenderParams) -> PixelValue<Float> {
+        if params
+            .context
+            .scene
+            .accel
+            .collision(params.origin)
+            .is_some()
+        {
+            PixelValue::new(1.0, 1.0, 1.0)
+        } else {
+            PixelValue::new(0.0, 0.0, 0.0)
+        }
+    }
+}
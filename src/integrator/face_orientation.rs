@@ -0,0 +1,28 @@
+//! The face orientation integrator is a diagnostic integrator that shows which side of each
+//! surface is seen.
+//!
+//! Surfaces that are seen from the front are green, and surfaces that are seen from behind are
+//! red. On a closed mesh seen from outside, every face should be green, so red facets point out
+//! triangles whose winding is inverted, which otherwise show up as dark facets or light leaks.
+//! Single-sided triangles can't be hit from behind at all, so a mesh has to be `double_sided` for
+//! its inverted faces to show up in red rather than as holes.
+
+use crate::{
+    integrator::{Integrator, RenderParams},
+    types::{Float, PixelValue},
+};
+use serde::{Deserialize, Serialize};
+
+/// The parameters for the `FaceOrientation` integrator
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct FaceOrientation {}
+
+impl Integrator for FaceOrientation {
+    fn render(&self, params: RenderParams) -> PixelValue<Float> {
+        match params.context.scene.accel.collision(params.origin) {
+            Some(record) if record.hit_record.front_face => PixelValue::new(0.0, 1.0, 0.0),
+            Some(_) => PixelValue::new(1.0, 0.0, 0.0),
+            None => params.context.background.sample(params.origin),
+        }
+    }
+}
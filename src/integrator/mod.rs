@@ -2,19 +2,29 @@
 //! integrators that can be extended so that other integrators can be easily added.
 
 use crate::{
+    material::MediumStack,
     ray::Ray,
     renderer::Renderer,
     sampler::Sampler,
     types::{Float, PixelValue},
 };
+use cgmath::Vector2;
 use enum_dispatch::enum_dispatch;
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 
+pub mod coverage;
+pub mod face_orientation;
 pub mod normal;
+pub mod test_pattern;
+pub mod uv_visualize;
 pub mod whitted;
 
+pub use coverage::Coverage;
+pub use face_orientation::FaceOrientation;
 pub use normal::Normal;
+pub use test_pattern::TestPattern;
+pub use uv_visualize::UvVisualize;
 pub use whitted::Whitted;
 
 /// A struct representing the various options and parameters that can be configured for the
@@ -39,6 +49,40 @@ pub struct RenderParams<'a, 'b, 'c> {
 
     /// A reference to the sampler to use with the integrator
     pub sampler: &'c mut dyn Sampler<Float>,
+
+    /// The media that the ray is currently inside of
+    ///
+    /// Rays from the camera start out in a vacuum, so this starts out empty.
+    pub medium: MediumStack,
+
+    /// The position of the sample on the image
+    ///
+    /// Both coordinates lie in [0, 1], where (0, 0) is the bottom left corner of the image. Most
+    /// integrators only care about the ray, but diagnostic integrators can use this to draw
+    /// something that doesn't depend on the scene.
+    pub image_uv: Vector2<Float>,
+
+    /// The number of surfaces the path has bounced off of before this ray
+    ///
+    /// The renderer starts camera rays at 0. Recursive integrators trace the next ray of a path by
+    /// calling `render` again with the depth incremented, and stop once it reaches their limit.
+    pub depth: u32,
+
+    /// The number of rays in the path before this one that were reflected off of a surface
+    ///
+    /// Together with `refraction_depth`, this splits up `depth` by whether each bounce stayed on
+    /// the same side of the surface, for integrators that limit them separately.
+    pub reflection_depth: u32,
+
+    /// The number of rays in the path before this one that passed through a surface
+    pub refraction_depth: u32,
+
+    /// Whether the surface the ray was scattered from already sampled the lights directly
+    ///
+    /// An integrator that samples the lights at every surface it can shouldn't add the light of a
+    /// sampled light when the scattered ray happens to hit it too, or that light would be counted
+    /// twice. Camera rays start out with this unset.
+    pub lights_sampled: bool,
 }
 
 /// A trait that defines an integrator. An integrator defines the operations that are responsible
@@ -52,9 +96,32 @@ pub trait Integrator: Debug + Send + Sync {
     fn render(&self, params: RenderParams) -> PixelValue<Float>;
 }
 
+/// The different types of integrators that can be used in the scene description
+///
+/// Like the other enums in the scene description, this is internally tagged, so the variant is
+/// named by a `type` field next to the integrator's parameters.
 #[enum_dispatch]
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(tag = "type")]
 pub enum SerializedIntegrator {
     Normal(Normal),
     Whitted(Whitted),
+    UvVisualize(UvVisualize),
+    Coverage(Coverage),
+    TestPattern(TestPattern),
+    FaceOrientation(FaceOrientation),
+}
+
+impl SerializedIntegrator {
+    /// The name of the integrator, as it's written in scene files
+    pub fn name(&self) -> &'static str {
+        match self {
+            SerializedIntegrator::Normal(_) => "Normal",
+            SerializedIntegrator::Whitted(_) => "Whitted",
+            SerializedIntegrator::UvVisualize(_) => "UvVisualize",
+            SerializedIntegrator::Coverage(_) => "Coverage",
+            SerializedIntegrator::TestPattern(_) => "TestPattern",
+            SerializedIntegrator::FaceOrientation(_) => "FaceOrientation",
+        }
+    }
 }
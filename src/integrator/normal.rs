@@ -16,8 +16,8 @@ pub struct Normal {}
 
 impl Integrator for Normal {
     fn render(&self, params: RenderParams) -> PixelValue<Float> {
-        if let Some(accel_record) = params.context.accel.collision(params.origin) {
-            let normal = accel_record.hit_record.normal;
+        if let Some(accel_record) = params.context.scene.accel.collision(params.origin) {
+            let normal = accel_record.hit_record.shading_normal;
             // Normals can range from -1 to 1, and we need to change that window to [0, 1]. We use
             // the simple formula x' = (0.5 * x) + 0.5
             let x = (normal.x * 0.5) + 0.5;
@@ -25,6 +25,6 @@ impl Integrator for Normal {
             let z = (normal.z * 0.5) + 0.5;
             return PixelValue::new(x, y, z);
         }
-        params.context.background
+        params.context.background.sample(params.origin)
     }
 }
@@ -0,0 +1,157 @@
+//! The test pattern integrator ignores the scene and draws a fixed pattern over the image.
+//!
+//! The color of each sample only depends on where it lands on the image, so the output doesn't
+//! depend on the geometry, the camera, or the sampler. This makes it useful for checking the
+//! export pipeline (bit depth, channel order, quantization) in isolation: any problem in the
+//! output image has to come from the exporter rather than the renderer.
+
+use crate::{
+    integrator::{Integrator, RenderParams},
+    types::{string_enum, Float, PixelValue},
+};
+use cgmath::Vector2;
+use serde::{Deserialize, Serialize};
+
+/// The number of squares along each side of the image in the checkerboard pattern
+const CHECKERBOARD_SQUARES: Float = 8.0;
+
+/// The number of discrete steps in the lower half of the gray ramp
+const GRAY_RAMP_STEPS: Float = 16.0;
+
+string_enum! {
+    /// The patterns that the `TestPattern` integrator can draw
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum Pattern {
+        /// Vertical bars of white, yellow, cyan, green, magenta, red, blue, and black
+        ///
+        /// Each primary and secondary color shows up exactly once, so swapped channels are easy
+        /// to spot.
+        #[default]
+        ColorBars,
+
+        /// A horizontal ramp from black to white
+        ///
+        /// The top half of the image is a smooth ramp and the bottom half is split into discrete
+        /// steps, which makes banding and rounding errors in the exporter visible.
+        GrayRamp,
+
+        /// A black and white checkerboard
+        Checkerboard,
+    }
+}
+
+/// The parameters for the `TestPattern` integrator
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct TestPattern {
+    /// The pattern to draw, which defaults to color bars
+    #[serde(default)]
+    pub pattern: Pattern,
+}
+
+impl TestPattern {
+    /// The color of the pattern at a point on the image, where (0, 0) is the bottom left corner
+    /// and (1, 1) is the top right corner
+    pub fn color(&self, uv: Vector2<Float>) -> PixelValue<Float> {
+        // Samples can land exactly on the right or top edge of the image
+        let u = uv.x.clamp(0.0, 1.0 - Float::EPSILON);
+        let v = uv.y.clamp(0.0, 1.0 - Float::EPSILON);
+
+        match self.pattern {
+            Pattern::ColorBars => {
+                // The bars are ordered so that each channel switches on and off at a different
+                // frequency: green for the first half, red in alternating pairs, and blue in
+                // alternating bars
+                let bar = 7 - (u * 8.0) as u32;
+                PixelValue::new(
+                    ((bar >> 1) & 1) as Float,
+                    ((bar >> 2) & 1) as Float,
+                    (bar & 1) as Float,
+                )
+            }
+            Pattern::GrayRamp => {
+                let value = if v >= 0.5 {
+                    u
+                } else {
+                    (u * GRAY_RAMP_STEPS).floor() / (GRAY_RAMP_STEPS - 1.0)
+                };
+                PixelValue::new(value, value, value)
+            }
+            Pattern::Checkerboard => {
+                let parity = (u * CHECKERBOARD_SQUARES) as u32 + (v * CHECKERBOARD_SQUARES) as u32;
+                let value = (parity % 2) as Float;
+                PixelValue::new(value, value, value)
+            }
+        }
+    }
+}
+
+impl Integrator for TestPattern {
+    fn render(&self, params: RenderParams) -> PixelValue<Float> {
+        self.color(params.image_uv)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_bars() {
+        let bars = TestPattern {
+            pattern: Pattern::ColorBars,
+        };
+        let expected = [
+            (1.0, 1.0, 1.0),
+            (1.0, 1.0, 0.0),
+            (0.0, 1.0, 1.0),
+            (0.0, 1.0, 0.0),
+            (1.0, 0.0, 1.0),
+            (1.0, 0.0, 0.0),
+            (0.0, 0.0, 1.0),
+            (0.0, 0.0, 0.0),
+        ];
+
+        for (i, &(r, g, b)) in expected.iter().enumerate() {
+            let u = (i as Float + 0.5) / 8.0;
+            assert_eq!(
+                bars.color(Vector2::new(u, 0.5)),
+                PixelValue::new(r, g, b),
+                "bar {}",
+                i
+            );
+        }
+        // The right edge of the image is part of the last bar
+        assert_eq!(
+            bars.color(Vector2::new(1.0, 0.5)),
+            PixelValue::new(0.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn gray_ramp() {
+        let ramp = TestPattern {
+            pattern: Pattern::GrayRamp,
+        };
+        assert_eq!(ramp.color(Vector2::new(0.25, 0.75)).x, 0.25);
+        // The stepped half spans the full range from black to white
+        assert_eq!(ramp.color(Vector2::new(0.0, 0.25)).x, 0.0);
+        assert_eq!(ramp.color(Vector2::new(1.0, 0.25)).x, 1.0);
+        assert_eq!(
+            ramp.color(Vector2::new(0.26, 0.25)),
+            ramp.color(Vector2::new(0.3, 0.25))
+        );
+    }
+
+    #[test]
+    fn checkerboard() {
+        let checkerboard = TestPattern {
+            pattern: Pattern::Checkerboard,
+        };
+        let black = PixelValue::new(0.0, 0.0, 0.0);
+        let white = PixelValue::new(1.0, 1.0, 1.0);
+        assert_eq!(checkerboard.color(Vector2::new(0.01, 0.01)), black);
+        assert_eq!(checkerboard.color(Vector2::new(0.14, 0.01)), white);
+        assert_eq!(checkerboard.color(Vector2::new(0.14, 0.14)), black);
+        assert_eq!(checkerboard.color(Vector2::new(1.0, 1.0)), black);
+    }
+}
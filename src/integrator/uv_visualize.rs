@@ -0,0 +1,45 @@
+//! The UV integrator is a diagnostic integrator that visualizes the surface coordinates of the
+//! surfaces it hits.
+//!
+//! This is useful to check that texture coordinates are sane (no flips, seams, or unexpected
+//! tiling) before applying an actual texture to a surface.
+
+use crate::{
+    integrator::{Integrator, RenderParams},
+    types::{Float, PixelValue},
+};
+use serde::{Deserialize, Serialize};
+
+/// The parameters for the `UvVisualize` integrator
+///
+/// The `u` coordinate is mapped to the red channel and the `v` coordinate is mapped to the green
+/// channel.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct UvVisualize {
+    /// The number of checkerboard squares per unit of UV space
+    ///
+    /// If this is set, every other square of the checkerboard is darkened, which makes it easy to
+    /// spot stretching and tiling in the coordinates. Otherwise, only the color gradient is shown.
+    #[serde(default)]
+    pub checkers: Option<u32>,
+}
+
+impl Integrator for UvVisualize {
+    fn render(&self, params: RenderParams) -> PixelValue<Float> {
+        if let Some(accel_record) = params.context.scene.accel.collision(params.origin) {
+            let uv = accel_record.hit_record.uv;
+            let color = PixelValue::new(uv.x, uv.y, 0.0);
+
+            if let Some(checkers) = self.checkers {
+                let scale = checkers as Float;
+                let parity = ((uv.x * scale).floor() + (uv.y * scale).floor()) as i64;
+
+                if parity.rem_euclid(2) == 1 {
+                    return color * 0.5;
+                }
+            }
+            return color;
+        }
+        params.context.background.sample(params.origin)
+    }
+}
@@ -1,13 +1,36 @@
 //! An implementation of the Whitted/direct lighting rendering technique. This is the "classic"
 //! ray-tracing technique. It was developed by Turner Whitted in 1980, in a paper titled "An
 //! Improved Illumination Model for Shaded Display."
+//!
+//! At every surface that isn't perfectly specular (see `BSDF::is_specular`), the integrator picks
+//! one of the lights that can be sampled, favoring larger lights, and casts a shadow ray towards it
+//! to add its light if it isn't blocked. This is what gives the technique its sharp shadows. The
+//! scattered ray is still traced to pick up light that bounces off of other surfaces, but it
+//! ignores the lights that were already sampled so they aren't counted twice.
 
 use crate::{
+    hittable::HitRecord,
     integrator::{Integrator, RenderParams},
-    types::{Float, PixelValue},
+    material::BSDF,
+    renderer::Renderer,
+    sampler::Sampler,
+    types::{is_black, Float, PixelValue},
 };
-use cgmath::{ElementWise, InnerSpace, Vector3};
+use cgmath::{ElementWise, InnerSpace};
 use serde::{Deserialize, Serialize};
+use std::{f32::consts::PI, sync::Arc};
+
+/// How far along the normal shadow rays start from the surface
+///
+/// Starting the shadow ray exactly on the surface can make it collide with the surface it's
+/// leaving because of floating point error, which would leave speckles of shadow on lit surfaces.
+const SHADOW_RAY_OFFSET: Float = 1e-4;
+
+/// The fraction of the distance to a light that something has to be closer than to block it
+///
+/// The shadow ray is expected to hit the light itself, so the collision with the light has to be
+/// told apart from an object just in front of it.
+const OCCLUSION_TOLERANCE: Float = 0.999;
 
 /// The parameters for the Whitted integrator
 #[derive(Debug, Serialize, Deserialize, Copy, Clone)]
@@ -17,53 +40,325 @@ pub struct Whitted {
     /// This settings sets an upper bound on the depth of the rays in the scene (this is necessary
     /// in case there is infinite recursion in the scene).
     pub max_depth: u32,
+
+    /// An optional limit on the number of reflections in a path
+    ///
+    /// A ray that scatters back to the side of the surface it came from, like a ray off of a
+    /// mirror, is a reflection. Reflected rays past this limit aren't traced. `max_depth` still
+    /// caps the total number of bounces, and there's no separate limit by default.
+    #[serde(default)]
+    pub max_reflection_depth: Option<u32>,
+
+    /// An optional limit on the number of refractions in a path
+    ///
+    /// A ray that passes through a surface, like a ray entering or leaving glass, is a
+    /// refraction. Refracted rays past this limit aren't traced. `max_depth` still caps the total
+    /// number of bounces, and there's no separate limit by default.
+    #[serde(default)]
+    pub max_refraction_depth: Option<u32>,
 }
 
 impl Default for Whitted {
     fn default() -> Self {
-        Self { max_depth: 5 }
+        Self {
+            max_depth: 5,
+            max_reflection_depth: None,
+            max_refraction_depth: None,
+        }
     }
 }
 
 impl Integrator for Whitted {
-    fn render(&self, params: RenderParams) -> PixelValue<Float> {
-        self.render_helper(params, 0)
-    }
-}
-
-impl Whitted {
-    /// The recursive helper method for the Whitted integrator
+    /// Trace a ray, recursing into the rays that it scatters into
     ///
-    /// This exists because we need to keep track of the stack depth as we cast new rays and the
-    /// `Integrator` trait doesn't have a parameter for depth.
-    fn render_helper(&self, params: RenderParams, depth: u32) -> PixelValue<Float> {
-        // First, we check to see if the ray hit anything, if not, we return a black background.
-        // TODO(afnan) change this to be more extensible, such as allowing for a gradient or
-        // an environment map
-        if let Some(collision) = params.context.accel.collision(&params.origin) {
-            if depth >= self.max_depth {
-                return params.context.background;
+    /// If the ray was scattered from a surface that already sampled the lights directly, hitting
+    /// one of those lights adds nothing.
+    fn render(&self, params: RenderParams) -> PixelValue<Float> {
+        // First, we check to see if the ray hit anything, if not, the ray sees the background
+        if let Some(collision) = params.context.scene.accel.collision(params.origin) {
+            // A path that's cut off doesn't pick up any more light
+            if params.depth >= self.max_depth {
+                return PixelValue::new(0.0, 0.0, 0.0);
             }
+            let mat = &collision.object.mat;
+            let hit_record = &collision.hit_record;
+            let is_sampled_light = params
+                .context
+                .scene
+                .lights
+                .iter()
+                .any(|light| Arc::ptr_eq(&light.mat, mat));
+            let emitted = if !params.lights_sampled || !is_sampled_light {
+                mat.emitted(params.origin, hit_record)
+            } else {
+                PixelValue::new(0.0, 0.0, 0.0)
+            };
+            // Sampling the lights is wasted at a specular surface, since a sampled light is never
+            // in the direction the surface scatters towards
+            let direct = if mat.is_specular() {
+                None
+            } else {
+                let ambient = Self::ambient_light(params.context, hit_record, &**mat);
+                Self::direct_light(params.context, params.sampler, hit_record, &**mat)
+                    .map(|light| light + ambient)
+            };
+            // The medium on the other side of the surface is the one we're in if we're entering
+            // the object, and the one beneath it if we're leaving
+            let mut medium = params.medium;
+            let outside_ior = if hit_record.front_face {
+                medium.current()
+            } else {
+                medium.outside()
+            };
             let bsdf_record =
-                collision
-                    .object
-                    .mat
-                    .scatter(params.sampler, params.origin, &collision.hit_record);
+                mat.scatter_in_medium(params.sampler, params.origin, hit_record, outside_ior);
+
+            let incoming = params.origin.direction.dot(hit_record.geometric_normal);
+            let outgoing = bsdf_record.out.direction.dot(hit_record.geometric_normal);
+            let refracted = incoming * outgoing > 0.0;
+            let (reflection_depth, refraction_depth) = if refracted {
+                (params.reflection_depth, params.refraction_depth + 1)
+            } else {
+                (params.reflection_depth + 1, params.refraction_depth)
+            };
+            let over_limit = |limit: Option<u32>, depth| limit.is_some_and(|limit| depth > limit);
+
+            // There's no point in tracing the scattered ray if none of its light makes it back,
+            // or if it's past the limit for its kind of bounce
+            if is_black(&bsdf_record.attenuation)
+                || over_limit(self.max_reflection_depth, reflection_depth)
+                || over_limit(self.max_refraction_depth, refraction_depth)
+            {
+                return emitted + direct.unwrap_or(PixelValue::new(0.0, 0.0, 0.0));
+            }
+
+            // If the ray passed through the surface, it has either entered or left the object's
+            // medium
+            if let Some(ior) = mat.refraction_index().filter(|_| refracted) {
+                if hit_record.front_face {
+                    medium.push(ior);
+                } else {
+                    medium.pop();
+                }
+            }
+
             // Calculate values of the rays recursively, accumulating as we go
             let new_params = RenderParams {
                 origin: &bsdf_record.out,
+                medium,
+                depth: params.depth + 1,
+                reflection_depth,
+                refraction_depth,
+                lights_sampled: direct.is_some(),
                 ..params
             };
-            let recursive_color = self.render_helper(new_params, depth + 1);
-            let color = bsdf_record.attenuation.mul_element_wise(recursive_color);
-            return color;
+            let recursive_color = self.render(new_params);
+            return emitted
+                + direct.unwrap_or(PixelValue::new(0.0, 0.0, 0.0))
+                + bsdf_record.attenuation.mul_element_wise(recursive_color);
+        }
+
+        params.context.background.sample(params.origin)
+    }
+}
+
+impl Whitted {
+    /// The scene's ambient light reflected by a surface
+    ///
+    /// Every surface that can be evaluated is lit by the constant ambient radiance from every
+    /// direction, ignoring whatever is in the way. A diffuse surface reflects that as its albedo
+    /// times the radiance, which is its BSDF towards the normal times pi. This is a fudge to keep
+    /// shadows from being pure black, not an estimate of the light bouncing around the scene.
+    fn ambient_light(
+        context: &Renderer,
+        hit_record: &HitRecord,
+        mat: &dyn BSDF,
+    ) -> PixelValue<Float> {
+        if is_black(&context.ambient) {
+            return context.ambient;
         }
+        match mat.eval(hit_record, &hit_record.shading_normal) {
+            Some(f) => (f * PI).mul_element_wise(context.ambient),
+            None => PixelValue::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    /// The light arriving at a surface directly from the lights that can be sampled
+    ///
+    /// One light is picked in proportion to its area, and a shadow ray is cast towards a random
+    /// point on it. The light only contributes if nothing is in between, and its contribution is
+    /// divided by the probability of picking it, so on average this is the light from all of the
+    /// lights. Returns `None` if the material can't be evaluated for an arbitrary direction, like a
+    /// mirror, in which case the lights have to be found by the scattered ray instead.
+    fn direct_light(
+        context: &Renderer,
+        sampler: &mut dyn Sampler<Float>,
+        hit_record: &HitRecord,
+        mat: &dyn BSDF,
+    ) -> Option<PixelValue<Float>> {
+        let black = PixelValue::new(0.0, 0.0, 0.0);
+        let from = hit_record.p + (hit_record.geometric_normal * SHADOW_RAY_OFFSET);
+        let u = sampler.next(1).unwrap()[0];
+        let (index, pick_pdf) = match context.scene.light_distribution.sample(u) {
+            Some(x) => x,
+            None => {
+                return mat
+                    .eval(hit_record, &hit_record.shading_normal)
+                    .map(|_| black)
+            }
+        };
+        let sample = match context.scene.lights[index].sample(from, sampler) {
+            Some(x) => x,
+            None => {
+                return mat
+                    .eval(hit_record, &hit_record.shading_normal)
+                    .map(|_| black)
+            }
+        };
+        let direction = sample.shadow_ray.direction;
+        let f = mat.eval(hit_record, &direction)?;
+        let cos_theta = hit_record.shading_normal.dot(direction);
+
+        if cos_theta <= 0.0 {
+            return Some(black);
+        }
+        let occluded = context
+            .scene
+            .accel
+            .collision_any(&sample.shadow_ray, sample.distance * OCCLUSION_TOLERANCE);
+
+        if occluded {
+            return Some(black);
+        }
+        Some(f.mul_element_wise(sample.radiance) * (cos_theta / (sample.pdf * pick_pdf)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        integrator::SerializedIntegrator,
+        material::{Diffuse, Mirror},
+        sampler::Random,
+        test_scene::{hit_at_origin, TestScene, GRAY, LIGHT},
+        types::approx_eq_vec,
+    };
+    use cgmath::Vector3;
+    use std::convert::TryFrom;
+
+    /// A renderer for a unit light above the origin, with an optional sphere in between
+    fn renderer(occluder: bool) -> Renderer {
+        let mut scene = TestScene::new()
+            .integrator(r#"{ "type": "Whitted", "max_depth": 5 }"#)
+            .resolution(1, 1);
+
+        if occluder {
+            scene = scene.sphere([0.0, 1.0, 0.0], 0.3, GRAY);
+        }
+        Renderer::try_from(scene.sphere([0.0, 2.0, 0.0], 0.5, LIGHT).build()).unwrap()
+    }
+
+    /// The average light arriving at a white diffuse surface at the origin facing up
+    fn mean_direct_light(renderer: &Renderer) -> Float {
+        let hit_record = hit_at_origin(Vector3::new(0.0, 1.0, 0.0));
+        let mat = Diffuse {
+            albedo: Vector3::new(1.0, 1.0, 1.0),
+        };
+        let sampler = &mut Random::with_seed(0);
+        let n = 1000;
+        let total: Float = (0..n)
+            .map(|_| {
+                Whitted::direct_light(renderer, sampler, &hit_record, &mat)
+                    .unwrap()
+                    .x
+            })
+            .sum();
+        total / n as Float
+    }
+
+    #[test]
+    fn direct_light() {
+        // The light directly overhead covers a cone with sin(theta) = r / d, and the cosine
+        // weighted integral over that cone of a white surface's BSDF is sin^2(theta)
+        let renderer = renderer(false);
+        assert_eq!(renderer.scene.lights.len(), 1);
+        assert!((mean_direct_light(&renderer) - 0.0625).abs() < 1e-3);
+    }
+
+    #[test]
+    fn occluded_light() {
+        assert_eq!(mean_direct_light(&renderer(true)), 0.0);
+    }
+
+    /// The average color of a sphere in front of a white background, seen head on
+    fn limited_sphere(mat: &str, reflections: Option<u32>, refractions: Option<u32>) -> Float {
+        let mut scene = TestScene::new()
+            .sphere([0.0, 0.0, -3.0], 1.0, mat)
+            .camera(
+                r#"{
+                    "type": "BasicPinhole",
+                    "origin": [0.0, 0.0, 0.0],
+                    "horizontal": [0.01, 0.0, 0.0],
+                    "vertical": [0.0, 0.01, 0.0],
+                    "lower_left": [-0.005, -0.005, -1.0]
+                }"#,
+            )
+            .background("[1, 1, 1]")
+            .samples_per_pixel(64)
+            .resolution(1, 1)
+            .build();
+        scene.seed = Some(1);
+        scene.integrator = SerializedIntegrator::Whitted(Whitted {
+            max_depth: 5,
+            max_reflection_depth: reflections,
+            max_refraction_depth: refractions,
+        });
+        Renderer::try_from(scene)
+            .unwrap()
+            .render(Some(1))
+            .unwrap()
+            .buffer[0]
+            .x
+    }
+
+    #[test]
+    fn separate_depth_limits() {
+        let mirror = r#"{ "type": "Mirror", "perturbation": 0.0, "albedo": [1, 1, 1] }"#;
+        let glass = r#"{ "type": "Dielectric", "refraction_index": 1.5 }"#;
+
+        // The mirror reflects the background straight back, unless reflections are cut off
+        assert!((limited_sphere(mirror, None, None) - 1.0).abs() < 1e-4);
+        assert!((limited_sphere(mirror, None, Some(0)) - 1.0).abs() < 1e-4);
+        assert_eq!(limited_sphere(mirror, Some(0), None), 0.0);
+
+        // Most of the light makes it through the glass, but only a few percent is reflected
+        assert!(limited_sphere(glass, None, None) > 0.9);
+        assert!(limited_sphere(glass, None, Some(1)) < 0.2);
+        assert!(limited_sphere(glass, Some(0), None) > 0.8);
+    }
 
-        // Background is a gradient (temporary measure)
-        let unit_dir = params.origin.direction.normalize();
-        let t = 0.5 * (unit_dir.y + 1.0);
+    #[test]
+    fn ambient_light() {
+        let mut renderer = renderer(false);
+        let hit_record = hit_at_origin(Vector3::new(0.0, 1.0, 0.0));
+        let diffuse = Diffuse {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        };
+        let black = PixelValue::new(0.0, 0.0, 0.0);
+        assert_eq!(
+            Whitted::ambient_light(&renderer, &hit_record, &diffuse),
+            black
+        );
 
-        // linearly interpolate a color based on the angle of the ray
-        return (Vector3::new(1.0, 1.0, 1.0) * (1.0 - t)) + (Vector3::new(0.7, 0.7, 0.7) * t);
+        // A diffuse surface reflects its albedo times the ambient light, and a mirror can't be
+        // evaluated, so it doesn't pick up any
+        renderer.ambient = PixelValue::new(0.2, 0.4, 0.6);
+        let ambient = Whitted::ambient_light(&renderer, &hit_record, &diffuse);
+        assert!(approx_eq_vec(&ambient, &PixelValue::new(0.1, 0.2, 0.3)));
+        assert_eq!(
+            Whitted::ambient_light(&renderer, &hit_record, &Mirror::default()),
+            black
+        );
     }
 }
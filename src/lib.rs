@@ -0,0 +1,75 @@
+//! nib is a physically based ray tracer
+//!
+//! Scenes are described by a [`Scene`], which is usually deserialized from a scene file. A scene
+//! is converted into a [`Renderer`], which renders it into a buffer of linear RGB pixels:
+//!
+//! ```no_run
+//! # fn main() -> anyhow::Result<()> {
+//! let scene: nib::Scene = json5::from_str(&std::fs::read_to_string("scene.json")?)?;
+//! let output = nib::render_scene(scene, None)?;
+//! println!("Rendered {} pixels", output.buffer.len());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Everything that can appear in a scene (geometry, materials, cameras, integrators, and
+//! acceleration structures) has a `Serialized*` enum, which is what the scene holds, and a trait,
+//! which is what the renderer uses at runtime. The command line interface is built on top of this
+//! library and needs the `cli` feature.
+//!
+//! The API is re-exported here, at the root of the crate, and the modules that implement it are
+//! private. The exceptions are `types`, `math`, and `sampler`, which hold the numeric types, vector
+//! helpers, and samplers that the API is written in terms of.
+
+#![allow(clippy::upper_case_acronyms)]
+
+mod accel;
+mod background;
+mod camera;
+mod color;
+mod compare;
+mod filter;
+mod framebuffer;
+mod hittable;
+mod image_exporter;
+mod integrator;
+mod light;
+mod material;
+pub mod math;
+mod post;
+mod processed_scene;
+mod ray;
+mod renderer;
+pub mod sampler;
+mod scene;
+#[cfg(test)]
+mod test_scene;
+pub mod types;
+
+pub use accel::{Accel, SerializedAccelerationStruct};
+pub use camera::{Camera, SerializedCamera};
+#[cfg(feature = "cli")]
+pub use compare::load_image;
+pub use compare::{compare, difference_image, CompareError, CompareResult, ImageDiff};
+pub use framebuffer::Framebuffer;
+pub use hittable::{
+    Aabb, HitRecord, Hittable, OrientedBoundingBox, SerializedHittable, SerializedTextured,
+    MESH_FORMATS,
+};
+#[cfg(feature = "cli")]
+pub use image_exporter::PNGExporter;
+pub use image_exporter::{
+    BitDepth, ChannelOrder, ExporterError, ExporterResult, FramebufferExporter,
+    FramebufferExporterBase, OutputType, PPMExporter, PpmEncoding, OUTPUT_FORMATS,
+};
+pub use integrator::{Integrator, SerializedIntegrator};
+pub use material::{AlbedoCorrection, SerializedMaterial, BSDF};
+pub use post::{downscale, expose, DownscaleKernel, PostError, PostResult};
+pub use processed_scene::ProcessedScene;
+pub use ray::Ray;
+pub use renderer::{
+    render_scene, render_to_rgba, HeatmapMetric, NoiseMode, PixelSampling, ProgressMode,
+    RenderOutput, Renderer,
+};
+pub use sampler::Sampler;
+pub use scene::{Scene, SceneFragment};
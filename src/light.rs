@@ -0,0 +1,194 @@
+//! Lights that integrators can sample directly
+//!
+//! Any object with an emissive material is a light, but an integrator can only aim rays at a
+//! light if it can pick points on the light's surface. The renderer keeps a list of these lights
+//! alongside the arena so integrators can cast shadow rays towards them instead of waiting for a
+//! path to hit them by chance.
+//!
+//! Integrators pick one light per sample with a `LightDistribution`, which favors larger lights,
+//! rather than sampling every light at every surface.
+
+use crate::{
+    hittable::{Hittable, Sphere},
+    material::BSDF,
+    ray::Ray,
+    sampler::Sampler,
+    types::{Float, PixelValue},
+};
+use cgmath::Point3;
+use std::sync::Arc;
+
+/// A light that can be sampled directly
+///
+/// Only spherical lights can be sampled for now.
+#[derive(Debug, Clone)]
+pub struct Light {
+    /// The shape of the light
+    pub shape: Sphere,
+
+    /// The emissive material of the light, shared with its object in the arena
+    pub mat: Arc<dyn BSDF>,
+}
+
+/// The light arriving at a point from a sampled direction towards a light
+#[derive(Debug)]
+pub struct LightSample {
+    /// The ray from the shading point towards the light
+    pub shadow_ray: Ray,
+
+    /// The distance from the shading point to the light along the shadow ray
+    pub distance: Float,
+
+    /// The radiance the light emits towards the shading point
+    pub radiance: PixelValue<Float>,
+
+    /// The pdf of the sampled direction with respect to solid angle
+    pub pdf: Float,
+}
+
+impl Light {
+    /// The surface area of the light
+    pub fn area(&self) -> Float {
+        self.shape.area()
+    }
+
+    /// Sample a direction from `from` towards the light
+    ///
+    /// This doesn't check whether anything is in the way, the caller has to trace the shadow ray
+    /// and compare the distance of the closest collision to `distance`. Returns `None` if the
+    /// sampled direction misses the light, which can happen at the very edge of the light.
+    pub fn sample(
+        &self,
+        from: Point3<Float>,
+        sampler: &mut dyn Sampler<Float>,
+    ) -> Option<LightSample> {
+        let (direction, pdf) = self.shape.sample_toward(from, sampler);
+        let shadow_ray = Ray {
+            origin: from,
+            direction,
+        };
+        // Floating point error can make a direction at the edge of the cone miss the sphere
+        let hit_record = self.shape.hit(&shadow_ray)?;
+        let radiance = self.mat.emitted(&shadow_ray, &hit_record);
+        Some(LightSample {
+            shadow_ray,
+            distance: hit_record.distance,
+            radiance,
+            pdf,
+        })
+    }
+}
+
+/// A discrete distribution over the lights of a scene, weighted by their surface area
+///
+/// Picking lights uniformly would spend as many samples on a tiny light as on a huge one, even
+/// though the huge one usually lights much more of the scene. Picking lights in proportion to
+/// their area spends the samples where most of the light comes from. Every pick comes with its
+/// probability, which the contribution of the light has to be divided by to stay unbiased.
+#[derive(Debug, Clone, Default)]
+pub struct LightDistribution {
+    /// The probability of picking each light
+    pmf: Vec<Float>,
+
+    /// The cumulative probability of picking each light or any light before it
+    cdf: Vec<Float>,
+}
+
+impl LightDistribution {
+    /// Build the distribution for a list of lights
+    ///
+    /// If the lights have no area at all, they're picked uniformly instead.
+    pub fn new(lights: &[Light]) -> Self {
+        let areas: Vec<Float> = lights.iter().map(|light| light.area().max(0.0)).collect();
+        let total: Float = areas.iter().sum();
+        let pmf: Vec<Float> = if total > 0.0 && total.is_finite() {
+            areas.iter().map(|area| area / total).collect()
+        } else {
+            vec![1.0 / lights.len() as Float; lights.len()]
+        };
+        let cdf = pmf
+            .iter()
+            .scan(0.0, |sum, p| {
+                *sum += p;
+                Some(*sum)
+            })
+            .collect();
+        Self { pmf, cdf }
+    }
+
+    /// Pick a light with a uniform random number `u` in [0, 1)
+    ///
+    /// Returns the index of the light and the probability that it was picked, or `None` if there
+    /// are no lights.
+    pub fn sample(&self, u: Float) -> Option<(usize, Float)> {
+        if self.pmf.is_empty() {
+            return None;
+        }
+        // Rounding can leave the last entry of the CDF just under 1
+        let index = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .min(self.pmf.len() - 1);
+        Some((index, self.pmf[index]))
+    }
+
+    /// The probability of picking the light at some index
+    pub fn pmf(&self, index: usize) -> Float {
+        self.pmf.get(index).copied().unwrap_or(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Mirror;
+
+    fn light(radius: Float) -> Light {
+        Light {
+            shape: Sphere {
+                center: Point3::new(0.0, 0.0, 0.0),
+                radius,
+            },
+            mat: Arc::new(Mirror::default()),
+        }
+    }
+
+    #[test]
+    fn area_weighted_selection() {
+        // The areas are 1:4:9
+        let distribution = LightDistribution::new(&[light(1.0), light(2.0), light(3.0)]);
+        let expected = [1.0 / 14.0, 4.0 / 14.0, 9.0 / 14.0];
+
+        for (i, p) in expected.iter().enumerate() {
+            assert!((distribution.pmf(i) - p).abs() < 1e-6);
+        }
+        assert_eq!(distribution.pmf(3), 0.0);
+
+        // Stratified picks land on each light in proportion to its area
+        let n = 14_000;
+        let mut counts = [0; 3];
+
+        for i in 0..n {
+            let (index, pmf) = distribution
+                .sample((i as Float + 0.5) / n as Float)
+                .unwrap();
+            assert_eq!(pmf, distribution.pmf(index));
+            counts[index] += 1;
+        }
+        for (count, p) in counts.iter().zip(&expected) {
+            assert!((*count as Float / n as Float - p).abs() < 1e-3);
+        }
+        assert_eq!(distribution.sample(0.0).unwrap().0, 0);
+        assert_eq!(distribution.sample(0.999_999_9).unwrap().0, 2);
+    }
+
+    #[test]
+    fn degenerate_distributions() {
+        assert!(LightDistribution::new(&[]).sample(0.5).is_none());
+
+        // Lights without any area are picked uniformly
+        let distribution = LightDistribution::new(&[light(0.0), light(0.0)]);
+        assert_eq!(distribution.sample(0.25), Some((0, 0.5)));
+        assert_eq!(distribution.sample(0.75), Some((1, 0.5)));
+    }
+}
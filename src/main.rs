@@ -1,39 +1,232 @@
+#[cfg(not(target_arch = "wasm32"))]
 #[global_allocator]
-static GLOBAL: MiMalloc = MiMalloc;
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
-mod accel;
-mod camera;
 mod cli;
-mod hittable;
-mod image_exporter;
-mod integrator;
-mod material;
-mod math;
-mod ray;
-mod renderer;
-mod sampler;
-mod scene;
-mod types;
-
-use crate::{
-    image_exporter::{FramebufferExporter, PPMExporter},
-    renderer::Renderer,
+
+use anyhow::format_err;
+use cli::{
+    dispatch_scene_parse, expand_output_template, export_scene, list_formats, print_info, Args,
+    OutputNameValues,
+};
+use log::{info, warn};
+use nib::{
+    FramebufferExporter, OutputType, PNGExporter, PPMExporter, ProgressMode, RenderOutput, Renderer,
 };
-use anyhow;
-use cli::{dispatch_scene_parse, Args};
-use mimalloc::MiMalloc;
-use std::{convert::TryFrom, path::Path};
+use std::{convert::TryFrom, path::PathBuf};
 use structopt::StructOpt;
 
 fn main() -> anyhow::Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
     let args = Args::from_args();
-    let scene = dispatch_scene_parse(&args.scene, args.filetype.as_deref())?;
+
+    if args.list_formats {
+        list_formats();
+        return Ok(());
+    }
+    // structopt guarantees that the scene is supplied unless we're listing formats
+    let scene_path = args.scene.expect("a scene file is required");
+    let mut scene = dispatch_scene_parse(&scene_path, args.filetype.as_deref())?;
+
+    if args.only_parse {
+        println!("Parsed \"{}\" successfully", scene_path.to_string_lossy());
+        return Ok(());
+    }
+
+    if args.max_time.is_some() {
+        scene.max_time = args.max_time;
+    }
+
+    if args.seed.is_some() {
+        scene.seed = args.seed;
+    }
+
+    if args.max_primitives.is_some() {
+        scene.max_primitives = args.max_primitives;
+    }
+
+    if let Some(pixel_sampling) = args.pixel_sampling {
+        scene.pixel_sampling = Some(pixel_sampling);
+    }
+
+    if let Some(aa_filter) = args.aa_filter {
+        scene.pixel_sampling = Some(aa_filter.pixel_sampling());
+    }
+
+    if let Some(exposure) = args.exposure {
+        scene.exposure = exposure;
+    }
+
+    if args.aspect_ratio.is_some() {
+        scene.aspect_ratio = args.aspect_ratio;
+    }
+
+    if (args.cam_origin.is_some() || args.cam_target.is_some())
+        && !scene.override_camera(args.cam_origin, args.cam_target)
+    {
+        warn!("The camera origin and target can't be overridden for a BasicPinhole camera");
+    }
+
+    if let Some(factor) = args.scale {
+        scene.scale_resolution(factor)?;
+    }
+
+    if let Some(path) = &args.export_scene {
+        export_scene(&scene, path)?;
+        println!("Exported the scene to \"{}\"", path.to_string_lossy());
+        return Ok(());
+    }
     let (height, width) = (scene.height, scene.width);
+    let scene_name = scene_path
+        .file_stem()
+        .map(|x| x.to_string_lossy())
+        .unwrap_or_default();
+
+    // The output name and the exporters use the size of the output image, not the size of the
+    // supersampled render
+    if let Some(factor) = args.supersample {
+        let supersampled = |x: u32| {
+            x.checked_mul(factor)
+                .filter(|&x| x > 0)
+                .ok_or_else(|| format_err!("Can't supersample by a factor of {}", factor))
+        };
+        scene.width = supersampled(width)?;
+        scene.height = supersampled(height)?;
+    }
+
+    if args.info {
+        return print_info(&scene_name, scene);
+    }
+    let output_name = expand_output_template(
+        args.output.as_deref().unwrap_or("out.png"),
+        &OutputNameValues {
+            scene: &scene_name,
+            frame: args.frame,
+            width,
+            height,
+            integrator: scene.integrator.name(),
+        },
+    )?;
+    let output_path = match &args.output_dir {
+        Some(dir) => dir.join(output_name),
+        None => PathBuf::from(output_name),
+    };
+    let output_path = output_path.as_path();
+
+    // The output name can have directories in it too, like `{integrator}/{frame:04}.png`
+    if let Some(dir) = output_path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
     let mut renderer = Renderer::try_from(scene)?;
-    let buffer = renderer.render(args.threads)?;
-    let exporter = PPMExporter { width, height };
-    let output_str = &args.output.unwrap_or("out.ppm".to_string());
-    let output_path = Path::new(output_str);
+    let stats = renderer.scene.stats;
+    info!(
+        "Prepared {} objects ({} primitives, {} materials, {} lights) in {:.2?}",
+        stats.objects, stats.primitives, stats.materials, stats.lights, stats.elapsed
+    );
+    renderer.chunk_size = args.chunk_size;
+    renderer.progress = if args.hide_progress {
+        ProgressMode::Never
+    } else if args.show_progress {
+        ProgressMode::Always
+    } else {
+        ProgressMode::Auto
+    };
+    renderer.track_convergence = args.log_convergence;
+    let seed = renderer.use_frame_seed(args.frame, args.animate_noise);
+    info!("Rendering frame {} with seed {}", args.frame, seed);
+    let (bit_depth, channel_order, ppm_encoding) =
+        (args.bit_depth, args.channel_order, args.ppm_encoding);
+    // Figure out the output type before rendering so we don't throw away a render because of a
+    // typo in the filename
+    let exporter_for = |path| -> anyhow::Result<Box<dyn FramebufferExporter>> {
+        Ok(match OutputType::from_path(path)? {
+            OutputType::PNG => Box::new(PNGExporter {
+                width,
+                height,
+                bit_depth,
+                channel_order,
+            }),
+            OutputType::PPM => Box::new(PPMExporter {
+                width,
+                height,
+                bit_depth,
+                channel_order,
+                encoding: ppm_encoding,
+            }),
+        })
+    };
+    let exporter = exporter_for(output_path)?;
+    let diff_exporter = args
+        .compare_output
+        .as_deref()
+        .map(exporter_for)
+        .transpose()?;
+    // Same goes for the reference image
+    let reference = match &args.compare {
+        Some(reference_path) => {
+            let (reference, ref_width, ref_height) = nib::load_image(reference_path)?;
+
+            if (ref_width, ref_height) != (width, height) {
+                return Err(format_err!(
+                    "The reference image is {}x{}, but the render is {}x{}",
+                    ref_width,
+                    ref_height,
+                    width,
+                    height
+                ));
+            }
+            Some(reference)
+        }
+        None => None,
+    };
+    let buffer = match args.heatmap {
+        Some(metric) => {
+            let (buffer, max_count) = renderer.render_heatmap(metric, args.threads)?;
+            info!(
+                "The busiest pixel has a {:?} count of {}",
+                metric, max_count
+            );
+            buffer
+        }
+        None => {
+            let RenderOutput {
+                buffer,
+                elapsed,
+                samples_taken,
+                terminated_early,
+                ..
+            } = renderer.render(args.threads)?;
+            info!(
+                "Rendered {} samples per pixel in {:.2?}",
+                samples_taken, elapsed
+            );
+
+            if terminated_early {
+                info!(
+                    "The render ran out of time after {} of {} samples per pixel",
+                    samples_taken, renderer.samples_per_pixel
+                );
+            }
+            buffer
+        }
+    };
+    let buffer = if args.supersample.is_some() {
+        nib::downscale(&buffer, (width, height), args.downscale_kernel)?
+    } else {
+        buffer
+    };
     exporter.export(&buffer[..], output_path)?;
+
+    if let Some(reference) = reference {
+        let diff = nib::compare(&buffer, &reference)?;
+        println!("Max absolute error: {}", diff.max_abs_error);
+        println!("Mean squared error: {}", diff.mse);
+        println!("PSNR: {:.2} dB", diff.psnr);
+
+        if let (Some(diff_exporter), Some(diff_path)) = (diff_exporter, &args.compare_output) {
+            let image = nib::difference_image(&buffer, &reference)?;
+            diff_exporter.export(&image[..], diff_path)?;
+        }
+    }
     Ok(())
 }
@@ -1,5 +1,253 @@
+use crate::{
+    hittable::HitRecord,
+    material::{clamp_unit, AlbedoCorrection, BSDFRecord, BSDF},
+    math::{from_local, mirror, safe_normalize},
+    ray::Ray,
+    sampler::{
+        primitives::{sample_phong_lobe, sample_unit_sphere},
+        Sampler,
+    },
+    types::Float,
+};
+use cgmath::{ElementWise, InnerSpace, Vector3};
+use serde::{Deserialize, Serialize};
+
 /// An implementation of the Blinn-Phong BSDF
 ///
 /// This simulates a reflective model that was pioneered by Blinn & Phong. It is a way to
-/// approximate partially glossy surfaces.
-pub struct BlinnPhong {}
+/// approximate partially glossy surfaces, as a diffuse base with a glossy highlight on top. The
+/// highlight is the normalized Blinn-Phong lobe, which is proportional to `cos^shininess` of the
+/// angle between the normal and the half-vector between the incoming and outgoing directions.
+///
+/// Each scattered ray comes from either the diffuse or the specular part of the BSDF, picked with
+/// a probability proportional to the brightness of their albedos. The BSDF can't be evaluated
+/// without the direction the light is reflected towards, so lights are found by the scattered ray
+/// rather than being sampled directly.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct BlinnPhong {
+    /// The color of the diffuse base of the surface
+    #[serde(deserialize_with = "crate::color::deserialize")]
+    pub diffuse_albedo: Vector3<Float>,
+
+    /// The color of the glossy highlight
+    ///
+    /// The diffuse and specular albedos have to add up to at most 1 in each channel, otherwise the
+    /// surface reflects more light than it receives. Albedos that add up to more are scaled down
+    /// when the scene is loaded.
+    #[serde(deserialize_with = "crate::color::deserialize")]
+    pub specular_albedo: Vector3<Float>,
+
+    /// The exponent of the specular lobe
+    ///
+    /// Larger exponents make for smaller, sharper highlights. An exponent of 0 spreads the
+    /// highlight over the whole hemisphere.
+    pub shininess: Float,
+}
+
+impl BlinnPhong {
+    /// Clamp the albedos so that the surface doesn't reflect more light than it receives
+    ///
+    /// Each albedo is clamped to [0, 1], and then in every channel where they add up to more than
+    /// 1, both are scaled down by the same factor, which keeps the balance between the base and
+    /// the highlight. Returns the first albedo that had to be clamped, or the original albedos if
+    /// only their sum was too large, and `None` if the albedos were already valid.
+    pub fn clamp_albedo(&mut self) -> Option<AlbedoCorrection> {
+        let diffuse = clamp_unit(&mut self.diffuse_albedo);
+        let specular = clamp_unit(&mut self.specular_albedo);
+        let (diffuse_albedo, specular_albedo) = (self.diffuse_albedo, self.specular_albedo);
+        let total = diffuse_albedo + specular_albedo;
+        let scale = total.map(|x| if x > 1.0 { 1.0 / x } else { 1.0 });
+        self.diffuse_albedo.mul_assign_element_wise(scale);
+        self.specular_albedo.mul_assign_element_wise(scale);
+
+        if let Some(albedo) = diffuse.or(specular) {
+            Some(AlbedoCorrection::Clamped(albedo))
+        } else if total.x > 1.0 || total.y > 1.0 || total.z > 1.0 {
+            Some(AlbedoCorrection::Scaled {
+                diffuse: diffuse_albedo,
+                specular: specular_albedo,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// The probability that a scattered ray samples the specular lobe
+    fn specular_probability(&self) -> Float {
+        let diffuse = self.diffuse_albedo.x + self.diffuse_albedo.y + self.diffuse_albedo.z;
+        let specular = self.specular_albedo.x + self.specular_albedo.y + self.specular_albedo.z;
+
+        if diffuse + specular <= 0.0 {
+            0.0
+        } else {
+            specular / (diffuse + specular)
+        }
+    }
+
+    /// Sample a direction from the specular lobe
+    ///
+    /// Returns the direction along with the specular BSDF times the cosine term, divided by the
+    /// probability density of the direction. This is zero if the direction is below the surface.
+    fn scatter_specular(
+        &self,
+        s: &mut dyn Sampler<Float>,
+        incoming: &Vector3<Float>,
+        normal: &Vector3<Float>,
+    ) -> (Vector3<Float>, Float) {
+        let shininess = self.shininess.max(0.0);
+        let (local, _) = sample_phong_lobe(shininess, s);
+        let half = from_local(&local, normal);
+        let direction = mirror(incoming, &half);
+        let cos_theta = normal.dot(direction);
+        let cos_half = half.dot(direction);
+
+        if cos_theta <= 0.0 || cos_half <= 0.0 {
+            return (direction, 0.0);
+        }
+        // The half-vector has a density of (n + 1) / (2 pi) cos^n(theta_h), which becomes
+        // density / (4 cos_half) for the reflected direction, so everything but the cosines
+        // cancels against the normalized lobe, (n + 8) / (8 pi) cos^n(theta_h)
+        let weight = (shininess + 8.0) / (shininess + 1.0) * cos_half * cos_theta;
+        (direction, weight)
+    }
+}
+
+impl BSDF for BlinnPhong {
+    fn scatter(&self, s: &mut dyn Sampler<Float>, ray: &Ray, hit_record: &HitRecord) -> BSDFRecord {
+        let normal = hit_record.shading_normal;
+        let incoming = safe_normalize(&ray.direction).unwrap_or(-normal);
+        let specular_probability = self.specular_probability();
+        let u = s.next(1).unwrap()[0];
+
+        let (direction, attenuation) = if u < specular_probability {
+            let (direction, weight) = self.scatter_specular(s, &incoming, &normal);
+            (
+                direction,
+                self.specular_albedo * (weight / specular_probability),
+            )
+        } else {
+            // The same cosine-weighted sampling as the diffuse BSDF, so the cosine and the
+            // density cancel out
+            let direction = safe_normalize(&(normal + sample_unit_sphere(s))).unwrap_or(normal);
+            (
+                direction,
+                self.diffuse_albedo / (1.0 - specular_probability),
+            )
+        };
+
+        // A highlight sampled around a grazing reflection can point into the surface, in which
+        // case it doesn't carry any light and we send it out along the normal instead
+        let (direction, attenuation) = if normal.dot(direction) > 0.0 {
+            (direction, attenuation)
+        } else {
+            (normal, Vector3::new(0.0, 0.0, 0.0))
+        };
+        BSDFRecord {
+            out: Ray {
+                origin: hit_record.p,
+                direction,
+            },
+            attenuation,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sampler::Random, test_scene::hit_at_origin, types::approx_eq_vec};
+    use cgmath::Point3;
+
+    #[test]
+    fn upper_hemisphere() {
+        let mut sampler = Random::with_seed(3);
+        let hit_record = hit_at_origin(Vector3::new(0.0, 0.0, 1.0));
+        let head_on = Vector3::new(0.2, 0.0, -1.0).normalize();
+        let grazing = Vector3::new(1.0, 0.0, -0.05).normalize();
+
+        for direction in [head_on, grazing] {
+            for shininess in [0.0, 10.0, 500.0] {
+                let mat = BlinnPhong {
+                    diffuse_albedo: Vector3::new(0.3, 0.2, 0.1),
+                    specular_albedo: Vector3::new(0.5, 0.5, 0.5),
+                    shininess,
+                };
+                let ray = Ray {
+                    origin: Point3::new(0.0, 0.0, 0.0) - direction,
+                    direction,
+                };
+
+                for _ in 0..1000 {
+                    let record = mat.scatter(&mut sampler, &ray, &hit_record);
+                    assert!(record.out.direction.dot(hit_record.shading_normal) > 0.0);
+                    assert!((record.out.direction.magnitude() - 1.0).abs() < 1e-4);
+                    assert!(record.attenuation.x >= 0.0 && record.attenuation.x.is_finite());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sharp_highlight() {
+        // With a large exponent and no diffuse base, rays leave close to the mirror direction
+        let mut sampler = Random::with_seed(5);
+        let mat = BlinnPhong {
+            diffuse_albedo: Vector3::new(0.0, 0.0, 0.0),
+            specular_albedo: Vector3::new(1.0, 1.0, 1.0),
+            shininess: 10_000.0,
+        };
+        let hit_record = hit_at_origin(Vector3::new(0.0, 1.0, 0.0));
+        let ray = Ray {
+            origin: Point3::new(-1.0, 1.0, 0.0),
+            direction: Vector3::new(1.0, -1.0, 0.0).normalize(),
+        };
+        let expected = Vector3::new(1.0, 1.0, 0.0).normalize();
+
+        for _ in 0..100 {
+            let record = mat.scatter(&mut sampler, &ray, &hit_record);
+            assert!(record.out.direction.dot(expected) > 0.99);
+        }
+    }
+
+    #[test]
+    fn clamp_albedo() {
+        let mut mat = BlinnPhong {
+            diffuse_albedo: Vector3::new(0.6, 0.2, 1.5),
+            specular_albedo: Vector3::new(0.6, 0.5, 0.0),
+            shininess: 10.0,
+        };
+        assert_eq!(
+            mat.clamp_albedo(),
+            Some(AlbedoCorrection::Clamped(Vector3::new(0.6, 0.2, 1.5)))
+        );
+        assert!(approx_eq_vec(
+            &mat.diffuse_albedo,
+            &Vector3::new(0.5, 0.2, 1.0)
+        ));
+        assert!(approx_eq_vec(
+            &mat.specular_albedo,
+            &Vector3::new(0.5, 0.5, 0.0)
+        ));
+        // Clamping is idempotent
+        assert_eq!(mat.clamp_albedo(), None);
+
+        // Albedos that are valid on their own are still scaled down if their sum is too large
+        mat.diffuse_albedo = Vector3::new(0.8, 0.2, 0.2);
+        mat.specular_albedo = Vector3::new(0.8, 0.2, 0.2);
+        assert_eq!(
+            mat.clamp_albedo(),
+            Some(AlbedoCorrection::Scaled {
+                diffuse: Vector3::new(0.8, 0.2, 0.2),
+                specular: Vector3::new(0.8, 0.2, 0.2),
+            })
+        );
+        assert!(approx_eq_vec(
+            &mat.diffuse_albedo,
+            &Vector3::new(0.5, 0.2, 0.2)
+        ));
+        assert!(approx_eq_vec(
+            &mat.specular_albedo,
+            &Vector3::new(0.5, 0.2, 0.2)
+        ));
+    }
+}
@@ -2,8 +2,8 @@
 
 use crate::{
     hittable::HitRecord,
-    material::{BSDFRecord, BSDF},
-    math::{mirror, schlick},
+    material::{fresnel::fresnel_dielectric, BSDFRecord, BSDF, VACUUM_IOR},
+    math::{mirror, safe_normalize},
     ray::Ray,
     sampler::Sampler,
     types::Float,
@@ -26,7 +26,10 @@ pub struct Dielectric {
     ///
     /// This is just the color/tint of the material. The default value for this is `[1.0, 1.0,
     /// 1.0]`
-    #[serde(default = "default_albedo")]
+    #[serde(
+        default = "default_albedo",
+        deserialize_with = "crate::color::deserialize"
+    )]
     pub albedo: Vector3<Float>,
 }
 
@@ -49,44 +52,66 @@ impl Default for Dielectric {
 
 /// Determine whether a ray will refract given the parameters
 ///
-/// This method will return a refraction vector if the method refracts. If the material doesn't
+/// `v` is the incoming direction, `n` is the unit normal on the side of the surface the ray is
+/// coming from, and `ni_over_nt` is the ratio of the index of refraction the ray is leaving to the
+/// one it's entering. This method will return the refracted direction and the cosine of the angle
+/// between the refracted direction and the normal if the ray refracts. If the material doesn't
 /// refract (meaning that there will be total internal reflection), then it will return the `None`
 /// variant.
-fn refract(v: Vector3<Float>, n: Vector3<Float>, ni_over_nt: Float) -> Option<Vector3<Float>> {
-    // unit vector
-    let uv = v.normalize();
-    let dt = uv.dot(n);
-    let discriminant = 1.0 - ni_over_nt * ni_over_nt * (1.0 - dt * dt);
-    if discriminant > 1.0 {
-        Some(
-            ((uv - n.map(|x| x * dt)) - n.map(|x| x * discriminant.sqrt())).map(|x| x * ni_over_nt),
-        )
-    } else {
-        None
+fn refract(
+    v: Vector3<Float>,
+    n: Vector3<Float>,
+    ni_over_nt: Float,
+) -> Option<(Vector3<Float>, Float)> {
+    // unit vector, if the incoming direction is degenerate we treat it as a total internal
+    // reflection
+    let uv = safe_normalize(&v)?;
+    let cos_incident = -uv.dot(n);
+    let discriminant = 1.0 - ni_over_nt * ni_over_nt * (1.0 - cos_incident * cos_incident);
+
+    if discriminant < 0.0 {
+        return None;
     }
+    let cos_transmitted = discriminant.sqrt();
+    let refracted = (uv * ni_over_nt) + (n * ((ni_over_nt * cos_incident) - cos_transmitted));
+    Some((refracted, cos_transmitted))
 }
 
 impl BSDF for Dielectric {
     fn scatter(&self, s: &mut dyn Sampler<Float>, ray: &Ray, hit_record: &HitRecord) -> BSDFRecord {
-        let reflection_vector = mirror(&ray.direction, &hit_record.normal);
-        let (outward_normal, ni_over_nt, cosine) = if ray.direction.dot(hit_record.normal) > 0.0 {
+        self.scatter_in_medium(s, ray, hit_record, VACUUM_IOR)
+    }
+
+    /// Scatter a ray at the boundary between this material and the surrounding medium
+    ///
+    /// When the ray hits the front of the surface it's entering the dielectric from the
+    /// surrounding medium, otherwise it's leaving the dielectric for the surrounding medium.
+    fn scatter_in_medium(
+        &self,
+        s: &mut dyn Sampler<Float>,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        outside_ior: Float,
+    ) -> BSDFRecord {
+        let reflection_vector = mirror(&ray.direction, &hit_record.shading_normal);
+        // We need the normal on the side the ray is coming from
+        let (normal, ni, nt) = if hit_record.front_face {
             (
-                -hit_record.normal,
+                hit_record.shading_normal,
+                outside_ior,
                 self.refraction_index,
-                self.refraction_index * ray.direction.dot(hit_record.normal)
-                    / ray.direction.magnitude(),
             )
         } else {
             (
-                hit_record.normal,
-                1.0 / self.refraction_index,
-                -self.refraction_index * ray.direction.dot(hit_record.normal)
-                    / ray.direction.magnitude(),
+                -hit_record.shading_normal,
+                self.refraction_index,
+                outside_ior,
             )
         };
-        let outgoing_direction = match refract(ray.direction, outward_normal, ni_over_nt) {
-            Some(refracted) => {
-                let reflection_prob = schlick(cosine, self.refraction_index);
+        let outgoing_direction = match refract(ray.direction, normal, ni / nt) {
+            Some((refracted, _)) => {
+                let cosine = -ray.direction.normalize().dot(normal);
+                let reflection_prob = fresnel_dielectric(cosine, nt / ni);
                 let r = s.next(1).unwrap()[0];
 
                 if r < reflection_prob {
@@ -98,11 +123,117 @@ impl BSDF for Dielectric {
             None => reflection_vector,
         };
         BSDFRecord {
-            attenuation: Vector3::new(1.0, 1.0, 1.0),
+            attenuation: self.albedo,
             out: Ray {
                 origin: hit_record.p,
                 direction: outgoing_direction,
             },
         }
     }
+
+    fn refraction_index(&self) -> Option<Float> {
+        Some(self.refraction_index)
+    }
+
+    /// A smooth dielectric only reflects or refracts in the one direction that Fresnel picks
+    fn is_specular(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sampler::Random, test_scene::hit_at_origin};
+    use cgmath::Point3;
+
+    fn hit_record(front_face: bool) -> HitRecord {
+        HitRecord {
+            front_face,
+            ..hit_at_origin(Vector3::new(0.0, 0.0, 1.0))
+        }
+    }
+
+    /// The sine of the angle between a direction and the z axis
+    fn sin_theta(v: Vector3<Float>) -> Float {
+        let v = v.normalize();
+        (v.x * v.x + v.y * v.y).sqrt()
+    }
+
+    /// A ray passing between two media with the same index of refraction isn't bent at all
+    #[test]
+    fn matched_media_pass_straight_through() {
+        let mut sampler = Random::default();
+        let glass = Dielectric {
+            refraction_index: 1.5,
+            ..Default::default()
+        };
+        let ray = Ray {
+            origin: Point3::new(0.0, 0.0, 1.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+
+        for _ in 0..100 {
+            let record = glass.scatter_in_medium(&mut sampler, &ray, &hit_record(true), 1.5);
+            assert!((record.out.direction - ray.direction).magnitude() < 1e-5);
+        }
+    }
+
+    /// Transmitted rays obey Snell's law with the outside index of refraction, for both entering
+    /// and leaving the material
+    #[test]
+    fn snells_law() {
+        let mut sampler = Random::default();
+        let water = Dielectric {
+            refraction_index: 1.33,
+            ..Default::default()
+        };
+        let glass_ior = 1.5;
+        let cases = [
+            (true, Vector3::new(0.5, 0.0, -1.0), glass_ior, 1.33),
+            (false, Vector3::new(0.3, 0.0, 1.0), 1.33, glass_ior),
+        ];
+
+        for (front_face, direction, ni, nt) in cases {
+            let ray = Ray {
+                origin: Point3::new(0.0, 0.0, 0.0) - direction,
+                direction,
+            };
+            let mut transmitted = 0;
+
+            for _ in 0..200 {
+                let record =
+                    water.scatter_in_medium(&mut sampler, &ray, &hit_record(front_face), glass_ior);
+                let out = record.out.direction;
+
+                // Skip the reflected samples
+                if out.z * direction.z < 0.0 {
+                    continue;
+                }
+                transmitted += 1;
+                assert!((ni * sin_theta(direction) - nt * sin_theta(out)).abs() < 1e-4);
+            }
+            assert!(transmitted > 0);
+        }
+    }
+
+    /// Leaving glass for a vacuum at a grazing angle always reflects
+    #[test]
+    fn total_internal_reflection() {
+        let mut sampler = Random::default();
+        let glass = Dielectric {
+            refraction_index: 1.5,
+            ..Default::default()
+        };
+        let ray = Ray {
+            origin: Point3::new(0.0, 0.0, 0.0),
+            direction: Vector3::new(1.0, 0.0, 0.2),
+        };
+
+        for _ in 0..100 {
+            let record =
+                glass.scatter_in_medium(&mut sampler, &ray, &hit_record(false), VACUUM_IOR);
+            assert!(record.out.direction.z < 0.0);
+        }
+    }
 }
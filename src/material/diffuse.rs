@@ -1,12 +1,14 @@
 use crate::{
     hittable::HitRecord,
     material::{BSDFRecord, BSDF},
+    math::safe_normalize,
     ray::Ray,
     sampler::{primitives::sample_unit_sphere, Sampler},
-    types::Float,
+    types::{Float, PixelValue},
 };
-use cgmath::Vector3;
+use cgmath::{InnerSpace, Vector3};
 use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
 
 /// A diffuse BSDF function
 ///
@@ -15,6 +17,7 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Diffuse {
     /// The fraction of light that is absorbed for each color channel.
+    #[serde(deserialize_with = "crate::color::deserialize")]
     pub albedo: Vector3<Float>,
 }
 
@@ -25,12 +28,27 @@ impl BSDF for Diffuse {
         _ray: &Ray,
         hit_record: &HitRecord,
     ) -> BSDFRecord {
-        let target = hit_record.p + hit_record.normal + sample_unit_sphere(s);
+        // The sample can land exactly opposite the normal, in which case the direction is
+        // degenerate and we just scatter along the normal
+        let direction = safe_normalize(&(hit_record.shading_normal + sample_unit_sphere(s)))
+            .unwrap_or(hit_record.shading_normal);
         let out = Ray {
             origin: hit_record.p,
-            direction: (target - hit_record.p),
+            direction,
         };
         let attenuation = self.albedo;
         BSDFRecord { out, attenuation }
     }
+
+    fn eval(
+        &self,
+        hit_record: &HitRecord,
+        direction: &Vector3<Float>,
+    ) -> Option<PixelValue<Float>> {
+        // Light can't reach the surface from below it
+        if hit_record.shading_normal.dot(*direction) <= 0.0 {
+            return Some(PixelValue::new(0.0, 0.0, 0.0));
+        }
+        Some(self.albedo / PI)
+    }
 }
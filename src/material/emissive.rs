@@ -0,0 +1,272 @@
+//! A material for surfaces that emit light
+
+use crate::{
+    hittable::HitRecord,
+    material::{BSDFRecord, BSDF},
+    math::safe_normalize,
+    ray::Ray,
+    sampler::Sampler,
+    types::{string_enum, Float, PixelValue},
+};
+use cgmath::{InnerSpace, Vector3};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+string_enum! {
+    /// The unit that the emission of a light is specified in
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum EmissionUnit {
+        /// The radiance emitted from every point on the surface
+        ///
+        /// A light's brightness doesn't depend on its size, so a larger light emits more power in
+        /// total.
+        #[default]
+        Radiance,
+
+        /// The total power emitted by the whole surface, in watts
+        ///
+        /// The power is spread out over the surface of the light, so a larger light is dimmer up
+        /// close but lights the scene just as much.
+        Watts,
+    }
+}
+
+/// A directional falloff for the light leaving an emissive surface
+///
+/// Light leaving the surface within `inner_angle` of `axis` is emitted at full strength, and it
+/// falls off smoothly to nothing at `outer_angle`. This models spotlights and recessed lights,
+/// which only light up the area in front of them.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SpotProfile {
+    /// The direction the light points in
+    ///
+    /// This doesn't need to be normalized.
+    pub axis: Vector3<Float>,
+
+    /// The angle from the axis, in degrees, within which light is emitted at full strength
+    pub inner_angle: Float,
+
+    /// The angle from the axis, in degrees, beyond which no light is emitted
+    pub outer_angle: Float,
+}
+
+impl SpotProfile {
+    /// The fraction of the light emitted in `direction`, which lies in [0, 1]
+    pub fn falloff(&self, direction: &Vector3<Float>) -> Float {
+        let cos_angle = match (safe_normalize(&self.axis), safe_normalize(direction)) {
+            (Some(axis), Some(direction)) => axis.dot(direction),
+            _ => return 0.0,
+        };
+        let cos_inner = self.inner_angle.to_radians().cos();
+        let cos_outer = self.outer_angle.to_radians().cos();
+
+        // A cone without any falloff has a hard edge
+        if cos_inner <= cos_outer {
+            return if cos_angle >= cos_outer { 1.0 } else { 0.0 };
+        }
+        let t = ((cos_angle - cos_outer) / (cos_inner - cos_outer)).clamp(0.0, 1.0);
+        t * t * (3.0 - (2.0 * t))
+    }
+}
+
+/// A light-emitting surface
+///
+/// Emissive surfaces are lights: they emit a constant radiance and absorb any light that hits
+/// them. By default, a surface only emits light from its front face (the side its normal points
+/// towards), which is what you want for something like a ceiling panel. Set `two_sided` to make
+/// the surface emit from both sides.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Emissive {
+    /// The light emitted by the surface in each color channel, in the units given by `unit`
+    ///
+    /// Unlike albedo values, this isn't limited to the range [0, 1]. This used to always be a
+    /// radiance, so it can also be written as `radiance` in scene files.
+    #[serde(alias = "radiance", deserialize_with = "crate::color::deserialize")]
+    pub emission: PixelValue<Float>,
+
+    /// The unit of `emission`
+    ///
+    /// This defaults to radiance. Power is converted to radiance when the scene is loaded, once
+    /// the surface area of the light is known.
+    #[serde(default)]
+    pub unit: EmissionUnit,
+
+    /// Whether the surface emits light from its back face as well as its front face
+    #[serde(default)]
+    pub two_sided: bool,
+
+    /// An optional directional falloff for the emitted light
+    ///
+    /// Without one, the surface emits light uniformly in every direction. A light's power is
+    /// converted to radiance as if it emitted uniformly, so a spotlight given in watts emits less
+    /// than that in total.
+    #[serde(default)]
+    pub spot: Option<SpotProfile>,
+}
+
+impl Emissive {
+    /// Convert a light's power to the radiance it emits, given the surface area of the light
+    ///
+    /// A surface that emits the same radiance `L` in every direction from each side emits a total
+    /// power of `L * pi * area` per side. A light that's already specified as a radiance is left
+    /// as is, and a light with no area emits nothing.
+    pub fn resolve_power(&mut self, area: Float) {
+        if self.unit == EmissionUnit::Radiance {
+            return;
+        }
+        let sides = if self.two_sided { 2.0 } else { 1.0 };
+        let emitting_area = sides * PI * area;
+        self.emission = if emitting_area > 0.0 {
+            self.emission / emitting_area
+        } else {
+            PixelValue::new(0.0, 0.0, 0.0)
+        };
+        self.unit = EmissionUnit::Radiance;
+    }
+}
+
+impl BSDF for Emissive {
+    fn scatter(
+        &self,
+        _s: &mut dyn Sampler<Float>,
+        ray: &Ray,
+        hit_record: &HitRecord,
+    ) -> BSDFRecord {
+        // Lights absorb everything that hits them, so the direction of the outgoing ray doesn't
+        // matter
+        BSDFRecord {
+            out: Ray {
+                origin: hit_record.p,
+                direction: ray.direction,
+            },
+            attenuation: Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    fn emitted(&self, ray: &Ray, hit_record: &HitRecord) -> PixelValue<Float> {
+        debug_assert_eq!(
+            self.unit,
+            EmissionUnit::Radiance,
+            "a light's power must be resolved before rendering"
+        );
+        if !(hit_record.front_face || self.two_sided) {
+            return PixelValue::new(0.0, 0.0, 0.0);
+        }
+        match &self.spot {
+            // The light travels from the surface back along the incoming ray
+            Some(spot) => self.emission * spot.falloff(&-ray.direction),
+            None => self.emission,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_scene::hit_at_origin;
+    use cgmath::Point3;
+
+    fn hit_record(front_face: bool) -> HitRecord {
+        HitRecord {
+            front_face,
+            ..hit_at_origin(Vector3::new(0.0, 0.0, 1.0))
+        }
+    }
+
+    fn ray() -> Ray {
+        Ray {
+            origin: Point3::new(0.0, 0.0, 1.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        }
+    }
+
+    #[test]
+    fn one_sided_only_emits_from_front() {
+        let light = Emissive {
+            emission: PixelValue::new(4.0, 2.0, 1.0),
+            unit: EmissionUnit::Radiance,
+            two_sided: false,
+            spot: None,
+        };
+        assert_eq!(light.emitted(&ray(), &hit_record(true)), light.emission);
+        assert_eq!(
+            light.emitted(&ray(), &hit_record(false)),
+            PixelValue::new(0.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn two_sided_emits_from_both_sides() {
+        let light = Emissive {
+            emission: PixelValue::new(4.0, 2.0, 1.0),
+            unit: EmissionUnit::Radiance,
+            two_sided: true,
+            spot: None,
+        };
+        assert_eq!(light.emitted(&ray(), &hit_record(true)), light.emission);
+        assert_eq!(light.emitted(&ray(), &hit_record(false)), light.emission);
+    }
+
+    #[test]
+    fn power_is_spread_over_the_area() {
+        let mut light = Emissive {
+            emission: PixelValue::new(PI, 2.0 * PI, 0.0),
+            unit: EmissionUnit::Watts,
+            two_sided: false,
+            spot: None,
+        };
+        light.resolve_power(2.0);
+        assert_eq!(light.unit, EmissionUnit::Radiance);
+        assert!((light.emission - PixelValue::new(0.5, 1.0, 0.0)).magnitude() < 1e-6);
+
+        // Resolving is a no-op once the emission is a radiance
+        light.resolve_power(4.0);
+        assert!((light.emission - PixelValue::new(0.5, 1.0, 0.0)).magnitude() < 1e-6);
+
+        // A two-sided light spreads its power over both sides
+        let mut light = Emissive {
+            emission: PixelValue::new(PI, 2.0 * PI, 0.0),
+            unit: EmissionUnit::Watts,
+            two_sided: true,
+            spot: None,
+        };
+        light.resolve_power(1.0);
+        assert!((light.emission - PixelValue::new(0.5, 1.0, 0.0)).magnitude() < 1e-6);
+    }
+
+    #[test]
+    fn spot_falloff() {
+        let spot = SpotProfile {
+            axis: Vector3::new(0.0, 0.0, 2.0),
+            inner_angle: 20.0,
+            outer_angle: 40.0,
+        };
+        let at = |degrees: Float| {
+            let radians = degrees.to_radians();
+            spot.falloff(&Vector3::new(radians.sin(), 0.0, radians.cos()))
+        };
+        assert_eq!(at(0.0), 1.0);
+        assert_eq!(at(15.0), 1.0);
+        assert!(at(30.0) > 0.0 && at(30.0) < 1.0);
+        assert!(at(25.0) > at(35.0));
+        assert_eq!(at(45.0), 0.0);
+        assert_eq!(at(180.0), 0.0);
+
+        // The falloff applies to the light leaving the surface, which travels against the ray
+        let light = Emissive {
+            emission: PixelValue::new(4.0, 2.0, 1.0),
+            unit: EmissionUnit::Radiance,
+            two_sided: false,
+            spot: Some(spot),
+        };
+        assert_eq!(light.emitted(&ray(), &hit_record(true)), light.emission);
+        let grazing = Ray {
+            origin: Point3::new(1.0, 0.0, 0.1),
+            direction: Vector3::new(-1.0, 0.0, -0.1),
+        };
+        assert_eq!(
+            light.emitted(&grazing, &hit_record(true)),
+            PixelValue::new(0.0, 0.0, 0.0)
+        );
+    }
+}
@@ -0,0 +1,104 @@
+//! The Fresnel equations, which give the fraction of light that's reflected at a surface
+//!
+//! The rest of the light is transmitted into the surface (for dielectrics) or absorbed (for
+//! conductors). `fresnel_dielectric` evaluates the exact equations for unpolarized light. Schlick's
+//! cheaper approximation is exact at normal incidence and at grazing angles, but for glass it
+//! underestimates the reflectance by up to 2% at moderate angles and overestimates it by up to 4%
+//! near grazing angles, which the tests below document.
+
+use crate::types::Float;
+
+/// The exact Fresnel reflectance of a boundary between two dielectrics
+///
+/// `cos_theta` is the cosine of the angle between the incoming direction and the normal, where the
+/// normal is on the side of the surface that the light is coming from. `eta` is the ratio of the
+/// index of refraction that the light is entering to the one it's leaving. A negative
+/// `cos_theta` means the light is coming from the other side of the surface, so the media are
+/// swapped. Returns 1 when there's total internal reflection.
+pub fn fresnel_dielectric(cos_theta: Float, eta: Float) -> Float {
+    let (cos_i, eta) = if cos_theta < 0.0 {
+        (-cos_theta, 1.0 / eta)
+    } else {
+        (cos_theta, eta)
+    };
+    let cos_i = cos_i.min(1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (parallel * parallel + perpendicular * perpendicular) / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num::pow;
+
+    /// Schlick's approximation of the Fresnel reflectance
+    ///
+    /// `f0` is the reflectance at normal incidence, and `cos_theta` is the cosine of the angle
+    /// between the normal and the direction on the side of the surface with the lower index of
+    /// refraction.
+    fn fresnel_schlick(cos_theta: Float, f0: Float) -> Float {
+        f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5)
+    }
+
+    /// The reflectance at normal incidence of a boundary between media with a ratio of indices of
+    /// refraction `eta`, for use with `fresnel_schlick`
+    fn normal_reflectance(eta: Float) -> Float {
+        let r0 = (1.0 - eta) / (1.0 + eta);
+        r0 * r0
+    }
+
+    #[test]
+    fn exact_and_approximate() {
+        let f0 = normal_reflectance(1.5);
+        assert!((f0 - 0.04).abs() < 1e-6);
+
+        // Both agree at normal and grazing incidence
+        assert!((fresnel_dielectric(1.0, 1.5) - f0).abs() < 1e-6);
+        assert!((fresnel_schlick(1.0, f0) - f0).abs() < 1e-6);
+        assert!((fresnel_dielectric(0.0, 1.5) - 1.0).abs() < 1e-6);
+        assert!((fresnel_schlick(0.0, f0) - 1.0).abs() < 1e-6);
+
+        // In between, Schlick's approximation is too low at moderate angles and too high near
+        // grazing angles
+        let error = |degrees: Float| {
+            let cos_theta = degrees.to_radians().cos();
+            fresnel_dielectric(cos_theta, 1.5) - fresnel_schlick(cos_theta, f0)
+        };
+        assert!(error(10.0).abs() < 1e-4);
+        assert!(error(30.0) > 0.001 && error(30.0) < 0.002);
+        assert!(error(60.0) > 0.015 && error(60.0) < 0.025);
+        assert!(error(85.0) < -0.03 && error(85.0) > -0.04);
+
+        // The reflectance only increases towards grazing angles
+        let mut previous = 0.0;
+        for degrees in 0..90 {
+            let r = fresnel_dielectric((degrees as Float).to_radians().cos(), 1.5);
+            assert!(r >= previous);
+            previous = r;
+        }
+    }
+
+    #[test]
+    fn dielectric_from_inside() {
+        // Past the critical angle of glass (about 41.8 degrees) everything is reflected
+        let critical = (1.0 as Float / 1.5).asin();
+        assert_eq!(fresnel_dielectric((critical + 0.01).cos(), 1.0 / 1.5), 1.0);
+        assert!(fresnel_dielectric((critical - 0.01).cos(), 1.0 / 1.5) < 1.0);
+
+        // A negative cosine swaps the media
+        assert_eq!(
+            fresnel_dielectric(-0.9, 1.5),
+            fresnel_dielectric(0.9, 1.0 / 1.5)
+        );
+
+        // Light passes straight through a boundary between matched media
+        assert_eq!(fresnel_dielectric(0.5, 1.0), 0.0);
+    }
+}
@@ -0,0 +1,204 @@
+//! Phase functions, which scatter light in every direction rather than off of a surface
+//!
+//! A phase function describes how light is scattered at a point inside of a participating medium,
+//! like fog or smoke, as a distribution over the angle between the direction the light was
+//! travelling in and the direction it leaves in. They're implemented as BSDFs so they can be
+//! attached to objects like any other material. On a surface, they scatter light to both sides,
+//! like a thin scattering shell.
+//!
+//! Both phase functions here are sampled exactly, so the probability density of a scattered
+//! direction is the value of the phase function and the attenuation is just the albedo.
+
+use crate::{
+    hittable::HitRecord,
+    material::{BSDFRecord, BSDF},
+    math::{from_local, safe_normalize},
+    ray::Ray,
+    sampler::Sampler,
+    types::Float,
+};
+use cgmath::Vector3;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+/// The largest magnitude of the Henyey-Greenstein anisotropy
+///
+/// At an anisotropy of 1 or -1 the phase function collapses into a single direction, so the
+/// anisotropy is clamped to just short of that.
+const MAX_ANISOTROPY: Float = 0.99;
+
+/// Anisotropies closer to 0 than this are treated as isotropic
+///
+/// The sampling formula for Henyey-Greenstein divides by the anisotropy, so it loses precision
+/// near 0.
+const ISOTROPIC_THRESHOLD: Float = 1e-3;
+
+/// A phase function that scatters light uniformly in every direction
+///
+/// The probability density of every direction is `1 / (4 pi)`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Isotropic {
+    /// The fraction of light that is scattered, rather than absorbed, in each color channel
+    #[serde(deserialize_with = "crate::color::deserialize")]
+    pub albedo: Vector3<Float>,
+}
+
+impl Isotropic {
+    /// The value of the phase function, which is the same for every angle
+    pub fn phase(&self, _cos_theta: Float) -> Float {
+        henyey_greenstein(0.0, 0.0)
+    }
+}
+
+impl BSDF for Isotropic {
+    fn scatter(&self, s: &mut dyn Sampler<Float>, ray: &Ray, hit_record: &HitRecord) -> BSDFRecord {
+        scatter_henyey_greenstein(0.0, self.albedo, s, ray, hit_record)
+    }
+}
+
+/// The Henyey-Greenstein phase function, which favors scattering forwards or backwards
+///
+/// This is the usual model for media like clouds and smoke, which mostly scatter light onwards in
+/// the direction it was already travelling.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct HenyeyGreenstein {
+    /// The fraction of light that is scattered, rather than absorbed, in each color channel
+    #[serde(deserialize_with = "crate::color::deserialize")]
+    pub albedo: Vector3<Float>,
+
+    /// The anisotropy of the phase function, which is the average cosine of the scattering angle
+    ///
+    /// Positive values scatter light forwards, negative values scatter it back towards where it
+    /// came from, and 0 is isotropic. This is clamped to [-0.99, 0.99].
+    pub g: Float,
+}
+
+impl HenyeyGreenstein {
+    /// The value of the phase function for the cosine of the angle between the direction the light
+    /// was travelling in and the direction it's scattered in
+    pub fn phase(&self, cos_theta: Float) -> Float {
+        henyey_greenstein(cos_theta, clamp_anisotropy(self.g))
+    }
+}
+
+impl BSDF for HenyeyGreenstein {
+    fn scatter(&self, s: &mut dyn Sampler<Float>, ray: &Ray, hit_record: &HitRecord) -> BSDFRecord {
+        scatter_henyey_greenstein(clamp_anisotropy(self.g), self.albedo, s, ray, hit_record)
+    }
+}
+
+/// Clamp an anisotropy to the range where the phase function is well-behaved
+fn clamp_anisotropy(g: Float) -> Float {
+    if g.is_nan() {
+        0.0
+    } else {
+        g.clamp(-MAX_ANISOTROPY, MAX_ANISOTROPY)
+    }
+}
+
+/// The Henyey-Greenstein phase function for an anisotropy of `g`
+///
+/// `cos_theta` is the cosine of the angle between the direction the light was travelling in and
+/// the direction it's scattered in. This integrates to 1 over the sphere of directions.
+fn henyey_greenstein(cos_theta: Float, g: Float) -> Float {
+    let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * PI * denominator * denominator.sqrt())
+}
+
+/// Sample the cosine of the scattering angle from the Henyey-Greenstein distribution
+fn sample_cos_theta(g: Float, u: Float) -> Float {
+    if g.abs() < ISOTROPIC_THRESHOLD {
+        return 1.0 - 2.0 * u;
+    }
+    // Invert the CDF of the distribution, which goes from straight ahead at u = 0 to straight
+    // back at u = 1
+    let term = (1.0 - g * g) / (1.0 + g - 2.0 * g * u);
+    ((1.0 + g * g - term * term) / (2.0 * g)).clamp(-1.0, 1.0)
+}
+
+/// Scatter a ray with the Henyey-Greenstein phase function
+fn scatter_henyey_greenstein(
+    g: Float,
+    albedo: Vector3<Float>,
+    s: &mut dyn Sampler<Float>,
+    ray: &Ray,
+    hit_record: &HitRecord,
+) -> BSDFRecord {
+    let rs = s.next(2).unwrap();
+    let cos_theta = sample_cos_theta(g, rs[0]);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * rs[1];
+    let local = Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+    let forward = safe_normalize(&ray.direction).unwrap_or(-hit_record.shading_normal);
+    BSDFRecord {
+        out: Ray {
+            origin: hit_record.p,
+            direction: from_local(&local, &forward),
+        },
+        attenuation: albedo,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sampler::Random, test_scene::hit_at_origin};
+    use cgmath::{InnerSpace, Point3};
+
+    /// The average cosine between the incoming ray and the scattered rays
+    fn mean_cosine(mat: &dyn BSDF) -> Float {
+        let mut sampler = Random::with_seed(1);
+        let hit_record = hit_at_origin(Vector3::new(0.0, 1.0, 0.0));
+        let ray = Ray {
+            origin: Point3::new(-1.0, 1.0, 0.0),
+            direction: Vector3::new(1.0, -1.0, 0.0),
+        };
+        let n = 20_000;
+        let total: Float = (0..n)
+            .map(|_| {
+                let record = mat.scatter(&mut sampler, &ray, &hit_record);
+                assert!((record.out.direction.magnitude() - 1.0).abs() < 1e-4);
+                record.out.direction.dot(ray.direction.normalize())
+            })
+            .sum();
+        total / n as Float
+    }
+
+    #[test]
+    fn mean_cosine_is_the_anisotropy() {
+        let albedo = Vector3::new(0.5, 0.5, 0.5);
+        assert!(mean_cosine(&Isotropic { albedo }).abs() < 0.02);
+
+        for g in [-0.6, 0.3, 0.8] {
+            let mean = mean_cosine(&HenyeyGreenstein { albedo, g });
+            assert!((mean - g).abs() < 0.02, "g = {}, mean = {}", g, mean);
+        }
+    }
+
+    #[test]
+    fn phase_functions_are_normalized() {
+        let albedo = Vector3::new(1.0, 1.0, 1.0);
+        assert!((Isotropic { albedo }.phase(0.3) - 1.0 / (4.0 * PI)).abs() < 1e-7);
+
+        // Integrate over the cosine of the angle, the azimuth contributes 2 pi
+        let n = 10_000;
+        for g in [-0.9, 0.0, 0.5, 0.9] {
+            let phase = HenyeyGreenstein { albedo, g };
+            let integral: Float = (0..n)
+                .map(|i| {
+                    let cos_theta = -1.0 + 2.0 * (i as Float + 0.5) / n as Float;
+                    phase.phase(cos_theta) * 2.0 * PI * (2.0 / n as Float)
+                })
+                .sum();
+            assert!((integral - 1.0).abs() < 0.01, "g = {}, {}", g, integral);
+        }
+
+        // Forward scattering favors the direction the light was already going in
+        let forward = HenyeyGreenstein { albedo, g: 0.5 };
+        assert!(forward.phase(1.0) > forward.phase(-1.0));
+
+        // A single direction can't be represented, so the anisotropy is clamped
+        let beam = HenyeyGreenstein { albedo, g: 1.0 };
+        assert!(beam.phase(1.0).is_finite());
+    }
+}
@@ -0,0 +1,114 @@
+//! Tracking the media that a ray is travelling through
+//!
+//! Refraction at an interface depends on the indices of refraction on both sides of it. When
+//! dielectrics are nested inside each other (like water inside a glass), the medium outside of an
+//! object isn't necessarily a vacuum, so the integrator keeps a stack of the media that a ray has
+//! entered and not yet left.
+
+use crate::types::Float;
+
+/// The index of refraction of a vacuum, which is what a ray travels through when it isn't inside
+/// any object
+pub const VACUUM_IOR: Float = 1.0;
+
+/// The maximum number of nested media whose indices of refraction are remembered
+///
+/// Rays that are nested deeper than this are still tracked, but the media beyond this depth are
+/// treated as a vacuum.
+const MAX_NESTED_MEDIA: usize = 8;
+
+/// A stack of the indices of refraction of the media that a ray is inside of
+///
+/// The stack is a fixed size array so that it's `Copy`, which lets every branch of a path carry
+/// its own copy without allocating.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MediumStack {
+    /// The indices of refraction, with the innermost medium at `depth - 1`
+    iors: [Float; MAX_NESTED_MEDIA],
+
+    /// The number of media the ray is currently inside
+    depth: usize,
+}
+
+impl Default for MediumStack {
+    fn default() -> Self {
+        Self {
+            iors: [VACUUM_IOR; MAX_NESTED_MEDIA],
+            depth: 0,
+        }
+    }
+}
+
+impl MediumStack {
+    /// The index of refraction of the medium the ray is currently travelling through
+    pub fn current(&self) -> Float {
+        self.ior_at(self.depth)
+    }
+
+    /// The index of refraction of the medium the ray will be in once it leaves the current one
+    pub fn outside(&self) -> Float {
+        self.ior_at(self.depth.saturating_sub(1))
+    }
+
+    /// Record that the ray entered a medium
+    pub fn push(&mut self, ior: Float) {
+        if self.depth < MAX_NESTED_MEDIA {
+            self.iors[self.depth] = ior;
+        }
+        self.depth += 1;
+    }
+
+    /// Record that the ray left the medium it was in
+    ///
+    /// Popping an empty stack is a no-op, which can happen if the ray started inside an object.
+    pub fn pop(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+
+    /// The index of refraction when the ray is inside `depth` media
+    fn ior_at(&self, depth: usize) -> Float {
+        if depth == 0 || depth > MAX_NESTED_MEDIA {
+            VACUUM_IOR
+        } else {
+            self.iors[depth - 1]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_and_pop() {
+        let mut stack = MediumStack::default();
+        assert_eq!(stack.current(), VACUUM_IOR);
+        assert_eq!(stack.outside(), VACUUM_IOR);
+
+        stack.push(1.5);
+        assert_eq!(stack.current(), 1.5);
+        assert_eq!(stack.outside(), VACUUM_IOR);
+
+        stack.push(1.33);
+        assert_eq!(stack.current(), 1.33);
+        assert_eq!(stack.outside(), 1.5);
+
+        stack.pop();
+        assert_eq!(stack.current(), 1.5);
+        stack.pop();
+        stack.pop();
+        assert_eq!(stack.current(), VACUUM_IOR);
+    }
+
+    #[test]
+    fn overflow_stays_balanced() {
+        let mut stack = MediumStack::default();
+
+        for i in 0..(MAX_NESTED_MEDIA + 2) {
+            stack.push(2.0 + i as Float);
+        }
+        stack.pop();
+        stack.pop();
+        assert_eq!(stack.current(), 2.0 + (MAX_NESTED_MEDIA - 1) as Float);
+    }
+}
@@ -1,7 +1,7 @@
 use crate::{
     hittable::HitRecord,
     material::{BSDFRecord, BSDF},
-    math::mirror,
+    math::{mirror, safe_normalize},
     ray::Ray,
     sampler::{primitives::sample_unit_sphere, Sampler},
     types::Float,
@@ -19,16 +19,23 @@ pub struct Mirror {
     /// The perturbation factor for the surface, which dictates how "fuzzy" reflections will be
     ///
     /// A perturbation of zero corresponds to a perfectly mirror surface. You can increase this
-    /// factor to 1.0, which will yield very fuzzy reflections.
+    /// factor to 1.0, which will yield very fuzzy reflections. Perturbed directions that end up
+    /// below the surface are resampled, so rough mirrors reflect as much light as smooth ones.
     pub perturbation: Float,
 
     /// The color of the mirror surface
     ///
     /// We allow reflective materials to have color. A regular mirror would have an albedo of
     /// `[1.0, 1.0, 1.0]`.
+    #[serde(deserialize_with = "crate::color::deserialize")]
     pub albedo: Vector3<Float>,
 }
 
+/// The number of times a perturbed direction that points into the surface is resampled
+///
+/// If every attempt fails, the last direction is reflected back above the surface instead.
+const MAX_PERTURBATION_ATTEMPTS: usize = 8;
+
 impl Default for Mirror {
     fn default() -> Self {
         Self {
@@ -40,10 +47,28 @@ impl Default for Mirror {
 
 impl BSDF for Mirror {
     fn scatter(&self, s: &mut dyn Sampler<Float>, ray: &Ray, hit_record: &HitRecord) -> BSDFRecord {
-        let mirror_direction = mirror(&ray.direction, &hit_record.normal);
-        let direction =
-            (mirror_direction + sample_unit_sphere(s).map(|x| x * self.perturbation)).normalize();
-        let attenuation = if hit_record.normal.dot(direction) > 0.0 {
+        let normal = hit_record.shading_normal;
+        let mirror_direction = mirror(&ray.direction, &normal);
+        let mut direction = mirror_direction;
+
+        if self.perturbation > 0.0 {
+            for _ in 0..MAX_PERTURBATION_ATTEMPTS {
+                direction = safe_normalize(
+                    &(mirror_direction + sample_unit_sphere(s).map(|x| x * self.perturbation)),
+                )
+                .unwrap_or(mirror_direction);
+
+                if normal.dot(direction) > 0.0 {
+                    break;
+                }
+            }
+
+            // Rather than absorbing the ray, fold it back across the tangent plane
+            if normal.dot(direction) < 0.0 {
+                direction = mirror(&direction, &normal);
+            }
+        }
+        let attenuation = if normal.dot(direction) > 0.0 {
             self.albedo
         } else {
             Vector3::new(0.0, 0.0, 0.0)
@@ -56,4 +81,47 @@ impl BSDF for Mirror {
             attenuation,
         }
     }
+
+    /// Only a perfect mirror is specular, a rough one spreads its reflections over a cone
+    fn is_specular(&self) -> bool {
+        self.perturbation == 0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sampler::Random, test_scene::hit_at_origin};
+    use cgmath::Point3;
+
+    /// The average attenuation of rays hitting a mirror at a grazing angle
+    fn average_attenuation(perturbation: Float) -> Float {
+        let mut sampler = Random::default();
+        let mirror = Mirror {
+            perturbation,
+            ..Default::default()
+        };
+        let ray = Ray {
+            origin: Point3::new(-1.0, 0.0, 0.1),
+            direction: Vector3::new(1.0, 0.0, -0.1).normalize(),
+        };
+        let hit_record = hit_at_origin(Vector3::new(0.0, 0.0, 1.0));
+        let samples = 1000;
+        let total: Float = (0..samples)
+            .map(|_| {
+                let record = mirror.scatter(&mut sampler, &ray, &hit_record);
+                assert!(record.out.direction.z >= 0.0);
+                record.attenuation.x
+            })
+            .sum();
+        total / samples as Float
+    }
+
+    /// Under uniform illumination, a rough mirror should be as bright as a smooth one
+    #[test]
+    fn rough_mirrors_conserve_energy() {
+        for &perturbation in &[0.0, 0.25, 0.5, 1.0] {
+            assert_eq!(average_attenuation(perturbation), 1.0);
+        }
+    }
 }
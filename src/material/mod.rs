@@ -2,13 +2,22 @@
 //! gives us texture and lighting information by defining a BSDF function for a particular
 //! geometric object.
 
-use crate::{hittable::HitRecord, ray::Ray, sampler::Sampler, types::Float};
+use crate::{
+    hittable::HitRecord,
+    ray::Ray,
+    sampler::Sampler,
+    types::{Float, PixelValue},
+};
 use cgmath::Vector3;
 use std::fmt::Debug;
 
 mod blinn_phong;
 mod dielectric;
 mod diffuse;
+mod emissive;
+pub mod fresnel;
+mod isotropic;
+mod medium;
 mod mirror;
 
 use enum_dispatch::enum_dispatch;
@@ -17,6 +26,9 @@ use serde::{Deserialize, Serialize};
 pub use blinn_phong::BlinnPhong;
 pub use dielectric::Dielectric;
 pub use diffuse::Diffuse;
+pub use emissive::Emissive;
+pub use isotropic::{HenyeyGreenstein, Isotropic};
+pub use medium::{MediumStack, VACUUM_IOR};
 pub use mirror::Mirror;
 
 /// This trait defines some sort of object that can specify how light is scattered when the
@@ -27,6 +39,58 @@ pub use mirror::Mirror;
 pub trait BSDF: Debug + Send + Sync {
     /// Return the result of a scattering function on an input ray
     fn scatter(&self, s: &mut dyn Sampler<Float>, ray: &Ray, hit_record: &HitRecord) -> BSDFRecord;
+
+    /// The radiance emitted by the surface towards the incoming ray
+    ///
+    /// Most materials don't emit any light, so the default implementation returns black.
+    fn emitted(&self, _ray: &Ray, _hit_record: &HitRecord) -> PixelValue<Float> {
+        PixelValue::new(0.0, 0.0, 0.0)
+    }
+
+    /// The value of the BSDF for light arriving at the hit point from `direction`
+    ///
+    /// Integrators use this to weight the light from a light that was sampled directly, so it
+    /// doesn't include the cosine term. This is `None` for materials that only scatter light in a
+    /// single direction, like mirrors, since a sampled direction will never be that one.
+    fn eval(
+        &self,
+        _hit_record: &HitRecord,
+        _direction: &Vector3<Float>,
+    ) -> Option<PixelValue<Float>> {
+        None
+    }
+
+    /// Whether the material only scatters light in a single direction, like a perfect mirror
+    ///
+    /// A light that's sampled directly will never be in that direction, so integrators skip direct
+    /// lighting at specular surfaces and find the lights with the scattered ray instead. The
+    /// default implementation returns false.
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    /// Return the result of a scattering function for a ray travelling through some medium
+    ///
+    /// `outside_ior` is the index of refraction of the medium on the other side of the surface
+    /// from the material's interior. Only materials that refract care about the surrounding
+    /// medium, so the default implementation ignores it and calls `scatter`.
+    fn scatter_in_medium(
+        &self,
+        s: &mut dyn Sampler<Float>,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        _outside_ior: Float,
+    ) -> BSDFRecord {
+        self.scatter(s, ray, hit_record)
+    }
+
+    /// The index of refraction of the material's interior
+    ///
+    /// This is `None` for materials that light can't pass through. Integrators use this to track
+    /// which media a ray is inside.
+    fn refraction_index(&self) -> Option<Float> {
+        None
+    }
 }
 
 /// The result of the BSDF scatter function
@@ -41,10 +105,129 @@ pub struct BSDFRecord {
 }
 
 /// The different types of `BSDF` types that can be used as input objects
+///
+/// The material is named by a `type` field next to its parameters, like
+/// `{ "type": "Diffuse", "albedo": [0.5, 0.5, 0.5] }`.
 #[enum_dispatch]
 #[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+#[serde(tag = "type")]
 pub enum SerializedMaterial {
     Diffuse(Diffuse),
     Mirror(Mirror),
     Dielectric(Dielectric),
+    Emissive(Emissive),
+    Isotropic(Isotropic),
+    HenyeyGreenstein(HenyeyGreenstein),
+    BlinnPhong(BlinnPhong),
+}
+
+/// How a material's albedo was changed to keep it from reflecting more light than it receives
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlbedoCorrection {
+    /// The albedo had channels outside of [0, 1], so they were clamped
+    ///
+    /// This holds the original albedo.
+    Clamped(Vector3<Float>),
+
+    /// A diffuse and a specular albedo added up to more than 1, so both were scaled down
+    ///
+    /// This holds the original albedos, which were each within [0, 1].
+    Scaled {
+        /// The original diffuse albedo
+        diffuse: Vector3<Float>,
+
+        /// The original specular albedo
+        specular: Vector3<Float>,
+    },
+}
+
+/// A neutral gray diffuse material, which objects without a material of their own use
+impl Default for SerializedMaterial {
+    fn default() -> Self {
+        SerializedMaterial::Diffuse(Diffuse {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        })
+    }
+}
+
+impl SerializedMaterial {
+    /// Clamp every channel of the material's albedo to [0, 1]
+    ///
+    /// An albedo above 1 reflects more light than the surface receives, so the energy in a path
+    /// grows with every bounce and the render diverges. A negative albedo is just as meaningless.
+    /// Returns how the albedo was changed, so the caller can warn the user, and `None` if the
+    /// albedo was already valid or the material has no albedo. The Blinn-Phong material has two
+    /// albedos, which also can't add up to more than 1, see `BlinnPhong::clamp_albedo`.
+    pub fn clamp_albedo(&mut self) -> Option<AlbedoCorrection> {
+        let albedo = match self {
+            SerializedMaterial::Diffuse(x) => &mut x.albedo,
+            SerializedMaterial::Mirror(x) => &mut x.albedo,
+            SerializedMaterial::Dielectric(x) => &mut x.albedo,
+            SerializedMaterial::Isotropic(x) => &mut x.albedo,
+            SerializedMaterial::HenyeyGreenstein(x) => &mut x.albedo,
+            SerializedMaterial::BlinnPhong(x) => return x.clamp_albedo(),
+            SerializedMaterial::Emissive(_) => return None,
+        };
+        clamp_unit(albedo).map(AlbedoCorrection::Clamped)
+    }
+}
+
+/// Clamp every channel of an albedo to [0, 1], returning the original albedo if it changed
+fn clamp_unit(albedo: &mut Vector3<Float>) -> Option<Vector3<Float>> {
+    let original = *albedo;
+    let clamped = original.map(|x| if x.is_nan() { 0.0 } else { x.clamp(0.0, 1.0) });
+
+    if clamped == original {
+        return None;
+    }
+    *albedo = clamped;
+    Some(original)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clamp_albedo() {
+        let mut mat = SerializedMaterial::Diffuse(Diffuse {
+            albedo: Vector3::new(1.5, 0.5, -0.1),
+        });
+        assert_eq!(
+            mat.clamp_albedo(),
+            Some(AlbedoCorrection::Clamped(Vector3::new(1.5, 0.5, -0.1)))
+        );
+
+        match mat {
+            SerializedMaterial::Diffuse(x) => assert_eq!(x.albedo, Vector3::new(1.0, 0.5, 0.0)),
+            _ => unreachable!(),
+        }
+        // Clamping is idempotent
+        assert_eq!(mat.clamp_albedo(), None);
+
+        let mut mat = SerializedMaterial::Mirror(Mirror::default());
+        assert_eq!(mat.clamp_albedo(), None);
+    }
+
+    #[test]
+    fn is_specular() {
+        let rough = Mirror {
+            perturbation: 0.3,
+            ..Default::default()
+        };
+        assert!(Mirror::default().is_specular());
+        assert!(!rough.is_specular());
+        assert!(Dielectric::default().is_specular());
+        let diffuse = SerializedMaterial::Diffuse(Diffuse {
+            albedo: Vector3::new(0.5, 0.5, 0.5),
+        });
+        assert!(!diffuse.is_specular());
+        let light = SerializedMaterial::Emissive(Emissive {
+            emission: PixelValue::new(1.0, 1.0, 1.0),
+            unit: Default::default(),
+            two_sided: false,
+            spot: None,
+        });
+        assert!(!light.is_specular());
+    }
 }
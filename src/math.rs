@@ -1,8 +1,8 @@
 //! Utility math functions
 
-use crate::types::Float;
-use cgmath::{prelude::*, Vector3};
-use num::pow;
+use crate::types::{Float, ETA};
+use cgmath::{prelude::*, Point3, Vector3};
+use serde::{Deserialize, Serialize};
 
 /// Mirror a vector about a unit direction
 ///
@@ -12,12 +12,188 @@ pub fn mirror(vector: &Vector3<Float>, normal: &Vector3<Float>) -> Vector3<Float
     vector - (normal * 2.0 * vector.dot(*normal))
 }
 
-/// Schlick's algorithm for computing a reflection coefficient
+/// Normalize a vector, unless it's too short to have a meaningful direction
 ///
-/// An implementation of Schlick's algorithm for approximating the contribution of the Fresnel
-/// factor in a specular reflection.
-pub fn schlick(cosine: Float, ref_idx: Float) -> Float {
-    let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
-    let r0 = r0 * r0;
-    r0 + (1.0 - r0) * pow(1.0 - cosine, 5)
+/// Normalizing a zero-length vector divides by zero and yields NaN components, which silently
+/// propagate through the rest of the render. This returns `None` if the magnitude of `v` is below
+/// `ETA` (or isn't finite), so the caller can pick an appropriate fallback direction.
+pub fn safe_normalize(v: &Vector3<Float>) -> Option<Vector3<Float>> {
+    let magnitude = v.magnitude();
+
+    if magnitude.is_finite() && magnitude >= ETA {
+        Some(v / magnitude)
+    } else {
+        None
+    }
+}
+
+/// Build an orthonormal basis around a unit normal vector
+///
+/// Returns two tangent vectors that are perpendicular to `n` and to each other, such that
+/// `(t, b, n)` forms a right-handed coordinate frame. This uses the branchless construction from
+/// Duff et al., "Building an Orthonormal Basis, Revisited" (2017), which is numerically stable
+/// for every direction, including normals that point along the negative z axis. Note that `n`
+/// must be a unit vector.
+pub fn onb_from_normal(n: &Vector3<Float>) -> (Vector3<Float>, Vector3<Float>) {
+    let sign = 1.0_f32.copysign(n.z);
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    let t = Vector3::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x);
+    let bitangent = Vector3::new(b, sign + n.y * n.y * a, -n.y);
+    (t, bitangent)
+}
+
+/// Transform a world space direction into the local frame around a unit normal
+///
+/// In the local frame, the normal is the +z axis and the tangents from `onb_from_normal` are the
+/// x and y axes.
+pub fn to_local(v: &Vector3<Float>, n: &Vector3<Float>) -> Vector3<Float> {
+    let (t, b) = onb_from_normal(n);
+    Vector3::new(v.dot(t), v.dot(b), v.dot(*n))
+}
+
+/// Transform a direction in the local frame around a unit normal back into world space
+///
+/// This is the inverse of `to_local`.
+pub fn from_local(v: &Vector3<Float>, n: &Vector3<Float>) -> Vector3<Float> {
+    let (t, b) = onb_from_normal(n);
+    (t * v.x) + (b * v.y) + (n * v.z)
+}
+
+/// The axis that points up in a scene's coordinate system
+///
+/// Internally, nib uses a right-handed coordinate system where +Y is up, and a camera with no
+/// rotation looks down -Z. This is the convention of glTF and most real-time tools. Scenes that
+/// come from tools where +Z is up, like Blender, can say so instead of converting every coordinate
+/// by hand, and they're rotated into the internal convention when they're prepared for rendering.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UpAxis {
+    /// +Y is up, which is the internal convention, so nothing is converted
+    #[default]
+    Y,
+
+    /// +Z is up and +Y points away from the viewer, like in Blender
+    ///
+    /// This is rotated a quarter turn about the X axis, so `(x, y, z)` becomes `(x, z, -y)`.
+    Z,
+}
+
+impl UpAxis {
+    /// Convert a direction from this convention to the internal one
+    pub fn convert_vector(self, v: Vector3<Float>) -> Vector3<Float> {
+        match self {
+            UpAxis::Y => v,
+            UpAxis::Z => Vector3::new(v.x, v.z, -v.y),
+        }
+    }
+
+    /// Convert a point from this convention to the internal one
+    pub fn convert_point(self, p: Point3<Float>) -> Point3<Float> {
+        Point3::from_vec(self.convert_vector(p.to_vec()))
+    }
+
+    /// Convert the size of an axis-aligned box from this convention to the internal one
+    ///
+    /// The conversion only permutes the axes, so axis-aligned boxes stay axis-aligned, and their
+    /// sizes stay positive.
+    pub fn convert_extents(self, v: Vector3<Float>) -> Vector3<Float> {
+        self.convert_vector(v).map(Float::abs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::approx_eq_vec;
+    use float_cmp::approx_eq;
+
+    /// A set of unit normals that cover the problematic cases for naive basis constructions, such
+    /// as normals that lie along (or very close to) the coordinate axes
+    fn test_normals() -> Vec<Vector3<Float>> {
+        vec![
+            Vector3::unit_x(),
+            Vector3::unit_y(),
+            Vector3::unit_z(),
+            -Vector3::unit_x(),
+            -Vector3::unit_y(),
+            -Vector3::unit_z(),
+            Vector3::new(1.0, 1.0, 1.0).normalize(),
+            Vector3::new(-0.3, 0.2, -0.9).normalize(),
+            Vector3::new(0.0001, 0.0, -1.0).normalize(),
+        ]
+    }
+
+    #[test]
+    fn onb_is_orthonormal() {
+        for n in test_normals() {
+            let (t, b) = onb_from_normal(&n);
+            assert!(approx_eq!(Float, t.magnitude(), 1.0, epsilon = 1e-5));
+            assert!(approx_eq!(Float, b.magnitude(), 1.0, epsilon = 1e-5));
+            assert!(t.dot(n).abs() < 1e-5);
+            assert!(b.dot(n).abs() < 1e-5);
+            assert!(t.dot(b).abs() < 1e-5);
+            // The frame should be right-handed
+            assert!(t.cross(b).dot(n) > 0.99);
+        }
+    }
+
+    #[test]
+    fn local_frame_round_trip() {
+        let v = Vector3::new(0.3, -0.5, 0.8);
+
+        for n in test_normals() {
+            let local = to_local(&v, &n);
+            let world = from_local(&local, &n);
+            assert!((world - v).magnitude() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn normal_maps_to_local_z() {
+        for n in test_normals() {
+            let local = to_local(&n, &n);
+            assert!((local - Vector3::unit_z()).magnitude() < 1e-5);
+            assert!(approx_eq_vec(&from_local(&Vector3::unit_z(), &n), &n));
+        }
+    }
+
+    #[test]
+    fn safe_normalize_zero_vector() {
+        assert_eq!(safe_normalize(&Vector3::new(0.0, 0.0, 0.0)), None);
+        assert_eq!(safe_normalize(&Vector3::new(ETA / 2.0, 0.0, 0.0)), None);
+        assert_eq!(safe_normalize(&Vector3::new(Float::NAN, 0.0, 0.0)), None);
+        assert_eq!(
+            safe_normalize(&Vector3::new(Float::INFINITY, 0.0, 0.0)),
+            None
+        );
+    }
+
+    #[test]
+    fn safe_normalize_unit_length() {
+        let v = safe_normalize(&Vector3::new(3.0, -4.0, 0.0)).unwrap();
+        assert!((v.magnitude() - 1.0).abs() < 1e-6);
+        assert!((v - Vector3::new(0.6, -0.8, 0.0)).magnitude() < 1e-6);
+    }
+
+    #[test]
+    fn up_axis() {
+        let v = Vector3::new(1.0, 2.0, 3.0);
+        assert_eq!(UpAxis::Y.convert_vector(v), v);
+
+        // Up stays up, and the handedness of the coordinate system is preserved
+        let z = UpAxis::Z;
+        assert_eq!(z.convert_vector(Vector3::unit_z()), Vector3::unit_y());
+        assert_eq!(z.convert_vector(Vector3::unit_y()), -Vector3::unit_z());
+        assert_eq!(z.convert_vector(Vector3::unit_x()), Vector3::unit_x());
+        assert_eq!(
+            z.convert_vector(Vector3::unit_x())
+                .cross(z.convert_vector(Vector3::unit_y())),
+            z.convert_vector(Vector3::unit_z())
+        );
+        assert_eq!(
+            z.convert_point(Point3::new(1.0, 2.0, 3.0)),
+            Point3::new(1.0, 3.0, -2.0)
+        );
+        assert_eq!(z.convert_extents(v), Vector3::new(1.0, 3.0, 2.0));
+    }
 }
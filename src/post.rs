@@ -0,0 +1,283 @@
+//! Post-processing steps that run on a finished render
+//!
+//! Rendered buffers hold linear RGB values, and the exporters only apply the transfer function
+//! when the image is written out. Anything that combines pixels has to happen on the linear
+//! buffer, before it's exported: averaging gamma encoded values darkens high contrast edges,
+//! since the average of the encoded values of black and white is much darker than the encoded
+//! value of their average.
+
+use crate::{
+    framebuffer::Framebuffer,
+    types::{Float, PixelValue},
+};
+use anyhow::format_err;
+use std::{f32::consts::PI, str::FromStr};
+use thiserror::Error;
+
+/// The possible errors that can arise when post-processing a render
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum PostError {
+    #[error("A {width}x{height} image has {expected} pixels, but the buffer has {actual}")]
+    WrongBufferSize {
+        width: u32,
+        height: u32,
+        expected: usize,
+        actual: usize,
+    },
+
+    #[error("A {from_width}x{from_height} image can't be downscaled to {to_width}x{to_height}")]
+    InvalidDownscale {
+        from_width: u32,
+        from_height: u32,
+        to_width: u32,
+        to_height: u32,
+    },
+}
+
+/// A result that can return a `PostError`
+pub type PostResult<T> = Result<T, PostError>;
+
+/// The number of lobes of the Lanczos kernel on each side of its center
+const LANCZOS_LOBES: Float = 3.0;
+
+/// The kernels that can be used to downscale an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DownscaleKernel {
+    /// Every output pixel is the average of the input pixels it covers, weighted by how much of
+    /// each input pixel it covers
+    #[default]
+    Box,
+
+    /// A windowed sinc with three lobes, which is sharper than a box but can ring around edges
+    Lanczos,
+}
+
+impl DownscaleKernel {
+    /// The weight of an input pixel whose center is `d` output pixels from an output pixel's
+    /// center, where `scale` is the number of input pixels per output pixel
+    fn weight(self, d: Float, scale: Float) -> Float {
+        match self {
+            // The overlap of the input pixel with the output pixel, in output pixels
+            DownscaleKernel::Box => {
+                let half_input = 0.5 / scale;
+                let overlap = (d.abs() + half_input).min(0.5) - (d.abs() - half_input).max(-0.5);
+                overlap.max(0.0)
+            }
+            DownscaleKernel::Lanczos => {
+                if d.abs() >= LANCZOS_LOBES {
+                    0.0
+                } else {
+                    sinc(d) * sinc(d / LANCZOS_LOBES)
+                }
+            }
+        }
+    }
+
+    /// How far from an output pixel's center the kernel reaches, in output pixels
+    fn radius(self) -> Float {
+        match self {
+            DownscaleKernel::Box => 0.5,
+            DownscaleKernel::Lanczos => LANCZOS_LOBES,
+        }
+    }
+}
+
+impl FromStr for DownscaleKernel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "box" => Ok(DownscaleKernel::Box),
+            "lanczos" => Ok(DownscaleKernel::Lanczos),
+            _ => Err(format_err!(
+                "\"{}\" is not a supported downscaling kernel. Valid values are: box, lanczos",
+                s
+            )),
+        }
+    }
+}
+
+/// The normalized sinc function
+fn sinc(x: Float) -> Float {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Scale a linear RGB image by some exposure, in stops
+///
+/// Every pixel is multiplied by `2^exposure`. Exposure is the first step on the way to the output
+/// image: it happens on the linear buffer, before any tone mapping or gamma encoding, so that a
+/// stop always means doubling the light that reached the camera. An exposure of 0 leaves the
+/// image unchanged.
+pub fn expose(buffer: &mut [PixelValue<Float>], exposure: Float) {
+    if exposure == 0.0 {
+        return;
+    }
+    let scale = exposure.exp2();
+
+    for pixel in buffer {
+        *pixel *= scale;
+    }
+}
+
+/// Downscale a linear RGB image
+///
+/// `to` is the width and height of the output image, which can't be larger than the input along
+/// either axis. The image is filtered along each axis separately,
+/// and the weights for every output pixel are normalized, so a constant image stays the same.
+/// Negative values from the lobes of the Lanczos kernel are clamped to zero.
+///
+/// The buffer must hold linear values, like the buffer of a `RenderOutput`, so this has to happen
+/// before the image is exported.
+pub fn downscale(
+    buffer: &Framebuffer,
+    to: (u32, u32),
+    kernel: DownscaleKernel,
+) -> PostResult<Framebuffer> {
+    let (from_width, from_height) = (buffer.width(), buffer.height());
+    let (to_width, to_height) = to;
+
+    if to_width == 0 || to_height == 0 || to_width > from_width || to_height > from_height {
+        return Err(PostError::InvalidDownscale {
+            from_width,
+            from_height,
+            to_width,
+            to_height,
+        });
+    }
+
+    // Downscale the rows first, then the columns of the result
+    let row_weights = weights(from_width as usize, to_width as usize, kernel);
+    let mut rows = Vec::with_capacity(to_width as usize * from_height as usize);
+
+    for row in buffer.rows() {
+        rows.extend(row_weights.iter().map(|w| apply(w, |i| row[i])));
+    }
+    let rows = Framebuffer::from_vec(to_width, from_height, rows)?;
+    let column_weights = weights(from_height as usize, to_height as usize, kernel);
+    let mut output = Framebuffer::new(to_width, to_height);
+
+    for (y, w) in column_weights.iter().enumerate() {
+        for x in 0..to_width {
+            let color = apply(w, |i| rows.get(x, i as u32).unwrap());
+            output.set(x, y as u32, color.map(|c| c.max(0.0)));
+        }
+    }
+    Ok(output)
+}
+
+/// The normalized weights of the input pixels that contribute to each output pixel along one axis
+fn weights(from: usize, to: usize, kernel: DownscaleKernel) -> Vec<Vec<(usize, Float)>> {
+    let scale = from as Float / to as Float;
+    let radius = kernel.radius() * scale;
+
+    (0..to)
+        .map(|i| {
+            // The center of the output pixel, in input pixels
+            let center = (i as Float + 0.5) * scale;
+            let first = (center - radius - 0.5).floor().max(0.0) as usize;
+            let last = ((center + radius + 0.5).ceil() as usize).min(from);
+            let mut w: Vec<(usize, Float)> = (first..last)
+                .map(|j| (j, kernel.weight((j as Float + 0.5 - center) / scale, scale)))
+                .filter(|&(_, weight)| weight != 0.0)
+                .collect();
+            let total: Float = w.iter().map(|(_, weight)| weight).sum();
+
+            for (_, weight) in &mut w {
+                *weight /= total;
+            }
+            w
+        })
+        .collect()
+}
+
+/// The weighted sum of the pixels at some indices
+fn apply(
+    weights: &[(usize, Float)],
+    pixel: impl Fn(usize) -> PixelValue<Float>,
+) -> PixelValue<Float> {
+    weights
+        .iter()
+        .fold(PixelValue::new(0.0, 0.0, 0.0), |sum, &(i, weight)| {
+            sum + pixel(i) * weight
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::InnerSpace;
+
+    #[test]
+    fn box_downscale() {
+        let black = PixelValue::new(0.0, 0.0, 0.0);
+        let white = PixelValue::new(1.0, 1.0, 1.0);
+
+        // A checkerboard averages to linear gray, not to the much darker average of its sRGB
+        // encoded values
+        let checkers: Vec<_> = (0..16)
+            .map(|i| {
+                if (i % 4 + i / 4) % 2 == 0 {
+                    black
+                } else {
+                    white
+                }
+            })
+            .collect();
+        let checkers = Framebuffer::from_vec(4, 4, checkers).unwrap();
+        let image = downscale(&checkers, (2, 2), DownscaleKernel::Box).unwrap();
+        assert_eq!(*image, [PixelValue::new(0.5, 0.5, 0.5); 4]);
+
+        // A pixel that straddles two output pixels is split between them
+        let row = Framebuffer::from_vec(3, 1, vec![white, black, black]).unwrap();
+        let image = downscale(&row, (2, 1), DownscaleKernel::Box).unwrap();
+        assert!((image[0].x - 2.0 / 3.0).abs() < 1e-6);
+        assert_eq!(image[1], black);
+
+        // The same size is a copy
+        assert_eq!(
+            downscale(&checkers, (4, 4), DownscaleKernel::Box).unwrap(),
+            checkers
+        );
+    }
+
+    #[test]
+    fn lanczos_downscale() {
+        // The weights are normalized, so a constant image stays the same
+        let color = PixelValue::new(0.25, 0.5, 0.75);
+        let image = Framebuffer::from_vec(12, 6, vec![color; 12 * 6]).unwrap();
+        let image = downscale(&image, (4, 3), DownscaleKernel::Lanczos).unwrap();
+        assert_eq!((image.width(), image.height()), (4, 3));
+        assert!(image.iter().all(|x| (x - color).magnitude() < 1e-5));
+        assert_eq!(
+            "Lanczos".parse::<DownscaleKernel>().unwrap(),
+            DownscaleKernel::Lanczos
+        );
+        assert!("bicubic".parse::<DownscaleKernel>().is_err());
+    }
+
+    #[test]
+    fn exposure() {
+        let color = PixelValue::new(0.25, 0.5, 1.0);
+        let mut image = vec![color; 4];
+        expose(&mut image, 0.0);
+        assert_eq!(image, vec![color; 4]);
+        expose(&mut image, 1.0);
+        assert_eq!(image, vec![color * 2.0; 4]);
+        expose(&mut image, -3.0);
+        assert_eq!(image, vec![color * 0.25; 4]);
+    }
+
+    #[test]
+    fn invalid_downscale() {
+        let image = Framebuffer::new(2, 2);
+        assert!(matches!(
+            downscale(&image, (4, 1), DownscaleKernel::Box),
+            Err(PostError::InvalidDownscale { .. })
+        ));
+        assert!(downscale(&image, (0, 1), DownscaleKernel::Box).is_err());
+    }
+}
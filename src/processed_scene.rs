@@ -0,0 +1,329 @@
+//! The runtime form of a scene
+//!
+//! A `Scene` is a direct representation of the scene file, which is convenient to parse and
+//! validate, but not to render. Turning it into a `ProcessedScene` does all of the expensive work
+//! that only has to happen once per render: loading and expanding meshes, resolving materials,
+//! collecting the lights, setting up the camera, converting everything to the internal up axis,
+//! and building the acceleration structure.
+
+use crate::{
+    accel::Accel,
+    camera::{Camera, SerializedCamera},
+    hittable::SerializedHittable,
+    light::{Light, LightDistribution},
+    material::{SerializedMaterial, BSDF},
+    renderer::Arena,
+    scene::Scene,
+    types::Float,
+};
+use anyhow::format_err;
+use log::warn;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// The fraction of the primitive limit at which a warning is logged
+const PRIMITIVE_WARNING_FRACTION: f64 = 0.8;
+
+/// Statistics about the work done to prepare a scene for rendering
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreprocessStats {
+    /// The number of objects in the scene file
+    pub objects: usize,
+
+    /// The number of primitives the objects were expanded into, such as the triangles of a mesh
+    pub primitives: usize,
+
+    /// The number of materials in the material table, which has one for every object that has
+    /// any primitives
+    ///
+    /// Materials aren't deduplicated, so two objects with identical materials count twice.
+    pub materials: usize,
+
+    /// The number of lights that can be sampled directly
+    pub lights: usize,
+
+    /// How long it took to prepare the scene
+    pub elapsed: Duration,
+}
+
+/// A scene that's ready to be rendered
+#[derive(Debug)]
+pub struct ProcessedScene {
+    /// Every primitive in the scene
+    pub arena: Arena,
+
+    /// The material table, with one material for every object in the scene file that has any
+    /// primitives
+    ///
+    /// Every primitive that an object was expanded into shares that object's material.
+    pub materials: Vec<Arc<dyn BSDF>>,
+
+    /// The lights in the arena that integrators can sample directly
+    pub lights: Vec<Light>,
+
+    /// The distribution that integrators pick one of the `lights` from, weighted by their area
+    pub light_distribution: LightDistribution,
+
+    /// The acceleration structure built over the arena
+    pub accel: Arc<dyn Accel>,
+
+    /// The scene's camera, with its basis computed for the scene's aspect ratio
+    ///
+    /// This is the camera that `Renderer::render` uses. Other views of the scene can be rendered
+    /// with `Renderer::render_view`.
+    pub camera: Box<dyn Camera>,
+
+    /// Statistics about preparing the scene
+    pub stats: PreprocessStats,
+}
+
+impl ProcessedScene {
+    /// Prepare a scene for rendering
+    ///
+    /// This fails if the scene has an invalid aspect ratio, if a mesh can't be loaded, if the
+    /// scene has more primitives than its `max_primitives` limit, or if the acceleration structure
+    /// can't be built.
+    pub fn new(scene: &Scene) -> anyhow::Result<Self> {
+        let start = Instant::now();
+        let aspect_ratio = match scene.aspect_ratio {
+            Some(x) if !(x.is_finite() && x > 0.0) => {
+                return Err(format_err!(
+                    "The aspect ratio must be a positive number, got {}",
+                    x
+                ))
+            }
+            Some(x) => x,
+            None => (scene.width as Float) / (scene.height as Float),
+        };
+        let mut objects = Vec::with_capacity(scene.objects.len());
+        let mut materials = Vec::with_capacity(scene.objects.len());
+        let mut lights = Vec::new();
+        let mut warned_about_limit = false;
+
+        for object in &scene.objects {
+            let mut object = object.clone();
+            object.mat.get_or_insert(scene.default_material);
+            object.convert_up_axis(scene.up_axis);
+
+            if let SerializedHittable::Mesh(mesh) = &mut object.geometry {
+                mesh.normalize |= scene.normalize_meshes;
+            }
+            let sphere = match (&object.geometry, &object.mat) {
+                (SerializedHittable::Sphere(x), Some(SerializedMaterial::Emissive(_))) => Some(*x),
+                _ => None,
+            };
+            let expanded = object.expand()?;
+
+            // An empty mesh has no material to add to the table
+            let mat = match expanded.first() {
+                Some(x) => x.mat.clone(),
+                None => continue,
+            };
+            objects.extend(expanded);
+
+            // The limit is checked after every object, so a scene with several huge meshes stops
+            // loading as soon as it's over the limit
+            if let Some(limit) = scene.max_primitives {
+                if objects.len() > limit {
+                    return Err(format_err!(
+                        "The scene contains at least {} primitives, which exceeds the limit of {}. \
+                         Raise the limit with \"--max-primitives\" or the scene's \"max_primitives\" setting.",
+                        objects.len(),
+                        limit
+                    ));
+                }
+
+                if !warned_about_limit
+                    && objects.len() as f64 >= limit as f64 * PRIMITIVE_WARNING_FRACTION
+                {
+                    warn!(
+                        "The scene contains at least {} primitives, which is close to the limit of {}",
+                        objects.len(),
+                        limit
+                    );
+                    warned_about_limit = true;
+                }
+            }
+
+            // The light shares its material with the object, whose power has been resolved
+            if let Some(shape) = sphere {
+                lights.push(Light {
+                    shape,
+                    mat: mat.clone(),
+                });
+            }
+            materials.push(mat);
+        }
+        let arena: Arena = Arc::new(objects);
+        let mut camera = scene.camera;
+        camera.convert_up_axis(scene.up_axis);
+        let camera: Box<dyn Camera> = match camera {
+            SerializedCamera::Pinhole(x) => Box::new(x.init(aspect_ratio)),
+            SerializedCamera::BasicPinhole(x) => Box::new(x),
+            SerializedCamera::ThinLens(x) => Box::new(x.init(aspect_ratio)),
+        };
+        let accel = scene.acceleration_structure.to_accel(arena.clone())?;
+        let stats = PreprocessStats {
+            objects: scene.objects.len(),
+            primitives: arena.len(),
+            materials: materials.len(),
+            lights: lights.len(),
+            elapsed: start.elapsed(),
+        };
+        Ok(Self {
+            arena,
+            materials,
+            light_distribution: LightDistribution::new(&lights),
+            lights,
+            accel,
+            camera,
+            stats,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        math::UpAxis,
+        ray::Ray,
+        sampler::Random,
+        test_scene::{TestScene, GRAY, LIGHT, PINHOLE},
+    };
+    use cgmath::{InnerSpace, Point3, Vector3};
+
+    /// A scene with a spherical light and a triangle
+    fn scene() -> Scene {
+        TestScene::new()
+            .sphere([0.0, 2.0, -1.0], 0.5, LIGHT)
+            .object(
+                r#"{
+                    "type": "Triangle",
+                    "vertices": [[0.0, 0.0, -1.0], [1.0, 0.0, -1.0], [0.0, 1.0, -1.0]]
+                }"#,
+                GRAY,
+            )
+            .camera(PINHOLE)
+            .resolution(20, 10)
+            .build()
+    }
+
+    #[test]
+    fn stats() {
+        let processed = ProcessedScene::new(&scene()).unwrap();
+        let stats = processed.stats;
+        assert_eq!(
+            (
+                stats.objects,
+                stats.primitives,
+                stats.materials,
+                stats.lights
+            ),
+            (2, 2, 2, 1)
+        );
+        // The light and its object share a material
+        assert!(Arc::ptr_eq(
+            &processed.lights[0].mat,
+            &processed.arena[0].mat
+        ));
+    }
+
+    #[test]
+    fn primitive_limit() {
+        let mut scene = scene();
+        scene.max_primitives = Some(2);
+        assert!(ProcessedScene::new(&scene).is_ok());
+
+        scene.max_primitives = Some(1);
+        let error = ProcessedScene::new(&scene).unwrap_err().to_string();
+        assert!(error.contains("exceeds the limit of 1"), "{}", error);
+    }
+
+    #[test]
+    fn default_material() {
+        let mut scene = scene();
+        scene.objects[1].mat = None;
+        let ray = Ray::new(Point3::new(0.2, 0.2, -2.0), Vector3::new(0.0, 0.0, 1.0));
+        let hit = |processed: &ProcessedScene| processed.arena[1].geometry.hit(&ray).unwrap();
+
+        // Without a default in the scene file, objects are a neutral gray
+        let processed = ProcessedScene::new(&scene).unwrap();
+        let record =
+            processed.arena[1]
+                .mat
+                .scatter(&mut Random::with_seed(1), &ray, &hit(&processed));
+        assert_eq!(record.attenuation, Vector3::new(0.5, 0.5, 0.5));
+
+        // The emissive sphere keeps its own material, and still shares it with its light
+        scene.default_material = SerializedMaterial::Emissive(
+            json5::from_str(r#"{ "emission": [2.0, 2.0, 2.0] }"#).unwrap(),
+        );
+        let processed = ProcessedScene::new(&scene).unwrap();
+        assert_eq!(processed.stats.lights, 1);
+        let emitted = processed.arena[1].mat.emitted(&ray, &hit(&processed));
+        assert_eq!(emitted, Vector3::new(2.0, 2.0, 2.0));
+    }
+
+    /// A scene written with +Z up renders the same as the scene written with +Y up
+    #[test]
+    fn up_axis() {
+        let scene = |up_axis, origin, target, up, center, vertices, sun| -> Scene {
+            let mut scene = TestScene::new()
+                .object(
+                    &format!(
+                        r#"{{ "type": "Sphere", "center": {}, "radius": 0.5 }}"#,
+                        center
+                    ),
+                    GRAY,
+                )
+                .object(
+                    &format!(r#"{{ "type": "Triangle", "vertices": {} }}"#, vertices),
+                    GRAY,
+                )
+                .camera(&format!(
+                    r#"{{
+                        "type": "Pinhole",
+                        "origin": {},
+                        "target": {},
+                        "vfov": 60.0,
+                        "up": {}
+                    }}"#,
+                    origin, target, up
+                ))
+                .background(&format!(r#"{{ "type": "Sky", "sun_direction": {} }}"#, sun))
+                .resolution(16, 12)
+                .build();
+            scene.seed = Some(1);
+            scene.up_axis = up_axis;
+            scene
+        };
+        let y_up = scene(
+            UpAxis::Y,
+            "[0, 0.5, 2]",
+            "[0, 0, -1]",
+            "[0, 1, 0]",
+            "[0, 0, -1]",
+            "[[-1, -0.5, -2], [1, -0.5, -2], [0, 1, -2]]",
+            "[0.3, 1, -1]",
+        );
+        let z_up = scene(
+            UpAxis::Z,
+            "[0, -2, 0.5]",
+            "[0, 1, 0]",
+            "[0, 0, 1]",
+            "[0, 1, 0]",
+            "[[-1, 2, -0.5], [1, 2, -0.5], [0, 2, 1]]",
+            "[0.3, 1, 1]",
+        );
+        let y_up = crate::render_scene(y_up, None).unwrap().buffer;
+        let z_up = crate::render_scene(z_up, None).unwrap().buffer;
+
+        for (a, b) in y_up.iter().zip(&z_up) {
+            assert!((a - b).magnitude() < 1e-4, "{:?} != {:?}", a, b);
+        }
+    }
+}
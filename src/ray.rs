@@ -1,14 +1,14 @@
 //! Defines a `Ray` type with convenience methods
 
-use crate::types::{approx_eq_vec, Float};
-use cgmath::Vector3;
+use crate::types::{approx_eq_point, approx_eq_vec, Float};
+use cgmath::{Point3, Vector3};
 use serde::{Deserialize, Serialize};
 
 /// A standard ray with an origin point and a directional vector
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Ray {
     /// The origin point of the ray in three-dimensional space
-    pub origin: Vector3<Float>,
+    pub origin: Point3<Float>,
 
     /// The normalized direction of the ray
     ///
@@ -19,14 +19,59 @@ pub struct Ray {
 
 impl PartialEq for Ray {
     fn eq(&self, other: &Self) -> bool {
-        approx_eq_vec(&self.origin, &other.origin)
+        approx_eq_point(&self.origin, &other.origin)
             && approx_eq_vec(&self.direction, &other.direction)
     }
 }
 
 impl Ray {
     /// A convenience method to create a new ray given an origin and direction
-    pub fn new(origin: Vector3<Float>, direction: Vector3<Float>) -> Self {
+    pub fn new(origin: Point3<Float>, direction: Vector3<Float>) -> Self {
         Self { origin, direction }
     }
+
+    /// The point at parameter `t` along the ray
+    ///
+    /// If the direction is normalized, `t` is the distance from the origin to the point.
+    pub fn at(&self, t: Float) -> Point3<Float> {
+        self.origin + (self.direction * t)
+    }
+
+    /// The component-wise reciprocal of the direction
+    ///
+    /// This is used by the slab test for axis-aligned bounding boxes, which divides by each
+    /// component of the direction. A component that is zero yields an infinity with the same sign
+    /// as the zero, which the slab test handles correctly.
+    pub fn inverse_dir(&self) -> Vector3<Float> {
+        Vector3::new(
+            1.0 / self.direction.x,
+            1.0 / self.direction.y,
+            1.0 / self.direction.z,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn at() {
+        let ray = Ray::new(Point3::new(1.0, 2.0, 3.0), Vector3::new(0.0, -1.0, 0.5));
+        assert_eq!(ray.at(0.0), ray.origin);
+        assert_eq!(ray.at(2.0), Point3::new(1.0, 0.0, 4.0));
+        assert_eq!(ray.at(-1.0), Point3::new(1.0, 3.0, 2.5));
+    }
+
+    #[test]
+    fn inverse_dir() {
+        let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::new(2.0, -0.5, 0.0));
+        let inverse = ray.inverse_dir();
+        assert_eq!(inverse.x, 0.5);
+        assert_eq!(inverse.y, -2.0);
+        assert_eq!(inverse.z, Float::INFINITY);
+
+        let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::new(1.0, -0.0, 1.0));
+        assert_eq!(ray.inverse_dir().y, Float::NEG_INFINITY);
+    }
 }
@@ -4,111 +4,1061 @@
 //! This acts as the main executor module to coordinate computation in the renderer.
 
 use crate::{
-    accel::Accel,
-    camera,
+    accel::TraversalStats,
+    background::Background,
+    camera::Camera,
+    filter::FilterType,
+    framebuffer::Framebuffer,
     hittable::Textured,
+    image_exporter::to_rgba8,
     integrator::{Integrator, RenderParams},
+    material::MediumStack,
+    post,
+    processed_scene::ProcessedScene,
     sampler::{self, Sampler},
-    types::{Float, PixelValue},
+    scene::Scene,
+    types::{false_color, Float, PixelValue},
 };
-use anyhow;
-use indicatif::{ProgressBar, ProgressStyle};
+use anyhow::format_err;
+use cgmath::{InnerSpace, Vector2};
+#[cfg(feature = "cli")]
+use console::Term;
+#[cfg(feature = "cli")]
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::info;
 use rayon::prelude::*;
-use std::{sync::Arc, time::Duration};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "cli")]
+use std::io::IsTerminal;
+use std::{
+    convert::TryFrom,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
+/// The shared storage for every object in the scene
+///
+/// The processed scene and the acceleration structure each hold a clone of the arena, and
+/// acceleration structures hand out references into it that are tied to their own lifetime.
 pub type Arena = Arc<Vec<Textured>>;
 
+/// A progress bar for the samples taken during a render
+///
+/// The progress bar is drawn on the terminal with `indicatif`, which is only available with the
+/// `cli` feature. Without it, progress isn't reported at all.
+struct Progress {
+    #[cfg(feature = "cli")]
+    bar: ProgressBar,
+}
+
+impl Progress {
+    /// Create a progress bar that's complete after `n` samples
+    #[cfg(feature = "cli")]
+    fn new(n: u64, mode: ProgressMode) -> Self {
+        let bar = match mode {
+            ProgressMode::Never => {
+                return Self {
+                    bar: ProgressBar::hidden(),
+                }
+            }
+            ProgressMode::Auto if !std::io::stderr().is_terminal() => {
+                return Self {
+                    bar: ProgressBar::hidden(),
+                }
+            }
+            ProgressMode::Auto => ProgressBar::new(n),
+            // indicatif won't draw on anything but a terminal by itself, so it's given stderr
+            // without being told what kind of file it is
+            ProgressMode::Always => ProgressBar::with_draw_target(
+                Some(n),
+                ProgressDrawTarget::term_like_with_hz(Box::new(Term::stderr()), 20),
+            ),
+        };
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("{elapsed_precise}/{eta_precise} [{wide_bar}] {percent}%")
+                .unwrap()
+                .progress_chars("=> "),
+        );
+        bar.enable_steady_tick(Duration::from_millis(300));
+        Self { bar }
+    }
+
+    #[cfg(not(feature = "cli"))]
+    fn new(_n: u64, _mode: ProgressMode) -> Self {
+        Self {}
+    }
+
+    /// Record that a sample was taken
+    fn inc(&self) {
+        #[cfg(feature = "cli")]
+        self.bar.inc(1);
+    }
+
+    /// Remove the progress bar from the terminal once the render is done
+    fn finish(&self) {
+        #[cfg(feature = "cli")]
+        self.bar.finish_and_clear();
+    }
+}
+
+/// When the progress bar is drawn while rendering
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProgressMode {
+    /// Draw the progress bar if stderr is a terminal
+    ///
+    /// When stderr is redirected to a file or a CI log, the escape codes that redraw the bar
+    /// would just pile up in it, so the bar is hidden.
+    #[default]
+    Auto,
+
+    /// Always draw the progress bar on stderr, even if it isn't a terminal
+    Always,
+
+    /// Never draw the progress bar
+    Never,
+}
+
+/// How the noise in the frames of an animation relates from one frame to the next
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NoiseMode {
+    /// Every frame gets its own seed, so the noise changes ("boils") from frame to frame
+    ///
+    /// The seeds are derived from the seed of the render and the frame index, so the animation is
+    /// still reproducible.
+    #[default]
+    Boil,
+
+    /// Every frame uses the same seed, so the noise stays in place on the screen
+    ///
+    /// This is useful for comparing frames, since only the changes in the scene show up.
+    Static,
+}
+
+impl FromStr for NoiseMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "boil" => Ok(NoiseMode::Boil),
+            "static" => Ok(NoiseMode::Static),
+            _ => Err(format_err!(
+                "\"{}\" is not a supported noise mode. Valid values are: \"boil\", \"static\".",
+                s
+            )),
+        }
+    }
+}
+
+/// The seed to render a frame of an animation with, given the seed of the animation
+///
+/// The first frame always uses the animation's seed, so rendering a single frame gives the same
+/// image as rendering the scene without an animation. With `NoiseMode::Boil`, every other frame
+/// offsets the seed by a large odd constant, which the per-sample seed hashing turns into
+/// unrelated random numbers.
+pub fn frame_seed(seed: u64, frame: u32, mode: NoiseMode) -> u64 {
+    match mode {
+        NoiseMode::Boil => seed.wrapping_add(u64::from(frame).wrapping_mul(0x9e37_79b9_7f4a_7c15)),
+        NoiseMode::Static => seed,
+    }
+}
+
+/// Where the samples of a pixel are placed within it
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PixelSampling {
+    /// Every sample goes through the center of the pixel
+    ///
+    /// This doesn't anti-alias the image at all. With one sample per pixel it gives a clean
+    /// reference image to compare against other renderers.
+    Center,
+
+    /// The samples are spread over a regular grid of cells within the pixel, one sample at the
+    /// center of each cell
+    ///
+    /// The grid is as close to square as the number of samples per pixel allows. If that isn't a
+    /// square number, the last row of the grid is only partly filled.
+    Grid,
+
+    /// Every sample is placed at a uniformly random position within the pixel
+    Random,
+
+    /// Every sample is placed at a random position drawn from a tent distribution centered on the
+    /// pixel, which reaches one pixel out in every direction
+    ///
+    /// Samples near the center of the pixel are the most likely, so this approximates a triangle
+    /// filter without splatting the samples into neighboring pixels, and gives smoother edges than
+    /// `Random` for the same cost. Reconstruction filters other than the box filter already spread
+    /// every sample over its neighbors, so with those this places samples like `Random` does.
+    #[default]
+    Tent,
+}
+
+/// Map a uniform random number in [0, 1] to the tent distribution on [-1, 1]
+///
+/// This is the inverse of the tent's CDF, which is made of two parabolas that meet at 0.
+fn sample_tent(u: Float) -> Float {
+    if u < 0.5 {
+        (2.0 * u).sqrt() - 1.0
+    } else {
+        1.0 - (2.0 - 2.0 * u).sqrt()
+    }
+}
+
+impl PixelSampling {
+    /// The offset of a sample from the top left corner of its pixel
+    ///
+    /// `pass` is the index of the sample within the pixel, out of `samples_per_pixel`, and
+    /// `random` is a pair of uniform random numbers, which only `Random` and `Tent` use. `Tent`
+    /// offsets can lie up to a pixel outside of the pixel.
+    pub fn offset(
+        self,
+        pass: u32,
+        samples_per_pixel: u32,
+        random: (Float, Float),
+    ) -> (Float, Float) {
+        match self {
+            PixelSampling::Center => (0.5, 0.5),
+            PixelSampling::Grid => {
+                let columns = (samples_per_pixel as Float).sqrt().ceil().max(1.0) as u32;
+                let rows = samples_per_pixel.div_ceil(columns);
+                let column = pass % columns;
+                let row = pass / columns;
+                (
+                    (column as Float + 0.5) / columns as Float,
+                    (row as Float + 0.5) / rows.max(1) as Float,
+                )
+            }
+            PixelSampling::Random => random,
+            PixelSampling::Tent => (0.5 + sample_tent(random.0), 0.5 + sample_tent(random.1)),
+        }
+    }
+
+    /// The pattern that's used together with a reconstruction filter
+    ///
+    /// A tent jitter stands in for a filter, so with any filter other than the box filter, samples
+    /// are jittered uniformly instead.
+    pub fn with_filter(self, filter: FilterType) -> Self {
+        match self {
+            PixelSampling::Tent if filter != FilterType::Box => PixelSampling::Random,
+            x => x,
+        }
+    }
+}
+
+impl FromStr for PixelSampling {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "center" => Ok(PixelSampling::Center),
+            "grid" => Ok(PixelSampling::Grid),
+            "random" => Ok(PixelSampling::Random),
+            "tent" => Ok(PixelSampling::Tent),
+            _ => Err(format_err!(
+                "\"{}\" is not a supported pixel sampling pattern. Valid values are: \"center\", \"grid\", \"random\", \"tent\".",
+                s
+            )),
+        }
+    }
+}
+
+/// What a heatmap of the acceleration structure counts for each pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeatmapMetric {
+    /// Every step of the traversal, which is the nodes that were visited plus the objects that
+    /// were tested
+    Traversal,
+
+    /// Only the objects that were tested for an intersection
+    Intersections,
+}
+
+impl HeatmapMetric {
+    /// The value of the metric for a traversal
+    pub fn count(self, stats: &TraversalStats) -> usize {
+        match self {
+            HeatmapMetric::Traversal => stats.nodes_visited + stats.intersection_tests,
+            HeatmapMetric::Intersections => stats.intersection_tests,
+        }
+    }
+}
+
+impl FromStr for HeatmapMetric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "traversal" => Ok(HeatmapMetric::Traversal),
+            "intersections" => Ok(HeatmapMetric::Intersections),
+            _ => Err(format_err!(
+                "\"{}\" is not a supported heatmap metric. Valid values are: \"traversal\", \"intersections\".",
+                s
+            )),
+        }
+    }
+}
+
+/// The result of a render, along with some information about how it went
+#[derive(Debug, Clone)]
+pub struct RenderOutput {
+    /// The rendered pixels
+    pub buffer: Framebuffer,
+
+    /// The wall-clock time the render took
+    pub elapsed: Duration,
+
+    /// The number of samples that were taken for each pixel
+    ///
+    /// This is less than the number of samples requested by the scene if the render was cut short.
+    pub samples_taken: u32,
+
+    /// Whether the render stopped before taking every requested sample because it ran out of time
+    pub terminated_early: bool,
+
+    /// How much the image changed with each pass after the first, if the renderer was asked to
+    /// track convergence
+    ///
+    /// See `Renderer::track_convergence`. This is empty if convergence wasn't tracked.
+    pub convergence: Vec<Float>,
+}
+
 /// All of the information associated with the renderer required for generating an image from the
 /// scene
 ///
 /// The `RendererContext` struct contains all of the information that an integrator needs to
 /// generate an image. This is generated from the input `Scene` struct that is primarily used for
 /// serializing and deserializing scene information from user input.
+///
+/// Rendering only borrows the renderer, so one renderer can render several views at once, and the
+/// prepared scene is reference counted, so renderers with different settings can share it.
 #[derive(Debug)]
 pub struct Renderer {
-    pub arena: Arena,
-    pub accel: Box<dyn Accel>,
-    pub camera: Box<dyn camera::Camera>,
-    pub background: PixelValue<Float>,
+    /// The scene, prepared for rendering
+    ///
+    /// Preparing a scene is expensive, so this can be cloned into another renderer to render the
+    /// same scene with a different integrator or settings without preparing it again.
+    pub scene: Arc<ProcessedScene>,
+    pub background: Background,
+
+    /// The constant ambient light that integrators can add at diffuse surfaces
+    pub ambient: PixelValue<Float>,
     pub samples_per_pixel: u32,
     pub integrator: Box<dyn Integrator>,
     pub height: u32,
     pub width: u32,
+
+    /// An optional wall-clock budget for the render
+    ///
+    /// If this is set, the renderer stops taking new samples once the budget has been exceeded
+    /// and returns the average of the samples it has taken so far.
+    pub max_time: Option<Duration>,
+
+    /// The reconstruction filter used to combine samples into pixels
+    pub filter: FilterType,
+
+    /// Where the samples of each pixel are placed within it
+    pub pixel_sampling: PixelSampling,
+
+    /// The exposure of the rendered image, in stops
+    ///
+    /// The final buffer is multiplied by `2^exposure`, see `post::expose`.
+    pub exposure: Float,
+
+    /// The minimum number of pixels each thread processes at a time
+    ///
+    /// Larger chunks spend less time scheduling work, which helps cheap integrators, while smaller
+    /// chunks balance the load between threads better, which helps expensive ones. If this is
+    /// unset, the chunk size is derived from the size of the image.
+    pub chunk_size: Option<usize>,
+
+    /// When to draw a progress bar while rendering
+    pub progress: ProgressMode,
+
+    /// Whether to measure how much the image changes with each pass
+    ///
+    /// After every pass but the first, the renderer logs the root mean square difference between
+    /// the image so far and the image after the previous pass, over every channel of every pixel.
+    /// Each pass moves the average less than the last, so this falls roughly with the number of
+    /// samples taken, and it levels off relative to the previous passes once more samples stop
+    /// paying off. Measuring this averages the whole image after every pass, so it's off by
+    /// default.
+    pub track_convergence: bool,
+
+    /// The seed that every sampler used in the render is derived from
+    pub seed: u64,
 }
 
 impl Renderer {
-    /// A small convenience method to generate the progress bar for the CLI
-    fn create_progress_bar(&self) -> ProgressBar {
-        let n = (self.width * self.height).into();
-        let pb = ProgressBar::new(n);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("{elapsed_precise}/{eta_precise} [{wide_bar}] {percent}%")
-                .unwrap()
-                .progress_chars("=> "),
-        );
-        pb.enable_steady_tick(Duration::from_millis(300));
-        pb
+    /// Switch to the seed for a frame of an animation, see `frame_seed`
+    ///
+    /// Returns the seed of the animation, which is the one to report to the user. Rendering the
+    /// scene with that seed and the same frame reproduces the frame, whereas the per-frame seed
+    /// would have the frame's offset applied a second time.
+    pub fn use_frame_seed(&mut self, frame: u32, mode: NoiseMode) -> u64 {
+        let seed = self.seed;
+        self.seed = frame_seed(seed, frame, mode);
+        seed
     }
 
-    /// Render the image, returning a buffer of pixels
+    /// Render the image, returning a buffer of pixels along with some statistics about the render
     ///
     /// You can optionally specify the number of threads you'd like to use. If this is unset or set
     /// to 0, Rayon will automatically infer the number of threads to use based on the number of
-    /// logical CPUs detected on the system.
-    pub fn render(&mut self, num_threads: Option<usize>) -> anyhow::Result<Vec<PixelValue<Float>>> {
-        let pb = self.create_progress_bar();
-        let sampler = sampler::Random::default();
+    /// logical CPUs detected on the system. With a single thread, the image is rendered on the
+    /// calling thread without Rayon at all, which keeps backtraces and debuggers free of Rayon's
+    /// worker threads. The image is the same either way.
+    ///
+    /// The image is rendered in passes, where each pass takes one sample for every pixel. This
+    /// means that if the render is cut short by `max_time`, every pixel has still been sampled the
+    /// same number of times and the result is a valid (if noisier) image.
+    ///
+    /// Each sample is added to the pixels around it, weighted by the reconstruction filter, and the
+    /// final value of a pixel is the weighted average of every sample that contributed to it.
+    pub fn render(&self, num_threads: Option<usize>) -> anyhow::Result<RenderOutput> {
+        let sequential = num_threads == Some(1);
 
-        if let Some(n) = num_threads {
+        if let Some(n) = num_threads.filter(|_| !sequential) {
             set_threads(n)?;
         }
+        self.render_samples(
+            self.scene.camera.as_ref(),
+            self.width,
+            self.height,
+            self.samples_per_pixel,
+            sequential,
+        )
+    }
+
+    /// Render the scene from another camera, at another resolution, or with another number of
+    /// samples per pixel
+    ///
+    /// This renders with the renderer's integrator and every other setting, like `render`, but
+    /// with the given view instead of the scene's camera and size. The scene is shared rather
+    /// than prepared again, and rendering only borrows the renderer, so several views can be
+    /// rendered at the same time, like the two eyes of a stereo pair. The camera should already
+    /// be set up for the aspect ratio of the view.
+    ///
+    /// This runs on the current Rayon thread pool.
+    pub fn render_view(
+        &self,
+        camera: &dyn Camera,
+        width: u32,
+        height: u32,
+        samples_per_pixel: u32,
+    ) -> anyhow::Result<RenderOutput> {
+        self.render_samples(camera, width, height, samples_per_pixel, false)
+    }
+
+    /// Render a view, either on the current Rayon thread pool or, if `sequential` is set, one
+    /// sample after another on the calling thread
+    fn render_samples(
+        &self,
+        camera: &dyn Camera,
+        width: u32,
+        height: u32,
+        samples_per_pixel: u32,
+        sequential: bool,
+    ) -> anyhow::Result<RenderOutput> {
+        // Without any samples, every pixel would be an average over nothing
+        if samples_per_pixel == 0 {
+            return Err(format_err!(
+                "The renderer must take at least one sample per pixel"
+            ));
+        }
+        let n_samples = u64::from(width) * u64::from(height) * u64::from(samples_per_pixel);
+        let pb = Progress::new(n_samples, self.progress);
+        let start = Instant::now();
+
+        let pixel_sampling = self.pixel_sampling.with_filter(self.filter);
 
         // So we can avoid recomputing these with every pixel
-        let width_float = self.width as Float;
-        let height_float = self.height as Float;
-        let spp_float = self.samples_per_pixel as Float;
-
-        // We use a sampler per thread rather than sharing a sampler over all threads because the
-        // lock contention causes a large performance hit.
-        let mut buffer = Vec::with_capacity((self.width * self.height) as usize);
-        (0..(self.width * self.height))
+        let width_float = width as Float;
+        let height_float = height as Float;
+
+        let n_pixels = (width * height) as usize;
+        let mut color_sums = vec![PixelValue::new(0.0, 0.0, 0.0); n_pixels];
+        let mut weight_sums: Vec<Float> = vec![0.0; n_pixels];
+        let mut samples_taken = 0;
+        let mut convergence = Vec::new();
+        let mut previous_image: Option<Vec<PixelValue<Float>>> = None;
+
+        for pass in 0..samples_per_pixel {
+            // We always take at least one pass so there is something to return
+            if let Some(max_time) = self.max_time {
+                if pass > 0 && start.elapsed() >= max_time {
+                    break;
+                }
+            }
+
+            // Every sample gets its own sampler, seeded from the pixel and the pass, rather than
+            // sharing one sampler over all threads. This avoids lock contention, and it makes the
+            // random numbers for a sample independent of which thread renders it, so renders with
+            // the same seed are reproducible.
+            let sample = |i| {
+                let sampler = &mut sampler::Random::with_seed(sample_seed(self.seed, pass, i));
+                // Two dimensions for the position within the pixel, two for the lens, and one for
+                // the time
+                let camera_samples = sampler.next(5).unwrap();
+
+                let (dx, dy) = pixel_sampling.offset(
+                    pass,
+                    samples_per_pixel,
+                    (camera_samples[0], camera_samples[1]),
+                );
+
+                // The position of the sample in image space, where (0, 0) is the top left corner
+                // of the image
+                let x = (i % width) as Float + dx;
+                let y = (i / width) as Float + dy;
+                let u = x / width_float;
+                let v = 1.0 - (y / height_float);
+                let ray = camera.generate_ray(
+                    u,
+                    v,
+                    (camera_samples[2], camera_samples[3]),
+                    camera_samples[4],
+                );
+                let params = RenderParams {
+                    origin: &ray,
+                    context: self,
+                    sampler,
+                    medium: MediumStack::default(),
+                    image_uv: Vector2::new(u, v),
+                    depth: 0,
+                    reflection_depth: 0,
+                    refraction_depth: 0,
+                    lights_sampled: false,
+                };
+                let color = self.integrator.render(params);
+                pb.inc();
+                (i, x, y, color)
+            };
+            let samples: Vec<_> = if sequential {
+                (0..(width * height)).map(sample).collect()
+            } else {
+                // Asking for the number of threads starts Rayon's thread pool, so this is only
+                // done when it's going to be used
+                let chunk_size = self
+                    .chunk_size
+                    .unwrap_or_else(|| default_chunk_size(n_pixels))
+                    .max(1);
+                let mut samples = Vec::with_capacity(n_pixels);
+                (0..(width * height))
+                    .into_par_iter()
+                    .with_min_len(chunk_size)
+                    .map(sample)
+                    .collect_into_vec(&mut samples);
+                samples
+            };
+
+            for (i, x, y, color) in samples {
+                self.splat(
+                    &mut color_sums,
+                    &mut weight_sums,
+                    (width, height),
+                    i,
+                    x,
+                    y,
+                    color,
+                );
+            }
+            samples_taken += 1;
+
+            if self.track_convergence {
+                let image = average(&color_sums, &weight_sums);
+
+                if let Some(previous) = &previous_image {
+                    let change = rms_difference(previous, &image);
+                    info!(
+                        "The image changed by {:.6} (RMS) with pass {}",
+                        change, samples_taken
+                    );
+                    convergence.push(change);
+                }
+                previous_image = Some(image);
+            }
+        }
+        pb.finish();
+        let mut buffer = Framebuffer::from_vec(width, height, average(&color_sums, &weight_sums))?;
+        post::expose(&mut buffer, self.exposure);
+        Ok(RenderOutput {
+            buffer,
+            elapsed: start.elapsed(),
+            samples_taken,
+            terminated_early: samples_taken < samples_per_pixel,
+            convergence,
+        })
+    }
+
+    /// Render a heatmap of how much work the acceleration structure does for each pixel
+    ///
+    /// Each pixel is colored by the work done to find the closest collision of the primary ray
+    /// through its center, scaled so that the busiest pixel is red and a pixel that took no work
+    /// is blue. The integrator isn't run, so this is quick, and it shows where the acceleration
+    /// structure is doing more work than it should. Returns the image along with the count of the
+    /// busiest pixel.
+    pub fn render_heatmap(
+        &self,
+        metric: HeatmapMetric,
+        num_threads: Option<usize>,
+    ) -> anyhow::Result<(Framebuffer, usize)> {
+        if let Some(n) = num_threads {
+            set_threads(n)?;
+        }
+        let counts: Vec<usize> = (0..(self.width * self.height))
             .into_par_iter()
-            .map_with(
-                || sampler.clone(),
-                |sampler_generator, i| {
-                    let mut sampler = sampler_generator();
-                    let x = (i % self.width) as Float;
-                    let y = (self.height - (i / self.width)) as Float;
-                    let acc: PixelValue<Float> = (0..self.samples_per_pixel)
-                        .map(|_| {
-                            let camera_samples = sampler.next(2).unwrap();
-
-                            let u = (x + camera_samples[0]) / width_float;
-                            let v = (y + camera_samples[1]) / height_float;
-                            let ray = self.camera.to_ray(u, v);
-                            let params = RenderParams {
-                                origin: &ray,
-                                context: &self,
-                                sampler: &mut sampler,
-                            };
-                            let color = self.integrator.render(params);
-                            color
-                        })
-                        .fold(PixelValue::new(0.0, 0.0, 0.0), |acc, x| acc + x);
-                    pb.inc(1);
-                    PixelValue::new(acc.x / spp_float, acc.y / spp_float, acc.z / spp_float)
-                },
-            )
-            .collect_into_vec(&mut buffer);
-        pb.finish_and_clear();
-        Ok(buffer)
+            .map(|i| {
+                let u = ((i % self.width) as Float + 0.5) / self.width as Float;
+                let v = 1.0 - ((i / self.width) as Float + 0.5) / self.height as Float;
+                let ray = self.scene.camera.generate_ray(u, v, (0.5, 0.5), 0.5);
+                let mut stats = TraversalStats::default();
+                self.scene.accel.collision_with_stats(&ray, &mut stats);
+                metric.count(&stats)
+            })
+            .collect();
+        let max_count = counts.iter().copied().max().unwrap_or(0);
+        let buffer = counts
+            .into_iter()
+            .map(|count| false_color(count as Float / max_count.max(1) as Float))
+            .collect();
+        Ok((
+            Framebuffer::from_vec(self.width, self.height, buffer)?,
+            max_count,
+        ))
+    }
+
+    /// Add a sample's contribution to the pixels around it
+    ///
+    /// `i` is the index of the pixel the sample was generated for, and `(x, y)` is the position of
+    /// the sample in image space. With a box filter the sample only counts towards its own pixel,
+    /// otherwise it counts towards every pixel within the filter's radius. `size` is the width and
+    /// height of the image.
+    #[allow(clippy::too_many_arguments)]
+    fn splat(
+        &self,
+        color_sums: &mut [PixelValue<Float>],
+        weight_sums: &mut [Float],
+        size: (u32, u32),
+        i: u32,
+        x: Float,
+        y: Float,
+        color: PixelValue<Float>,
+    ) {
+        if self.filter == FilterType::Box {
+            color_sums[i as usize] += color;
+            weight_sums[i as usize] += 1.0;
+            return;
+        }
+        let radius = self.filter.radius();
+
+        // The range of pixels whose centers lie within the filter radius, clamped to the image
+        let x_min = ((x - radius - 0.5).ceil() as i64).max(0);
+        let (width, height) = size;
+        let x_max = ((x + radius - 0.5).floor() as i64).min(i64::from(width) - 1);
+        let y_min = ((y - radius - 0.5).ceil() as i64).max(0);
+        let y_max = ((y + radius - 0.5).floor() as i64).min(i64::from(height) - 1);
+
+        for py in y_min..=y_max {
+            for px in x_min..=x_max {
+                let weight = self
+                    .filter
+                    .weight(px as Float + 0.5 - x, py as Float + 0.5 - y);
+                let idx = (py * i64::from(width) + px) as usize;
+                color_sums[idx] += color * weight;
+                weight_sums[idx] += weight;
+            }
+        }
     }
 }
 
+/// The weighted average of the samples in each pixel, or black for a pixel without any samples
+fn average(color_sums: &[PixelValue<Float>], weight_sums: &[Float]) -> Vec<PixelValue<Float>> {
+    color_sums
+        .iter()
+        .zip(weight_sums)
+        .map(|(&color, &weight)| {
+            if weight > 0.0 {
+                color / weight
+            } else {
+                PixelValue::new(0.0, 0.0, 0.0)
+            }
+        })
+        .collect()
+}
+
+/// The root mean square difference between two images of the same size, over every channel
+fn rms_difference(a: &[PixelValue<Float>], b: &[PixelValue<Float>]) -> Float {
+    let squared_error_sum: f64 = a
+        .iter()
+        .zip(b)
+        .map(|(a, b)| f64::from((a - b).magnitude2()))
+        .sum();
+    let n_channels = (a.len() * 3).max(1) as f64;
+    (squared_error_sum / n_channels).sqrt() as Float
+}
+
+/// Render a scene entirely in memory
+///
+/// This is the whole render pipeline without any of the CLI's file handling, so it can be used
+/// when nib is embedded in another application. See `Renderer::render` for the meaning of
+/// `num_threads`.
+pub fn render_scene(scene: Scene, num_threads: Option<usize>) -> anyhow::Result<RenderOutput> {
+    Renderer::try_from(scene)?.render(num_threads)
+}
+
+/// Render a scene to 8-bit RGBA bytes, returning the bytes along with the width and height
+///
+/// The pixels are in row-major order starting from the top left corner of the image, which can be
+/// uploaded directly to a canvas or a texture.
+pub fn render_to_rgba(scene: Scene) -> anyhow::Result<(Vec<u8>, u32, u32)> {
+    let (width, height) = (scene.width, scene.height);
+    let output = render_scene(scene, None)?;
+    Ok((to_rgba8(&output.buffer), width, height))
+}
+
+/// Derive the seed for the sampler of a single sample from the seed of the render
+///
+/// Each pass and pixel gets a distinct seed, so no two samples in a render share their random
+/// numbers. Neighboring pixels would get seeds that only differ in a few low bits, and the first
+/// numbers a PRNG generates from similar seeds can be correlated, which shows up as faint
+/// structured noise. To avoid that, the index of the sample is hashed with SplitMix64 before it's
+/// combined with the seed, and the result is hashed again, so every bit of the seed depends on
+/// every bit of the pass, the pixel, and the seed of the render.
+fn sample_seed(seed: u64, pass: u32, pixel: u32) -> u64 {
+    let index = (u64::from(pass) << 32) | u64::from(pixel);
+    splitmix64(seed ^ splitmix64(index))
+}
+
+/// The SplitMix64 mixing function
+///
+/// This is a bijection on 64 bit integers where flipping any bit of the input flips about half
+/// of the bits of the output, see Steele et al., "Fast Splittable Pseudorandom Number
+/// Generators" (2014).
+fn splitmix64(x: u64) -> u64 {
+    let x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// The number of chunks per thread that the default chunk size aims for
+///
+/// Having several chunks per thread lets Rayon rebalance the work when some parts of the image are
+/// more expensive to render than others.
+const CHUNKS_PER_THREAD: usize = 16;
+
+/// The largest chunk size the renderer will pick on its own
+const MAX_DEFAULT_CHUNK_SIZE: usize = 1024;
+
+/// Pick a chunk size for an image with `n_pixels` pixels
+///
+/// This splits the image into a handful of chunks for each thread, so large images are processed in
+/// batches while small images still keep every thread busy.
+fn default_chunk_size(n_pixels: usize) -> usize {
+    let n_chunks = rayon::current_num_threads() * CHUNKS_PER_THREAD;
+    (n_pixels / n_chunks).clamp(1, MAX_DEFAULT_CHUNK_SIZE)
+}
+
 /// Set the number of threads in the global threadpool
 fn set_threads(num_threads: usize) -> Result<(), rayon::ThreadPoolBuildError> {
     rayon::ThreadPoolBuilder::new()
         .num_threads(num_threads)
         .build_global()
 }
+
+/// A compile time check that everything the worker threads share can be shared between threads
+///
+/// The traits for the scene's components all require `Send + Sync`, but the structs that hold
+/// them are only checked where they happen to be used from a thread pool. This makes a
+/// regression show up as an error here, instead of somewhere deep inside rayon.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync + ?Sized>() {}
+    assert_send_sync::<Renderer>();
+    assert_send_sync::<ProcessedScene>();
+    assert_send_sync::<Arena>();
+    assert_send_sync::<crate::light::Light>();
+    assert_send_sync::<dyn crate::camera::Camera>();
+    assert_send_sync::<dyn Integrator>();
+    assert_send_sync::<dyn crate::accel::Accel>();
+    assert_send_sync::<dyn crate::material::BSDF>();
+    assert_send_sync::<dyn crate::hittable::Hittable>();
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        accel::{GridParams, SerializedAccelerationStruct},
+        camera::BasicPinhole,
+        test_scene::{TestScene, GRAY},
+    };
+    use cgmath::Vector3;
+
+    /// A small scene with a diffuse sphere, where every sample depends on random numbers
+    fn diffuse_scene(seed: Option<u64>) -> Scene {
+        let mut scene = TestScene::new()
+            .sphere([0.0, 0.0, -1.0], 0.8, GRAY)
+            .background(r#"{ "type": "Gradient", "bottom": [1, 1, 1], "top": [0.7, 0.7, 0.7] }"#)
+            .samples_per_pixel(2)
+            .integrator(r#"{ "type": "Whitted", "max_depth": 4 }"#)
+            .build();
+        scene.seed = seed;
+        scene
+    }
+
+    #[test]
+    fn seeded_renders_are_reproducible() {
+        let render = |seed| render_scene(diffuse_scene(seed), None).unwrap().buffer;
+        assert_eq!(render(Some(7)), render(Some(7)));
+        assert_ne!(render(Some(7)), render(Some(8)));
+
+        // Without a seed, every render gets a fresh one
+        let renderer = Renderer::try_from(diffuse_scene(None)).unwrap();
+        let other = Renderer::try_from(diffuse_scene(None)).unwrap();
+        assert_ne!(renderer.seed, other.seed);
+    }
+
+    /// The first sample of a pixel shouldn't be correlated with the first sample of its neighbor
+    #[test]
+    fn neighboring_seeds_are_independent() {
+        let first_sample = |pass, pixel| {
+            let mut sampler = sampler::Random::with_seed(sample_seed(42, pass, pixel));
+            sampler.next(1).unwrap()[0]
+        };
+        let n = 10_000;
+        let pairs: Vec<(Float, Float)> = (0..n)
+            .map(|i| (first_sample(0, i), first_sample(0, i + 1)))
+            .chain((0..n).map(|i| (first_sample(i, 0), first_sample(i + 1, 0))))
+            .collect();
+
+        // For independent uniform samples, the mean is 1/2 and the correlation is 0, with a
+        // standard error of about 1 / sqrt(2n) = 0.007
+        let count = pairs.len() as Float;
+        let mean = pairs.iter().map(|(a, _)| a).sum::<Float>() / count;
+        let covariance = pairs
+            .iter()
+            .map(|(a, b)| (a - 0.5) * (b - 0.5))
+            .sum::<Float>()
+            / count;
+        let correlation = covariance * 12.0;
+        assert!((mean - 0.5).abs() < 0.02, "mean {}", mean);
+        assert!(correlation.abs() < 0.03, "correlation {}", correlation);
+    }
+
+    #[test]
+    fn frame_seeds() {
+        assert_eq!(frame_seed(7, 0, NoiseMode::Boil), 7);
+        assert_ne!(
+            frame_seed(7, 1, NoiseMode::Boil),
+            frame_seed(7, 2, NoiseMode::Boil)
+        );
+        assert_ne!(frame_seed(7, 1, NoiseMode::Boil), 7);
+        assert_eq!(frame_seed(7, 3, NoiseMode::Static), 7);
+
+        assert_eq!("Static".parse::<NoiseMode>().unwrap(), NoiseMode::Static);
+        assert_eq!("boil".parse::<NoiseMode>().unwrap(), NoiseMode::Boil);
+        assert!("flicker".parse::<NoiseMode>().is_err());
+
+        // Rendering with the reported seed and the same frame gives the same per-frame seed
+        let mut renderer = Renderer::try_from(diffuse_scene(None)).unwrap();
+        let reported = renderer.use_frame_seed(3, NoiseMode::Boil);
+        let mut rerun = Renderer::try_from(diffuse_scene(Some(reported))).unwrap();
+        rerun.use_frame_seed(3, NoiseMode::Boil);
+        assert_ne!(renderer.seed, reported);
+        assert_eq!(rerun.seed, renderer.seed);
+    }
+
+    #[test]
+    fn heatmap() {
+        // The object list tests the one sphere for every pixel, so every pixel is the busiest
+        let renderer = Renderer::try_from(diffuse_scene(Some(0))).unwrap();
+        let (buffer, max_count) = renderer
+            .render_heatmap(HeatmapMetric::Intersections, None)
+            .unwrap();
+        assert_eq!(max_count, 1);
+        assert_eq!(*buffer, [false_color(1.0); 16 * 8]);
+
+        // The grid does more work in the middle of the image, where the sphere is
+        let mut scene = diffuse_scene(Some(0));
+        scene.acceleration_structure =
+            SerializedAccelerationStruct::Grid(GridParams { resolution: 4 });
+        let renderer = Renderer::try_from(scene).unwrap();
+        let (buffer, max_count) = renderer
+            .render_heatmap(HeatmapMetric::Traversal, None)
+            .unwrap();
+        let center = buffer[4 * 16 + 8];
+        assert!(max_count > 1);
+        assert_ne!(center, false_color(0.0));
+        assert!(buffer.iter().any(|&x| x != center));
+
+        assert_eq!(
+            "Intersections".parse::<HeatmapMetric>().unwrap(),
+            HeatmapMetric::Intersections
+        );
+        assert!("nodes".parse::<HeatmapMetric>().is_err());
+    }
+
+    #[test]
+    fn sequential_renders_match_parallel_ones() {
+        // A wide filter splats every sample into several pixels, so the order that samples are
+        // added in matters too
+        let mut scene = diffuse_scene(Some(9));
+        scene.filter = FilterType::Gaussian;
+        let renderer = Renderer::try_from(scene).unwrap();
+        let camera = renderer.scene.camera.as_ref();
+        let sequential = renderer.render_samples(camera, 16, 8, 2, true).unwrap();
+        let parallel = renderer.render_samples(camera, 16, 8, 2, false).unwrap();
+        assert_eq!(sequential.buffer, parallel.buffer);
+        assert_eq!(sequential.buffer, renderer.render(Some(1)).unwrap().buffer);
+    }
+
+    #[test]
+    fn render_view() {
+        let renderer = Renderer::try_from(diffuse_scene(Some(3))).unwrap();
+
+        // The scene's own view is the same as a normal render
+        let view = renderer
+            .render_view(renderer.scene.camera.as_ref(), 16, 8, 2)
+            .unwrap();
+        assert_eq!(view.buffer, renderer.render(None).unwrap().buffer);
+
+        // Two eyes of a stereo pair can be rendered at the same time from the same scene
+        let eye = |x| BasicPinhole {
+            origin: Vector3::new(x, 0.0, 0.0),
+            horizontal: Vector3::new(4.0, 0.0, 0.0),
+            vertical: Vector3::new(0.0, 2.0, 0.0),
+            lower_left: Vector3::new(x - 2.0, -1.0, -1.0),
+        };
+        let (left, right) = rayon::join(
+            || renderer.render_view(&eye(-0.5), 8, 4, 1).unwrap(),
+            || renderer.render_view(&eye(0.5), 8, 4, 1).unwrap(),
+        );
+        assert_eq!(left.buffer.len(), 8 * 4);
+        assert_eq!(left.samples_taken, 1);
+        assert_ne!(left.buffer, right.buffer);
+        assert!(renderer.render_view(&eye(0.0), 8, 4, 0).is_err());
+    }
+
+    #[test]
+    fn convergence() {
+        let mut scene = diffuse_scene(Some(5));
+        scene.samples_per_pixel = 16;
+        let mut renderer = Renderer::try_from(scene).unwrap();
+        assert!(renderer.render(None).unwrap().convergence.is_empty());
+
+        // The image changes less and less as samples are added, and tracking it doesn't change
+        // the image
+        renderer.track_convergence = true;
+        let output = renderer.render(None).unwrap();
+        assert_eq!(output.convergence.len(), 15);
+        assert!(output.convergence[14] < output.convergence[0]);
+        assert_eq!(output.buffer, renderer.render(None).unwrap().buffer);
+
+        let gray = vec![PixelValue::new(0.5, 0.5, 0.5); 2];
+        let mut brighter = gray.clone();
+        brighter[0].x = 1.1;
+        assert_eq!(rms_difference(&gray, &gray), 0.0);
+        assert!((rms_difference(&gray, &brighter) - 0.06f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn exposure() {
+        let render = |exposure| {
+            let mut scene = diffuse_scene(Some(3));
+            scene.exposure = exposure;
+            render_scene(scene, None).map(|x| x.buffer)
+        };
+        let base = render(0.0).unwrap();
+        let brighter = render(1.0).unwrap();
+
+        for (a, b) in base.iter().zip(&brighter) {
+            assert!((a * 2.0 - b).magnitude() < 1e-5);
+        }
+        assert!(render(Float::NAN).is_err());
+    }
+
+    #[test]
+    fn pixel_sampling() {
+        assert_eq!(PixelSampling::Center.offset(3, 4, (0.1, 0.9)), (0.5, 0.5));
+        assert_eq!(PixelSampling::Random.offset(3, 4, (0.1, 0.9)), (0.1, 0.9));
+
+        // The tent is centered on the pixel and reaches halfway into the neighboring pixels for
+        // an eighth of the samples on each side
+        assert_eq!(PixelSampling::Tent.offset(3, 4, (0.5, 0.5)), (0.5, 0.5));
+        assert_eq!(PixelSampling::Tent.offset(3, 4, (0.0, 1.0)), (-0.5, 1.5));
+        assert_eq!(PixelSampling::Tent.offset(3, 4, (0.125, 0.875)), (0.0, 1.0));
+
+        // The tent jitter is only used with the box filter
+        assert_eq!(
+            PixelSampling::Tent.with_filter(FilterType::Box),
+            PixelSampling::Tent
+        );
+        assert_eq!(
+            PixelSampling::Tent.with_filter(FilterType::Gaussian),
+            PixelSampling::Random
+        );
+        assert_eq!(
+            PixelSampling::Grid.with_filter(FilterType::Gaussian),
+            PixelSampling::Grid
+        );
+
+        // Four samples make a 2x2 grid, and three fill the top row of one and half of the bottom
+        let grid: Vec<_> = (0..4)
+            .map(|i| PixelSampling::Grid.offset(i, 4, (0.0, 0.0)))
+            .collect();
+        assert_eq!(
+            grid,
+            [(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)]
+        );
+        assert_eq!(PixelSampling::Grid.offset(2, 3, (0.0, 0.0)), (0.25, 0.75));
+        assert_eq!(PixelSampling::Grid.offset(0, 1, (0.0, 0.0)), (0.5, 0.5));
+
+        // Samples through the center of every pixel don't depend on the seed, since the scene has
+        // no other randomness for a camera ray that misses the sphere
+        let render = |seed| {
+            let mut scene = diffuse_scene(Some(seed));
+            scene.pixel_sampling = Some(PixelSampling::Center);
+            scene.objects.clear();
+            render_scene(scene, None).unwrap().buffer
+        };
+        assert_eq!(render(1), render(2));
+
+        assert_eq!(
+            "Grid".parse::<PixelSampling>().unwrap(),
+            PixelSampling::Grid
+        );
+        assert!("stratified".parse::<PixelSampling>().is_err());
+    }
+
+    #[test]
+    fn zero_samples_per_pixel() {
+        let mut scene = diffuse_scene(Some(0));
+        scene.samples_per_pixel = 0;
+        assert!(Renderer::try_from(scene).is_err());
+
+        let mut renderer = Renderer::try_from(diffuse_scene(Some(0))).unwrap();
+        renderer.samples_per_pixel = 0;
+        assert!(renderer.render(None).is_err());
+    }
+
+    #[test]
+    fn render_to_rgba() {
+        let scene = TestScene::new()
+            .sphere([0.0, 0.0, -1.0], 0.8, GRAY)
+            .samples_per_pixel(4)
+            .integrator(r#"{ "type": "Coverage" }"#)
+            .resolution(8, 4)
+            .build();
+        let (bytes, width, height) = super::render_to_rgba(scene).unwrap();
+        assert_eq!((width, height), (8, 4));
+        assert_eq!(bytes.len(), 8 * 4 * 4);
+        // Every pixel is opaque
+        assert!(bytes.chunks(4).all(|pixel| pixel[3] == u8::MAX));
+
+        // The sphere covers the center of the image, but not the corners
+        let pixel = |x: usize, y: usize| &bytes[(y * 8 + x) * 4..(y * 8 + x) * 4 + 3];
+        assert_eq!(pixel(4, 2), &[u8::MAX; 3]);
+        assert_eq!(pixel(0, 0), &[0; 3]);
+    }
+}
@@ -115,4 +115,17 @@ where
     /// If there are no more dimensions remaining for this particular index, then this will return
     /// an error, or an incomplete
     fn next(&mut self, dimensions: u32) -> SamplerResult<Vec<T>, T>;
+
+    /// Clone the sampler into a new trait object, including its current state
+    ///
+    /// `Clone` isn't object safe, so this is how a sampler that's only known as a trait object,
+    /// like one picked in the scene file, can be copied for each thread. The clone continues from
+    /// the same state as the original, but advancing one doesn't advance the other.
+    fn clone_sampler(&self) -> Box<dyn Sampler<T>>;
+}
+
+impl<T: GenFloat> Clone for Box<dyn Sampler<T>> {
+    fn clone(&self) -> Self {
+        self.clone_sampler()
+    }
 }
@@ -5,60 +5,135 @@
 use crate::{sampler::Sampler, types::GenFloat};
 use cgmath::{InnerSpace, Vector3};
 
+/// The maximum number of candidates the rejection sampling functions will draw
+///
+/// Rejection sampling relies on the sampler eventually producing a point inside the target
+/// region. A misbehaving or exhausted sampler might never do that, so after this many attempts
+/// we give up and project the last candidate onto the boundary of the region instead of looping
+/// forever.
+pub const MAX_REJECTION_ATTEMPTS: usize = 64;
+
 /// Generate a random sample in the unit sphere
 ///
 /// This generates a random sample in the unit sphere by generating random vectors in each axis
-/// which generates a random sample in the unit cube, and then normalizing the vectors so that they
-/// lie within the unit sphere.
+/// which generates a random sample in the unit cube, and rejecting the vectors that don't lie
+/// within the unit sphere.
 ///
-/// We reject vectors with very small norms, as this can be an issue with floating point numbers.
+/// If no candidate lands inside the sphere within `MAX_REJECTION_ATTEMPTS` attempts, the last
+/// candidate is normalized so the result is always a valid point.
 pub fn sample_unit_sphere<T: GenFloat>(sampler: &mut dyn Sampler<T>) -> Vector3<T> {
-    let mut v = Vector3::new(
-        T::from(2).unwrap(),
-        T::from(2).unwrap(),
-        T::from(2).unwrap(),
-    );
     let unit = Vector3::new(
         T::from(1).unwrap(),
         T::from(1).unwrap(),
         T::from(1).unwrap(),
     );
     let scaling_factor = T::from(2).unwrap();
-    while v.magnitude2() >= T::from(1.0).unwrap() {
+    rejection_sample(|| {
         let rs = sampler.next(3).unwrap();
         debug_assert_eq!(rs.len(), 3);
-        v = (Vector3::new(rs[0], rs[1], rs[2]) * scaling_factor) - unit;
-    }
-    v
+        (Vector3::new(rs[0], rs[1], rs[2]) * scaling_factor) - unit
+    })
 }
 
 /// Generate a random sample in the unit disk
 ///
-/// This method uses rejection sampling to generate the point.
-pub fn sample_disk<T: GenFloat>(sampler: &mut dyn Sampler<T>) -> Vector3<T> {
-    let mut v = Vector3::new(
-        T::from(2).unwrap(),
-        T::from(2).unwrap(),
-        T::from(2).unwrap(),
-    );
-
-    while v.magnitude2() >= T::from(1.0).unwrap() {
+/// This method uses rejection sampling to generate the point, which lies in the XY plane. Like
+/// `sample_unit_sphere`, it falls back to normalizing the last candidate if the sampler doesn't
+/// produce a point inside the disk within `MAX_REJECTION_ATTEMPTS` attempts.
+pub fn sample_unit_disk<T: GenFloat>(sampler: &mut dyn Sampler<T>) -> Vector3<T> {
+    let unit = Vector3::new(T::from(1).unwrap(), T::from(1).unwrap(), T::zero());
+    let scaling_factor = T::from(2).unwrap();
+    rejection_sample(|| {
         let rs = sampler.next(2).unwrap();
-        v = (Vector3::new(rs[0], rs[1], T::from(0).unwrap())
-            - Vector3::new(
-                T::from(1).unwrap(),
-                T::from(1).unwrap(),
-                T::from(0).unwrap(),
-            ))
-        .map(|x| x * T::from(2).unwrap());
+        debug_assert_eq!(rs.len(), 2);
+        (Vector3::new(rs[0], rs[1], T::zero()) * scaling_factor) - unit
+    })
+}
+
+/// Map a point in the unit square [0, 1]^2 to the unit disk
+///
+/// This is the concentric mapping from Shirley and Chiu's "A Low Distortion Map Between Disk and
+/// Square", which preserves areas, so uniform points in the square are uniform on the disk. Unlike
+/// `sample_unit_disk`, it uses exactly two numbers, so it works with samples that were drawn ahead
+/// of time, like the lens sample that the renderer passes to cameras. The point lies in the XY
+/// plane.
+pub fn map_unit_disk<T: GenFloat>(u: T, v: T) -> Vector3<T> {
+    let one = T::from(1).unwrap();
+    let two = T::from(2).unwrap();
+    let quarter_pi = T::from(std::f64::consts::FRAC_PI_4).unwrap();
+    let (x, y) = (u * two - one, v * two - one);
+
+    if x.is_zero() && y.is_zero() {
+        return Vector3::new(T::zero(), T::zero(), T::zero());
+    }
+    // Squares around the center map to circles, and the angle is spread evenly along each side
+    let (radius, theta) = if x.abs() > y.abs() {
+        (x, quarter_pi * (y / x))
+    } else {
+        (y, quarter_pi * two - quarter_pi * (x / y))
+    };
+    Vector3::new(radius * theta.cos(), radius * theta.sin(), T::zero())
+}
+
+/// Sample a direction around the +Z axis from a cosine-power (Phong) lobe
+///
+/// The directions are distributed with a density proportional to `cos^exponent(theta)`, where
+/// `theta` is the angle between the direction and the +Z axis, so larger exponents concentrate
+/// the samples closer to the axis. This returns the unit direction in the local frame of the lobe
+/// along with its probability density with respect to solid angle. Materials can rotate the
+/// direction into the frame of the reflected ray with `math::from_local`.
+///
+/// An exponent of 0 samples the hemisphere uniformly and an exponent of 1 is cosine-weighted
+/// sampling.
+pub fn sample_phong_lobe<T: GenFloat>(
+    exponent: T,
+    sampler: &mut dyn Sampler<T>,
+) -> (Vector3<T>, T) {
+    let one = T::from(1).unwrap();
+    let two_pi = T::from(std::f64::consts::TAU).unwrap();
+    let rs = sampler.next(2).unwrap();
+    debug_assert_eq!(rs.len(), 2);
+
+    // Invert the CDF of the lobe's distribution of cos(theta), which is cos^(n + 1)(theta)
+    let cos_theta = rs[0].powf(one / (exponent + one));
+    let sin_theta = (one - cos_theta * cos_theta).max(T::zero()).sqrt();
+    let phi = two_pi * rs[1];
+    let direction = Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+    let pdf = (exponent + one) / two_pi * cos_theta.powf(exponent);
+    (direction, pdf)
+}
+
+/// Draw candidates until one lies strictly inside the unit ball
+///
+/// The candidates are expected to lie within [-1, 1] on each axis. If none of the first
+/// `MAX_REJECTION_ATTEMPTS` candidates are accepted, the last one is normalized. A degenerate
+/// zero-length candidate can't be normalized, so the origin is returned in that case, which is
+/// still a valid point.
+fn rejection_sample<T: GenFloat>(mut candidate: impl FnMut() -> Vector3<T>) -> Vector3<T> {
+    let one = T::from(1).unwrap();
+    let mut v = Vector3::new(T::zero(), T::zero(), T::zero());
+
+    for _ in 0..MAX_REJECTION_ATTEMPTS {
+        v = candidate();
+
+        if v.magnitude2() < one {
+            return v;
+        }
+    }
+
+    let magnitude = v.magnitude();
+
+    if magnitude > T::zero() && magnitude.is_finite() {
+        v / magnitude
+    } else {
+        Vector3::new(T::zero(), T::zero(), T::zero())
     }
-    v
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::sampler::Random;
+    use crate::sampler::{Random, SamplerResult};
 
     // TODO add a test to check if the outputs are uniformly distributed within the sphere
     #[test]
@@ -74,4 +149,119 @@ mod tests {
             assert!(sphere_coordinates[2] >= -1.0 && sphere_coordinates[2] <= 1.0);
         }
     }
+
+    /// A sampler that always returns the same value for every dimension
+    #[derive(Debug, Clone)]
+    struct ConstantSampler(f32);
+
+    impl Sampler<f32> for ConstantSampler {
+        fn sample_idx(&mut self, _index: u32) -> SamplerResult<f32, f32> {
+            Ok(self.0)
+        }
+
+        fn sampler_idx_dims(
+            &mut self,
+            _index: u32,
+            dimensions: u32,
+        ) -> SamplerResult<Vec<f32>, f32> {
+            Ok(vec![self.0; dimensions as usize])
+        }
+
+        fn next(&mut self, dimensions: u32) -> SamplerResult<Vec<f32>, f32> {
+            Ok(vec![self.0; dimensions as usize])
+        }
+
+        fn clone_sampler(&self) -> Box<dyn Sampler<f32>> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn test_sample_unit_disk() {
+        let mut sampler = Random::default();
+        let mut quadrants = [false; 4];
+
+        for _ in 0..1000 {
+            let p = sample_unit_disk::<f32>(&mut sampler);
+            assert!(p.magnitude2() < 1.0);
+            assert_eq!(p.z, 0.0);
+            quadrants[(p.x >= 0.0) as usize * 2 + (p.y >= 0.0) as usize] = true;
+        }
+        // Every quadrant of the disk should be reachable
+        assert!(quadrants.iter().all(|&q| q));
+    }
+
+    #[test]
+    fn test_map_unit_disk() {
+        assert_eq!(map_unit_disk::<f32>(0.5, 0.5), Vector3::new(0.0, 0.0, 0.0));
+        assert!((map_unit_disk::<f32>(1.0, 0.5) - Vector3::new(1.0, 0.0, 0.0)).magnitude() < 1e-6);
+        assert!((map_unit_disk::<f32>(0.5, 0.0) - Vector3::new(0.0, -1.0, 0.0)).magnitude() < 1e-6);
+
+        // The corners of the square land on the edge of the disk, and the points are spread evenly
+        // over its area, so a quarter of them are within half of the radius
+        let n = 64;
+        let mut inner = 0;
+
+        for i in 0..n {
+            for j in 0..n {
+                let u = (i as f32 + 0.5) / n as f32;
+                let v = (j as f32 + 0.5) / n as f32;
+                let p = map_unit_disk(u, v);
+                assert!(p.magnitude() <= 1.0 + 1e-6);
+                inner += (p.magnitude() < 0.5) as usize;
+            }
+        }
+        assert_eq!(inner, n * n / 4);
+        assert!((map_unit_disk::<f32>(1.0, 1.0).magnitude() - 1.0).abs() < 1e-6);
+    }
+
+    /// The mean cosine of a Phong lobe with exponent `n` is `(n + 1) / (n + 2)`
+    #[test]
+    fn phong_lobe_mean_cosine() {
+        let mut sampler = Random::default();
+        let n_samples = 20000;
+
+        for exponent in [0.0, 1.0, 10.0, 100.0] {
+            let mut sum = 0.0;
+
+            for _ in 0..n_samples {
+                let (direction, pdf) = sample_phong_lobe::<f32>(exponent, &mut sampler);
+                assert!((direction.magnitude() - 1.0).abs() < 1e-5);
+                assert!(direction.z >= 0.0);
+                assert!(pdf >= 0.0);
+                sum += direction.z;
+            }
+            let mean = sum / n_samples as f32;
+            let expected = (exponent + 1.0) / (exponent + 2.0);
+            assert!(
+                (mean - expected).abs() < 0.01,
+                "exponent {}: mean cosine {} != {}",
+                exponent,
+                mean,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn phong_lobe_pdf() {
+        // A sample of 1 maps to the axis of the lobe, where the density peaks
+        let mut sampler = ConstantSampler(1.0);
+        let (direction, pdf) = sample_phong_lobe(4.0, &mut sampler);
+        assert!((direction - Vector3::new(0.0, 0.0, 1.0)).magnitude() < 1e-6);
+        assert!((pdf - 5.0 / (2.0 * std::f32::consts::PI)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn pathological_sampler_terminates() {
+        // A sampler that always returns 1 maps to the corner of the cube, which is never accepted
+        let mut sampler = ConstantSampler(1.0);
+
+        let p = sample_unit_sphere(&mut sampler);
+        assert!((p.magnitude() - 1.0).abs() < 1e-6);
+
+        let p = sample_unit_disk(&mut sampler);
+        assert!((p.magnitude() - 1.0).abs() < 1e-6);
+        assert_eq!(p.z, 0.0);
+    }
 }
@@ -76,7 +76,7 @@ where
 
 impl<T> Sampler<T> for Random<T>
 where
-    T: GenFloat,
+    T: GenFloat + 'static,
     rand::distributions::Standard: rand::distributions::Distribution<T>,
 {
     fn sample_idx(&mut self, _index: u32) -> SamplerResult<T, T> {
@@ -88,6 +88,28 @@ where
     }
 
     fn next(&mut self, dimensions: u32) -> SamplerResult<Vec<T>, T> {
-        Ok((0..dimensions).map(|_| rand::thread_rng().gen()).collect())
+        Ok((0..dimensions).map(|_| self.prng.gen()).collect())
+    }
+
+    fn clone_sampler(&self) -> Box<dyn Sampler<T>> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clone_trait_object() {
+        let mut original: Box<dyn Sampler<f32>> = Box::new(Random::with_seed(9));
+        original.next(3).unwrap();
+        let mut clone = original.clone();
+
+        // The clone picks up where the original left off, and the two advance independently
+        let expected = original.next(4).unwrap();
+        assert_eq!(clone.next(4).unwrap(), expected);
+        assert_eq!(clone.next(4).unwrap(), original.next(4).unwrap());
+        assert_ne!(clone.next(4).unwrap(), expected);
     }
 }
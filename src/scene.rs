@@ -5,15 +5,27 @@
 
 use crate::{
     accel::SerializedAccelerationStruct,
-    camera::{Camera, SerializedCamera},
-    hittable::SerializedTextured,
+    background::Background,
+    camera::SerializedCamera,
+    filter::FilterType,
+    hittable::{SerializedHittable, SerializedTextured},
     integrator::{Integrator, SerializedIntegrator},
-    renderer::{Arena, Renderer},
+    material::SerializedMaterial,
+    math::UpAxis,
+    processed_scene::ProcessedScene,
+    renderer::{PixelSampling, ProgressMode, Renderer},
     types::{Float, PixelValue},
 };
-use anyhow;
+use anyhow::format_err;
+use cgmath::Vector3;
+use log::warn;
 use serde::{Deserialize, Serialize};
-use std::{convert::TryFrom, sync::Arc};
+use std::{
+    convert::TryFrom,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 /// A struct representing the scene description as the user will input it
 ///
@@ -23,14 +35,35 @@ pub struct Scene {
     /// A list of all of the geometric objects in the scene
     pub objects: Vec<SerializedTextured>,
 
+    /// The material of every object that doesn't have a `mat` of its own
+    ///
+    /// This defaults to a neutral gray diffuse material, so sketching a scene doesn't require a
+    /// material for every object. Objects from included fragments use this too.
+    #[serde(default)]
+    pub default_material: SerializedMaterial,
+
     /// The acceleration structure to use with the scene
     pub acceleration_structure: SerializedAccelerationStruct,
 
     /// The camera to use with the scene
     pub camera: SerializedCamera,
 
-    /// The background color to return when no objects are hit
-    pub background: PixelValue<Float>,
+    /// What rays see when they don't hit any objects
+    ///
+    /// This can be a bare color, or a `Background` with a `type`, like a sky.
+    #[serde(deserialize_with = "crate::background::deserialize")]
+    pub background: Background,
+
+    /// A constant ambient light that the `Whitted` integrator adds at every diffuse surface
+    ///
+    /// This is a cheap stand-in for the light that bounces between surfaces, so that shadows
+    /// aren't pure black. It isn't global illumination: it lights every surface the same way,
+    /// no matter what's around it. It defaults to black, which turns it off.
+    #[serde(
+        default = "default_ambient",
+        deserialize_with = "crate::color::deserialize"
+    )]
+    pub ambient: PixelValue<Float>,
 
     /// The number of samples to take per pixel. This is effectively the anti-aliasing factor.
     pub samples_per_pixel: u32,
@@ -43,32 +76,432 @@ pub struct Scene {
 
     /// The horizontal resolution of the scene, in pixels
     pub width: u32,
+
+    /// An optional limit on how long the render can take, in seconds
+    ///
+    /// Once the limit is exceeded, the renderer stops sampling and outputs the image it has
+    /// accumulated so far.
+    #[serde(default)]
+    pub max_time: Option<Float>,
+
+    /// The reconstruction filter used to combine samples into pixels
+    ///
+    /// This defaults to a box filter, where every sample only contributes to its own pixel.
+    #[serde(default)]
+    pub filter: FilterType,
+
+    /// Where the samples of each pixel are placed within it
+    ///
+    /// This defaults to `Tent`, which jitters every sample around the center of its pixel, favoring
+    /// positions near the center. `Random` jitters the samples uniformly within the pixel,
+    /// `Center` turns anti-aliasing off, and `Grid` spreads the samples evenly over the pixel.
+    ///
+    /// This is `None` if the scene doesn't set it, so that the default tent jitter can be replaced
+    /// for other filters without a warning.
+    #[serde(default)]
+    pub pixel_sampling: Option<PixelSampling>,
+
+    /// The aspect ratio (width / height) of the camera's image plane
+    ///
+    /// By default this is derived from the resolution, so the image isn't stretched. Setting it to
+    /// something else stretches the image horizontally (if it's larger than width / height) or
+    /// vertically (if it's smaller), which is useful when the output will be displayed with
+    /// non-square pixels. This only affects cameras that are configured with a field of view, and
+    /// those cameras don't have an aspect ratio setting of their own.
+    #[serde(default)]
+    pub aspect_ratio: Option<Float>,
+
+    /// The seed for the random numbers used while rendering
+    ///
+    /// Rendering the same scene with the same seed produces the same image, regardless of the
+    /// machine or the number of threads. If this isn't set, a random seed is picked for every
+    /// render.
+    #[serde(default)]
+    pub seed: Option<u64>,
+
+    /// An optional limit on the number of primitives in the scene, after meshes are expanded
+    ///
+    /// Preparing a scene with more primitives than this fails with an error, rather than running
+    /// out of memory partway through loading a huge mesh. There's no limit by default.
+    #[serde(default)]
+    pub max_primitives: Option<usize>,
+
+    /// The exposure of the image, in stops (EV)
+    ///
+    /// The rendered linear image is multiplied by `2^exposure`, so every stop doubles (or for
+    /// negative values, halves) the brightness. This is applied before the image is converted for
+    /// output, and defaults to 0, which leaves the image unchanged.
+    #[serde(default)]
+    pub exposure: Float,
+
+    /// The axis that points up in the scene file's coordinates
+    ///
+    /// nib uses +Y as up internally (see `UpAxis`). Scenes exported from tools that use +Z, like
+    /// Blender, can set this to `Z`, and their geometry, lights, camera, and sky are rotated into
+    /// the internal convention when the scene is prepared for rendering.
+    #[serde(default)]
+    pub up_axis: UpAxis,
+
+    /// Whether to fit every mesh in the scene in a unit cube at the origin
+    ///
+    /// This sets `normalize` for every mesh, see `MeshParameters::normalize`.
+    #[serde(default)]
+    pub normalize_meshes: bool,
+
+    /// Other files whose objects are added to the scene
+    ///
+    /// This lets a scene be split across several files, such as a file with the lighting rig
+    /// and a file with the geometry. Relative paths are resolved relative to the scene file, and
+    /// the included files are scene fragments. The includes are resolved when the scene file is
+    /// parsed by the command line interface, which leaves this empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<PathBuf>,
+}
+
+/// A piece of a scene that can be included by a scene file
+///
+/// A fragment only holds objects, and can include more fragments. Including a fragment adds its
+/// objects to the scene.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SceneFragment {
+    /// The geometric objects that the fragment adds to the scene
+    #[serde(default)]
+    pub objects: Vec<SerializedTextured>,
+
+    /// Other fragments to include, relative to this fragment
+    #[serde(default)]
+    pub include: Vec<PathBuf>,
+}
+
+impl SceneFragment {
+    /// Resolve the relative paths of any files the fragment refers to, such as meshes
+    ///
+    /// `base` should be the directory containing the fragment.
+    pub fn resolve_paths(&mut self, base: &Path) {
+        resolve_paths(&mut self.objects, base);
+    }
+}
+
+/// Resolve the relative paths of any files that some objects refer to
+fn resolve_paths(objects: &mut [SerializedTextured], base: &Path) {
+    for object in objects {
+        if let SerializedHittable::Mesh(mesh) = &mut object.geometry {
+            mesh.resolve_path(base);
+        }
+    }
+}
+
+/// A helper method for serde that turns the ambient light off by default
+fn default_ambient() -> PixelValue<Float> {
+    PixelValue::new(0.0, 0.0, 0.0)
+}
+
+impl Scene {
+    /// Multiply the resolution of the scene by some factor
+    ///
+    /// The new width and height are rounded to the nearest pixel, and are never smaller than one
+    /// pixel. Since the camera's aspect ratio is computed from the resolution when the renderer is
+    /// constructed, this should be applied before converting the scene to a `Renderer`.
+    pub fn scale_resolution(&mut self, factor: Float) -> anyhow::Result<()> {
+        if !(factor.is_finite() && factor > 0.0) {
+            return Err(format_err!(
+                "The resolution scale must be a positive number, got {}",
+                factor
+            ));
+        }
+        let scale = |x: u32| ((x as Float) * factor).round().max(1.0) as u32;
+        self.width = scale(self.width);
+        self.height = scale(self.height);
+        Ok(())
+    }
+
+    /// Move the camera and change what it's pointing at
+    ///
+    /// This only applies to cameras that are defined by an origin and a target. `BasicPinhole`
+    /// cameras are defined by their image plane instead, so they're left unchanged, and this
+    /// returns false to let the caller know the override was ignored.
+    pub fn override_camera(
+        &mut self,
+        origin: Option<Vector3<Float>>,
+        target: Option<Vector3<Float>>,
+    ) -> bool {
+        let (camera_origin, camera_target) = match &mut self.camera {
+            SerializedCamera::Pinhole(x) => (&mut x.origin, &mut x.target),
+            SerializedCamera::ThinLens(x) => (&mut x.origin, &mut x.target),
+            SerializedCamera::BasicPinhole(_) => return false,
+        };
+
+        if let Some(origin) = origin {
+            *camera_origin = origin;
+        }
+
+        if let Some(target) = target {
+            *camera_target = target;
+        }
+        true
+    }
+
+    /// Check whether the pixel sampling pattern that was asked for can be used
+    ///
+    /// A tent jitter stands in for a reconstruction filter, so with any other filter than the box
+    /// filter, samples are jittered uniformly instead. That's only worth a warning if the tent
+    /// jitter was set explicitly, rather than being the default.
+    pub fn pixel_sampling_warning(&self) -> Option<String> {
+        let requested = self.pixel_sampling?;
+
+        if requested.with_filter(self.filter) == requested {
+            return None;
+        }
+        Some(format!(
+            "The tent jitter is only used with the box filter, so samples are jittered uniformly \
+             for the {:?} filter",
+            self.filter
+        ))
+    }
+
+    /// Resolve the relative paths of any files the scene refers to, such as meshes
+    ///
+    /// `base` should be the directory containing the scene file, so that scenes can refer to
+    /// files next to them regardless of the working directory.
+    pub fn resolve_paths(&mut self, base: &Path) {
+        resolve_paths(&mut self.objects, base);
+    }
 }
 
 impl TryFrom<Scene> for Renderer {
     type Error = anyhow::Error;
 
     fn try_from(scene: Scene) -> Result<Self, Self::Error> {
-        let aspect_ratio = (scene.height as Float) / (scene.width as Float);
-        // We just destructure the serialized struct and convert them to boxed dynamic
-        // implementations
-        let arena: Arena = Arc::new(scene.objects.iter().map(|&x| x.into()).collect());
-        let camera: Box<dyn Camera> = match scene.camera {
-            SerializedCamera::Pinhole(x) => Box::new(x.init(aspect_ratio)),
-            SerializedCamera::BasicPinhole(x) => Box::new(x),
-            SerializedCamera::ThinLens(x) => Box::new(x),
-        };
+        if scene.samples_per_pixel == 0 {
+            return Err(format_err!(
+                "The scene must take at least one sample per pixel"
+            ));
+        }
+
+        if !scene.exposure.is_finite() {
+            return Err(format_err!(
+                "The exposure must be a finite number, got {}",
+                scene.exposure
+            ));
+        }
+        let processed = ProcessedScene::new(&scene)?;
+        let mut background = scene.background;
+        background.convert_up_axis(scene.up_axis);
         let integrator: Box<dyn Integrator> = Box::new(scene.integrator);
-        let accel = scene.acceleration_structure.to_accel(arena.clone())?;
+
+        if let Some(warning) = scene.pixel_sampling_warning() {
+            warn!("{}", warning);
+        }
         Ok(Renderer {
-            arena,
-            camera,
+            scene: Arc::new(processed),
             integrator,
-            accel,
-            background: scene.background,
+            background,
+            ambient: scene.ambient,
             samples_per_pixel: scene.samples_per_pixel,
             height: scene.height,
             width: scene.width,
+            max_time: scene
+                .max_time
+                .map(Duration::try_from_secs_f32)
+                .transpose()?,
+            filter: scene.filter,
+            pixel_sampling: scene
+                .pixel_sampling
+                .unwrap_or_default()
+                .with_filter(scene.filter),
+            exposure: scene.exposure,
+            chunk_size: None,
+            progress: ProgressMode::Auto,
+            track_convergence: false,
+            seed: scene.seed.unwrap_or_else(rand::random),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_scene::{TestScene, PINHOLE};
+    use cgmath::{InnerSpace, Point3};
+
+    fn scene(width: u32, height: u32) -> Scene {
+        TestScene::new().resolution(width, height).build()
+    }
+
+    #[test]
+    fn scale_resolution() {
+        let mut s = scene(200, 100);
+        s.scale_resolution(0.5).unwrap();
+        assert_eq!((s.width, s.height), (100, 50));
+
+        let mut s = scene(200, 100);
+        s.scale_resolution(2.0).unwrap();
+        assert_eq!((s.width, s.height), (400, 200));
+
+        // The dimensions are rounded, and never collapse to zero
+        let mut s = scene(3, 1);
+        s.scale_resolution(0.5).unwrap();
+        assert_eq!((s.width, s.height), (2, 1));
+
+        for factor in [0.0, -1.0, Float::NAN, Float::INFINITY] {
+            assert!(scene(200, 100).scale_resolution(factor).is_err());
+        }
+    }
+
+    /// A scene with a pinhole camera looking down -z with a 90 degree vertical field of view
+    fn pinhole_scene(width: u32, height: u32) -> Scene {
+        TestScene::new()
+            .camera(PINHOLE)
+            .resolution(width, height)
+            .build()
+    }
+
+    /// The angles subtended by one pixel horizontally and vertically at the center of the image
+    fn pixel_angles(scene: Scene) -> (Float, Float) {
+        let (width, height) = (scene.width as Float, scene.height as Float);
+        let renderer = Renderer::try_from(scene).unwrap();
+        let center = renderer.scene.camera.to_ray(0.5, 0.5).direction;
+        let right = renderer
+            .scene
+            .camera
+            .to_ray(0.5 + 1.0 / width, 0.5)
+            .direction;
+        let up = renderer
+            .scene
+            .camera
+            .to_ray(0.5, 0.5 + 1.0 / height)
+            .direction;
+        (center.angle(right).0, center.angle(up).0)
+    }
+
+    #[test]
+    fn pinhole_is_not_stretched() {
+        for (width, height) in [(200, 100), (100, 200), (100, 100)] {
+            let (horizontal, vertical) = pixel_angles(pinhole_scene(width, height));
+            assert!(
+                (horizontal - vertical).abs() < 1e-4,
+                "{}x{}: {} != {}",
+                width,
+                height,
+                horizontal,
+                vertical
+            );
+        }
+
+        // The vertical field of view doesn't depend on the aspect ratio
+        let renderer = Renderer::try_from(pinhole_scene(200, 100)).unwrap();
+        let top = renderer.scene.camera.to_ray(0.5, 1.0).direction;
+        assert!((top - Vector3::new(0.0, 1.0, -1.0).normalize()).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn camera_override() {
+        let mut s = pinhole_scene(100, 100);
+        assert!(s.override_camera(Some(Vector3::new(0.0, 0.0, 5.0)), None));
+        // The camera keeps looking at the original target from its new origin
+        let renderer = Renderer::try_from(s.clone()).unwrap();
+        let ray = renderer.scene.camera.to_ray(0.5, 0.5);
+        assert!((ray.origin - Point3::new(0.0, 0.0, 5.0)).magnitude() < 1e-6);
+        assert!((ray.direction - Vector3::new(0.0, 0.0, -1.0)).magnitude() < 1e-5);
+
+        assert!(s.override_camera(None, Some(Vector3::new(5.0, 0.0, 5.0))));
+        let renderer = Renderer::try_from(s).unwrap();
+        let ray = renderer.scene.camera.to_ray(0.5, 0.5);
+        assert!((ray.direction - Vector3::new(1.0, 0.0, 0.0)).magnitude() < 1e-5);
+
+        // Basic pinhole cameras don't have an origin and target to override
+        let mut s = scene(100, 100);
+        assert!(!s.override_camera(Some(Vector3::new(0.0, 0.0, 5.0)), None));
+    }
+
+    #[test]
+    fn aspect_ratio_override() {
+        // Doubling the aspect ratio of a square image doubles the horizontal extent of each pixel
+        let mut s = pinhole_scene(100, 100);
+        s.aspect_ratio = Some(2.0);
+        let (horizontal, vertical) = pixel_angles(s);
+        assert!(horizontal > 1.9 * vertical);
+
+        for ratio in [0.0, -1.0, Float::NAN] {
+            let mut s = pinhole_scene(100, 100);
+            s.aspect_ratio = Some(ratio);
+            assert!(Renderer::try_from(s).is_err());
+        }
+
+        // The corners of a 2:1 image are twice as far out horizontally as vertically
+        let renderer = Renderer::try_from(pinhole_scene(20, 10)).unwrap();
+        let corner = renderer.scene.camera.to_ray(1.0, 1.0).direction;
+        assert!((corner - Vector3::new(2.0, 1.0, -1.0).normalize()).magnitude() < 1e-5);
+
+        // The camera can't set an aspect ratio, so one in the scene file isn't silently ignored
+        let camera = r#"{
+            "type": "Pinhole",
+            "origin": [0, 0, 0],
+            "target": [0, 0, -1],
+            "up": [0, 1, 0],
+            "vfov": 90,
+            "aspect_ratio": 2
+        }"#;
+        let err = json5::from_str::<SerializedCamera>(camera).unwrap_err();
+        assert!(err.to_string().contains("aspect_ratio"), "{}", err);
+    }
+
+    /// Only a tent jitter that was asked for warns when a filter replaces it
+    #[test]
+    fn pixel_sampling_warning() {
+        let mut scene = scene(4, 2);
+        scene.filter = FilterType::Gaussian;
+        assert_eq!(scene.pixel_sampling, None);
+        assert_eq!(scene.pixel_sampling_warning(), None);
+        let renderer = Renderer::try_from(scene.clone()).unwrap();
+        assert_eq!(renderer.pixel_sampling, PixelSampling::Random);
+
+        scene.pixel_sampling = Some(PixelSampling::Tent);
+        assert!(scene.pixel_sampling_warning().is_some());
+
+        scene.pixel_sampling = Some(PixelSampling::Grid);
+        assert_eq!(scene.pixel_sampling_warning(), None);
+
+        scene.pixel_sampling = Some(PixelSampling::Tent);
+        scene.filter = FilterType::Box;
+        assert_eq!(scene.pixel_sampling_warning(), None);
+        let renderer = Renderer::try_from(scene).unwrap();
+        assert_eq!(renderer.pixel_sampling, PixelSampling::Tent);
+    }
+
+    /// The scene enums are internally tagged, and survive a round trip through every format
+    #[test]
+    fn round_trip() {
+        let mut original = pinhole_scene(4, 2);
+        original.objects = json5::from_str(
+            r#"[
+                {
+                    "geometry": { "type": "Sphere", "center": [0, 0, -1], "radius": 0.5 },
+                    "mat": { "type": "Emissive", "emission": [1, 2, 3], "unit": "Watts" }
+                },
+                {
+                    "geometry": { "type": "Triangle", "vertices": [[0, 0, 0], [1, 0, 0], [0, 1, 0]], "handedness": "Clockwise" },
+                    "mat": { "type": "Diffuse", "albedo": [0.5, 0.5, 0.5] }
+                }
+            ]"#,
+        )
+        .unwrap();
+        original.integrator =
+            json5::from_str(r#"{ "type": "TestPattern", "pattern": "GrayRamp" }"#).unwrap();
+        let expected = format!("{:?}", original);
+
+        let json = json5::to_string(&original).unwrap();
+        assert!(json.contains(r#""type":"Pinhole""#), "{}", json);
+        let from_json: Scene = json5::from_str(&json).unwrap();
+        assert_eq!(format!("{:?}", from_json), expected);
+
+        let yaml = serde_yaml::to_string(&original).unwrap();
+        let from_yaml: Scene = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(format!("{:?}", from_yaml), expected);
+
+        let ron = ron::ser::to_string(&original).unwrap();
+        let from_ron: Scene = ron::de::from_str(&ron).unwrap();
+        assert_eq!(format!("{:?}", from_ron), expected);
+    }
+}
@@ -0,0 +1,143 @@
+//! A builder for the small scenes that the unit tests render
+//!
+//! Every test that needs a whole scene starts from the same base scene and only spells out the
+//! parts it cares about, so a change to the scene format only has to be made here.
+
+use crate::{hittable::HitRecord, scene::Scene, types::Float};
+use cgmath::{Point3, Vector2, Vector3};
+
+/// A gray diffuse material, written like it would be in a scene file
+pub(crate) const GRAY: &str = r#"{ "type": "Diffuse", "albedo": [0.5, 0.5, 0.5] }"#;
+
+/// A white light, written like it would be in a scene file
+pub(crate) const LIGHT: &str = r#"{ "type": "Emissive", "emission": [1.0, 1.0, 1.0] }"#;
+
+/// A pinhole camera at the origin looking down -z, with a 90 degree vertical field of view
+pub(crate) const PINHOLE: &str = r#"{
+    "type": "Pinhole",
+    "origin": [0, 0, 0],
+    "target": [0, 0, -1],
+    "vfov": 90,
+    "up": [0, 1, 0]
+}"#;
+
+/// A hit on the front face of a surface at the origin, with both normals along `normal`
+///
+/// Materials and integrators only look at the hit record, so their tests can shade this without
+/// tracing a ray. The normal must be a unit vector.
+pub(crate) fn hit_at_origin(normal: Vector3<Float>) -> HitRecord {
+    HitRecord {
+        p: Point3::new(0.0, 0.0, 0.0),
+        geometric_normal: normal,
+        shading_normal: normal,
+        distance: 1.0,
+        front_face: true,
+        uv: Vector2::new(0.0, 0.0),
+    }
+}
+
+/// A builder for a scene to use in a test
+///
+/// The scene starts out empty, with a 16x8 image seen through a `BasicPinhole` camera at the
+/// origin looking down -z, a black background, and the `Normal` integrator with one sample per
+/// pixel. The parts of the scene are given as JSON5, the same way they're written in scene
+/// files, and anything the builder doesn't cover can be set on the built scene.
+#[derive(Debug, Clone)]
+pub(crate) struct TestScene {
+    objects: Vec<String>,
+    camera: String,
+    background: String,
+    integrator: String,
+    samples_per_pixel: u32,
+    width: u32,
+    height: u32,
+}
+
+impl TestScene {
+    pub fn new() -> Self {
+        TestScene {
+            objects: Vec::new(),
+            camera: r#"{
+                "type": "BasicPinhole",
+                "origin": [0.0, 0.0, 0.0],
+                "horizontal": [4.0, 0.0, 0.0],
+                "vertical": [0.0, 2.0, 0.0],
+                "lower_left": [-2.0, -1.0, -1.0]
+            }"#
+            .to_string(),
+            background: "[0, 0, 0]".to_string(),
+            integrator: r#"{ "type": "Normal" }"#.to_string(),
+            samples_per_pixel: 1,
+            width: 16,
+            height: 8,
+        }
+    }
+
+    /// Add an object with some geometry and material
+    pub fn object(mut self, geometry: &str, mat: &str) -> Self {
+        self.objects
+            .push(format!(r#"{{ "geometry": {}, "mat": {} }}"#, geometry, mat));
+        self
+    }
+
+    /// Add a sphere with some material
+    pub fn sphere(self, center: [Float; 3], radius: Float, mat: &str) -> Self {
+        self.object(
+            &format!(
+                r#"{{ "type": "Sphere", "center": {:?}, "radius": {:?} }}"#,
+                center, radius
+            ),
+            mat,
+        )
+    }
+
+    pub fn camera(mut self, camera: &str) -> Self {
+        self.camera = camera.to_string();
+        self
+    }
+
+    pub fn background(mut self, background: &str) -> Self {
+        self.background = background.to_string();
+        self
+    }
+
+    pub fn integrator(mut self, integrator: &str) -> Self {
+        self.integrator = integrator.to_string();
+        self
+    }
+
+    pub fn samples_per_pixel(mut self, samples_per_pixel: u32) -> Self {
+        self.samples_per_pixel = samples_per_pixel;
+        self
+    }
+
+    pub fn resolution(mut self, width: u32, height: u32) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    /// Parse the scene, panicking if any of its parts are invalid
+    pub fn build(self) -> Scene {
+        let scene = format!(
+            r#"{{
+                "objects": [{}],
+                "acceleration_structure": {{ "ObjectList": {{}} }},
+                "camera": {},
+                "background": {},
+                "samples_per_pixel": {},
+                "integrator": {},
+                "height": {},
+                "width": {}
+            }}"#,
+            self.objects.join(", "),
+            self.camera,
+            self.background,
+            self.samples_per_pixel,
+            self.integrator,
+            self.height,
+            self.width
+        );
+        json5::from_str(&scene).unwrap_or_else(|e| panic!("invalid test scene: {}\n{}", e, scene))
+    }
+}
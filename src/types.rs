@@ -1,9 +1,8 @@
 //! Defines generic numeric types for the integrator so that operations can be done with generic
 //! integers or floating point numbers.
 
-use cgmath::{BaseFloat, BaseNum, Vector3};
+use cgmath::{BaseFloat, BaseNum, EuclideanSpace, Point3, Vector3};
 use float_cmp::approx_eq;
-use num;
 use std::fmt::{Debug, Display};
 
 /// Generate a trait that is the sum of other trait bounds
@@ -12,7 +11,7 @@ use std::fmt::{Debug, Display};
 /// B`, that implements the other traits automatically.
 ///
 /// For example, `add_traits!(A; B, C)` generates:
-/// ```
+/// ```ignore
 /// pub trait A: B + C {}
 /// impl<T> A for T where T: B + C {}
 /// ```
@@ -29,6 +28,57 @@ aggregate_trait!(GenReal; BaseNum, Sync, PartialOrd, Copy, Clone, Display, Debug
 aggregate_trait!(GenInteger; num::Integer, GenReal);
 aggregate_trait!(GenFloat; BaseFloat, GenReal, num::Signed);
 
+/// Define a unit enum that's serialized as the name of its variant, as a string
+///
+/// serde writes a unit variant as a bare identifier in RON, like `Flip`. The `Serialized*` enums
+/// are internally tagged, so serde buffers their fields before it knows their types, and RON
+/// can't read a bare identifier back from that buffer. A string reads back the same way in every
+/// format, and the derived `Deserialize` accepts it.
+///
+/// This derives `Serialize` and `Deserialize`, and implements the conversion to a string that
+/// serialization goes through. The enum has to derive `Clone` itself.
+///
+/// ```ignore
+/// string_enum! {
+///     #[derive(Debug, Clone, Copy)]
+///     pub enum Winding {
+///         Unchanged,
+///         Flip,
+///     }
+/// }
+/// ```
+macro_rules! string_enum {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident {
+            $(
+                $(#[$variant_meta:meta])*
+                $variant:ident
+            ),+ $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(::serde::Serialize, ::serde::Deserialize)]
+        #[serde(into = "&'static str")]
+        $vis enum $name {
+            $(
+                $(#[$variant_meta])*
+                $variant,
+            )+
+        }
+
+        impl From<$name> for &'static str {
+            fn from(value: $name) -> Self {
+                match value {
+                    $($name::$variant => stringify!($variant),)+
+                }
+            }
+        }
+    };
+}
+
+pub(crate) use string_enum;
+
 /// The particular floating point type that is going to be used in this program. If you want to
 /// switch the float type to another type, simply change the type here.
 pub type Float = f32;
@@ -44,6 +94,37 @@ pub type Integer = i32;
 /// final color value that is output to the buffer.
 pub type PixelValue<T> = Vector3<T>;
 
+/// The luminance of a linear RGB color, using the Rec. 709 weights
+///
+/// This is how bright the color looks, which is mostly down to the green channel. Use this rather
+/// than weighting the channels by hand, so every part of the renderer agrees on what "bright"
+/// means.
+pub fn luminance(color: &PixelValue<Float>) -> Float {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+/// Whether every channel of a color is zero
+pub fn is_black(color: &PixelValue<Float>) -> bool {
+    color.x == 0.0 && color.y == 0.0 && color.z == 0.0
+}
+
+/// The largest channel of a color
+pub fn max_component(color: &PixelValue<Float>) -> Float {
+    color.x.max(color.y).max(color.z)
+}
+
+/// Map a value in [0, 1] to a false color going from blue through green and yellow to red
+///
+/// This is for visualizing a quantity, such as an error or a count, as an image.
+pub fn false_color(t: Float) -> PixelValue<Float> {
+    let t = t.clamp(0.0, 1.0);
+    PixelValue::new(
+        (2.0 * t - 0.5).clamp(0.0, 1.0),
+        (1.0 - (2.0 * t - 1.0).abs() * 1.5).clamp(0.0, 1.0),
+        (1.0 - 2.0 * t).clamp(0.0, 1.0),
+    )
+}
+
 /// The floating point error threshold to use with the renderer
 pub const ETA: Float = 0.000001;
 
@@ -53,9 +134,75 @@ pub fn eta<T: GenFloat>() -> T {
     T::from(ETA).unwrap()
 }
 
+/// One of the three spatial axes
+///
+/// This is for code that works one axis at a time, like the slab test for bounding boxes, so it
+/// can loop over the axes and index vectors by them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dimension {
+    X,
+    Y,
+    Z,
+}
+
+impl Dimension {
+    /// Every axis, in order
+    pub const fn all() -> [Dimension; 3] {
+        [Dimension::X, Dimension::Y, Dimension::Z]
+    }
+
+    /// The index of the axis's component in a vector
+    ///
+    /// `Vector3` implements `Index<usize>`, so `v[d.index()]` is the component of `v` along `d`.
+    pub const fn index(self) -> usize {
+        match self {
+            Dimension::X => 0,
+            Dimension::Y => 1,
+            Dimension::Z => 2,
+        }
+    }
+}
+
 /// Compare two floating point vectors
 pub fn approx_eq_vec(left: &Vector3<Float>, right: &Vector3<Float>) -> bool {
     approx_eq!(Float, left.x, right.x)
         && approx_eq!(Float, left.y, right.y)
         && approx_eq!(Float, left.z, right.z)
 }
+
+/// Compare two floating point points
+pub fn approx_eq_point(left: &Point3<Float>, right: &Point3<Float>) -> bool {
+    approx_eq_vec(&left.to_vec(), &right.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dimension_indices() {
+        let v = Vector3::new(1.0, 2.0, 3.0);
+        let components: Vec<Float> = Dimension::all().iter().map(|d| v[d.index()]).collect();
+        assert_eq!(components, vec![v.x, v.y, v.z]);
+    }
+
+    #[test]
+    fn color_helpers() {
+        let white = PixelValue::new(1.0, 1.0, 1.0);
+        let green = PixelValue::new(0.0, 1.0, 0.0);
+        let black = PixelValue::new(0.0, 0.0, 0.0);
+        assert!(approx_eq!(Float, luminance(&white), 1.0, epsilon = 1e-6));
+        assert!(approx_eq!(Float, luminance(&green), 0.7152));
+        assert_eq!(luminance(&black), 0.0);
+
+        assert!(is_black(&black));
+        assert!(is_black(&-black));
+        assert!(!is_black(&green));
+
+        assert_eq!(max_component(&PixelValue::new(0.2, 3.0, 0.5)), 3.0);
+        assert_eq!(max_component(&black), 0.0);
+
+        assert_eq!(false_color(0.0), PixelValue::new(0.0, 0.0, 1.0));
+        assert_eq!(false_color(1.0), PixelValue::new(1.0, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,143 @@
+//! Checks that every example scene in `scenes/` can be parsed and rendered
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Collect the paths of all of the example scene files
+fn scene_files() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes");
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .expect("the scenes directory should exist")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn gallery_covers_every_format() {
+    let extensions: Vec<String> = scene_files()
+        .iter()
+        .filter_map(|path| path.extension())
+        .map(|ext| ext.to_string_lossy().into_owned())
+        .collect();
+
+    for ext in ["ron", "yaml", "json"] {
+        assert!(
+            extensions.iter().any(|x| x == ext),
+            "there is no example scene with the \"{}\" extension",
+            ext
+        );
+    }
+}
+
+#[test]
+fn scenes_parse() {
+    for path in scene_files() {
+        let output = Command::new(env!("CARGO_BIN_EXE_nib"))
+            .arg(&path)
+            .arg("--only-parse")
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "failed to parse {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
+
+#[test]
+fn scenes_render() {
+    let out_dir = std::env::temp_dir().join(format!("nib-scene-files-{}", std::process::id()));
+    fs::create_dir_all(&out_dir).unwrap();
+
+    for path in scene_files() {
+        let out_path = out_dir
+            .join(path.file_stem().unwrap())
+            .with_extension("ppm");
+        let output = Command::new(env!("CARGO_BIN_EXE_nib"))
+            .arg(&path)
+            .arg("--output")
+            .arg(&out_path)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "failed to render {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(out_path.exists());
+    }
+    fs::remove_dir_all(&out_dir).unwrap();
+}
+
+#[test]
+fn info() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/mesh.ron");
+    let output = Command::new(env!("CARGO_BIN_EXE_nib"))
+        .arg(&path)
+        .arg("--info")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    for row in ["Objects", "Primitives", "Total samples", "Estimated memory"] {
+        assert!(stdout.contains(row), "{}", stdout);
+    }
+    // The cube in the scene is made of 12 triangles
+    assert!(
+        stdout
+            .lines()
+            .any(|line| line.starts_with("Primitives") && line.ends_with(" 12")),
+        "{}",
+        stdout
+    );
+}
+
+/// `--info` doesn't render, so it shouldn't expand the output name or create its directories
+#[test]
+fn info_ignores_output() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/mesh.ron");
+    let out_dir = std::env::temp_dir().join(format!("nib-info-{}", std::process::id()));
+    fs::create_dir_all(&out_dir).unwrap();
+
+    for output_name in ["{bad}/x.png", "out/{integrator}/frame.png"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_nib"))
+            .arg(&path)
+            .arg("--info")
+            .arg("--output")
+            .arg(output_name)
+            .current_dir(&out_dir)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    assert_eq!(fs::read_dir(&out_dir).unwrap().count(), 0);
+    fs::remove_dir_all(&out_dir).unwrap();
+}
+
+#[test]
+fn list_formats() {
+    let output = Command::new(env!("CARGO_BIN_EXE_nib"))
+        .arg("--list-formats")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    for format in ["ron", "json", "yaml", "ply", "png", "ppm"] {
+        assert!(stdout.contains(format), "{}", stdout);
+    }
+}